@@ -21,6 +21,9 @@ pub const TYPE_OBJECT: u8 = 0x03;
 pub const TYPE_DATETIME: u8 = 0x09;
 pub const TYPE_BINARY: u8 = 0x05;
 
+/// Largest encoded document accepted anywhere, in bytes.
+pub const MAX_DOCUMENT_SIZE: usize = 16 * 1024 * 1024;
+
 /// Simple BSON serialization error
 #[derive(Debug, thiserror::Error)]
 pub enum BsonError {
@@ -68,7 +71,7 @@ impl<W: Write + Seek> BsonEncoder<W> {
     pub fn new (writer: W) -> Self {
         Self {
             writer,
-            memory_limit: 16 * 1024 * 1024, // 16MB default
+            memory_limit: MAX_DOCUMENT_SIZE, // 16MB default
             bytes_written: 0,
             progress_callback: None,
             max_nesting_depth: 100, // Reasonable limit for nesting
@@ -399,7 +402,7 @@ impl<R: Read> BsonDecoder<R> {
     pub fn new(reader: R) -> Self {
         Self {
             reader,
-            memory_limit: 16 * 1024 * 1024, // 16MB default
+            memory_limit: MAX_DOCUMENT_SIZE, // 16MB default
             bytes_read: 0,
             progress_callback: None,
         }
@@ -584,9 +587,14 @@ impl<R: Read> BsonDecoder<R> {
             }
         }
         
+        // Keep the document's own _id when it was requested, as deserialize_document does
+        let id = data_map
+            .remove("_id")
+            .unwrap_or_else(|| Value::ObjectId(ObjectId::new()));
+
         Ok(Document {
             data: data_map,
-            id: Value::ObjectId(ObjectId::new()),
+            id,
         })
     }
 
@@ -830,7 +838,7 @@ pub fn deserialize_document(data: &[u8]) -> Result<Document, BsonError> {
         }
         
         // Check for maximum document size (16MB)
-        if document_length > 16 * 1024 * 1024 {
+        if document_length > MAX_DOCUMENT_SIZE {
             return Err(BsonError::DocumentTooLarge(document_length));
         }
         
@@ -2202,6 +2210,20 @@ mod tests {
         assert_eq!(partial.data.len(), 2); // Only 2 fields
     }
 
+    /// Test partial document reading keeps the requested _id
+    #[test]
+    fn test_partial_document_reading_keeps_id() {
+        let mut doc = Document::new();
+        doc.set("name", Value::String("Alice".to_string()));
+
+        let serialized = serialize_document(&doc).unwrap();
+        let mut decoder = BsonDecoder::new(Cursor::new(&serialized));
+
+        let partial = decoder.decode_partial_document(&["_id"]).unwrap();
+        assert_eq!(partial.id(), doc.id());
+        assert!(partial.is_empty());
+    }
+
     /// Test partial document reading with missing field
     #[test]
     fn test_partial_document_reading_missing_field() {
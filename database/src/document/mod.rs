@@ -88,6 +88,44 @@ impl Document {
         cur
     }
 
+    /// Set the value at a dotted path, creating embedded objects along the way.
+    /// Returns false, changing nothing, if the path runs through a value that
+    /// is not an object.
+    pub fn set_path(&mut self, input: &str, val: Value) -> bool {
+        let mut keys: Vec<&str> = input.split('.').collect();
+        let last = keys.pop().unwrap_or_default();
+        let mut map = &mut self.data;
+
+        for key in keys {
+            let entry = map
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Object(BTreeMap::new()));
+            match entry {
+                Value::Object(inner) => map = inner,
+                _ => return false,
+            }
+        }
+
+        map.insert(last.to_string(), val);
+        true
+    }
+
+    /// Remove and return the value at a dotted path.
+    pub fn remove_path(&mut self, input: &str) -> Option<Value> {
+        let mut keys: Vec<&str> = input.split('.').collect();
+        let last = keys.pop()?;
+        let mut map = &mut self.data;
+
+        for key in keys {
+            match map.get_mut(key) {
+                Some(Value::Object(inner)) => map = inner,
+                _ => return None,
+            }
+        }
+
+        map.remove(last)
+    }
+
     pub fn get_id(&self) -> Option<&ObjectId> {
         match &self.id {
             Value::ObjectId(oid) => Some(oid),
@@ -95,6 +133,17 @@ impl Document {
         }
     }
 
+    /// Replace the document's `_id`
+    pub fn set_id(&mut self, id: ObjectId) {
+        self.id = Value::ObjectId(id);
+    }
+
+    /// Replace the `_id` with a value of any type, such as the key of an
+    /// aggregation group
+    pub fn set_id_value(&mut self, id: Value) {
+        self.id = id;
+    }
+
     /// Get the raw ID value (useful for testing and comparisons)
     pub fn id(&self) -> &Value {
         &self.id
@@ -217,6 +266,29 @@ mod tests {
         assert_eq!(doc.get_path("no.such.path"), None);
     }
 
+    #[test]
+    fn test_set_path() {
+        let mut doc = Document::new();
+        assert!(doc.set_path("x.y.z", Value::I32(1)));
+        assert!(doc.set_path("x.w", Value::I32(2)));
+        assert_eq!(doc.get_path("x.y.z"), Some(&Value::I32(1)));
+        assert_eq!(doc.get_path("x.w"), Some(&Value::I32(2)));
+
+        // Cannot reach through a non-object
+        assert!(!doc.set_path("x.w.v", Value::I32(3)));
+        assert_eq!(doc.get_path("x.w"), Some(&Value::I32(2)));
+    }
+
+    #[test]
+    fn test_remove_path() {
+        let mut doc = Document::new();
+        doc.set_path("x.y", Value::I32(1));
+        assert_eq!(doc.remove_path("x.w"), None);
+        assert_eq!(doc.remove_path("x.y.z"), None);
+        assert_eq!(doc.remove_path("x.y"), Some(Value::I32(1)));
+        assert_eq!(doc.get_path("x"), Some(&Value::Object(BTreeMap::new())));
+    }
+
     #[test]
     fn test_get_id_and_ensure_id() {
         let mut doc = Document::new();
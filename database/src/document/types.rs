@@ -297,12 +297,15 @@ impl Value {
         }
     }
 
+    /// Convert parsed JSON. Integers become I32 when they fit and I64
+    /// otherwise, rather than being truncated to 32 bits; other numbers
+    /// become F64.
     pub fn from_json_value(v: serde_json::Value) -> Self {
         match v {
             serde_json::Value::Bool(b) => Value::Bool(b),
             serde_json::Value::Number(n) => {
                 if let Some(i) = n.as_i64() {
-                    Value::I32(i as i32)
+                    i32::try_from(i).map_or(Value::I64(i), Value::I32)
                 } else if let Some(f) = n.as_f64() {
                     Value::F64(f)
                 } else {
@@ -444,6 +447,21 @@ mod tests {
         assert_eq!(value.to_str(), Some("3.14".to_string()));
     }
 
+    #[test]
+    fn test_from_json_value_integer_widths() {
+        let number = |json: &str| Value::from_json_value(serde_json::from_str(json).unwrap());
+        assert_eq!(number("2147483647"), Value::I32(i32::MAX));
+        assert_eq!(number("-2147483648"), Value::I32(i32::MIN));
+        assert_eq!(number("2147483648"), Value::I64(2_147_483_648));
+        assert_eq!(number("-2147483649"), Value::I64(-2_147_483_649));
+        assert_eq!(number("9223372036854775807"), Value::I64(i64::MAX));
+        assert_eq!(number("1.5"), Value::F64(1.5));
+        assert_eq!(
+            number("[2147483648, 1]"),
+            Value::Array(vec![Value::I64(2_147_483_648), Value::I32(1)])
+        );
+    }
+
     #[test]
     fn test_value_is_object_id() {
         let oid = ObjectId::new();
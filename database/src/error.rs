@@ -1,3 +1,4 @@
+use crate::document::types::Value;
 use bincode;
 use serde_json;
 use std::fmt;
@@ -11,6 +12,11 @@ pub enum DatabaseError {
     Index(String),
     Network(String),
     Validation(String),
+    /// A write would give two documents the same key in a unique index.
+    DuplicateKey { index: String, key: Value },
+    /// A concurrent writer changed or latched a page this write needed. The
+    /// write left no trace and can be run again.
+    WriteConflict,
     InvalidChecksum,
     Io(io::Error),
     Json(serde_json::Error),
@@ -26,6 +32,10 @@ impl fmt::Display for DatabaseError {
             DatabaseError::Index(msg) => write!(f, "Index error: {}", msg),
             DatabaseError::Network(msg) => write!(f, "Network error: {}", msg),
             DatabaseError::Validation(msg) => write!(f, "Validation error: {}", msg),
+            DatabaseError::DuplicateKey { index, key } => {
+                write!(f, "Duplicate key error: {} already exists in index '{}'", key, index)
+            }
+            DatabaseError::WriteConflict => write!(f, "Write conflict with a concurrent writer"),
             DatabaseError::InvalidChecksum => write!(f, "Invalid page checksum"),
             DatabaseError::Io(err) => write!(f, "IO error: {}", err),
             DatabaseError::Json(err) => write!(f, "JSON error: {}", err),
@@ -129,4 +139,16 @@ mod tests {
             "Validation error: Invalid data format"
         );
     }
+
+    #[test]
+    fn test_duplicate_key_error_display() {
+        let duplicate_key_error = DatabaseError::DuplicateKey {
+            index: "email_1".to_string(),
+            key: Value::String("ann@example.com".to_string()),
+        };
+        assert_eq!(
+            format!("{}", duplicate_key_error),
+            "Duplicate key error: ann@example.com already exists in index 'email_1'"
+        );
+    }
 }
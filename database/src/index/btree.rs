@@ -0,0 +1,665 @@
+// A B+tree whose nodes live in `PageType::Index` pages and are read and written
+// through the buffer pool like any other page.
+//
+// Keys and values are opaque byte strings compared with plain byte ordering, so
+// callers are responsible for encoding their keys in an order-preserving way.
+//
+// Node layout inside the page payload:
+//   kind (u8) | entry count (u16) | link (u64) | entries...
+// For leaves the link is the next leaf to the right and each entry is
+// `key_len (u16) | key | value_len (u16) | value`. For internal nodes the link is
+// the leftmost child and each entry is `key_len (u16) | key | child (u64)`, where
+// the child holds every key >= its separator.
+//
+// The root page never moves: when it splits, its contents are copied into a new
+// page and the root is rewritten as an internal node. Deletes remove entries
+// without merging nodes, so emptied leaves simply stay in the chain.
+
+use crate::error::DatabaseError;
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::file::DatabaseFile;
+use crate::storage::page::{PAGE_HEADER_SIZE, PAGE_SIZE, PageType};
+use std::ops::Bound;
+
+const NODE_LEAF: u8 = 0;
+const NODE_INTERNAL: u8 = 1;
+const NO_PAGE: u64 = u64::MAX;
+const NODE_HEADER_SIZE: usize = 11; // kind (1) + entry count (2) + link (8)
+const NODE_CAPACITY: usize = PAGE_SIZE - PAGE_HEADER_SIZE - NODE_HEADER_SIZE;
+
+/// Largest encoded key + value accepted by the tree. Keeping entries to a quarter
+/// of a node guarantees both halves of a split fit in a page.
+pub const MAX_ENTRY_SIZE: usize = NODE_CAPACITY / 4;
+
+/// A key and its value, as stored in a leaf.
+pub type Entry = (Vec<u8>, Vec<u8>);
+
+type LeafEntry = Entry;
+type InternalEntry = (Vec<u8>, u64);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Leaf {
+        entries: Vec<LeafEntry>,
+        next: Option<u64>,
+    },
+    Internal {
+        leftmost: u64,
+        entries: Vec<InternalEntry>,
+    },
+}
+
+impl Node {
+    fn empty_leaf() -> Self {
+        Node::Leaf {
+            entries: Vec::new(),
+            next: None,
+        }
+    }
+
+    fn leaf_entry_size(entry: &LeafEntry) -> usize {
+        4 + entry.0.len() + entry.1.len()
+    }
+
+    fn internal_entry_size(entry: &InternalEntry) -> usize {
+        2 + entry.0.len() + 8
+    }
+
+    fn encoded_size(&self) -> usize {
+        match self {
+            Node::Leaf { entries, .. } => entries.iter().map(Self::leaf_entry_size).sum(),
+            Node::Internal { entries, .. } => entries.iter().map(Self::internal_entry_size).sum(),
+        }
+    }
+
+    fn fits(&self) -> bool {
+        self.encoded_size() <= NODE_CAPACITY
+    }
+
+    fn encode(&self, buf: &mut [u8]) {
+        let (kind, count, link) = match self {
+            Node::Leaf { entries, next } => (NODE_LEAF, entries.len(), next.unwrap_or(NO_PAGE)),
+            Node::Internal { leftmost, entries } => (NODE_INTERNAL, entries.len(), *leftmost),
+        };
+        buf[0] = kind;
+        buf[1..3].copy_from_slice(&(count as u16).to_le_bytes());
+        buf[3..11].copy_from_slice(&link.to_le_bytes());
+
+        let mut pos = NODE_HEADER_SIZE;
+        let mut put = |bytes: &[u8]| {
+            buf[pos..pos + bytes.len()].copy_from_slice(bytes);
+            pos += bytes.len();
+        };
+        match self {
+            Node::Leaf { entries, .. } => {
+                for (key, value) in entries {
+                    put(&(key.len() as u16).to_le_bytes());
+                    put(key);
+                    put(&(value.len() as u16).to_le_bytes());
+                    put(value);
+                }
+            }
+            Node::Internal { entries, .. } => {
+                for (key, child) in entries {
+                    put(&(key.len() as u16).to_le_bytes());
+                    put(key);
+                    put(&child.to_le_bytes());
+                }
+            }
+        }
+    }
+
+    fn decode(buf: &[u8]) -> Result<Self, DatabaseError> {
+        let mut reader = NodeReader { buf, pos: 0 };
+        let kind = reader.bytes(1)?[0];
+        let count = reader.u16()? as usize;
+        let link = reader.u64()?;
+
+        match kind {
+            NODE_LEAF => {
+                let mut entries = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let key_len = reader.u16()? as usize;
+                    let key = reader.bytes(key_len)?.to_vec();
+                    let value_len = reader.u16()? as usize;
+                    let value = reader.bytes(value_len)?.to_vec();
+                    entries.push((key, value));
+                }
+                let next = if link == NO_PAGE { None } else { Some(link) };
+                Ok(Node::Leaf { entries, next })
+            }
+            NODE_INTERNAL => {
+                let mut entries = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let key_len = reader.u16()? as usize;
+                    let key = reader.bytes(key_len)?.to_vec();
+                    entries.push((key, reader.u64()?));
+                }
+                Ok(Node::Internal {
+                    leftmost: link,
+                    entries,
+                })
+            }
+            _ => Err(NodeReader::corrupt()),
+        }
+    }
+}
+
+/// Bounds-checked cursor over an encoded node.
+struct NodeReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> NodeReader<'a> {
+    fn corrupt() -> DatabaseError {
+        DatabaseError::Index("Corrupt B+tree node".to_string())
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DatabaseError> {
+        let bytes = self
+            .buf
+            .get(self.pos..self.pos + len)
+            .ok_or_else(Self::corrupt)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16, DatabaseError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, DatabaseError> {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+}
+
+/// Pick a split point so that both halves carry roughly half of the bytes.
+fn split_point(sizes: impl Iterator<Item = usize>, len: usize) -> usize {
+    let sizes: Vec<usize> = sizes.collect();
+    let half = sizes.iter().sum::<usize>() / 2;
+    let mut running = 0;
+    for (i, size) in sizes.iter().enumerate() {
+        running += size;
+        if running >= half {
+            return (i + 1).clamp(1, len - 1);
+        }
+    }
+    len / 2
+}
+
+/// Handle to a B+tree identified by its (fixed) root page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BTree {
+    root_page_id: u64,
+}
+
+impl BTree {
+    /// Allocate a root page and initialise it as an empty tree.
+    pub fn create(
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+    ) -> Result<Self, DatabaseError> {
+        let root_page_id = database_file.allocate_page_with_type(PageType::Index)?;
+        let tree = Self { root_page_id };
+        tree.write_node(
+            buffer_pool,
+            database_file,
+            root_page_id,
+            &Node::empty_leaf(),
+        )?;
+        Ok(tree)
+    }
+
+    /// Open an existing tree rooted at `root_page_id`.
+    pub fn open(root_page_id: u64) -> Self {
+        Self { root_page_id }
+    }
+
+    pub fn root_page_id(&self) -> u64 {
+        self.root_page_id
+    }
+
+    /// Look up the value stored under `key`.
+    pub fn get(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let leaf_id = self.find_leaf(buffer_pool, database_file, Some(key))?;
+        match self.read_node(buffer_pool, database_file, leaf_id)? {
+            Node::Leaf { entries, .. } => Ok(entries
+                .binary_search_by(|(k, _)| k.as_slice().cmp(key))
+                .ok()
+                .map(|i| entries[i].1.clone())),
+            Node::Internal { .. } => {
+                Err(DatabaseError::Index("Expected a B+tree leaf".to_string()))
+            }
+        }
+    }
+
+    /// Insert `key` -> `value`, replacing and returning any previous value.
+    pub fn insert(
+        &self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        if Node::leaf_entry_size(&(key.to_vec(), value.to_vec())) > MAX_ENTRY_SIZE {
+            return Err(DatabaseError::Index(format!(
+                "Index entry of {} bytes exceeds the {} byte limit",
+                key.len() + value.len(),
+                MAX_ENTRY_SIZE
+            )));
+        }
+
+        let (previous, split) =
+            self.insert_into(buffer_pool, database_file, self.root_page_id, key, value)?;
+
+        if let Some((separator, right_id)) = split {
+            // The root already holds the left half; move it out so the root page
+            // id stays stable and becomes the new internal node above both halves.
+            let left = self.read_node(buffer_pool, database_file, self.root_page_id)?;
+            let left_id = database_file.allocate_page_with_type(PageType::Index)?;
+            self.write_node(buffer_pool, database_file, left_id, &left)?;
+
+            let root = Node::Internal {
+                leftmost: left_id,
+                entries: vec![(separator, right_id)],
+            };
+            self.write_node(buffer_pool, database_file, self.root_page_id, &root)?;
+        }
+
+        Ok(previous)
+    }
+
+    /// Remove `key`, returning its value if it was present.
+    pub fn remove(
+        &self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, DatabaseError> {
+        let leaf_id = self.find_leaf(buffer_pool, database_file, Some(key))?;
+        let mut node = self.read_node(buffer_pool, database_file, leaf_id)?;
+        let removed = match &mut node {
+            Node::Leaf { entries, .. } => {
+                match entries.binary_search_by(|(k, _)| k.as_slice().cmp(key)) {
+                    Ok(i) => Some(entries.remove(i).1),
+                    Err(_) => None,
+                }
+            }
+            Node::Internal { .. } => None,
+        };
+        if removed.is_some() {
+            self.write_node(buffer_pool, database_file, leaf_id, &node)?;
+        }
+        Ok(removed)
+    }
+
+    /// Return all entries whose keys fall within the given bounds, in key order.
+    pub fn range(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<Vec<Entry>, DatabaseError> {
+        let start = match lower {
+            Bound::Included(key) | Bound::Excluded(key) => Some(key),
+            Bound::Unbounded => None,
+        };
+        let mut leaf_id = Some(self.find_leaf(buffer_pool, database_file, start)?);
+        let mut results = Vec::new();
+
+        while let Some(page_id) = leaf_id {
+            let Node::Leaf { entries, next } =
+                self.read_node(buffer_pool, database_file, page_id)?
+            else {
+                return Err(DatabaseError::Index("Expected a B+tree leaf".to_string()));
+            };
+            for (key, value) in entries {
+                let above_lower = match lower {
+                    Bound::Included(l) => key.as_slice() >= l,
+                    Bound::Excluded(l) => key.as_slice() > l,
+                    Bound::Unbounded => true,
+                };
+                if !above_lower {
+                    continue;
+                }
+                let below_upper = match upper {
+                    Bound::Included(u) => key.as_slice() <= u,
+                    Bound::Excluded(u) => key.as_slice() < u,
+                    Bound::Unbounded => true,
+                };
+                if !below_upper {
+                    return Ok(results);
+                }
+                results.push((key, value));
+            }
+            leaf_id = next;
+        }
+
+        Ok(results)
+    }
+
+    /// Call `visit` with every key of the tree in order, one leaf at a time.
+    pub fn for_each_key(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+        mut visit: impl FnMut(&[u8]),
+    ) -> Result<(), DatabaseError> {
+        let mut leaf_id = Some(self.find_leaf(buffer_pool, database_file, None)?);
+        while let Some(page_id) = leaf_id {
+            let Node::Leaf { entries, next } =
+                self.read_node(buffer_pool, database_file, page_id)?
+            else {
+                return Err(DatabaseError::Index("Expected a B+tree leaf".to_string()));
+            };
+            entries.iter().for_each(|(key, _)| visit(key));
+            leaf_id = next;
+        }
+        Ok(())
+    }
+
+    /// Every page of the tree, root first.
+    pub fn page_ids(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+    ) -> Result<Vec<u64>, DatabaseError> {
+        let mut page_ids = Vec::new();
+        let mut pending = vec![self.root_page_id];
+        while let Some(page_id) = pending.pop() {
+            page_ids.push(page_id);
+            if let Node::Internal { leftmost, entries } =
+                self.read_node(buffer_pool, database_file, page_id)?
+            {
+                pending.push(leftmost);
+                pending.extend(entries.into_iter().map(|(_, child)| child));
+            }
+        }
+        Ok(page_ids)
+    }
+
+    /// Walk from the root to the leaf that would hold `key` (or the leftmost leaf).
+    fn find_leaf(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+        key: Option<&[u8]>,
+    ) -> Result<u64, DatabaseError> {
+        let mut page_id = self.root_page_id;
+        loop {
+            match self.read_node(buffer_pool, database_file, page_id)? {
+                Node::Leaf { .. } => return Ok(page_id),
+                Node::Internal { leftmost, entries } => {
+                    page_id = match key {
+                        Some(key) => Self::child_for(leftmost, &entries, key).1,
+                        None => leftmost,
+                    };
+                }
+            }
+        }
+    }
+
+    /// Returns (position to insert a new separator at, child page) for `key`.
+    fn child_for(leftmost: u64, entries: &[InternalEntry], key: &[u8]) -> (usize, u64) {
+        let pos = entries.partition_point(|(k, _)| k.as_slice() <= key);
+        let child = if pos == 0 {
+            leftmost
+        } else {
+            entries[pos - 1].1
+        };
+        (pos, child)
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn insert_into(
+        &self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        page_id: u64,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(Option<Vec<u8>>, Option<(Vec<u8>, u64)>), DatabaseError> {
+        match self.read_node(buffer_pool, database_file, page_id)? {
+            Node::Leaf { mut entries, next } => {
+                let previous = match entries.binary_search_by(|(k, _)| k.as_slice().cmp(key)) {
+                    Ok(i) => Some(std::mem::replace(&mut entries[i].1, value.to_vec())),
+                    Err(i) => {
+                        entries.insert(i, (key.to_vec(), value.to_vec()));
+                        None
+                    }
+                };
+
+                let node = Node::Leaf { entries, next };
+                if node.fits() {
+                    self.write_node(buffer_pool, database_file, page_id, &node)?;
+                    return Ok((previous, None));
+                }
+
+                let Node::Leaf { mut entries, next } = node else {
+                    unreachable!()
+                };
+                let mid = split_point(entries.iter().map(Node::leaf_entry_size), entries.len());
+                let right_entries = entries.split_off(mid);
+                let separator = right_entries[0].0.clone();
+                let right_id = database_file.allocate_page_with_type(PageType::Index)?;
+
+                let right = Node::Leaf {
+                    entries: right_entries,
+                    next,
+                };
+                let left = Node::Leaf {
+                    entries,
+                    next: Some(right_id),
+                };
+                self.write_node(buffer_pool, database_file, right_id, &right)?;
+                self.write_node(buffer_pool, database_file, page_id, &left)?;
+                Ok((previous, Some((separator, right_id))))
+            }
+            Node::Internal {
+                leftmost,
+                mut entries,
+            } => {
+                let (pos, child) = Self::child_for(leftmost, &entries, key);
+                let (previous, split) =
+                    self.insert_into(buffer_pool, database_file, child, key, value)?;
+                let Some((separator, new_child)) = split else {
+                    return Ok((previous, None));
+                };
+
+                entries.insert(pos, (separator, new_child));
+                let node = Node::Internal { leftmost, entries };
+                if node.fits() {
+                    self.write_node(buffer_pool, database_file, page_id, &node)?;
+                    return Ok((previous, None));
+                }
+
+                let Node::Internal {
+                    leftmost,
+                    mut entries,
+                } = node
+                else {
+                    unreachable!()
+                };
+                let mid = split_point(entries.iter().map(Node::internal_entry_size), entries.len());
+                let mut right_entries = entries.split_off(mid);
+                // The middle separator moves up; its child becomes the right node's leftmost
+                let (promoted, right_leftmost) = right_entries.remove(0);
+                let right_id = database_file.allocate_page_with_type(PageType::Index)?;
+
+                let right = Node::Internal {
+                    leftmost: right_leftmost,
+                    entries: right_entries,
+                };
+                let left = Node::Internal { leftmost, entries };
+                self.write_node(buffer_pool, database_file, right_id, &right)?;
+                self.write_node(buffer_pool, database_file, page_id, &left)?;
+                Ok((previous, Some((promoted, right_id))))
+            }
+        }
+    }
+
+    fn read_node(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+        page_id: u64,
+    ) -> Result<Node, DatabaseError> {
+        buffer_pool.read_page(page_id, database_file, |page| {
+            if page.get_page_type() == PageType::Index {
+                Node::decode(page.payload())
+            } else {
+                Err(DatabaseError::Index(format!(
+                    "Page {} is not an index page",
+                    page_id
+                )))
+            }
+        })?
+    }
+
+    fn write_node(
+        &self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        page_id: u64,
+        node: &Node,
+    ) -> Result<(), DatabaseError> {
+        let page = buffer_pool.pin_page(page_id, database_file)?;
+        node.encode(page.payload_mut());
+        buffer_pool.unpin_page(page_id, true);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup() -> (tempfile::TempDir, BufferPool, DatabaseFile) {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let database_file = DatabaseFile::create(&temp_dir.path().join("btree.db")).unwrap();
+        (temp_dir, BufferPool::new(16), database_file)
+    }
+
+    fn key(i: u32) -> Vec<u8> {
+        i.to_be_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_node_encode_decode_roundtrip() {
+        let leaf = Node::Leaf {
+            entries: vec![(b"a".to_vec(), b"1".to_vec()), (b"bb".to_vec(), Vec::new())],
+            next: Some(7),
+        };
+        let internal = Node::Internal {
+            leftmost: 3,
+            entries: vec![(b"m".to_vec(), 4), (b"t".to_vec(), 5)],
+        };
+
+        for node in [leaf, internal, Node::empty_leaf()] {
+            let mut buf = vec![0u8; PAGE_SIZE - PAGE_HEADER_SIZE];
+            node.encode(&mut buf);
+            assert_eq!(Node::decode(&buf).unwrap(), node);
+        }
+    }
+
+    #[test]
+    fn test_insert_get_remove() {
+        let (_dir, mut pool, mut file) = setup();
+        let tree = BTree::create(&mut pool, &mut file).unwrap();
+
+        assert_eq!(
+            tree.insert(&mut pool, &mut file, b"k1", b"v1").unwrap(),
+            None
+        );
+        assert_eq!(
+            tree.get(&mut pool, &mut file, b"k1").unwrap(),
+            Some(b"v1".to_vec())
+        );
+
+        // Upsert returns the previous value
+        assert_eq!(
+            tree.insert(&mut pool, &mut file, b"k1", b"v2").unwrap(),
+            Some(b"v1".to_vec())
+        );
+        assert_eq!(
+            tree.remove(&mut pool, &mut file, b"k1").unwrap(),
+            Some(b"v2".to_vec())
+        );
+        assert_eq!(tree.get(&mut pool, &mut file, b"k1").unwrap(), None);
+        assert_eq!(tree.remove(&mut pool, &mut file, b"k1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_many_keys_split_and_stay_ordered() {
+        let (_dir, mut pool, mut file) = setup();
+        let tree = BTree::create(&mut pool, &mut file).unwrap();
+        let value = vec![0xAB; 64];
+
+        // Insert in a scrambled order to exercise splits at every position
+        for i in 0..5000u32 {
+            let k = (i * 7919) % 5000;
+            tree.insert(&mut pool, &mut file, &key(k), &value).unwrap();
+        }
+        assert!(file.page_count() > 10);
+
+        for i in 0..5000u32 {
+            assert_eq!(
+                tree.get(&mut pool, &mut file, &key(i)).unwrap(),
+                Some(value.clone())
+            );
+        }
+
+        let all = tree
+            .range(&mut pool, &mut file, Bound::Unbounded, Bound::Unbounded)
+            .unwrap();
+        let keys: Vec<_> = all.into_iter().map(|(k, _)| k).collect();
+        let expected: Vec<_> = (0..5000u32).map(key).collect();
+        assert_eq!(keys, expected);
+
+        // Nothing else lives in the file, so the tree owns every page
+        let mut page_ids = tree.page_ids(&mut pool, &mut file).unwrap();
+        page_ids.sort_unstable();
+        assert_eq!(page_ids, (0..file.page_count()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_range_bounds() {
+        let (_dir, mut pool, mut file) = setup();
+        let tree = BTree::create(&mut pool, &mut file).unwrap();
+        for i in 0..100u32 {
+            tree.insert(&mut pool, &mut file, &key(i), b"").unwrap();
+        }
+
+        let range = tree
+            .range(
+                &mut pool,
+                &mut file,
+                Bound::Excluded(&key(10)),
+                Bound::Included(&key(20)),
+            )
+            .unwrap();
+        let keys: Vec<_> = range.into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, (11..=20).map(key).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_rejects_oversized_entry() {
+        let (_dir, mut pool, mut file) = setup();
+        let tree = BTree::create(&mut pool, &mut file).unwrap();
+        let huge = vec![0u8; MAX_ENTRY_SIZE];
+        assert!(matches!(
+            tree.insert(&mut pool, &mut file, &huge, b""),
+            Err(DatabaseError::Index(_))
+        ));
+    }
+}
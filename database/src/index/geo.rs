@@ -0,0 +1,193 @@
+// Keys of geospatial indexes: points on a Z-order curve.
+//
+// Longitude and latitude are each scaled to a 32-bit integer and their bits
+// interleaved, longitude first, into one u64 stored big-endian. Nearby points
+// mostly share a long prefix, and every prefix of 2n bits names one cell of a
+// quadtree that splits the map n times, so the points in a cell are one range
+// of keys. A box is looked up as the handful of cells, of whichever sizes,
+// that cover it; points in the cells but outside the box are dropped by the
+// filter.
+
+use crate::index::secondary::KeyRange;
+use crate::query::geo::Point;
+
+/// Bytes of a cell at the start of a key.
+pub(crate) const CELL_SIZE: usize = 8;
+
+/// Most cells a box is covered with. More cells fit the box more closely but
+/// each is a separate walk of the tree.
+const MAX_CELLS: usize = 24;
+
+const STEPS: f64 = (1u64 << 32) as f64;
+
+/// The key of the smallest cell holding `point`.
+pub(crate) fn encode(point: &Point) -> [u8; CELL_SIZE] {
+    let (x, y) = quantize(point);
+    interleave(x, y).to_be_bytes()
+}
+
+/// The point at the corner of the cell a key starts with, within a
+/// centimetre or so of the point it was made from.
+pub(crate) fn decode(key: &[u8]) -> Option<Point> {
+    let cell = u64::from_be_bytes(key.get(..CELL_SIZE)?.try_into().ok()?);
+    let (x, y) = deinterleave(cell);
+    Some(Point {
+        lng: x as f64 / STEPS * 360.0 - 180.0,
+        lat: y as f64 / STEPS * 180.0 - 90.0,
+    })
+}
+
+/// Ranges of keys, in order, that hold every point in the box from `min` to
+/// `max` and few others.
+pub(crate) fn covering(min: &Point, max: &Point) -> Vec<KeyRange> {
+    let (min_x, min_y) = quantize(min);
+    let (max_x, max_y) = quantize(max);
+    let bounds = |level: u32, x: u64, y: u64| {
+        let shift = 32 - level;
+        let low = (x << shift, y << shift);
+        let high = (((x + 1) << shift) - 1, ((y + 1) << shift) - 1);
+        (low, high)
+    };
+
+    // Split cells that straddle the edge of the box, level by level, while
+    // the cover stays small enough
+    let mut covered: Vec<(u32, u64, u64)> = Vec::new();
+    let mut partial = vec![(0, 0, 0)];
+    for level in 1..=32 {
+        let mut children = Vec::new();
+        for &(_, x, y) in &partial {
+            for (dx, dy) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+                let (x, y) = (x * 2 + dx, y * 2 + dy);
+                let ((low_x, low_y), (high_x, high_y)) = bounds(level, x, y);
+                if high_x >= min_x && low_x <= max_x && high_y >= min_y && low_y <= max_y {
+                    children.push((level, x, y));
+                }
+            }
+        }
+        if covered.len() + children.len() > MAX_CELLS {
+            break;
+        }
+        partial.clear();
+        for (level, x, y) in children {
+            let ((low_x, low_y), (high_x, high_y)) = bounds(level, x, y);
+            if low_x >= min_x && high_x <= max_x && low_y >= min_y && high_y <= max_y {
+                covered.push((level, x, y));
+            } else {
+                partial.push((level, x, y));
+            }
+        }
+        if partial.is_empty() {
+            break;
+        }
+    }
+    covered.extend(partial);
+
+    let mut cells: Vec<(u128, u128)> = covered
+        .into_iter()
+        .map(|(level, x, y)| {
+            let ((low_x, low_y), _) = bounds(level, x, y);
+            let start = interleave(low_x, low_y) as u128;
+            (start, start + (1u128 << (2 * (32 - level))))
+        })
+        .collect();
+    cells.sort();
+    let mut merged: Vec<(u128, u128)> = Vec::new();
+    for (start, end) in cells {
+        match merged.last_mut() {
+            Some(last) if last.1 == start => last.1 = end,
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+        .into_iter()
+        .map(|(start, end)| KeyRange {
+            start: (start as u64).to_be_bytes().to_vec(),
+            end: (end <= u64::MAX as u128).then(|| (end as u64).to_be_bytes().to_vec()),
+        })
+        .collect()
+}
+
+fn quantize(point: &Point) -> (u64, u64) {
+    let scale = |fraction: f64| ((fraction * STEPS) as u64).min(u32::MAX as u64);
+    (
+        scale((point.lng + 180.0) / 360.0),
+        scale((point.lat + 90.0) / 180.0),
+    )
+}
+
+/// The bits of `x` and `y` alternately, starting with the top bit of `x`.
+fn interleave(x: u64, y: u64) -> u64 {
+    (spread(x) << 1) | spread(y)
+}
+
+fn deinterleave(cell: u64) -> (u64, u64) {
+    (compact(cell >> 1), compact(cell))
+}
+
+/// The low 32 bits of `value`, moved to the even bit positions.
+fn spread(value: u64) -> u64 {
+    let mut value = value & 0xFFFF_FFFF;
+    value = (value | (value << 16)) & 0x0000_FFFF_0000_FFFF;
+    value = (value | (value << 8)) & 0x00FF_00FF_00FF_00FF;
+    value = (value | (value << 4)) & 0x0F0F_0F0F_0F0F_0F0F;
+    value = (value | (value << 2)) & 0x3333_3333_3333_3333;
+    (value | (value << 1)) & 0x5555_5555_5555_5555
+}
+
+fn compact(value: u64) -> u64 {
+    let mut value = value & 0x5555_5555_5555_5555;
+    value = (value | (value >> 1)) & 0x3333_3333_3333_3333;
+    value = (value | (value >> 2)) & 0x0F0F_0F0F_0F0F_0F0F;
+    value = (value | (value >> 4)) & 0x00FF_00FF_00FF_00FF;
+    value = (value | (value >> 8)) & 0x0000_FFFF_0000_FFFF;
+    (value | (value >> 16)) & 0xFFFF_FFFF
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lng: f64, lat: f64) -> Point {
+        Point::new(lng, lat).unwrap()
+    }
+
+    fn in_cover(ranges: &[KeyRange], point: &Point) -> bool {
+        let key = encode(point);
+        ranges.iter().any(|range| {
+            range.start.as_slice() <= &key[..]
+                && range
+                    .end
+                    .as_ref()
+                    .is_none_or(|end| &key[..] < end.as_slice())
+        })
+    }
+
+    #[test]
+    fn test_keys_roundtrip() {
+        for (lng, lat) in [(0.0, 0.0), (-180.0, -90.0), (180.0, 90.0), (13.405, 52.52)] {
+            let decoded = decode(&encode(&point(lng, lat))).unwrap();
+            assert!((decoded.lng - lng).abs() < 1e-6 && (decoded.lat - lat).abs() < 1e-6);
+        }
+        assert!(encode(&point(1.0, 1.0)) < encode(&point(1.0, 2.0)));
+    }
+
+    #[test]
+    fn test_covering_holds_the_box() {
+        let (min, max) = (point(13.0, 52.0), point(14.0, 53.0));
+        let ranges = covering(&min, &max);
+        assert!(!ranges.is_empty() && ranges.len() <= MAX_CELLS);
+        assert!(ranges.windows(2).all(|pair| pair[0].start < pair[1].start));
+        for lng in [13.0, 13.3, 13.999, 14.0] {
+            for lat in [52.0, 52.5, 53.0] {
+                assert!(in_cover(&ranges, &point(lng, lat)));
+            }
+        }
+        assert!(!in_cover(&ranges, &point(0.0, 0.0)));
+        assert!(!in_cover(&ranges, &point(13.5, 60.0)));
+
+        // The whole map is one range
+        let everything = covering(&point(-180.0, -90.0), &point(180.0, 90.0));
+        assert_eq!(everything.len(), 1);
+        assert_eq!(everything[0].end, None);
+    }
+}
@@ -0,0 +1,260 @@
+// Order-preserving byte encoding of `Value`s for index keys.
+//
+// Comparing two encodings byte by byte orders them the way `query::compare`
+// orders the values they came from. Every encoding starts with the value's kind
+// rank, so all values of one kind form a contiguous key range:
+//
+//   number    rank | f64 with the sign bit flipped (all bits for negatives), big-endian
+//   string    rank | UTF-8 bytes with 0x00 escaped as 0x00 0xFF | 0x00 0x00
+//   binary    rank | bytes, escaped and terminated like strings
+//   object    rank | (0x01 | escaped key | value)... | 0x00
+//   array     rank | (0x01 | element)... | 0x00
+//   object id rank | 12 raw bytes
+//   bool      rank | 0x00 or 0x01
+//   date      rank | milliseconds since the epoch, sign bit flipped, big-endian
+//   null      rank
+//
+// No encoding is a prefix of another, so an index can append the document's
+// location to make each key unique and still find every entry for a value by
+// prefix. Numbers are all widened to f64, so distinct integers beyond 2^53 may
+// share a key; index lookups are always rechecked against the filter.
+
+use crate::document::types::Value;
+use crate::query::compare::kind_rank;
+
+/// Append the encoding of `value` to `out`.
+pub fn encode(value: &Value, out: &mut Vec<u8>) {
+    out.push(kind_rank(value));
+    match value {
+        Value::Null => {}
+        Value::I32(_) | Value::I64(_) | Value::F64(_) => {
+            out.extend_from_slice(&encode_number(value.as_f64().unwrap_or(f64::NAN)))
+        }
+        Value::String(s) => encode_bytes(s.as_bytes(), out),
+        Value::Binary(bytes) => encode_bytes(bytes, out),
+        Value::Object(map) => {
+            for (key, value) in map {
+                out.push(0x01);
+                encode_bytes(key.as_bytes(), out);
+                encode(value, out);
+            }
+            out.push(0x00);
+        }
+        Value::Array(items) => {
+            for item in items {
+                out.push(0x01);
+                encode(item, out);
+            }
+            out.push(0x00);
+        }
+        Value::ObjectId(id) => out.extend_from_slice(&id.to_bytes()),
+        Value::Bool(b) => out.push(*b as u8),
+        Value::DateTime(dt) => {
+            out.extend_from_slice(&((dt.timestamp_millis() as u64) ^ (1 << 63)).to_be_bytes())
+        }
+    }
+}
+
+/// The encoding of `value` on its own.
+pub fn encode_value(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode(value, &mut out);
+    out
+}
+
+/// The first key of the values of `value`'s kind and the first key past them.
+pub fn kind_range(value: &Value) -> (Vec<u8>, Vec<u8>) {
+    let rank = kind_rank(value);
+    (vec![rank], vec![rank + 1])
+}
+
+/// The length of the encoding `bytes` starts with, reading every byte inverted
+/// if `inverted`, or None if `bytes` does not start with a whole encoding.
+pub fn encoded_len(bytes: &[u8], inverted: bool) -> Option<usize> {
+    let byte = |i: usize| bytes.get(i).map(|&b| if inverted { !b } else { b });
+    skip_value(&byte, 0)
+}
+
+/// The position just past the encoding starting at `start`. Ranks are those of
+/// `compare::kind_rank`.
+fn skip_value(byte: &impl Fn(usize) -> Option<u8>, start: usize) -> Option<usize> {
+    let mut position = start + 1;
+    let fixed = match byte(start)? {
+        0 => 0,
+        1 | 8 => 8,
+        6 => 12,
+        7 => 1,
+        2 | 5 => return skip_bytes(byte, position),
+        rank @ (3 | 4) => loop {
+            match byte(position)? {
+                0x00 => return Some(position + 1),
+                _ if rank == 3 => {
+                    position = skip_bytes(byte, position + 1)?;
+                    position = skip_value(byte, position)?;
+                }
+                _ => position = skip_value(byte, position + 1)?,
+            }
+        },
+        _ => return None,
+    };
+    position += fixed;
+    (fixed == 0 || byte(position - 1).is_some()).then_some(position)
+}
+
+fn skip_bytes(byte: &impl Fn(usize) -> Option<u8>, mut position: usize) -> Option<usize> {
+    loop {
+        match (byte(position)?, byte(position + 1)?) {
+            (0x00, 0x00) => return Some(position + 2),
+            (0x00, _) => position += 2,
+            _ => position += 1,
+        }
+    }
+}
+
+fn encode_number(n: f64) -> [u8; 8] {
+    // NaN sorts below every other number, and -0.0 equals 0.0
+    if n.is_nan() {
+        return [0; 8];
+    }
+    let bits = if n == 0.0 { 0.0f64 } else { n }.to_bits();
+    let bits = if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits | (1 << 63)
+    };
+    bits.to_be_bytes()
+}
+
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    for &byte in bytes {
+        out.push(byte);
+        if byte == 0x00 {
+            out.push(0xFF);
+        }
+    }
+    out.extend_from_slice(&[0x00, 0x00]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::object_id::ObjectId;
+    use crate::query::compare::compare;
+    use std::collections::BTreeMap;
+
+    fn assert_ordered(values: &[Value]) {
+        for pair in values.windows(2) {
+            assert!(
+                compare(&pair[0], &pair[1]).is_lt(),
+                "{:?} < {:?}",
+                pair[0],
+                pair[1]
+            );
+            assert!(
+                encode_value(&pair[0]) < encode_value(&pair[1]),
+                "encoding of {:?} < {:?}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn test_numbers_keep_their_order() {
+        assert_ordered(&[
+            Value::F64(f64::NAN),
+            Value::F64(f64::NEG_INFINITY),
+            Value::I64(i64::MIN),
+            Value::F64(-2.5),
+            Value::I32(-1),
+            Value::I32(0),
+            Value::F64(0.5),
+            Value::I64(1 << 40),
+            Value::F64(f64::INFINITY),
+        ]);
+        assert_eq!(encode_value(&Value::I32(7)), encode_value(&Value::F64(7.0)));
+        assert_eq!(
+            encode_value(&Value::F64(-0.0)),
+            encode_value(&Value::I64(0))
+        );
+    }
+
+    #[test]
+    fn test_strings_arrays_and_objects_keep_their_order() {
+        assert_ordered(&[
+            Value::String(String::new()),
+            Value::String("a".to_string()),
+            Value::String("a\0".to_string()),
+            Value::String("a\0b".to_string()),
+            Value::String("ab".to_string()),
+            Value::String("b".to_string()),
+        ]);
+        assert_ordered(&[
+            Value::Array(vec![]),
+            Value::Array(vec![Value::I32(1)]),
+            Value::Array(vec![Value::I32(1), Value::I32(0)]),
+            Value::Array(vec![Value::I32(2)]),
+        ]);
+        let object = |pairs: &[(&str, i32)]| {
+            Value::Object(
+                pairs
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), Value::I32(*v)))
+                    .collect::<BTreeMap<_, _>>(),
+            )
+        };
+        assert_ordered(&[
+            object(&[]),
+            object(&[("a", 1)]),
+            object(&[("a", 1), ("b", 0)]),
+            object(&[("a", 2)]),
+            object(&[("b", 0)]),
+        ]);
+    }
+
+    #[test]
+    fn test_kinds_keep_their_order() {
+        assert_ordered(&[
+            Value::Null,
+            Value::F64(1e300),
+            Value::String("zzz".to_string()),
+            Value::Object(BTreeMap::new()),
+            Value::Array(vec![Value::Null]),
+            Value::Binary(vec![0xFF]),
+            Value::ObjectId(ObjectId::from_bytes([0xFF; 12])),
+            Value::Bool(false),
+            Value::Bool(true),
+            Value::DateTime(chrono::DateTime::UNIX_EPOCH - chrono::Duration::days(1)),
+            Value::DateTime(chrono::DateTime::UNIX_EPOCH),
+        ]);
+        let (start, end) = kind_range(&Value::I32(3));
+        let key = encode_value(&Value::F64(-1e300));
+        assert!(start <= key && key < end);
+    }
+
+    #[test]
+    fn test_encoded_len_finds_the_end_of_each_kind() {
+        let mut object = BTreeMap::new();
+        object.insert("a\0".to_string(), Value::Array(vec![Value::Null, Value::I32(1)]));
+        let values = [
+            Value::Null,
+            Value::F64(2.5),
+            Value::String("x\0y".to_string()),
+            Value::Object(object),
+            Value::Array(vec![Value::String(String::new()), Value::Bool(true)]),
+            Value::Binary(vec![0, 0xFF, 0]),
+            Value::ObjectId(ObjectId::from_bytes([0; 12])),
+            Value::Bool(false),
+            Value::DateTime(chrono::DateTime::UNIX_EPOCH),
+        ];
+        for value in &values {
+            let encoded = encode_value(value);
+            let mut followed = encoded.clone();
+            followed.extend_from_slice(&[0x07; 3]);
+            assert_eq!(encoded_len(&followed, false), Some(encoded.len()), "{:?}", value);
+            let inverted: Vec<u8> = followed.iter().map(|byte| !byte).collect();
+            assert_eq!(encoded_len(&inverted, true), Some(encoded.len()), "{:?}", value);
+            assert_eq!(encoded_len(&encoded[..encoded.len() - 1], false), None);
+        }
+    }
+}
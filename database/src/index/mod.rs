@@ -0,0 +1,6 @@
+pub mod btree;
+pub mod geo;
+pub mod key;
+pub mod primary;
+pub mod secondary;
+pub mod text;
@@ -0,0 +1,111 @@
+// Maps a document's `_id` to its current physical location.
+//
+// Keys are the 12 raw ObjectId bytes (which already sort by creation time) and
+// values are the page id (u64, little-endian) followed by the slot id (u16).
+
+use crate::document::object_id::ObjectId;
+use crate::error::DatabaseError;
+use crate::index::btree::BTree;
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::file::DatabaseFile;
+use crate::storage::storage_engine::DocumentId;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PrimaryIndex {
+    tree: BTree,
+}
+
+impl PrimaryIndex {
+    pub fn new(tree: BTree) -> Self {
+        Self { tree }
+    }
+
+    pub fn root_page_id(&self) -> u64 {
+        self.tree.root_page_id()
+    }
+
+    /// Find where the document with `id` is stored.
+    pub fn get(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+        id: &ObjectId,
+    ) -> Result<Option<DocumentId>, DatabaseError> {
+        self.tree
+            .get(buffer_pool, database_file, &id.to_bytes())?
+            .map(|value| Self::decode_location(&value))
+            .transpose()
+    }
+
+    /// Record (or move) the location of the document with `id`.
+    pub fn insert(
+        &self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        id: &ObjectId,
+        location: DocumentId,
+    ) -> Result<(), DatabaseError> {
+        self.tree.insert(
+            buffer_pool,
+            database_file,
+            &id.to_bytes(),
+            &Self::encode_location(location),
+        )?;
+        Ok(())
+    }
+
+    /// Forget the document with `id`, returning its last known location.
+    pub fn remove(
+        &self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        id: &ObjectId,
+    ) -> Result<Option<DocumentId>, DatabaseError> {
+        self.tree
+            .remove(buffer_pool, database_file, &id.to_bytes())?
+            .map(|value| Self::decode_location(&value))
+            .transpose()
+    }
+
+    /// Every page of the underlying tree.
+    pub fn page_ids(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+    ) -> Result<Vec<u64>, DatabaseError> {
+        self.tree.page_ids(buffer_pool, database_file)
+    }
+
+    fn encode_location(location: DocumentId) -> [u8; 10] {
+        let mut bytes = [0u8; 10];
+        bytes[0..8].copy_from_slice(&location.page_id().to_le_bytes());
+        bytes[8..10].copy_from_slice(&location.slot_id().to_le_bytes());
+        bytes
+    }
+
+    fn decode_location(bytes: &[u8]) -> Result<DocumentId, DatabaseError> {
+        if bytes.len() != 10 {
+            return Err(DatabaseError::Index(format!(
+                "Invalid primary index entry of {} bytes",
+                bytes.len()
+            )));
+        }
+        let mut page_id = [0u8; 8];
+        page_id.copy_from_slice(&bytes[0..8]);
+        let slot_id = u16::from_le_bytes([bytes[8], bytes[9]]);
+        Ok(DocumentId::new(u64::from_le_bytes(page_id), slot_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location_roundtrip() {
+        let location = DocumentId::new(0x0102_0304_0506_0708, 513);
+        let encoded = PrimaryIndex::encode_location(location);
+        assert_eq!(PrimaryIndex::decode_location(&encoded).unwrap(), location);
+        assert!(PrimaryIndex::decode_location(&encoded[..9]).is_err());
+    }
+}
@@ -0,0 +1,1450 @@
+// Secondary indexes: a B+tree over the values found at one or more dotted paths
+// of each document of a collection.
+//
+// Every key is the `index::key` encoding of the value at each indexed path in
+// turn, with the bytes of descending paths inverted, followed by the location
+// of the document holding them (page id as big-endian u64, slot id as
+// big-endian u16), so equal values stay unique and sort by location. Values
+// are empty. Encodings are prefix-free, so walking the tree in key order yields
+// documents in the order the index's paths and directions describe.
+//
+// A document is indexed under the value at each path, or null if the path is
+// missing. When that value is an array, each of its elements is indexed as
+// well, matching the way filters compare a field holding an array. The same
+// document therefore may appear under several keys, and lookups drop repeats.
+// An index remembers which of its paths have held an array. Such a path only
+// leaves the index's sort order intact when a query pins it to a single value
+// and doesn't sort on it, and a range on it keeps a single bound, since one
+// element may meet the lower bound and another the upper. At most one indexed
+// path of a document may hold an array.
+//
+// An index only narrows down which documents a query reads: the filter is
+// still checked against every document a lookup returns. It answers equality
+// and `$in` conditions on a leading run of its paths followed by a range on the
+// next one, and a sort on the paths after those pinned by equality, walked
+// forwards or backwards.
+//
+// A unique index refuses an entry whose value another document already has,
+// with `DatabaseError::DuplicateKey`; the engine checks the documents under
+// the same key before writing. Documents missing the paths count as null, so
+// at most one of them fits, unless the index is sparse. A document may repeat
+// a value in its own array.
+//
+// A text index keeps the words of the strings at its paths instead, laid out
+// as `index::text` describes. It serves `$text` searches and nothing else.
+//
+// A geospatial index has one path, and keys the documents holding a point
+// there by the cell of the point, as `index::geo` describes, followed by the
+// location. Documents without a point are left out. It serves `$geoWithin`,
+// weighed by the planner like any other index, and `$near`, which it alone
+// can serve, nearest first.
+//
+// An ordered index may leave documents out. A sparse one skips documents
+// missing all of its paths, and a partial one those that fail its filter. Such
+// an index only serves a query whose filter guarantees that every match is in
+// it, as `Filter::implies` tells: for a sparse index, that one of its paths is
+// there, and for a partial one, that its filter passes.
+//
+// An ordered index on one path may also expire documents: with `expire_after`
+// set, a document whose date there is older than that is deleted by
+// `StorageEngine::expire_documents`, as `storage::ttl` describes.
+
+use crate::document::Document;
+use crate::document::types::Value;
+use crate::error::DatabaseError;
+use crate::index::btree::BTree;
+use crate::index::{geo, key, text};
+use crate::query::compare::equal;
+use crate::query::filter::{Condition, Filter, TextSearch};
+use crate::query::geo::Point;
+use crate::query::sort::{Direction, Sort};
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::file::DatabaseFile;
+use crate::storage::storage_engine::DocumentId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ops::Bound;
+use std::time::Duration;
+
+pub(super) const LOCATION_SIZE: usize = 10;
+
+/// Most keys an index's statistics keep as samples.
+const MAX_SAMPLES: usize = 128;
+
+/// Longest index name accepted, in bytes.
+pub const MAX_INDEX_NAME_LEN: usize = 120;
+
+/// What to index and what to call the index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    name: String,
+    keys: Vec<(String, Direction)>,
+    #[serde(default)]
+    unique: bool,
+    #[serde(default)]
+    kind: IndexKind,
+    #[serde(default)]
+    expire_after: Option<Duration>,
+    #[serde(default)]
+    sparse: bool,
+    /// The JSON of the filter documents must pass to be indexed.
+    #[serde(default)]
+    partial: Option<String>,
+}
+
+/// What an index keeps of the values at its paths.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IndexKind {
+    /// The values themselves, in order, for equality, ranges and sorts.
+    #[default]
+    Ordered,
+    /// The words of the strings, for `$text` searches.
+    Text,
+    /// Points, for `$near` and `$geoWithin`.
+    Geo,
+}
+
+impl IndexDefinition {
+    /// Index the value at dotted `path`, ascending. The index is named
+    /// `<path>_1` unless renamed with [`IndexDefinition::named`].
+    pub fn new(path: &str) -> Self {
+        Self::compound(Sort::new().ascending(path))
+    }
+
+    /// Index the values at each path of `keys`, ordered the way `keys` would
+    /// sort documents. The index is named after its paths and directions, as
+    /// in `tag_1_created_-1`.
+    pub fn compound(keys: Sort) -> Self {
+        let keys = keys.keys().to_vec();
+        let name = keys
+            .iter()
+            .map(|(path, direction)| match direction {
+                Direction::Ascending => format!("{}_1", path),
+                Direction::Descending => format!("{}_-1", path),
+            })
+            .collect::<Vec<_>>()
+            .join("_");
+        Self {
+            name,
+            keys,
+            unique: false,
+            kind: IndexKind::Ordered,
+            expire_after: None,
+            sparse: false,
+            partial: None,
+        }
+    }
+
+    /// Index the points at dotted `path`, for `$near` and `$geoWithin`. The
+    /// index is named `<path>_2d`.
+    pub fn geo(path: &str) -> Self {
+        Self {
+            name: format!("{}_2d", path),
+            keys: vec![(path.to_string(), Direction::Ascending)],
+            unique: false,
+            kind: IndexKind::Geo,
+            expire_after: None,
+            sparse: false,
+            partial: None,
+        }
+    }
+
+    /// Index the words of the strings at each of `paths`, for `$text`
+    /// searches. The index is named after its paths, as in
+    /// `title_text_body_text`.
+    pub fn text(paths: &[&str]) -> Self {
+        let name = paths
+            .iter()
+            .map(|path| format!("{}_text", path))
+            .collect::<Vec<_>>()
+            .join("_");
+        Self {
+            name,
+            keys: paths
+                .iter()
+                .map(|path| (path.to_string(), Direction::Ascending))
+                .collect(),
+            unique: false,
+            kind: IndexKind::Text,
+            expire_after: None,
+            sparse: false,
+            partial: None,
+        }
+    }
+
+    pub fn named(mut self, name: &str) -> Self {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Refuse two documents with the same key.
+    pub fn unique(mut self) -> Self {
+        self.unique = true;
+        self
+    }
+
+    /// Delete documents once the date at the index's path is older than
+    /// `duration`. Documents without a date there never expire.
+    pub fn expire_after(mut self, duration: Duration) -> Self {
+        self.expire_after = Some(duration);
+        self
+    }
+
+    /// Leave out documents missing every path of the index.
+    pub fn sparse(mut self) -> Self {
+        self.sparse = true;
+        self
+    }
+
+    /// Only index documents passing `filter`, written as for `Filter::parse`.
+    pub fn partial(mut self, filter: &str) -> Self {
+        self.partial = Some(filter.to_string());
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The indexed paths and their directions, most significant first.
+    pub fn keys(&self) -> &[(String, Direction)] {
+        &self.keys
+    }
+
+    pub fn is_unique(&self) -> bool {
+        self.unique
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        self.kind
+    }
+
+    /// How long documents live past the date at the index's path, if they
+    /// expire.
+    pub fn expires_after(&self) -> Option<Duration> {
+        self.expire_after
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        self.sparse
+    }
+
+    /// The filter of a partial index, as written.
+    pub fn partial_filter(&self) -> Option<&str> {
+        self.partial.as_deref()
+    }
+
+    /// The parsed filter of a partial index.
+    fn parsed_partial(&self) -> Result<Option<Filter>, DatabaseError> {
+        self.partial.as_deref().map(Filter::parse).transpose()
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), DatabaseError> {
+        if self.name.is_empty() || self.name.len() > MAX_INDEX_NAME_LEN {
+            return Err(DatabaseError::Validation(format!(
+                "Index name must be 1 to {} bytes",
+                MAX_INDEX_NAME_LEN
+            )));
+        }
+        if self.keys.is_empty() {
+            return Err(DatabaseError::Validation(
+                "An index needs at least one path".to_string(),
+            ));
+        }
+        for (i, (path, _)) in self.keys.iter().enumerate() {
+            if path.is_empty() || path.split('.').any(str::is_empty) {
+                return Err(DatabaseError::Validation(format!(
+                    "Invalid index path '{}'",
+                    path
+                )));
+            }
+            if self.keys[..i].iter().any(|(earlier, _)| earlier == path) {
+                return Err(DatabaseError::Validation(format!(
+                    "Index path '{}' appears more than once",
+                    path
+                )));
+            }
+        }
+        if self.kind != IndexKind::Ordered && self.unique {
+            return Err(DatabaseError::Validation(
+                "Only an ordered index can be unique".to_string(),
+            ));
+        }
+        if self.kind == IndexKind::Geo && self.keys.len() != 1 {
+            return Err(DatabaseError::Validation(
+                "A geospatial index has exactly one path".to_string(),
+            ));
+        }
+        if self.kind != IndexKind::Ordered && (self.sparse || self.partial.is_some()) {
+            return Err(DatabaseError::Validation(
+                "Only an ordered index can be sparse or partial".to_string(),
+            ));
+        }
+        let partial = self.parsed_partial().map_err(|e| {
+            DatabaseError::Validation(format!("Invalid partial index filter: {}", e))
+        })?;
+        if let Some(filter) = partial
+            && (filter.text_search()?.is_some() || filter.near()?.is_some())
+        {
+            return Err(DatabaseError::Validation(
+                "A partial index filter cannot use $text or $near".to_string(),
+            ));
+        }
+        if self.expire_after.is_some() && (self.kind != IndexKind::Ordered || self.keys.len() != 1)
+        {
+            return Err(DatabaseError::Validation(
+                "Only an ordered index on one path can expire documents".to_string(),
+            ));
+        }
+        if self.keys.len() == 1 && self.keys[0].0 == "_id" {
+            return Err(DatabaseError::Validation(
+                "_id is already indexed by the primary index".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Keys from `start` up to but not including `end`, or to the last key if
+/// there is no `end`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KeyRange {
+    pub(super) start: Vec<u8>,
+    pub(super) end: Option<Vec<u8>>,
+}
+
+impl KeyRange {
+    /// Every key starting with `prefix`.
+    pub(super) fn prefixed(prefix: Vec<u8>) -> Self {
+        Self {
+            end: successor(&prefix),
+            start: prefix,
+        }
+    }
+}
+
+/// The encoded keys of a document's entries with the values they hold, and the
+/// indexed path at which the document holds an array, if any.
+type Entries = (BTreeMap<Vec<u8>, Value>, Option<String>);
+
+#[derive(Debug, Clone)]
+pub struct SecondaryIndex {
+    definition: IndexDefinition,
+    tree: BTree,
+    // Indexed paths at which some document has held an array
+    multikey_paths: BTreeSet<String>,
+    partial: Option<Filter>,
+}
+
+impl SecondaryIndex {
+    pub fn new(definition: IndexDefinition, tree: BTree) -> Self {
+        // Definitions are validated before they are stored, filter included
+        let partial = definition.parsed_partial().ok().flatten();
+        Self {
+            definition,
+            tree,
+            multikey_paths: BTreeSet::new(),
+            partial,
+        }
+    }
+
+    pub fn definition(&self) -> &IndexDefinition {
+        &self.definition
+    }
+
+    pub fn name(&self) -> &str {
+        &self.definition.name
+    }
+
+    pub fn root_page_id(&self) -> u64 {
+        self.tree.root_page_id()
+    }
+
+    /// Whether some document has been indexed by the elements of an array.
+    pub fn is_multikey(&self) -> bool {
+        !self.multikey_paths.is_empty()
+    }
+
+    /// The indexed paths at which some document has held an array.
+    pub fn multikey_paths(&self) -> &BTreeSet<String> {
+        &self.multikey_paths
+    }
+
+    pub(crate) fn set_multikey(&mut self, path: &str) {
+        self.multikey_paths.insert(path.to_string());
+    }
+
+    /// Whether `document` belongs in the index, rather than being left out by
+    /// a sparse or partial one.
+    pub fn covers(&self, document: &Document) -> bool {
+        let present = || {
+            self.definition
+                .keys
+                .iter()
+                .any(|(path, _)| path == "_id" || document.get_path(path).is_some())
+        };
+        (!self.definition.sparse || present())
+            && self
+                .partial
+                .as_ref()
+                .is_none_or(|filter| filter.matches(document))
+    }
+
+    /// Whether every document passing `filter` is in the index, so a lookup
+    /// for it misses nothing.
+    pub fn serves(&self, filter: &Filter) -> bool {
+        let present = || {
+            self.definition.keys.iter().any(|(path, _)| {
+                filter.implies(&Filter::Field {
+                    path: path.clone(),
+                    conditions: vec![Condition::Exists(true)],
+                })
+            })
+        };
+        (!self.definition.sparse || present())
+            && self
+                .partial
+                .as_ref()
+                .is_none_or(|partial| filter.implies(partial))
+    }
+
+    /// Add the entries of `document`, stored at `location`. Returns the indexed
+    /// path at which the document holds an array, if any.
+    pub fn insert(
+        &self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        document: &Document,
+        location: DocumentId,
+    ) -> Result<Option<String>, DatabaseError> {
+        if self.definition.kind == IndexKind::Text {
+            let terms = self.terms(document);
+            text::insert(&self.tree, buffer_pool, database_file, &terms, location)?;
+            return Ok(None);
+        }
+        let (entries, array_path) = self.entries(document)?;
+        for encoded in entries.keys() {
+            self.insert_entry(buffer_pool, database_file, encoded, location)?;
+        }
+        Ok(array_path)
+    }
+
+    /// Remove the entries of `document`, stored at `location`.
+    pub fn remove(
+        &self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        document: &Document,
+        location: DocumentId,
+    ) -> Result<(), DatabaseError> {
+        if self.definition.kind == IndexKind::Text {
+            let terms = self.terms(document);
+            return text::remove(&self.tree, buffer_pool, database_file, &terms, location);
+        }
+        for encoded in self.entries(document)?.0.keys() {
+            self.tree
+                .remove(buffer_pool, database_file, &Self::entry(encoded, location))?;
+        }
+        Ok(())
+    }
+
+    /// Swap the entries of `old` for those of `new`, both stored at `location`,
+    /// touching only the keys that differ. Returns the indexed path at which
+    /// `new` holds an array, if any.
+    pub fn update(
+        &self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        old: &Document,
+        new: &Document,
+        location: DocumentId,
+    ) -> Result<Option<String>, DatabaseError> {
+        if self.definition.kind == IndexKind::Text {
+            let (old_terms, new_terms) = (self.terms(old), self.terms(new));
+            if old_terms != new_terms {
+                text::remove(&self.tree, buffer_pool, database_file, &old_terms, location)?;
+                text::insert(&self.tree, buffer_pool, database_file, &new_terms, location)?;
+            }
+            return Ok(None);
+        }
+        let (old_entries, _) = self.entries(old)?;
+        let (new_entries, array_path) = self.entries(new)?;
+        for encoded in old_entries.keys() {
+            if !new_entries.contains_key(encoded) {
+                self.tree
+                    .remove(buffer_pool, database_file, &Self::entry(encoded, location))?;
+            }
+        }
+        for encoded in new_entries.keys() {
+            if !old_entries.contains_key(encoded) {
+                self.insert_entry(buffer_pool, database_file, encoded, location)?;
+            }
+        }
+        Ok(array_path)
+    }
+
+    fn insert_entry(
+        &self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        encoded: &[u8],
+        location: DocumentId,
+    ) -> Result<(), DatabaseError> {
+        self.tree.insert(
+            buffer_pool,
+            database_file,
+            &Self::entry(encoded, location),
+            &[],
+        )?;
+        Ok(())
+    }
+
+    /// For each value `new` would gain over `old`, both stored at `location`,
+    /// its value and the other documents already under the same key. A unique
+    /// index must refuse `new` if one of them holds the value, which
+    /// [`SecondaryIndex::holds`] tells: keys widen numbers to f64, so documents
+    /// under one key may still hold different integers beyond 2^53.
+    pub fn unique_candidates(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+        old: Option<&Document>,
+        new: &Document,
+        location: DocumentId,
+    ) -> Result<Vec<(Value, Vec<DocumentId>)>, DatabaseError> {
+        if !self.definition.unique {
+            return Ok(Vec::new());
+        }
+        let old_values: Vec<Value> = match old {
+            Some(old) => self.entries(old)?.0.into_values().collect(),
+            None => Vec::new(),
+        };
+        let mut candidates = Vec::new();
+        for (encoded, value) in self.entries(new)?.0 {
+            if old_values.iter().any(|held| equal(held, &value)) {
+                continue;
+            }
+            let range = KeyRange::prefixed(encoded);
+            let others: Vec<DocumentId> = self
+                .lookup(buffer_pool, database_file, &[range])?
+                .0
+                .into_iter()
+                .filter(|existing| *existing != location)
+                .collect();
+            if !others.is_empty() {
+                candidates.push((value, others));
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Whether `document` is indexed under exactly `value`, compared the way
+    /// filters compare.
+    pub fn holds(&self, document: &Document, value: &Value) -> Result<bool, DatabaseError> {
+        Ok(self
+            .entries(document)?
+            .0
+            .values()
+            .any(|held| equal(held, value)))
+    }
+
+    /// How this index can serve a query for `filter` ordered by `sort`, or None
+    /// if it can neither narrow down the documents read nor provide their order.
+    pub fn plan(&self, filter: &Filter, sort: Option<&Sort>) -> Option<IndexLookup> {
+        match self.definition.kind {
+            IndexKind::Ordered => {}
+            IndexKind::Text => return None,
+            IndexKind::Geo => return self.plan_within(filter),
+        }
+        if !self.serves(filter) {
+            return None;
+        }
+        let mut prefixes = vec![Vec::new()];
+        let mut equality_fields = 0;
+        let mut range = None;
+        for (path, direction) in &self.definition.keys {
+            let conditions = conditions_on(filter, path);
+            let Some(values) = points(&conditions) else {
+                let multikey = self.multikey_paths.contains(path);
+                range = field_range(&conditions, *direction, multikey);
+                break;
+            };
+            let mut encoded: Vec<Vec<u8>> = values
+                .iter()
+                .map(|value| encode_field(value, *direction))
+                .collect();
+            encoded.sort();
+            encoded.dedup();
+            prefixes = prefixes
+                .iter()
+                .flat_map(|prefix| {
+                    encoded
+                        .iter()
+                        .map(move |value| [prefix.as_slice(), value].concat())
+                })
+                .collect();
+            equality_fields += 1;
+        }
+
+        let filtered = equality_fields > 0 || range.is_some();
+        let reverse = match sort {
+            Some(sort) if prefixes.len() == 1 && self.arrays_keep_order(sort, equality_fields) => {
+                self.order(sort, equality_fields)
+            }
+            _ => None,
+        };
+        // Walking the whole index only pays off to skip a sort
+        let sorts = reverse.is_some() && sort.is_some_and(|sort| !sort.keys().is_empty());
+        if !filtered && !sorts {
+            return None;
+        }
+
+        let ranges = prefixes
+            .into_iter()
+            .map(|prefix| match &range {
+                None => KeyRange::prefixed(prefix),
+                Some((start, end)) => KeyRange {
+                    start: [prefix.as_slice(), start].concat(),
+                    end: match end {
+                        Some(end) => Some([prefix.as_slice(), end].concat()),
+                        None => successor(&prefix),
+                    },
+                },
+            })
+            .collect();
+        Some(IndexLookup {
+            index: self.clone(),
+            ranges,
+            equality_fields,
+            range: range.is_some(),
+            filtered,
+            sorted: reverse.is_some(),
+            reverse: reverse.unwrap_or(false),
+            near: None,
+        })
+    }
+
+    /// How this geospatial index serves a `$geoWithin` condition of `filter`.
+    fn plan_within(&self, filter: &Filter) -> Option<IndexLookup> {
+        let (path, _) = &self.definition.keys[0];
+        let shape = conditions_on(filter, path)
+            .into_iter()
+            .find_map(|condition| match condition {
+                Condition::GeoWithin(shape) => Some(shape),
+                _ => None,
+            })?;
+        let (min, max) = shape.bounds();
+        Some(IndexLookup {
+            index: self.clone(),
+            ranges: geo::covering(&min, &max),
+            equality_fields: 0,
+            range: true,
+            filtered: true,
+            sorted: false,
+            reverse: false,
+            near: None,
+        })
+    }
+
+    /// How this geospatial index serves `$near` `point`, looking no further
+    /// than `max_distance` meters if given.
+    pub fn plan_near(&self, point: Point, max_distance: Option<f64>) -> IndexLookup {
+        let ranges = match max_distance {
+            Some(max_distance) => {
+                let (min, max) = point.bounds_within(max_distance);
+                geo::covering(&min, &max)
+            }
+            None => vec![KeyRange {
+                start: Vec::new(),
+                end: None,
+            }],
+        };
+        IndexLookup {
+            index: self.clone(),
+            ranges,
+            equality_fields: 0,
+            range: true,
+            filtered: max_distance.is_some(),
+            sorted: false,
+            reverse: false,
+            near: Some(point),
+        }
+    }
+
+    /// How this text index serves `search`: a range of keys for each of its
+    /// terms.
+    pub fn plan_text(&self, search: &TextSearch) -> IndexLookup {
+        IndexLookup {
+            index: self.clone(),
+            ranges: search.terms().iter().map(|term| text::term_range(term)).collect(),
+            equality_fields: 0,
+            range: false,
+            filtered: true,
+            sorted: false,
+            reverse: false,
+            near: None,
+        }
+    }
+
+    /// Whether arrays leave the order of a walk with the first `equality_fields`
+    /// paths pinned to one value each intact for `sort`: every path that has
+    /// held an array must be pinned, and `sort` must not be on it. A document
+    /// then appears once under the pinned value, and the paths sorted on hold
+    /// no arrays to order by.
+    fn arrays_keep_order(&self, sort: &Sort, equality_fields: usize) -> bool {
+        let pinned = &self.definition.keys[..equality_fields];
+        self.multikey_paths.iter().all(|path| {
+            pinned.iter().any(|(pinned, _)| pinned == path)
+                && !sort.keys().iter().any(|(sorted, _)| sorted == path)
+        })
+    }
+
+    /// Whether walking the index with its first `equality_fields` paths pinned
+    /// yields documents in `sort` order: Some(false) forwards, Some(true)
+    /// backwards, None not at all.
+    fn order(&self, sort: &Sort, equality_fields: usize) -> Option<bool> {
+        let (pinned, rest) = self.definition.keys.split_at(equality_fields);
+        // A path pinned to one value leaves the order to the others
+        let wanted: Vec<&(String, Direction)> = sort
+            .keys()
+            .iter()
+            .filter(|(path, _)| !pinned.iter().any(|(pinned, _)| pinned == path))
+            .collect();
+        if wanted.len() > rest.len() {
+            return None;
+        }
+        let mut reverse = None;
+        for ((path, direction), (index_path, index_direction)) in wanted.into_iter().zip(rest) {
+            let flipped = direction != index_direction;
+            if path != index_path || *reverse.get_or_insert(flipped) != flipped {
+                return None;
+            }
+        }
+        Some(reverse.unwrap_or(false))
+    }
+
+    /// Locations of the documents with entries in `ranges`, in key order and
+    /// without repeats, and how many entries were read to find them.
+    pub fn lookup(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+        ranges: &[KeyRange],
+    ) -> Result<(Vec<DocumentId>, usize), DatabaseError> {
+        let mut seen = HashSet::new();
+        let mut locations = Vec::new();
+        let mut keys = 0;
+        for range in ranges {
+            let end = match &range.end {
+                Some(end) => Bound::Excluded(end.as_slice()),
+                None => Bound::Unbounded,
+            };
+            let entries = self.tree.range(
+                buffer_pool,
+                database_file,
+                Bound::Included(&range.start),
+                end,
+            )?;
+            keys += entries.len();
+            for (key, _) in entries {
+                let location = Self::decode_location(&key)?;
+                if seen.insert(location) {
+                    locations.push(location);
+                }
+            }
+        }
+        Ok((locations, keys))
+    }
+
+    /// Count the entries, pages and distinct keys of the index and sample its
+    /// keys, by reading all of it.
+    pub fn statistics(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+    ) -> Result<IndexStats, DatabaseError> {
+        let keys = &self.definition.keys;
+        let mut stats = IndexStats {
+            entries: 0,
+            pages: self.page_ids(buffer_pool, database_file)?.len(),
+            distinct: vec![0; keys.len()],
+            samples: Vec::new(),
+        };
+        // The planner never weighs a text index against the others
+        if self.definition.kind == IndexKind::Text {
+            return Ok(stats);
+        }
+        let mut previous: Vec<u8> = Vec::new();
+        let mut malformed = false;
+        let mut step = 1;
+        self.tree.for_each_key(buffer_pool, database_file, |key| {
+            stats.entries += 1;
+            // Keep every step-th key, thinning the samples out as they pile up
+            if (stats.entries - 1).is_multiple_of(step) {
+                stats.samples.push(key.to_vec());
+                if stats.samples.len() == 2 * MAX_SAMPLES {
+                    let mut keep = false;
+                    stats.samples.retain(|_| {
+                        keep = !keep;
+                        keep
+                    });
+                    step *= 2;
+                }
+            }
+            if self.definition.kind != IndexKind::Ordered {
+                return;
+            }
+            // The first path whose value differs from the previous key's starts
+            // a new distinct prefix for it and every later path
+            let mut end = 0;
+            for (i, (_, direction)) in keys.iter().enumerate() {
+                let inverted = *direction == Direction::Descending;
+                let Some(len) = key::encoded_len(&key[end..], inverted) else {
+                    malformed = true;
+                    return;
+                };
+                end += len;
+                if stats.entries == 1 || previous.get(..end) != Some(&key[..end]) {
+                    stats.distinct[i..].iter_mut().for_each(|count| *count += 1);
+                    break;
+                }
+            }
+            previous.clear();
+            previous.extend_from_slice(key);
+        })?;
+        if malformed {
+            return Err(DatabaseError::Index(format!(
+                "Malformed key in index '{}'",
+                self.definition.name
+            )));
+        }
+        Ok(stats)
+    }
+
+    /// Every page of the underlying tree.
+    pub fn page_ids(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+    ) -> Result<Vec<u64>, DatabaseError> {
+        self.tree.page_ids(buffer_pool, database_file)
+    }
+
+    /// The keys `document` is indexed under, each with the value it stands for
+    /// (an array of one value per path for a compound index), and whether the
+    /// document holds an array at an indexed path.
+    fn entries(
+        &self,
+        document: &Document,
+    ) -> Result<Entries, DatabaseError> {
+        if self.definition.kind == IndexKind::Geo {
+            let value = value_at(document, &self.definition.keys[0].0);
+            let entries = Point::from_value(value)
+                .map(|point| (geo::encode(&point).to_vec(), value.clone()))
+                .into_iter()
+                .collect();
+            return Ok((entries, None));
+        }
+        if !self.covers(document) {
+            return Ok((BTreeMap::new(), None));
+        }
+        let mut keys: Vec<(Vec<u8>, Vec<&Value>)> = vec![(Vec::new(), Vec::new())];
+        let mut array_path: Option<&str> = None;
+        for (path, direction) in &self.definition.keys {
+            let value = value_at(document, path);
+            let mut candidates = vec![value];
+            if let Value::Array(items) = value {
+                if let Some(other) = array_path {
+                    return Err(DatabaseError::Index(format!(
+                        "Cannot index parallel arrays at '{}' and '{}' in index '{}'",
+                        other, path, self.definition.name
+                    )));
+                }
+                array_path = Some(path);
+                candidates.extend(items);
+            }
+            keys = keys
+                .iter()
+                .flat_map(|(encoded, values)| {
+                    candidates.iter().map(move |&candidate| {
+                        let mut values = values.clone();
+                        values.push(candidate);
+                        (
+                            [encoded.as_slice(), &encode_field(candidate, *direction)].concat(),
+                            values,
+                        )
+                    })
+                })
+                .collect();
+        }
+
+        let entries = keys
+            .into_iter()
+            .map(|(encoded, values)| {
+                let value = match values.as_slice() {
+                    [value] => (*value).clone(),
+                    values => Value::Array(values.iter().map(|&value| value.clone()).collect()),
+                };
+                (encoded, value)
+            })
+            .collect();
+        Ok((entries, array_path.map(str::to_string)))
+    }
+
+    /// The words of the strings at the paths of this text index.
+    fn terms(&self, document: &Document) -> BTreeMap<String, u32> {
+        text::document_terms(
+            document,
+            self.definition.keys.iter().map(|(path, _)| path.as_str()),
+        )
+    }
+
+    pub(super) fn entry(value: &[u8], location: DocumentId) -> Vec<u8> {
+        let mut entry = Vec::with_capacity(value.len() + LOCATION_SIZE);
+        entry.extend_from_slice(value);
+        entry.extend_from_slice(&location.page_id().to_be_bytes());
+        entry.extend_from_slice(&location.slot_id().to_be_bytes());
+        entry
+    }
+
+    pub(super) fn decode_location(entry: &[u8]) -> Result<DocumentId, DatabaseError> {
+        let Some(split) = entry.len().checked_sub(LOCATION_SIZE) else {
+            return Err(DatabaseError::Index(format!(
+                "Invalid secondary index entry of {} bytes",
+                entry.len()
+            )));
+        };
+        let location = &entry[split..];
+        let page_id = u64::from_be_bytes(location[0..8].try_into().unwrap());
+        let slot_id = u16::from_be_bytes([location[8], location[9]]);
+        Ok(DocumentId::new(page_id, slot_id))
+    }
+}
+
+/// What reading all of an index found, for the query planner.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexStats {
+    pub entries: usize,
+    pub pages: usize,
+    /// Distinct values over the first 1, 2, ... paths of the index.
+    pub distinct: Vec<usize>,
+    /// Keys spread evenly over the index, in order, each standing for an equal
+    /// share of the entries.
+    pub samples: Vec<Vec<u8>>,
+}
+
+impl IndexStats {
+    /// Roughly how many entries fall in `range`, going by the samples.
+    pub fn entries_in(&self, range: &KeyRange) -> f64 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let start = self.samples.partition_point(|sample| *sample < range.start);
+        let end = match &range.end {
+            Some(end) => self.samples.partition_point(|sample| sample < end),
+            None => self.samples.len(),
+        };
+        end.saturating_sub(start) as f64 * self.entries as f64 / self.samples.len() as f64
+    }
+}
+
+/// An index and the key ranges a query reads from it, resolved lazily by the
+/// scan that uses it.
+#[derive(Debug, Clone)]
+pub struct IndexLookup {
+    pub index: SecondaryIndex,
+    pub ranges: Vec<KeyRange>,
+    /// How many leading paths of the index equality conditions pin.
+    pub equality_fields: usize,
+    /// Whether a range condition bounds the path after those.
+    pub range: bool,
+    /// Whether the ranges leave out part of the index.
+    pub filtered: bool,
+    /// Whether documents come out in the order the query asked for.
+    pub sorted: bool,
+    /// Whether the ranges are walked backwards to get that order.
+    pub reverse: bool,
+    /// The point a `$near` lookup measures from, returning documents nearest
+    /// first.
+    pub near: Option<Point>,
+}
+
+impl IndexLookup {
+    pub fn run(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+    ) -> Result<LookupResult, DatabaseError> {
+        if self.index.definition.kind == IndexKind::Text {
+            let (found, keys) =
+                text::search(&self.index.tree, buffer_pool, database_file, &self.ranges)?;
+            let (locations, scores) = found.into_iter().unzip();
+            return Ok(LookupResult {
+                locations,
+                scores,
+                keys,
+            });
+        }
+        if let Some(point) = &self.near {
+            return self.run_near(buffer_pool, database_file, point);
+        }
+        let (mut locations, keys) = self
+            .index
+            .lookup(buffer_pool, database_file, &self.ranges)?;
+        if self.reverse {
+            locations.reverse();
+        }
+        Ok(LookupResult {
+            locations,
+            scores: Vec::new(),
+            keys,
+        })
+    }
+
+    /// The documents in the ranges, by the distance of the point in their
+    /// key from `point`.
+    fn run_near(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+        point: &Point,
+    ) -> Result<LookupResult, DatabaseError> {
+        let mut found = Vec::new();
+        let mut keys = 0;
+        for range in &self.ranges {
+            let end = match &range.end {
+                Some(end) => Bound::Excluded(end.as_slice()),
+                None => Bound::Unbounded,
+            };
+            let entries = self.index.tree.range(
+                buffer_pool,
+                database_file,
+                Bound::Included(&range.start),
+                end,
+            )?;
+            keys += entries.len();
+            for (key, _) in entries {
+                let cell = geo::decode(&key).ok_or_else(|| {
+                    DatabaseError::Index(format!(
+                        "Malformed key in index '{}'",
+                        self.index.definition.name
+                    ))
+                })?;
+                let location = SecondaryIndex::decode_location(&key)?;
+                found.push((location, point.distance(&cell)));
+            }
+        }
+        found.sort_by(|a, b| a.1.total_cmp(&b.1));
+        Ok(LookupResult {
+            locations: found.into_iter().map(|(location, _)| location).collect(),
+            scores: Vec::new(),
+            keys,
+        })
+    }
+}
+
+/// What running an [`IndexLookup`] found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupResult {
+    /// Locations of the documents found, in the order to read them.
+    pub locations: Vec<DocumentId>,
+    /// The relevance of each document to a text search, in step with
+    /// `locations`. Empty for other lookups.
+    pub scores: Vec<f64>,
+    /// How many index entries were read.
+    pub keys: usize,
+}
+
+/// The first byte string after every string starting with `prefix`, or None if
+/// there is none.
+fn successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&byte| byte != 0xFF)?;
+    let mut next = prefix[..=last].to_vec();
+    next[last] += 1;
+    Some(next)
+}
+
+/// The value a document is indexed under at `path`, null if it is missing.
+fn value_at<'a>(document: &'a Document, path: &str) -> &'a Value {
+    const NULL: &Value = &Value::Null;
+    match path {
+        "_id" => document.id(),
+        _ => document.get_path(path).unwrap_or(NULL),
+    }
+}
+
+/// The encoding of `value` as one path of a key, inverted when descending.
+fn encode_field(value: &Value, direction: Direction) -> Vec<u8> {
+    let encoded = key::encode_value(value);
+    match direction {
+        Direction::Ascending => encoded,
+        Direction::Descending => encoded.into_iter().map(|byte| !byte).collect(),
+    }
+}
+
+/// The values an equality or `$in` condition among `conditions` allows.
+fn points<'a>(conditions: &[&'a Condition]) -> Option<&'a [Value]> {
+    conditions.iter().find_map(|condition| match condition {
+        Condition::Eq(value) => Some(std::slice::from_ref(value)),
+        Condition::In(values) => Some(values.as_slice()),
+        _ => None,
+    })
+}
+
+/// The part of a key for one path that the range conditions among
+/// `conditions` allow, as a start and an exclusive end, or None if there are
+/// no range conditions. On a `multikey` path different elements of an array
+/// may meet different bounds, so only the first bound is used and the filter
+/// checks the others.
+fn field_range(
+    conditions: &[&Condition],
+    direction: Direction,
+    multikey: bool,
+) -> Option<(Vec<u8>, Option<Vec<u8>>)> {
+    let mut range: Option<(Vec<u8>, Option<Vec<u8>>)> = None;
+    for condition in conditions {
+        let (value, lower) = match condition {
+            Condition::Gt(value) | Condition::Gte(value) => (value, true),
+            Condition::Lt(value) | Condition::Lte(value) => (value, false),
+            _ => continue,
+        };
+        // Bounds stay inclusive: numbers may share a key with their neighbours.
+        // Comparisons only match values of the same kind, whose keys share
+        // their first byte.
+        let encoded = encode_field(value, direction);
+        let kind = encoded[..1].to_vec();
+        let (start, end) = match (lower, direction) {
+            (true, Direction::Ascending) | (false, Direction::Descending) => {
+                (encoded, successor(&kind))
+            }
+            (false, Direction::Ascending) | (true, Direction::Descending) => {
+                (kind, successor(&encoded))
+            }
+        };
+        range = Some(match range {
+            None => (start, end),
+            Some((other_start, other_end)) => (
+                other_start.max(start),
+                match (other_end, end) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                },
+            ),
+        });
+        if multikey {
+            break;
+        }
+    }
+    range
+}
+
+/// The conditions `filter` puts on `path` that every match must meet.
+fn conditions_on<'a>(filter: &'a Filter, path: &str) -> Vec<&'a Condition> {
+    let mut conditions = Vec::new();
+    collect_conditions(filter, path, &mut conditions);
+    conditions
+}
+
+fn collect_conditions<'a>(filter: &'a Filter, path: &str, conditions: &mut Vec<&'a Condition>) {
+    match filter {
+        Filter::And(filters) => filters
+            .iter()
+            .for_each(|filter| collect_conditions(filter, path, conditions)),
+        Filter::Field {
+            path: field,
+            conditions: field_conditions,
+        } if field == path => conditions.extend(field_conditions),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(definition: IndexDefinition) -> SecondaryIndex {
+        SecondaryIndex::new(definition, BTree::open(0))
+    }
+
+    fn plan(index: &SecondaryIndex, filter: &str, sort: Option<&str>) -> Option<IndexLookup> {
+        let sort = sort.map(|sort| Sort::parse(sort).unwrap());
+        index.plan(&Filter::parse(filter).unwrap(), sort.as_ref())
+    }
+
+    #[test]
+    fn test_definition_validation() {
+        assert_eq!(
+            IndexDefinition::new("address.city").name(),
+            "address.city_1"
+        );
+        assert_eq!(
+            IndexDefinition::compound(Sort::new().ascending("tag").descending("created")).name(),
+            "tag_1_created_-1"
+        );
+        assert!(IndexDefinition::new("age").validate().is_ok());
+        assert!(IndexDefinition::new("a..b").validate().is_err());
+        assert!(IndexDefinition::new("_id").validate().is_err());
+        assert!(IndexDefinition::new("age").named("").validate().is_err());
+        assert!(IndexDefinition::compound(Sort::new()).validate().is_err());
+        assert!(
+            IndexDefinition::compound(Sort::new().ascending("a").descending("a"))
+                .validate()
+                .is_err()
+        );
+        assert!(
+            IndexDefinition::compound(Sort::new().ascending("a").ascending("_id"))
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_arrays_are_indexed_by_element() {
+        let mut doc = Document::new();
+        doc.set(
+            "tags",
+            Value::Array(vec![Value::I32(1), Value::I32(1), Value::I32(2)]),
+        );
+        let (entries, array_path) = index(IndexDefinition::new("tags")).entries(&doc).unwrap();
+        assert_eq!(array_path.as_deref(), Some("tags"));
+        assert_eq!(entries.len(), 3);
+        assert!(entries.contains_key(&key::encode_value(&Value::I32(2))));
+
+        // Missing fields are indexed as null
+        let (entries, array_path) = index(IndexDefinition::new("missing"))
+            .entries(&doc)
+            .unwrap();
+        assert_eq!(array_path, None);
+        assert_eq!(
+            entries,
+            BTreeMap::from([(key::encode_value(&Value::Null), Value::Null)])
+        );
+
+        // A compound index pairs each element with the other paths
+        doc.set("n", Value::I32(5));
+        let tags_n = index(IndexDefinition::compound(
+            Sort::new().ascending("tags").descending("n"),
+        ));
+        let (entries, _) = tags_n.entries(&doc).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(
+            entries
+                .values()
+                .any(|value| *value == Value::Array(vec![Value::I32(2), Value::I32(5)]))
+        );
+
+        // but refuses a second array
+        doc.set("n", Value::Array(vec![]));
+        assert!(tags_n.entries(&doc).is_err());
+    }
+
+    #[test]
+    fn test_descending_paths_reverse_the_order() {
+        let ordered = [
+            Value::Null,
+            Value::I32(-3),
+            Value::I32(7),
+            Value::String("a".to_string()),
+            Value::String("ab".to_string()),
+        ];
+        for pair in ordered.windows(2) {
+            assert!(
+                encode_field(&pair[0], Direction::Descending)
+                    > encode_field(&pair[1], Direction::Descending)
+            );
+        }
+        assert_eq!(successor(&[1, 0xFF, 0xFF]), Some(vec![2]));
+        assert_eq!(successor(&[0xFF]), None);
+    }
+
+    #[test]
+    fn test_plans_from_filters() {
+        let age = index(IndexDefinition::new("age"));
+        let point = |value: &Value| KeyRange::prefixed(key::encode_value(value));
+
+        assert_eq!(
+            plan(&age, r#"{"age": 5}"#, None).unwrap().ranges,
+            vec![point(&Value::I32(5))]
+        );
+        assert_eq!(
+            plan(&age, r#"{"age": {"$in": [7, 5, 5]}}"#, None)
+                .unwrap()
+                .ranges
+                .len(),
+            2
+        );
+        assert!(plan(&age, r#"{"name": "x"}"#, None).is_none());
+        assert!(plan(&age, r#"{"$or": [{"age": 1}, {"age": 2}]}"#, None).is_none());
+        assert!(plan(&age, r#"{"age": {"$ne": 1}}"#, None).is_none());
+
+        let lookup = plan(&age, r#"{"name": "x", "age": {"$gt": 5, "$lte": 9}}"#, None).unwrap();
+        assert_eq!(lookup.equality_fields, 0);
+        assert_eq!(
+            lookup.ranges,
+            vec![KeyRange {
+                start: key::encode_value(&Value::I32(5)),
+                end: successor(&key::encode_value(&Value::I32(9))),
+            }]
+        );
+
+        // A sort alone is served by walking the whole index
+        let lookup = plan(&age, r#"{}"#, Some(r#"{"age": -1}"#)).unwrap();
+        assert!(lookup.sorted && lookup.reverse && !lookup.filtered);
+        assert!(plan(&age, r#"{}"#, Some(r#"{"name": 1}"#)).is_none());
+
+        // but not once the index is multikey
+        let mut tags = index(IndexDefinition::new("tags"));
+        assert!(plan(&tags, r#"{}"#, Some(r#"{"tags": 1}"#)).is_some());
+        tags.set_multikey("tags");
+        assert!(plan(&tags, r#"{}"#, Some(r#"{"tags": 1}"#)).is_none());
+
+        // where a range keeps only its first bound, as elements may meet one each
+        let five = key::encode_value(&Value::I32(5));
+        assert_eq!(
+            plan(&tags, r#"{"tags": {"$gt": 5, "$lt": 10}}"#, None)
+                .unwrap()
+                .ranges,
+            vec![KeyRange {
+                start: five.clone(),
+                end: successor(&five[..1]),
+            }]
+        );
+
+        // A compound one still does when the array path is pinned and not sorted on
+        fn sorted(index: &SecondaryIndex, filter: &str, sort: &str) -> bool {
+            plan(index, filter, Some(sort)).is_some_and(|lookup| lookup.sorted)
+        }
+        let mut tag_created = index(IndexDefinition::compound(
+            Sort::new().ascending("tag").descending("created"),
+        ));
+        tag_created.set_multikey("tag");
+        let lookup = plan(&tag_created, r#"{"tag": "x"}"#, Some(r#"{"created": -1}"#)).unwrap();
+        assert!(lookup.sorted && !lookup.reverse);
+        let tags = r#"{"tag": {"$in": ["x", "y"]}}"#;
+        assert!(!sorted(&tag_created, tags, r#"{"created": -1}"#));
+        let by_tag = r#"[{"tag": 1}, {"created": -1}]"#;
+        assert!(!sorted(&tag_created, r#"{"tag": "x"}"#, by_tag));
+        assert!(!sorted(&tag_created, r#"{"tag": {"$gt": "x"}}"#, r#"{"tag": 1}"#));
+        tag_created.set_multikey("created");
+        assert!(!sorted(&tag_created, r#"{"tag": "x"}"#, r#"{"created": -1}"#));
+    }
+
+    #[test]
+    fn test_sparse_and_partial_plans() {
+        let email = index(IndexDefinition::new("email").sparse());
+        assert!(plan(&email, r#"{"email": "a@b.c"}"#, None).is_some());
+        assert!(plan(&email, r#"{"email": {"$gt": "a"}}"#, None).is_some());
+        // Documents without an email may match these, and the index lacks them
+        assert!(plan(&email, r#"{"email": null}"#, None).is_none());
+        assert!(plan(&email, r#"{"email": {"$ne": "a@b.c"}}"#, None).is_none());
+        assert!(plan(&email, r#"{}"#, Some(r#"{"email": 1}"#)).is_none());
+        assert!(
+            plan(
+                &email,
+                r#"{"email": {"$exists": true}}"#,
+                Some(r#"{"email": 1}"#)
+            )
+            .is_some()
+        );
+
+        let open = index(
+            IndexDefinition::new("due").partial(r#"{"status": "open", "due": {"$gte": 0}}"#),
+        );
+        assert!(plan(&open, r#"{"status": "open", "due": {"$gt": 10}}"#, None).is_some());
+        assert!(plan(&open, r#"{"status": "open", "due": {"$lt": 10}}"#, None).is_none());
+        assert!(plan(&open, r#"{"status": "closed", "due": 5}"#, None).is_none());
+        assert!(plan(&open, r#"{"due": 5}"#, None).is_none());
+
+        let mut doc = Document::new();
+        doc.set("status", Value::String("open".to_string()));
+        assert!(!email.covers(&doc) && !open.covers(&doc));
+        doc.set("due", Value::I32(3));
+        doc.set("email", Value::Null);
+        assert!(email.covers(&doc) && open.covers(&doc));
+        doc.set("status", Value::String("done".to_string()));
+        assert!(!open.covers(&doc));
+
+        assert!(IndexDefinition::new("a").partial("{").validate().is_err());
+        assert!(
+            IndexDefinition::new("a")
+                .partial(r#"{"$text": {"$search": "x"}}"#)
+                .validate()
+                .is_err()
+        );
+        assert!(IndexDefinition::text(&["a"]).sparse().validate().is_err());
+    }
+
+    #[test]
+    fn test_compound_plans() {
+        let tag_created = index(IndexDefinition::compound(
+            Sort::new().ascending("tag").descending("created"),
+        ));
+        let summary = |lookup: IndexLookup| (lookup.equality_fields, lookup.sorted, lookup.reverse);
+
+        let lookup = plan(&tag_created, r#"{"tag": "x"}"#, Some(r#"{"created": -1}"#));
+        assert_eq!(summary(lookup.unwrap()), (1, true, false));
+        let lookup = plan(
+            &tag_created,
+            r#"{"tag": "x"}"#,
+            Some(r#"[{"tag": -1}, {"created": 1}]"#),
+        );
+        assert_eq!(summary(lookup.unwrap()), (1, true, true));
+        let lookup = plan(&tag_created, r#"{"tag": "x"}"#, Some(r#"{"other": 1}"#));
+        assert_eq!(summary(lookup.unwrap()), (1, false, false));
+
+        // Several tags interleave their orders
+        let lookup = plan(
+            &tag_created,
+            r#"{"tag": {"$in": ["x", "y"]}}"#,
+            Some(r#"{"created": -1}"#),
+        );
+        assert_eq!(summary(lookup.unwrap()), (1, false, false));
+
+        // Sorting on both paths needs the index's own mix of directions
+        let lookup = plan(
+            &tag_created,
+            r#"{}"#,
+            Some(r#"[{"tag": -1}, {"created": 1}]"#),
+        );
+        assert_eq!(summary(lookup.unwrap()), (0, true, true));
+        assert!(
+            plan(
+                &tag_created,
+                r#"{}"#,
+                Some(r#"[{"tag": 1}, {"created": 1}]"#)
+            )
+            .is_none()
+        );
+
+        // A range on the second path follows the equality on the first
+        let lookup = plan(
+            &tag_created,
+            r#"{"tag": "x", "created": {"$gte": 3}}"#,
+            None,
+        )
+        .unwrap();
+        let prefix = encode_field(&Value::String("x".to_string()), Direction::Ascending);
+        let number = encode_field(&Value::I32(3), Direction::Descending);
+        let start = [prefix.as_slice(), &number[..1]].concat();
+        let end = [prefix.as_slice(), &successor(&number).unwrap()].concat();
+        assert_eq!(
+            lookup.ranges,
+            vec![KeyRange {
+                start,
+                end: Some(end)
+            }]
+        );
+
+        // With nothing pinning the first path, conditions on the second can't
+        // use the index
+        assert!(plan(&tag_created, r#"{"created": 3}"#, None).is_none());
+    }
+
+    #[test]
+    fn test_location_roundtrip() {
+        let location = DocumentId::new(0x0102_0304_0506_0708, 513);
+        let entry = SecondaryIndex::entry(&key::encode_value(&Value::Null), location);
+        assert_eq!(SecondaryIndex::decode_location(&entry).unwrap(), location);
+        assert!(SecondaryIndex::decode_location(&entry[..9]).is_err());
+    }
+}
@@ -0,0 +1,342 @@
+// Text indexes: an inverted index from words to the documents holding them,
+// kept in the same kind of B+tree as the other secondary indexes.
+//
+// The strings at the indexed paths (and the strings inside arrays there) are
+// split into words at anything that is not a letter or digit, lowercased,
+// stripped of stop words such as "the" and "of", and reduced to a stem by a
+// light suffix stripper ("batteries" -> "battery", "charging" -> "charg"),
+// so a search finds other forms of the words it names. Searches go through
+// the same steps.
+//
+// The tree holds three kinds of keys, told apart by their first byte:
+//
+//   0x00                      documents indexed and their total words (u64 each)
+//   0x01 location             words in the document at location (u32)
+//   0x02 term 0x00 location   times term appears in that document (u32)
+//
+// Locations are encoded as in ordered indexes. Terms never hold a zero byte,
+// so all the postings of a term share one prefix. Documents without any word
+// have no keys.
+//
+// Matches are scored with BM25 over the terms of the search they contain,
+// using the counts above for document frequencies and lengths.
+
+use crate::document::Document;
+use crate::document::types::Value;
+use crate::error::DatabaseError;
+use crate::index::btree::BTree;
+use crate::index::secondary::{KeyRange, LOCATION_SIZE, SecondaryIndex};
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::file::DatabaseFile;
+use crate::storage::storage_engine::DocumentId;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound;
+
+const STATS_KEY: &[u8] = &[0x00];
+const LENGTH_TAG: u8 = 0x01;
+const POSTING_TAG: u8 = 0x02;
+
+/// Longest word indexed, in bytes. Longer ones are left out.
+const MAX_TERM_LEN: usize = 64;
+
+/// How quickly repeating a term stops adding to the score.
+const BM25_K1: f64 = 1.2;
+/// How much a long document is marked down for its length.
+const BM25_B: f64 = 0.75;
+
+const STOP_WORDS: &[&str] = &[
+    "a", "about", "after", "all", "also", "am", "an", "and", "any", "are", "as", "at", "be",
+    "because", "been", "but", "by", "can", "could", "did", "do", "does", "for", "from", "had",
+    "has", "have", "he", "her", "his", "how", "i", "if", "in", "into", "is", "it", "its", "just",
+    "me", "more", "my", "no", "not", "of", "on", "or", "our", "out", "s", "she", "so", "some",
+    "such", "t", "than", "that", "the", "their", "them", "then", "there", "these", "they", "this",
+    "to", "too", "up", "us", "very", "was", "we", "were", "what", "when", "which", "who", "will",
+    "with", "would", "you", "your",
+];
+
+/// The terms of `text`, in order and with repeats.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .filter(|word| !STOP_WORDS.contains(&word.as_str()))
+        .map(|word| stem(&word))
+        .filter(|term| term.len() <= MAX_TERM_LEN)
+        .collect()
+}
+
+/// Strip common English inflections from a lowercase word.
+fn stem(word: &str) -> String {
+    if !word.is_ascii() || word.len() <= 3 {
+        return word.to_string();
+    }
+    let mut stem = word.to_string();
+
+    // Plurals
+    if stem.ends_with("sses")
+        || ["xes", "ches", "shes", "zes"]
+            .iter()
+            .any(|s| stem.ends_with(s))
+    {
+        stem.truncate(stem.len() - 2);
+    } else if stem.ends_with("ies") {
+        stem.truncate(stem.len() - 3);
+        stem.push('y');
+    } else if stem.ends_with('s') && !["ss", "us", "is"].iter().any(|s| stem.ends_with(s)) {
+        stem.pop();
+    }
+
+    // Verb endings, as long as a syllable is left
+    let verb = ["ing", "ed"]
+        .iter()
+        .find_map(|suffix| stem.strip_suffix(suffix));
+    if let Some(rest) = verb
+        && rest.len() >= 3
+        && rest.bytes().any(is_vowel)
+    {
+        let bytes = rest.as_bytes();
+        let last = bytes[bytes.len() - 1];
+        // "running" -> "run", but "falling" -> "fall"
+        let doubled = last == bytes[bytes.len() - 2] && !is_vowel(last) && !b"lsz".contains(&last);
+        stem = rest[..rest.len() - doubled as usize].to_string();
+    }
+
+    if let Some(rest) = stem.strip_suffix("ly")
+        && rest.len() >= 3
+    {
+        stem.truncate(rest.len());
+    }
+    stem
+}
+
+fn is_vowel(byte: u8) -> bool {
+    b"aeiouy".contains(&byte)
+}
+
+/// Every string in `value`, however deeply nested.
+pub(crate) fn strings<'a>(value: &'a Value, strings: &mut Vec<&'a str>) {
+    match value {
+        Value::String(text) => strings.push(text),
+        Value::Array(items) => items.iter().for_each(|item| self::strings(item, strings)),
+        Value::Object(fields) => fields
+            .values()
+            .for_each(|item| self::strings(item, strings)),
+        _ => {}
+    }
+}
+
+/// How often each term appears in the strings at `paths` of `document`.
+pub(crate) fn document_terms<'a>(
+    document: &Document,
+    paths: impl Iterator<Item = &'a str>,
+) -> BTreeMap<String, u32> {
+    let mut terms = BTreeMap::new();
+    for path in paths {
+        let texts: Vec<&str> = match document.get_path(path) {
+            Some(Value::String(text)) => vec![text],
+            Some(Value::Array(items)) => items
+                .iter()
+                .filter_map(|item| match item {
+                    Value::String(text) => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        for term in texts.into_iter().flat_map(tokenize) {
+            *terms.entry(term).or_insert(0) += 1;
+        }
+    }
+    terms
+}
+
+/// The keys holding every posting of `term`.
+pub(crate) fn term_range(term: &str) -> KeyRange {
+    KeyRange::prefixed(posting_prefix(term))
+}
+
+/// Add the postings and length of a document with `terms`, stored at
+/// `location`.
+pub(crate) fn insert(
+    tree: &BTree,
+    buffer_pool: &mut BufferPool,
+    database_file: &mut DatabaseFile,
+    terms: &BTreeMap<String, u32>,
+    location: DocumentId,
+) -> Result<(), DatabaseError> {
+    if terms.is_empty() {
+        return Ok(());
+    }
+    let location = SecondaryIndex::entry(&[], location);
+    for (term, count) in terms {
+        let key = [posting_prefix(term).as_slice(), &location].concat();
+        tree.insert(buffer_pool, database_file, &key, &count.to_be_bytes())?;
+    }
+    let length: u32 = terms.values().sum();
+    let key = [&[LENGTH_TAG][..], &location].concat();
+    tree.insert(buffer_pool, database_file, &key, &length.to_be_bytes())?;
+    update_stats(tree, buffer_pool, database_file, 1, length as i64)
+}
+
+/// Remove what [`insert`] added for the same `terms` and `location`.
+pub(crate) fn remove(
+    tree: &BTree,
+    buffer_pool: &mut BufferPool,
+    database_file: &mut DatabaseFile,
+    terms: &BTreeMap<String, u32>,
+    location: DocumentId,
+) -> Result<(), DatabaseError> {
+    if terms.is_empty() {
+        return Ok(());
+    }
+    let location = SecondaryIndex::entry(&[], location);
+    for term in terms.keys() {
+        let key = [posting_prefix(term).as_slice(), &location].concat();
+        tree.remove(buffer_pool, database_file, &key)?;
+    }
+    let key = [&[LENGTH_TAG][..], &location].concat();
+    tree.remove(buffer_pool, database_file, &key)?;
+    let length: u32 = terms.values().sum();
+    update_stats(tree, buffer_pool, database_file, -1, -(length as i64))
+}
+
+/// The documents with postings in `ranges` (one per search term, from
+/// [`term_range`]), most relevant first, each with its score, and how many
+/// keys were read to find them.
+pub(crate) fn search(
+    tree: &BTree,
+    buffer_pool: &BufferPool,
+    database_file: &DatabaseFile,
+    ranges: &[KeyRange],
+) -> Result<(Vec<(DocumentId, f64)>, usize), DatabaseError> {
+    let (documents, total_length) = read_stats(tree, buffer_pool, database_file)?;
+    let average_length = total_length as f64 / documents.max(1) as f64;
+
+    let mut scores: HashMap<DocumentId, f64> = HashMap::new();
+    let mut found = Vec::new();
+    let mut keys = 1;
+    for range in ranges {
+        let end = match &range.end {
+            Some(end) => Bound::Excluded(end.as_slice()),
+            None => Bound::Unbounded,
+        };
+        let postings = tree.range(
+            buffer_pool,
+            database_file,
+            Bound::Included(&range.start),
+            end,
+        )?;
+        keys += postings.len();
+        let frequency = postings.len() as f64;
+        let idf = (1.0 + (documents as f64 - frequency + 0.5) / (frequency + 0.5)).ln();
+        for (key, count) in postings {
+            let location = SecondaryIndex::decode_location(&key)?;
+            let length_key = [&[LENGTH_TAG][..], &key[key.len() - LOCATION_SIZE..]].concat();
+            let length = match tree.get(buffer_pool, database_file, &length_key)? {
+                Some(length) => decode_u32(&length)?,
+                None => return Err(malformed()),
+            };
+            keys += 1;
+            let count = decode_u32(&count)? as f64;
+            let norm = 1.0 - BM25_B + BM25_B * length as f64 / average_length.max(1.0);
+            let score = idf * count * (BM25_K1 + 1.0) / (count + BM25_K1 * norm);
+            match scores.get_mut(&location) {
+                Some(total) => *total += score,
+                None => {
+                    scores.insert(location, score);
+                    found.push(location);
+                }
+            }
+        }
+    }
+
+    let mut found: Vec<(DocumentId, f64)> = found
+        .into_iter()
+        .map(|location| (location, scores[&location]))
+        .collect();
+    found.sort_by(|a, b| b.1.total_cmp(&a.1));
+    Ok((found, keys))
+}
+
+fn posting_prefix(term: &str) -> Vec<u8> {
+    let mut prefix = Vec::with_capacity(term.len() + 2);
+    prefix.push(POSTING_TAG);
+    prefix.extend_from_slice(term.as_bytes());
+    prefix.push(0x00);
+    prefix
+}
+
+/// Documents indexed and their total words.
+fn read_stats(
+    tree: &BTree,
+    buffer_pool: &BufferPool,
+    database_file: &DatabaseFile,
+) -> Result<(u64, u64), DatabaseError> {
+    match tree.get(buffer_pool, database_file, STATS_KEY)? {
+        None => Ok((0, 0)),
+        Some(bytes) if bytes.len() == 16 => Ok((
+            u64::from_be_bytes(bytes[..8].try_into().unwrap()),
+            u64::from_be_bytes(bytes[8..].try_into().unwrap()),
+        )),
+        Some(_) => Err(malformed()),
+    }
+}
+
+fn update_stats(
+    tree: &BTree,
+    buffer_pool: &mut BufferPool,
+    database_file: &mut DatabaseFile,
+    documents: i64,
+    length: i64,
+) -> Result<(), DatabaseError> {
+    let (old_documents, old_length) = read_stats(tree, buffer_pool, database_file)?;
+    let mut bytes = Vec::with_capacity(16);
+    bytes.extend_from_slice(&old_documents.saturating_add_signed(documents).to_be_bytes());
+    bytes.extend_from_slice(&old_length.saturating_add_signed(length).to_be_bytes());
+    tree.insert(buffer_pool, database_file, STATS_KEY, &bytes)?;
+    Ok(())
+}
+
+fn decode_u32(bytes: &[u8]) -> Result<u32, DatabaseError> {
+    Ok(u32::from_be_bytes(
+        bytes.try_into().map_err(|_| malformed())?,
+    ))
+}
+
+fn malformed() -> DatabaseError {
+    DatabaseError::Index("Malformed text index entry".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_drops_stop_words_and_stems() {
+        assert_eq!(
+            tokenize("The Batteries are charging, and it's FAST!"),
+            vec!["battery", "charg", "fast"]
+        );
+        assert_eq!(
+            tokenize("running falls indexed boxes quickly"),
+            vec!["run", "fall", "index", "box", "quick"]
+        );
+        // Short words and other scripts are left alone
+        assert_eq!(tokenize("bus gas Größe"), vec!["bus", "gas", "größe"]);
+        assert!(tokenize("the of and").is_empty());
+    }
+
+    #[test]
+    fn test_document_terms_count_words_at_the_paths() {
+        let document = Document::from_json(
+            r#"{"title": "Red shoes", "tags": ["shoe", 3, "sale"], "body": "ignored"}"#,
+        )
+        .unwrap();
+        let terms = document_terms(&document, ["title", "tags", "missing"].into_iter());
+        let expected: BTreeMap<String, u32> = [("red", 1), ("shoe", 2), ("sale", 1)]
+            .into_iter()
+            .map(|(term, count)| (term.to_string(), count))
+            .collect();
+        assert_eq!(terms, expected);
+    }
+}
@@ -5,6 +5,8 @@ use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt};
 
 pub mod document;
 pub mod error;
+pub mod index;
+pub mod query;
 pub mod result;
 pub mod storage;
 pub mod ui;
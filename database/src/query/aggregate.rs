@@ -0,0 +1,740 @@
+// Aggregation pipelines.
+//
+// A pipeline is parsed from a JSON array of stages such as
+//
+//   [{"$match": {"status": "active"}},
+//    {"$unwind": "$tags"},
+//    {"$group": {"_id": "$tags", "count": {"$count": {}}, "age": {"$avg": "$age"}}},
+//    {"$sort": {"count": -1}},
+//    {"$limit": 10}]
+//
+// and run over any stream of documents, each stage feeding the next. $match,
+// $project, $unwind, $skip and $limit handle one document at a time, so they
+// stream. $group and $sort read their whole input the first time a result is
+// asked for; $sort spills to disk past `DEFAULT_SORT_MEMORY` as a cursor's
+// sort does.
+//
+// Expressions are "$path" for the value of a field (dotted paths and `_id`
+// included), an object of expressions, or a literal. A $group's `_id`
+// expression is the key documents are grouped by, and each result carries its
+// key as its `_id`; documents whose key evaluates to nothing share the null
+// group. Accumulators are $sum, $avg, $min, $max, $push and $count. $project
+// takes the inclusion or exclusion flags of a `Projection` plus computed
+// fields, which imply inclusion. $match takes any filter but $text, which
+// only a text index can answer; search with a query instead.
+
+use crate::{
+    document::{Document, bson::serialize_document, types::Value},
+    error::DatabaseError,
+    query::{
+        compare::{compare, equal},
+        filter::Filter,
+        numeric::add,
+        projection::Projection,
+        sort::{DEFAULT_SORT_MEMORY, Sort, Sorter},
+    },
+    storage::storage_engine::DocumentId,
+};
+use anyhow::Result;
+use serde_json::{Map, Value as Json};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+/// A stream of documents flowing between pipeline stages.
+pub type Documents<'a> = Box<dyn Iterator<Item = Result<Document>> + 'a>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    stages: Vec<Stage>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stage {
+    Match(Filter),
+    Project {
+        projection: Projection,
+        computed: Vec<(String, Expression)>,
+    },
+    Group(Group),
+    /// One document per element of the array at `path`.
+    Unwind {
+        path: String,
+        /// Keep documents where the field is missing, null or an empty array.
+        preserve_empty: bool,
+    },
+    Sort(Sort),
+    Skip(usize),
+    Limit(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Field(String),
+    Object(Vec<(String, Expression)>),
+    Literal(Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Group {
+    key: Expression,
+    accumulators: Vec<(String, Accumulator)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Accumulator {
+    /// Total of the numbers; other values are ignored.
+    Sum(Expression),
+    /// Mean of the numbers, or null if there were none.
+    Avg(Expression),
+    Min(Expression),
+    Max(Expression),
+    /// Every value, in input order.
+    Push(Expression),
+    /// Number of documents in the group.
+    Count,
+}
+
+impl Pipeline {
+    /// Parse a pipeline from its JSON text.
+    pub fn parse(json: &str) -> Result<Self, DatabaseError> {
+        let json: Json = serde_json::from_str(json).map_err(DatabaseError::Json)?;
+        Self::from_json(&json)
+    }
+
+    pub fn from_json(json: &Json) -> Result<Self, DatabaseError> {
+        let Json::Array(stages) = json else {
+            return Err(query_error(format!(
+                "A pipeline must be an array of stages, got {}",
+                json
+            )));
+        };
+        Ok(Self {
+            stages: stages
+                .iter()
+                .map(Stage::from_json)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    /// Run the pipeline over `documents`.
+    pub fn run<'a>(
+        &'a self,
+        documents: impl Iterator<Item = Result<Document>> + 'a,
+    ) -> Documents<'a> {
+        self.stages
+            .iter()
+            .fold(Box::new(documents), |input, stage| stage.apply(input))
+    }
+}
+
+impl Stage {
+    pub fn from_json(json: &Json) -> Result<Self, DatabaseError> {
+        let (name, spec) = match json {
+            Json::Object(map) if map.len() == 1 => map.iter().next().unwrap(),
+            _ => {
+                return Err(query_error(format!(
+                    "A stage must be an object with one field, got {}",
+                    json
+                )));
+            }
+        };
+
+        Ok(match name.as_str() {
+            "$match" => {
+                let filter = Filter::from_json(spec)?;
+                // A nested $text makes `text_search` fail, which is refused too
+                if !matches!(filter.text_search(), Ok(None)) {
+                    return Err(query_error(
+                        "$match cannot use $text; search through a query instead".to_string(),
+                    ));
+                }
+                Stage::Match(filter)
+            }
+            "$project" => parse_project(spec)?,
+            "$group" => Stage::Group(Group::from_json(spec)?),
+            "$unwind" => parse_unwind(spec)?,
+            "$sort" => Stage::Sort(Sort::from_json(spec)?),
+            "$skip" => Stage::Skip(count(name, spec)?),
+            "$limit" => Stage::Limit(count(name, spec)?),
+            _ => return Err(query_error(format!("Unknown stage {}", name))),
+        })
+    }
+
+    fn apply<'a>(&'a self, mut input: Documents<'a>) -> Documents<'a> {
+        match self {
+            Stage::Match(filter) => Box::new(
+                input.filter(move |item| item.as_ref().map_or(true, |doc| filter.matches(doc))),
+            ),
+            Stage::Project {
+                projection,
+                computed,
+            } => Box::new(
+                input.map(move |item| item.map(|document| project(projection, computed, document))),
+            ),
+            Stage::Group(group) => deferred(move || group.run(input)),
+            Stage::Unwind {
+                path,
+                preserve_empty,
+            } => Box::new(input.flat_map(move |item| match item {
+                Ok(document) => unwind(path, *preserve_empty, document),
+                Err(e) => vec![Err(e)],
+            })),
+            Stage::Sort(sort) => deferred(move || {
+                let mut sorter = Sorter::new(sort.clone(), DEFAULT_SORT_MEMORY);
+                for document in input {
+                    let document = document?;
+                    let size = serialize_document(&document)?.len();
+                    // Pipeline documents have no place in storage
+                    sorter.push(DocumentId::new(0, 0), document, size)?;
+                }
+                Ok(sorter
+                    .finish()?
+                    .map(|item| item.map(|(_, document)| document)))
+            }),
+            Stage::Skip(skip) => {
+                // Errors pass through rather than being skipped
+                let mut remaining = *skip;
+                Box::new(input.filter(move |item| {
+                    if item.is_ok() && remaining > 0 {
+                        remaining -= 1;
+                        return false;
+                    }
+                    true
+                }))
+            }
+            Stage::Limit(limit) => {
+                let (limit, mut taken) = (*limit, 0);
+                Box::new(std::iter::from_fn(move || {
+                    if taken >= limit {
+                        return None;
+                    }
+                    let item = input.next()?;
+                    taken += item.is_ok() as usize;
+                    Some(item)
+                }))
+            }
+        }
+    }
+}
+
+/// A stream whose documents are produced by `run` when the first is asked for.
+fn deferred<'a, I>(run: impl FnOnce() -> Result<I> + 'a) -> Documents<'a>
+where
+    I: Iterator<Item = Result<Document>> + 'a,
+{
+    let mut run = Some(run);
+    let mut output = None;
+    Box::new(std::iter::from_fn(move || {
+        if let Some(run) = run.take() {
+            match run() {
+                Ok(documents) => output = Some(documents),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        output.as_mut()?.next()
+    }))
+}
+
+impl Expression {
+    pub fn from_json(json: &Json) -> Result<Self, DatabaseError> {
+        match json {
+            Json::String(field) if field.starts_with('$') => match &field[1..] {
+                "" => Err(query_error("An empty field reference \"$\"".to_string())),
+                path => Ok(Expression::Field(path.to_string())),
+            },
+            Json::Object(map) => {
+                if let Some(operator) = map.keys().find(|key| key.starts_with('$')) {
+                    return Err(query_error(format!("Unknown expression {}", operator)));
+                }
+                Ok(Expression::Object(
+                    map.iter()
+                        .map(|(key, value)| Ok((key.clone(), Self::from_json(value)?)))
+                        .collect::<Result<_, DatabaseError>>()?,
+                ))
+            }
+            _ => Ok(Expression::Literal(Value::from_json_value(json.clone()))),
+        }
+    }
+
+    /// The value of the expression for `document`, or None for a missing field.
+    pub fn evaluate(&self, document: &Document) -> Option<Value> {
+        match self {
+            Expression::Field(path) => lookup(document, path).cloned(),
+            Expression::Object(fields) => Some(Value::Object(
+                fields
+                    .iter()
+                    .filter_map(|(key, expression)| {
+                        Some((key.clone(), expression.evaluate(document)?))
+                    })
+                    .collect(),
+            )),
+            Expression::Literal(value) => Some(value.clone()),
+        }
+    }
+}
+
+/// The value at a dotted path, reading `_id` from the document's id.
+fn lookup<'a>(document: &'a Document, path: &str) -> Option<&'a Value> {
+    match path.split_once('.') {
+        _ if path == "_id" => Some(document.id()),
+        Some(("_id", rest)) => rest
+            .split('.')
+            .try_fold(document.id(), |value, key| value.as_object()?.get(key)),
+        _ => document.get_path(path),
+    }
+}
+
+impl Group {
+    pub fn from_json(json: &Json) -> Result<Self, DatabaseError> {
+        let Json::Object(map) = json else {
+            return Err(query_error(format!("$group needs an object, got {}", json)));
+        };
+        let Some(key) = map.get("_id") else {
+            return Err(query_error("$group needs an _id expression".to_string()));
+        };
+
+        let mut accumulators = Vec::new();
+        for (field, spec) in map.iter().filter(|(field, _)| *field != "_id") {
+            if field.contains('.') || field.starts_with('$') {
+                return Err(query_error(format!("Invalid $group field '{}'", field)));
+            }
+            accumulators.push((field.clone(), Accumulator::from_json(spec)?));
+        }
+        Ok(Self {
+            key: Expression::from_json(key)?,
+            accumulators,
+        })
+    }
+
+    /// One document per group, in key order.
+    fn run<'a>(&self, input: Documents<'a>) -> Result<std::vec::IntoIter<Result<Document>>> {
+        let mut groups: BTreeMap<GroupKey, Vec<State>> = BTreeMap::new();
+        for document in input {
+            let document = document?;
+            let key = GroupKey(self.key.evaluate(&document).unwrap_or(Value::Null));
+            let states = groups.entry(key).or_insert_with(|| {
+                self.accumulators
+                    .iter()
+                    .map(|(_, accumulator)| State::new(accumulator))
+                    .collect()
+            });
+            for ((_, accumulator), state) in self.accumulators.iter().zip(states) {
+                state.add(accumulator, &document);
+            }
+        }
+
+        let results: Vec<_> = groups
+            .into_iter()
+            .map(|(GroupKey(key), states)| {
+                let mut document = Document::new();
+                document.set_id_value(key);
+                for ((field, _), state) in self.accumulators.iter().zip(states) {
+                    document.set(field.clone(), state.finish());
+                }
+                Ok(document)
+            })
+            .collect();
+        Ok(results.into_iter())
+    }
+}
+
+impl Accumulator {
+    pub fn from_json(json: &Json) -> Result<Self, DatabaseError> {
+        let (operator, operand) = match json {
+            Json::Object(map) if map.len() == 1 => map.iter().next().unwrap(),
+            _ => {
+                return Err(query_error(format!(
+                    "An accumulator must be an object with one operator, got {}",
+                    json
+                )));
+            }
+        };
+        let expression = || Expression::from_json(operand);
+        Ok(match operator.as_str() {
+            "$sum" => Accumulator::Sum(expression()?),
+            "$avg" => Accumulator::Avg(expression()?),
+            "$min" => Accumulator::Min(expression()?),
+            "$max" => Accumulator::Max(expression()?),
+            "$push" => Accumulator::Push(expression()?),
+            "$count" => match operand {
+                Json::Object(map) if map.is_empty() => Accumulator::Count,
+                _ => return Err(query_error(format!("$count takes {{}}, got {}", operand))),
+            },
+            _ => return Err(query_error(format!("Unknown accumulator {}", operator))),
+        })
+    }
+}
+
+/// A group key, ordered and compared as queries compare values.
+#[derive(Debug)]
+struct GroupKey(Value);
+
+impl PartialEq for GroupKey {
+    fn eq(&self, other: &Self) -> bool {
+        equal(&self.0, &other.0)
+    }
+}
+
+impl Eq for GroupKey {}
+
+impl PartialOrd for GroupKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for GroupKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&self.0, &other.0)
+    }
+}
+
+/// The running value of one accumulator for one group.
+enum State {
+    Sum(Value),
+    Avg { total: f64, count: usize },
+    Extreme(Option<Value>),
+    Push(Vec<Value>),
+}
+
+impl State {
+    fn new(accumulator: &Accumulator) -> Self {
+        match accumulator {
+            Accumulator::Sum(_) | Accumulator::Count => State::Sum(Value::I32(0)),
+            Accumulator::Avg(_) => State::Avg {
+                total: 0.0,
+                count: 0,
+            },
+            Accumulator::Min(_) | Accumulator::Max(_) => State::Extreme(None),
+            Accumulator::Push(_) => State::Push(Vec::new()),
+        }
+    }
+
+    fn add(&mut self, accumulator: &Accumulator, document: &Document) {
+        let value = match accumulator {
+            Accumulator::Count => Some(Value::I32(1)),
+            Accumulator::Sum(expression)
+            | Accumulator::Avg(expression)
+            | Accumulator::Min(expression)
+            | Accumulator::Max(expression)
+            | Accumulator::Push(expression) => expression.evaluate(document),
+        };
+        let Some(value) = value else { return };
+
+        match (self, accumulator) {
+            (State::Sum(total), _) => {
+                if let Some(sum) = add(total, &value) {
+                    *total = sum;
+                }
+            }
+            (State::Avg { total, count }, _) => {
+                if value.is_number() {
+                    *total += value.as_f64().unwrap_or_default();
+                    *count += 1;
+                }
+            }
+            (State::Extreme(extreme), accumulator) => {
+                let wanted = match accumulator {
+                    Accumulator::Min(_) => Ordering::Less,
+                    _ => Ordering::Greater,
+                };
+                let replace = match extreme {
+                    _ if value.is_null() => false,
+                    Some(current) => compare(&value, current) == wanted,
+                    None => true,
+                };
+                if replace {
+                    *extreme = Some(value);
+                }
+            }
+            (State::Push(values), _) => values.push(value),
+        }
+    }
+
+    fn finish(self) -> Value {
+        match self {
+            State::Sum(total) => total,
+            State::Avg { count: 0, .. } => Value::Null,
+            State::Avg { total, count } => Value::F64(total / count as f64),
+            State::Extreme(extreme) => extreme.unwrap_or(Value::Null),
+            State::Push(values) => Value::Array(values),
+        }
+    }
+}
+
+fn parse_project(json: &Json) -> Result<Stage, DatabaseError> {
+    let Json::Object(map) = json else {
+        return Err(query_error(format!(
+            "$project needs an object, got {}",
+            json
+        )));
+    };
+
+    let mut flags = Map::new();
+    let mut computed = Vec::new();
+    for (path, value) in map {
+        match value {
+            Json::Bool(_) | Json::Number(_) => {
+                flags.insert(path.clone(), value.clone());
+            }
+            _ => computed.push((path.clone(), Expression::from_json(value)?)),
+        }
+    }
+
+    let projection = match Projection::from_json(&Json::Object(flags))? {
+        // Computed fields imply inclusion
+        Projection::Exclude(paths) if !computed.is_empty() => {
+            if !paths.is_empty() {
+                return Err(query_error(
+                    "$project cannot both exclude and compute fields".to_string(),
+                ));
+            }
+            Projection::Include(Vec::new())
+        }
+        projection => projection,
+    };
+    Ok(Stage::Project {
+        projection,
+        computed,
+    })
+}
+
+fn project(
+    projection: &Projection,
+    computed: &[(String, Expression)],
+    document: Document,
+) -> Document {
+    let values: Vec<_> = computed
+        .iter()
+        .map(|(path, expression)| (path, expression.evaluate(&document)))
+        .collect();
+    let mut document = projection.apply(document);
+    for (path, value) in values {
+        match value {
+            Some(value) if path == "_id" => document.set_id_value(value),
+            Some(value) => {
+                document.set_path(path, value);
+            }
+            None => {}
+        }
+    }
+    document
+}
+
+fn parse_unwind(json: &Json) -> Result<Stage, DatabaseError> {
+    let (path, preserve_empty) = match json {
+        Json::String(path) => (path, false),
+        Json::Object(map) => match (map.get("path"), map.get("preserveNullAndEmptyArrays")) {
+            (Some(Json::String(path)), None) if map.len() == 1 => (path, false),
+            (Some(Json::String(path)), Some(Json::Bool(preserve))) if map.len() == 2 => {
+                (path, *preserve)
+            }
+            _ => return Err(query_error(format!("Invalid $unwind {}", json))),
+        },
+        _ => return Err(query_error(format!("Invalid $unwind {}", json))),
+    };
+    match path.strip_prefix('$') {
+        Some(path) if !path.is_empty() && path != "_id" => Ok(Stage::Unwind {
+            path: path.to_string(),
+            preserve_empty,
+        }),
+        _ => Err(query_error(format!(
+            "$unwind needs a \"$field\" path, got {}",
+            path
+        ))),
+    }
+}
+
+fn unwind(path: &str, preserve_empty: bool, document: Document) -> Vec<Result<Document>> {
+    let items = match document.get_path(path) {
+        Some(Value::Array(items)) if !items.is_empty() => items.clone(),
+        Some(Value::Array(_)) | Some(Value::Null) | None => {
+            return match preserve_empty {
+                true => vec![Ok(document)],
+                false => Vec::new(),
+            };
+        }
+        // A single value unwinds to itself
+        Some(_) => return vec![Ok(document)],
+    };
+    items
+        .into_iter()
+        .map(|item| {
+            let mut unwound = document.clone();
+            unwound.set_path(path, item);
+            Ok(unwound)
+        })
+        .collect()
+}
+
+fn count(stage: &str, json: &Json) -> Result<usize, DatabaseError> {
+    json.as_u64().map(|n| n as usize).ok_or_else(|| {
+        query_error(format!(
+            "{} needs a non-negative integer, got {}",
+            stage, json
+        ))
+    })
+}
+
+fn query_error(message: String) -> DatabaseError {
+    DatabaseError::Query(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn people() -> Vec<Document> {
+        [
+            r#"{"name": "ada", "city": "london", "age": 36, "tags": ["a", "b"]}"#,
+            r#"{"name": "bob", "city": "paris", "age": 17, "tags": ["b"]}"#,
+            r#"{"name": "cy", "city": "london", "age": 52.5, "tags": []}"#,
+            r#"{"name": "di", "city": "rome"}"#,
+        ]
+        .iter()
+        .map(|json| Document::from_json(json).unwrap())
+        .collect()
+    }
+
+    fn run(pipeline: &str) -> Vec<Document> {
+        let pipeline = Pipeline::parse(pipeline).unwrap();
+        pipeline
+            .run(people().into_iter().map(Ok))
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    fn names(documents: &[Document]) -> Vec<&str> {
+        documents
+            .iter()
+            .map(|doc| match doc.get("name") {
+                Some(Value::String(name)) => name.as_str(),
+                _ => "",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_match_sort_skip_limit() {
+        let results = run(r#"[{"$match": {"age": {"$gt": 1}}}, {"$sort": {"age": -1}},
+                {"$skip": 1}, {"$limit": 1}]"#);
+        assert_eq!(names(&results), vec!["ada"]);
+    }
+
+    #[test]
+    fn test_group_accumulators() {
+        let results = run(
+            r#"[{"$group": {"_id": "$city", "count": {"$count": {}}, "total": {"$sum": "$age"},
+                "mean": {"$avg": "$age"}, "oldest": {"$max": "$age"},
+                "youngest": {"$min": "$age"}, "names": {"$push": "$name"}}}]"#,
+        );
+        let keys: Vec<_> = results.iter().map(|doc| doc.id().clone()).collect();
+        assert_eq!(
+            keys,
+            ["london", "paris", "rome"].map(|city| Value::String(city.to_string()))
+        );
+
+        let london = &results[0];
+        assert_eq!(london.get("count"), Some(&Value::I32(2)));
+        assert_eq!(london.get("total"), Some(&Value::F64(88.5)));
+        assert_eq!(london.get("mean"), Some(&Value::F64(44.25)));
+        assert_eq!(london.get("oldest"), Some(&Value::F64(52.5)));
+        assert_eq!(london.get("youngest"), Some(&Value::I32(36)));
+        assert_eq!(
+            london.get("names"),
+            Some(&Value::Array(vec![
+                Value::String("ada".to_string()),
+                Value::String("cy".to_string())
+            ]))
+        );
+
+        // Nothing to average or compare in rome
+        let rome = &results[2];
+        assert_eq!(rome.get("total"), Some(&Value::I32(0)));
+        assert_eq!(rome.get("mean"), Some(&Value::Null));
+        assert_eq!(rome.get("oldest"), Some(&Value::Null));
+    }
+
+    #[test]
+    fn test_unwind_then_group() {
+        let results = run(
+            r#"[{"$unwind": "$tags"}, {"$group": {"_id": "$tags", "n": {"$sum": 1}}},
+                {"$sort": {"n": -1}}]"#,
+        );
+        let counts: Vec<_> = results
+            .iter()
+            .map(|doc| (doc.id().clone(), doc.get("n").cloned().unwrap()))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                (Value::String("b".to_string()), Value::I32(2)),
+                (Value::String("a".to_string()), Value::I32(1)),
+            ]
+        );
+
+        let preserved =
+            run(r#"[{"$unwind": {"path": "$tags", "preserveNullAndEmptyArrays": true}}]"#);
+        assert_eq!(names(&preserved), vec!["ada", "ada", "bob", "cy", "di"]);
+    }
+
+    #[test]
+    fn test_project() {
+        let results = run(r#"[{"$match": {"name": "ada"}},
+                {"$project": {"name": 1, "home.city": "$city", "fixed": "x"}}]"#);
+        let ada = &results[0];
+        assert_eq!(ada.len(), 3);
+        assert_eq!(
+            ada.get_path("home.city"),
+            Some(&Value::String("london".to_string()))
+        );
+        assert_eq!(ada.get("fixed"), Some(&Value::String("x".to_string())));
+
+        // A compound group key is read back through `_id.`
+        let results = run(
+            r#"[{"$group": {"_id": {"city": "$city"}}}, {"$project": {"city": "$_id.city"}},
+                {"$limit": 1}]"#,
+        );
+        assert_eq!(
+            results[0].get("city"),
+            Some(&Value::String("london".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_errors_pass_through() {
+        let pipeline = Pipeline::parse(r#"[{"$skip": 1}, {"$limit": 1}]"#).unwrap();
+        let input = vec![Err(anyhow::anyhow!("bad page")), Ok(Document::new())];
+        let results: Vec<_> = pipeline.run(input.into_iter()).collect();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+    }
+
+    #[test]
+    fn test_invalid_pipelines_are_rejected() {
+        for pipeline in [
+            r#"{"$match": {}}"#,
+            r#"[{"$match": {}, "$limit": 1}]"#,
+            r#"[{"$out": "x"}]"#,
+            r#"[{"$group": {"n": {"$sum": 1}}}]"#,
+            r#"[{"$group": {"_id": null, "n": {"$median": "$x"}}}]"#,
+            r#"[{"$group": {"_id": null, "n": {"$count": 1}}}]"#,
+            r#"[{"$unwind": "tags"}]"#,
+            r#"[{"$limit": -1}]"#,
+            r#"[{"$project": {"a": 0, "b": "$c"}}]"#,
+            r#"[{"$project": {"a": {"$add": [1, 2]}}}]"#,
+            r#"[{"$match": {"$text": {"$search": "x"}}}]"#,
+            r#"[{"$match": {"$or": [{"$text": {"$search": "x"}}, {"a": 1}]}}]"#,
+        ] {
+            assert!(
+                matches!(Pipeline::parse(pipeline), Err(DatabaseError::Query(_))),
+                "{} should be rejected",
+                pipeline
+            );
+        }
+    }
+}
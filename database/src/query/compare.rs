@@ -0,0 +1,138 @@
+// Ordering and equality of `Value`s as queries see them.
+//
+// Numbers compare by value whatever their width, so `I32(1)`, `I64(1)` and
+// `F64(1.0)` are equal. Values of different kinds are ordered by kind:
+//
+//   null < numbers < strings < objects < arrays < binary < object ids < booleans < dates
+//
+// which gives a total order for sorting. Range operators in filters only match
+// values of the same kind as their operand, so `{"age": {"$gt": 5}}` never
+// matches a string age.
+
+use crate::document::types::Value;
+use std::cmp::Ordering;
+
+/// Rank of a value's kind in the cross-kind sort order.
+pub fn kind_rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::I32(_) | Value::I64(_) | Value::F64(_) => 1,
+        Value::String(_) => 2,
+        Value::Object(_) => 3,
+        Value::Array(_) => 4,
+        Value::Binary(_) => 5,
+        Value::ObjectId(_) => 6,
+        Value::Bool(_) => 7,
+        Value::DateTime(_) => 8,
+    }
+}
+
+/// Whether `a` and `b` are of the same kind, so comparing them is meaningful.
+pub fn same_kind(a: &Value, b: &Value) -> bool {
+    kind_rank(a) == kind_rank(b)
+}
+
+/// Total order over all values.
+pub fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::ObjectId(a), Value::ObjectId(b)) => a.to_bytes().cmp(&b.to_bytes()),
+        (Value::DateTime(a), Value::DateTime(b)) => a.cmp(b),
+        (Value::Binary(a), Value::Binary(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| compare(a, b))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Value::Object(a), Value::Object(b)) => a
+            .iter()
+            .zip(b)
+            .map(|((ak, av), (bk, bv))| ak.cmp(bk).then_with(|| compare(av, bv)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        _ if kind_rank(a) == 1 && kind_rank(b) == 1 => compare_numbers(a, b),
+        _ => kind_rank(a).cmp(&kind_rank(b)),
+    }
+}
+
+/// Equality under `compare`, so `I32(1)` equals `F64(1.0)`.
+pub fn equal(a: &Value, b: &Value) -> bool {
+    compare(a, b) == Ordering::Equal
+}
+
+fn compare_numbers(a: &Value, b: &Value) -> Ordering {
+    match (integer(a), integer(b)) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => {
+            let (a, b) = (
+                a.as_f64().unwrap_or(f64::NAN),
+                b.as_f64().unwrap_or(f64::NAN),
+            );
+            // NaN sorts below every other number and equals itself
+            match (a.is_nan(), b.is_nan()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+                (false, false) => a.partial_cmp(&b).unwrap(),
+            }
+        }
+    }
+}
+
+fn integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::I32(i) => Some(*i as i64),
+        Value::I64(i) => Some(*i),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_numbers_compare_across_widths() {
+        assert!(equal(&Value::I32(1), &Value::F64(1.0)));
+        assert!(equal(&Value::I64(7), &Value::I32(7)));
+        assert_eq!(compare(&Value::I32(2), &Value::F64(2.5)), Ordering::Less);
+        assert_eq!(
+            compare(&Value::I64(i64::MAX), &Value::I64(i64::MAX - 1)),
+            Ordering::Greater
+        );
+        assert_eq!(
+            compare(&Value::F64(f64::NAN), &Value::I32(i32::MIN)),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn test_kinds_are_ordered() {
+        let ordered = [
+            Value::Null,
+            Value::F64(1e300),
+            Value::String(String::new()),
+            Value::Object(Default::default()),
+            Value::Array(Vec::new()),
+            Value::Binary(Vec::new()),
+            Value::ObjectId(crate::document::object_id::ObjectId::from_bytes([0; 12])),
+            Value::Bool(false),
+            Value::DateTime(chrono::DateTime::UNIX_EPOCH),
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(compare(&pair[0], &pair[1]), Ordering::Less);
+        }
+        assert!(!equal(&Value::String("1".to_string()), &Value::I32(1)));
+    }
+
+    #[test]
+    fn test_arrays_compare_element_wise() {
+        let short = Value::Array(vec![Value::I32(1)]);
+        let long = Value::Array(vec![Value::I32(1), Value::I32(0)]);
+        let bigger = Value::Array(vec![Value::I32(2)]);
+        assert_eq!(compare(&short, &long), Ordering::Less);
+        assert_eq!(compare(&long, &bigger), Ordering::Less);
+    }
+}
@@ -0,0 +1,274 @@
+// The documents of a collection that pass a filter, returned by
+// `StorageEngine::find`. Documents are read lazily, one data page at a time,
+// as the cursor is advanced. When a secondary index covers an equality or range
+// condition of the filter, or the cursor's sort, the query planner weighs it
+// against a collection scan; `explain` shows what it chose and why.
+//
+// A cursor can also sort, skip, limit and project its results:
+//
+//   engine.find(filter).sort(Sort::new().descending("age")).skip(20).limit(10)
+//
+// These apply in that order whatever order they are called in. Without a sort
+// the cursor still streams; with one, the first call to `next` reads every
+// match into a `Sorter`, which spills to disk past the cursor's memory budget,
+// unless the index it reads already returns them in sort order.
+// With a projection, only the top-level fields the filter, sort and projection
+// need are decoded from each document.
+//
+// A filter with `$text` reads the collection's text index, which returns the
+// matches most relevant first; `text_score` puts each one's relevance in a
+// field, where a sort or projection can use it. A filter with `$near` reads
+// the geospatial index on its field, which returns the matches nearest first;
+// `near_distance` puts each one's distance in a field the same way.
+
+use crate::{
+    document::{
+        Document,
+        bson::{BsonDecoder, deserialize_document},
+        types::Value,
+    },
+    query::{
+        filter::Filter,
+        geo::Point,
+        planner::{Explain, QueryPlan},
+        projection::Projection,
+        sort::{DEFAULT_SORT_MEMORY, Sort, Sorted, Sorter},
+    },
+    storage::storage_engine::{DocumentId, DocumentScan, StorageEngine},
+};
+use anyhow::Result;
+
+pub struct Cursor<'a> {
+    engine: &'a StorageEngine,
+    collection: String,
+    scan: DocumentScan<'a>,
+    plan: QueryPlan,
+    filter: Filter,
+    sort: Option<Sort>,
+    projection: Option<Projection>,
+    text_score: Option<String>,
+    // The field to set, and the path and point of the `$near` condition
+    near_distance: Option<(String, String, Point)>,
+    skip: usize,
+    limit: Option<usize>,
+    memory_budget: usize,
+    // Every match in order, once a sorted cursor has read them
+    sorted: Option<Sorted>,
+    returned: usize,
+}
+
+impl<'a> Cursor<'a> {
+    pub(crate) fn new(engine: &'a StorageEngine, collection: &str, filter: Filter) -> Self {
+        let (scan, plan) = engine.plan_scan(collection, &filter, None);
+        Self {
+            engine,
+            collection: collection.to_string(),
+            scan,
+            plan,
+            filter,
+            sort: None,
+            projection: None,
+            text_score: None,
+            near_distance: None,
+            skip: 0,
+            limit: None,
+            memory_budget: DEFAULT_SORT_MEMORY,
+            sorted: None,
+            returned: 0,
+        }
+    }
+
+    /// Return the documents in `sort` order.
+    pub fn sort(mut self, sort: Sort) -> Self {
+        // An index may serve the sort better than the one picked for the filter
+        let (scan, plan) = self
+            .engine
+            .plan_scan(&self.collection, &self.filter, Some(&sort));
+        self.scan = scan;
+        self.plan = plan;
+        self.sort = Some(sort);
+        self
+    }
+
+    /// Leave out the first `skip` documents.
+    pub fn skip(mut self, skip: usize) -> Self {
+        self.skip = skip;
+        self
+    }
+
+    /// Return at most `limit` documents.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Trim each document to `projection`.
+    pub fn project(mut self, projection: Projection) -> Self {
+        self.projection = Some(projection);
+        self
+    }
+
+    /// Set `field` of each document to its relevance to the filter's `$text`
+    /// search, higher meaning more relevant.
+    pub fn text_score(mut self, field: &str) -> Self {
+        self.text_score = Some(field.to_string());
+        self
+    }
+
+    /// Set `field` of each document to its distance in meters from the point
+    /// of the filter's `$near` condition.
+    pub fn near_distance(mut self, field: &str) -> Self {
+        if let Ok(Some((path, point, _))) = self.filter.near() {
+            self.near_distance = Some((field.to_string(), path.to_string(), point));
+        }
+        self
+    }
+
+    /// Bytes of documents a sort may hold in memory before spilling to disk.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = bytes;
+        self
+    }
+
+    pub fn filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    /// The secondary index the cursor reads matches from, or None for a
+    /// collection scan.
+    pub fn index(&self) -> Option<&str> {
+        self.scan.index_name()
+    }
+
+    /// Whether the index the cursor reads returns documents in sort order, so
+    /// the cursor needs no sort of its own.
+    pub fn is_presorted(&self) -> bool {
+        self.sort.is_some() && self.plan.chosen.sorted
+    }
+
+    /// Run the query to the end and report the plan it ran with, the plans
+    /// the planner rejected and how much the run read.
+    pub fn explain(mut self) -> Result<Explain> {
+        let mut documents_returned = 0;
+        for item in self.by_ref() {
+            item?;
+            documents_returned += 1;
+        }
+        Ok(Explain {
+            plan: self.plan.chosen,
+            rejected: self.plan.rejected,
+            cached: self.plan.cached,
+            documents_examined: self.scan.documents_examined(),
+            keys_examined: self.scan.keys_examined(),
+            pages_examined: self.scan.pages_examined(),
+            documents_returned,
+        })
+    }
+
+    /// The next document that passes the filter, in scan order.
+    fn next_match(&mut self) -> Option<Result<(DocumentId, Document, usize)>> {
+        loop {
+            let (document_id, bytes) = match self.scan.next_bytes()? {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.decode(&bytes) {
+                Ok(document) if !self.filter.matches(&document) => continue,
+                Ok(mut document) => {
+                    if let (Some(field), Some(score)) =
+                        (&self.text_score, self.scan.score(&document_id))
+                    {
+                        document.set(field, Value::F64(score));
+                    }
+                    if let Some((field, path, point)) = &self.near_distance {
+                        let found = document.get_path(path).and_then(Point::from_value);
+                        if let Some(found) = found {
+                            document.set(field, Value::F64(point.distance(&found)));
+                        }
+                    }
+                    return Some(Ok((document_id, document, bytes.len())));
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    /// Decode a document, or with a projection only the fields the cursor reads.
+    fn decode(&self, bytes: &[u8]) -> Result<Document> {
+        let searches = matches!(self.filter.text_search(), Ok(Some(_)));
+        let Some(projection) = self.projection.as_ref().filter(|_| !searches) else {
+            return Ok(deserialize_document(bytes)?);
+        };
+
+        let present = BsonDecoder::new(bytes).get_field_names()?;
+        let mut fields = projection.needed_fields(&present);
+        let mut read = self.filter.fields();
+        read.insert("_id");
+        if let Some(sort) = &self.sort {
+            read.extend(sort.keys().iter().map(|(path, _)| top_level(path)));
+        }
+        for field in &present {
+            if read.contains(field.as_str()) && !fields.contains(&field.as_str()) {
+                fields.push(field);
+            }
+        }
+        Ok(BsonDecoder::new(bytes).decode_partial_document(&fields)?)
+    }
+
+    /// Read every match into a sorter and keep its output.
+    fn sort_matches(&mut self) -> Result<Sorted> {
+        let sort = self.sort.clone().unwrap_or_default();
+        let mut sorter = Sorter::new(sort, self.memory_budget);
+        while let Some(item) = self.next_match() {
+            let (document_id, document, size) = item?;
+            sorter.push(document_id, document, size)?;
+        }
+        sorter.finish()
+    }
+}
+
+fn top_level(path: &str) -> &str {
+    path.split('.').next().unwrap_or(path)
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = Result<(DocumentId, Document)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.limit.is_some_and(|limit| self.returned >= limit) {
+                return None;
+            }
+
+            let item = match &mut self.sorted {
+                Some(sorted) => sorted.next()?,
+                None if self.sort.is_some() && !self.plan.chosen.sorted => match self.sort_matches() {
+                    Ok(sorted) => self.sorted.insert(sorted).next()?,
+                    Err(e) => {
+                        // Report the error once, then end
+                        self.sorted = Some(Sorted::InMemory(Vec::new().into_iter()));
+                        return Some(Err(e));
+                    }
+                },
+                None => self
+                    .next_match()?
+                    .map(|(document_id, document, _)| (document_id, document)),
+            };
+            let (document_id, document) = match item {
+                Ok(item) => item,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if self.skip > 0 {
+                self.skip -= 1;
+                continue;
+            }
+            self.returned += 1;
+            let document = match &self.projection {
+                Some(projection) => projection.apply(document),
+                None => document,
+            };
+            return Some(Ok((document_id, document)));
+        }
+    }
+}
@@ -0,0 +1,809 @@
+// Mongo-style document filters.
+//
+// A filter is parsed from JSON such as
+//
+//   {"age": {"$gte": 21}, "tags": {"$in": ["a", "b"]}, "$or": [{"x": 1}, {"y": 2}]}
+//
+// into a `Filter` tree and evaluated against documents. Top-level keys are
+// combined with AND. A field's value is either a literal, meaning equality, or
+// an object of operators that must all hold. Fields are looked up with
+// `Document::get_path`, so dotted paths reach into embedded objects, and `_id`
+// is the document's id. A field holding an array matches a condition if the
+// array as a whole or any of its elements does, as in MongoDB. Object ids and
+// dates are written `{"$oid": "<hex>"}` and `{"$date": "<RFC 3339>"}`.
+//
+// Supported operators: $and $or $nor $text at the top level; $eq $ne $gt $gte
+// $lt $lte $in $nin $exists $all $size $not $near $geoWithin on fields.
+// Comparisons follow `query::compare`.
+//
+// `{"$text": {"$search": "red shoes"}}` matches documents holding any of the
+// words of the search, in the sense of `index::text`. On its own a filter
+// looks for them in every string of the document; a query of a collection
+// needs a text index, which finds and scores the matches, and `$text` has to
+// be at the top of its filter, next to the other conditions.
+//
+// `{"loc": {"$geoWithin": <shape>}}` matches documents holding a point in the
+// shape at `loc`, and `{"loc": {"$near": {"$geometry": <point>, "$maxDistance":
+// <meters>}}}` those within that distance of the point (any distance if
+// `$maxDistance` is left out, and `{"$near": <point>}` is short for that).
+// Points and shapes are written as `query::geo` describes. A query with
+// `$near` needs a geospatial index on the field, which returns the matches
+// nearest first, and like `$text` it has to be at the top of its filter.
+
+use crate::{
+    document::{Document, object_id::ObjectId, types::Value},
+    error::DatabaseError,
+    index::text,
+    query::{
+        compare::{compare, equal, same_kind},
+        geo::{Point, Shape},
+    },
+};
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value as Json};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The path, point and maximum distance of a `$near` condition.
+pub(crate) type Near<'a> = (&'a str, Point, Option<f64>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// Every filter matches (an empty list matches every document).
+    And(Vec<Filter>),
+    /// At least one filter matches.
+    Or(Vec<Filter>),
+    /// No filter matches.
+    Nor(Vec<Filter>),
+    /// The value at `path` meets every condition.
+    Field {
+        path: String,
+        conditions: Vec<Condition>,
+    },
+    /// The document holds a word of the search.
+    Text(TextSearch),
+}
+
+/// The words a `$text` filter looks for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextSearch {
+    search: String,
+    terms: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Eq(Value),
+    Ne(Value),
+    Gt(Value),
+    Gte(Value),
+    Lt(Value),
+    Lte(Value),
+    In(Vec<Value>),
+    Nin(Vec<Value>),
+    Exists(bool),
+    /// The array holds every one of the values.
+    All(Vec<Value>),
+    /// The array has exactly this many elements.
+    Size(usize),
+    /// Not every one of the conditions holds.
+    Not(Vec<Condition>),
+    /// The value is a point within `max_distance` meters of `point`.
+    Near {
+        point: Point,
+        max_distance: Option<f64>,
+    },
+    /// The value is a point in the shape.
+    GeoWithin(Shape),
+}
+
+impl Filter {
+    /// A filter that matches every document.
+    pub fn all() -> Self {
+        Filter::And(Vec::new())
+    }
+
+    /// Parse a filter from its JSON text.
+    pub fn parse(json: &str) -> Result<Self, DatabaseError> {
+        let json: Json = serde_json::from_str(json).map_err(DatabaseError::Json)?;
+        Self::from_json(&json)
+    }
+
+    pub fn from_json(json: &Json) -> Result<Self, DatabaseError> {
+        let Json::Object(map) = json else {
+            return Err(query_error(format!(
+                "A filter must be an object, got {}",
+                json
+            )));
+        };
+
+        let mut filters = Vec::new();
+        for (key, value) in map {
+            filters.push(match key.as_str() {
+                "$and" => Filter::And(Self::parse_list(key, value)?),
+                "$or" => Filter::Or(Self::parse_list(key, value)?),
+                "$nor" => Filter::Nor(Self::parse_list(key, value)?),
+                "$text" => Filter::Text(TextSearch::from_json(value)?),
+                operator if operator.starts_with('$') => {
+                    return Err(query_error(format!(
+                        "Unknown top-level operator {}",
+                        operator
+                    )));
+                }
+                path => Filter::Field {
+                    path: path.to_string(),
+                    conditions: parse_conditions(value)?,
+                },
+            });
+        }
+
+        Ok(match filters.len() {
+            1 => filters.pop().unwrap(),
+            _ => Filter::And(filters),
+        })
+    }
+
+    /// Whether `document` passes the filter.
+    pub fn matches(&self, document: &Document) -> bool {
+        match self {
+            Filter::And(filters) => filters.iter().all(|filter| filter.matches(document)),
+            Filter::Or(filters) => filters.iter().any(|filter| filter.matches(document)),
+            Filter::Nor(filters) => !filters.iter().any(|filter| filter.matches(document)),
+            Filter::Field { path, conditions } => {
+                let value = if path == "_id" {
+                    Some(document.id())
+                } else {
+                    document.get_path(path)
+                };
+                conditions.iter().all(|condition| condition.matches(value))
+            }
+            Filter::Text(search) => search.matches(document),
+        }
+    }
+
+    /// The `$text` search of the filter, or an error if it has one anywhere
+    /// other than at the top.
+    pub(crate) fn text_search(&self) -> Result<Option<&TextSearch>, DatabaseError> {
+        let top = match self {
+            Filter::Text(search) => vec![search],
+            Filter::And(filters) => filters
+                .iter()
+                .filter_map(|filter| match filter {
+                    Filter::Text(search) => Some(search),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        if top.len() != self.text_searches() {
+            return Err(query_error(
+                "A filter may have one $text, at the top level".to_string(),
+            ));
+        }
+        Ok(top.first().copied())
+    }
+
+    /// The filter's `$near` condition, or an error if it has one anywhere
+    /// other than at the top.
+    pub(crate) fn near(&self) -> Result<Option<Near<'_>>, DatabaseError> {
+        let top: Vec<&Filter> = match self {
+            Filter::And(filters) => filters.iter().collect(),
+            filter => vec![filter],
+        };
+        let found: Vec<_> = top
+            .into_iter()
+            .filter_map(|filter| match filter {
+                Filter::Field { path, conditions } => Some((path, conditions)),
+                _ => None,
+            })
+            .flat_map(|(path, conditions)| {
+                conditions.iter().filter_map(move |condition| match condition {
+                    Condition::Near {
+                        point,
+                        max_distance,
+                    } => Some((path.as_str(), *point, *max_distance)),
+                    _ => None,
+                })
+            })
+            .collect();
+        if found.len() != self.near_conditions() {
+            return Err(query_error(
+                "A filter may have one $near, at the top level".to_string(),
+            ));
+        }
+        Ok(found.first().copied())
+    }
+
+    fn near_conditions(&self) -> usize {
+        match self {
+            Filter::And(filters) | Filter::Or(filters) | Filter::Nor(filters) => {
+                filters.iter().map(Filter::near_conditions).sum()
+            }
+            Filter::Field { conditions, .. } => conditions
+                .iter()
+                .map(|condition| match condition {
+                    Condition::Near { .. } => 1,
+                    Condition::Not(conditions) => conditions
+                        .iter()
+                        .filter(|condition| matches!(condition, Condition::Near { .. }))
+                        .count(),
+                    _ => 0,
+                })
+                .sum(),
+            Filter::Text(_) => 0,
+        }
+    }
+
+    fn text_searches(&self) -> usize {
+        match self {
+            Filter::And(filters) | Filter::Or(filters) | Filter::Nor(filters) => {
+                filters.iter().map(Filter::text_searches).sum()
+            }
+            Filter::Field { .. } => 0,
+            Filter::Text(_) => 1,
+        }
+    }
+
+    /// Top-level fields the filter reads, so a caller can decode only those.
+    pub(crate) fn fields(&self) -> BTreeSet<&str> {
+        let mut fields = BTreeSet::new();
+        self.collect_fields(&mut fields);
+        fields
+    }
+
+    fn collect_fields<'a>(&'a self, fields: &mut BTreeSet<&'a str>) {
+        match self {
+            Filter::And(filters) | Filter::Or(filters) | Filter::Nor(filters) => filters
+                .iter()
+                .for_each(|filter| filter.collect_fields(fields)),
+            Filter::Field { path, .. } => {
+                fields.insert(path.split('.').next().unwrap_or(path));
+            }
+            // A search reads every string; callers decode whole documents for it
+            Filter::Text(_) => {}
+        }
+    }
+
+    /// Whether every document passing the filter passes `other` as well, as
+    /// far as can be told from the two filters alone. A false answer may be
+    /// wrong, a true one never is.
+    pub(crate) fn implies(&self, other: &Filter) -> bool {
+        if self == other {
+            return true;
+        }
+        match (self, other) {
+            (_, Filter::And(others)) => others.iter().all(|other| self.implies(other)),
+            (Filter::Or(filters), _) => filters.iter().all(|filter| filter.implies(other)),
+            (_, Filter::Or(others)) if others.iter().any(|other| self.implies(other)) => true,
+            (_, Filter::Field { path, conditions }) => {
+                // Conditions on the path may come from different fields of an $and
+                let mut known = Vec::new();
+                self.top_conditions(path, &mut known);
+                conditions
+                    .iter()
+                    .all(|condition| known.iter().any(|known| known.implies(condition)))
+                    || matches!(self, Filter::And(filters)
+                        if filters.iter().any(|filter| filter.implies(other)))
+            }
+            (Filter::And(filters), _) => filters.iter().any(|filter| filter.implies(other)),
+            _ => false,
+        }
+    }
+
+    /// The conditions on `path` that every passing document meets directly.
+    fn top_conditions<'a>(&'a self, path: &str, conditions: &mut Vec<&'a Condition>) {
+        match self {
+            Filter::And(filters) => filters
+                .iter()
+                .for_each(|filter| filter.top_conditions(path, conditions)),
+            Filter::Field {
+                path: field,
+                conditions: field_conditions,
+            } if field == path => conditions.extend(field_conditions),
+            _ => {}
+        }
+    }
+
+    fn parse_list(operator: &str, json: &Json) -> Result<Vec<Filter>, DatabaseError> {
+        match json {
+            Json::Array(items) if !items.is_empty() => items.iter().map(Self::from_json).collect(),
+            _ => Err(query_error(format!(
+                "{} needs a non-empty array of filters",
+                operator
+            ))),
+        }
+    }
+}
+
+impl TextSearch {
+    /// Look for the words of `search`.
+    pub fn new(search: &str) -> Self {
+        let mut terms = text::tokenize(search);
+        terms.sort();
+        terms.dedup();
+        Self {
+            search: search.to_string(),
+            terms,
+        }
+    }
+
+    fn from_json(json: &Json) -> Result<Self, DatabaseError> {
+        match json {
+            Json::Object(map) if map.len() == 1 => match map.get("$search") {
+                Some(Json::String(search)) => Ok(Self::new(search)),
+                _ => Err(query_error(format!(
+                    "$text needs a $search string, got {}",
+                    json
+                ))),
+            },
+            _ => Err(query_error(format!(
+                "$text needs {{\"$search\": <words>}}, got {}",
+                json
+            ))),
+        }
+    }
+
+    /// The search as written.
+    pub fn search(&self) -> &str {
+        &self.search
+    }
+
+    /// The distinct terms of the search, in order.
+    pub fn terms(&self) -> &[String] {
+        &self.terms
+    }
+
+    /// Whether any string of `document` holds one of the terms.
+    pub fn matches(&self, document: &Document) -> bool {
+        let mut strings = Vec::new();
+        for (_, value) in document.iter() {
+            text::strings(value, &mut strings);
+        }
+        strings
+            .into_iter()
+            .flat_map(text::tokenize)
+            .any(|term| self.terms.binary_search(&term).is_ok())
+    }
+}
+
+impl Condition {
+    /// Whether the field value (None if the field is missing) meets the condition.
+    pub fn matches(&self, value: Option<&Value>) -> bool {
+        match self {
+            Condition::Eq(operand) => equals(value, operand),
+            Condition::Ne(operand) => !equals(value, operand),
+            Condition::Gt(operand) => ordered(value, operand, Ordering::is_gt),
+            Condition::Gte(operand) => ordered(value, operand, Ordering::is_ge),
+            Condition::Lt(operand) => ordered(value, operand, Ordering::is_lt),
+            Condition::Lte(operand) => ordered(value, operand, Ordering::is_le),
+            Condition::In(operands) => operands.iter().any(|operand| equals(value, operand)),
+            Condition::Nin(operands) => !operands.iter().any(|operand| equals(value, operand)),
+            Condition::Exists(exists) => value.is_some() == *exists,
+            Condition::All(operands) => {
+                !operands.is_empty() && operands.iter().all(|operand| equals(value, operand))
+            }
+            Condition::Size(size) => {
+                matches!(value, Some(Value::Array(items)) if items.len() == *size)
+            }
+            Condition::Not(conditions) => {
+                !conditions.iter().all(|condition| condition.matches(value))
+            }
+            Condition::Near {
+                point,
+                max_distance,
+            } => value.and_then(Point::from_value).is_some_and(|found| {
+                max_distance.is_none_or(|max_distance| point.distance(&found) <= max_distance)
+            }),
+            Condition::GeoWithin(shape) => value
+                .and_then(Point::from_value)
+                .is_some_and(|found| shape.contains(&found)),
+        }
+    }
+
+    /// Whether every value meeting the condition meets `other`, as far as can
+    /// be told from the two. A false answer may be wrong, a true one never is.
+    pub(crate) fn implies(&self, other: &Condition) -> bool {
+        use Condition::*;
+        if self == other {
+            return true;
+        }
+        // Whatever a missing field fails implies the field is there
+        if *other == Exists(true) {
+            return !self.matches(None);
+        }
+        let at_least = |a: &Value, b: &Value, strict: bool| {
+            same_kind(a, b)
+                && match compare(a, b) {
+                    Ordering::Greater => true,
+                    Ordering::Equal => !strict,
+                    Ordering::Less => false,
+                }
+        };
+        match (self, other) {
+            // A value equal to the operand, or an array holding it, meets any
+            // condition that one element is enough for, if the operand does
+            (Eq(operand), other) if !matches!(operand, Value::Array(_)) => {
+                other.holds_for_any_element()
+                    && other.matches(Some(operand))
+                    && (!operand.is_null() || other.matches(None))
+            }
+            (In(operands), other) => operands
+                .iter()
+                .all(|operand| Eq(operand.clone()).implies(other)),
+            (Gt(a), Gt(b)) | (Gt(a), Gte(b)) | (Gte(a), Gte(b)) => at_least(a, b, false),
+            (Gte(a), Gt(b)) => at_least(a, b, true),
+            (Lt(a), Lt(b)) | (Lt(a), Lte(b)) | (Lte(a), Lte(b)) => at_least(b, a, false),
+            (Lte(a), Lt(b)) => at_least(b, a, true),
+            _ => false,
+        }
+    }
+
+    /// Whether a field holding an array meets the condition as soon as one of
+    /// its elements does.
+    fn holds_for_any_element(&self) -> bool {
+        matches!(
+            self,
+            Condition::Eq(_)
+                | Condition::Gt(_)
+                | Condition::Gte(_)
+                | Condition::Lt(_)
+                | Condition::Lte(_)
+                | Condition::In(_)
+                | Condition::Exists(true)
+        )
+    }
+}
+
+/// Equality with array fan-out; a missing field equals null.
+fn equals(value: Option<&Value>, operand: &Value) -> bool {
+    match value {
+        None => operand.is_null(),
+        Some(value) => candidates(value).any(|candidate| equal(candidate, operand)),
+    }
+}
+
+/// A range comparison, only between values of the same kind.
+fn ordered(value: Option<&Value>, operand: &Value, accept: fn(Ordering) -> bool) -> bool {
+    value.is_some_and(|value| {
+        candidates(value)
+            .any(|candidate| same_kind(candidate, operand) && accept(compare(candidate, operand)))
+    })
+}
+
+/// The value itself and, for an array, each of its elements.
+fn candidates(value: &Value) -> impl Iterator<Item = &Value> {
+    let elements = match value {
+        Value::Array(items) => items.as_slice(),
+        _ => &[],
+    };
+    std::iter::once(value).chain(elements)
+}
+
+pub(crate) fn parse_conditions(json: &Json) -> Result<Vec<Condition>, DatabaseError> {
+    match json {
+        Json::Object(map) if map.keys().any(|key| key.starts_with('$')) => {
+            if !map.keys().all(|key| key.starts_with('$')) {
+                return Err(query_error(format!(
+                    "Cannot mix operators and fields in {}",
+                    json
+                )));
+            }
+            if map.contains_key("$oid") || map.contains_key("$date") {
+                return Ok(vec![Condition::Eq(literal(json)?)]);
+            }
+            map.iter()
+                .map(|(operator, operand)| parse_operator(operator, operand))
+                .collect()
+        }
+        _ => Ok(vec![Condition::Eq(literal(json)?)]),
+    }
+}
+
+fn parse_operator(operator: &str, operand: &Json) -> Result<Condition, DatabaseError> {
+    Ok(match operator {
+        "$eq" => Condition::Eq(literal(operand)?),
+        "$ne" => Condition::Ne(literal(operand)?),
+        "$gt" => Condition::Gt(literal(operand)?),
+        "$gte" => Condition::Gte(literal(operand)?),
+        "$lt" => Condition::Lt(literal(operand)?),
+        "$lte" => Condition::Lte(literal(operand)?),
+        "$in" => Condition::In(literals(operator, operand)?),
+        "$nin" => Condition::Nin(literals(operator, operand)?),
+        "$all" => Condition::All(literals(operator, operand)?),
+        "$exists" => match operand {
+            Json::Bool(exists) => Condition::Exists(*exists),
+            _ => {
+                return Err(query_error(format!(
+                    "$exists needs true or false, got {}",
+                    operand
+                )));
+            }
+        },
+        "$size" => match operand.as_u64() {
+            Some(size) => Condition::Size(size as usize),
+            None => {
+                return Err(query_error(format!(
+                    "$size needs a non-negative integer, got {}",
+                    operand
+                )));
+            }
+        },
+        "$not" => match operand {
+            Json::Object(map) if !map.is_empty() && map.keys().all(|key| key.starts_with('$')) => {
+                Condition::Not(parse_conditions(operand)?)
+            }
+            _ => {
+                return Err(query_error(format!(
+                    "$not needs an object of operators, got {}",
+                    operand
+                )));
+            }
+        },
+        "$near" => near(operand)?,
+        "$geoWithin" => Condition::GeoWithin(Shape::from_json(operand)?),
+        _ => return Err(query_error(format!("Unknown operator {}", operator))),
+    })
+}
+
+/// `$near` from either `<point>` or `{"$geometry": <point>, "$maxDistance":
+/// <meters>}`.
+fn near(operand: &Json) -> Result<Condition, DatabaseError> {
+    let Some(geometry) = operand.get("$geometry") else {
+        return Ok(Condition::Near {
+            point: Point::from_json(operand)?,
+            max_distance: None,
+        });
+    };
+    let max_distance = match operand.get("$maxDistance") {
+        None => None,
+        Some(distance) => match distance.as_f64() {
+            Some(distance) if distance >= 0.0 => Some(distance),
+            _ => {
+                return Err(query_error(format!(
+                    "$maxDistance needs a non-negative number, got {}",
+                    distance
+                )));
+            }
+        },
+    };
+    let known = 1 + max_distance.is_some() as usize;
+    if operand.as_object().is_some_and(|map| map.len() > known) {
+        return Err(query_error(format!("Unknown $near option in {}", operand)));
+    }
+    Ok(Condition::Near {
+        point: Point::from_json(geometry)?,
+        max_distance,
+    })
+}
+
+fn literals(operator: &str, json: &Json) -> Result<Vec<Value>, DatabaseError> {
+    match json {
+        Json::Array(items) => items.iter().map(literal).collect(),
+        _ => Err(query_error(format!(
+            "{} needs an array, got {}",
+            operator, json
+        ))),
+    }
+}
+
+/// A JSON operand as a `Value`, recognising `{"$oid": ...}` and `{"$date": ...}`.
+pub(crate) fn literal(json: &Json) -> Result<Value, DatabaseError> {
+    match json {
+        Json::Array(items) => Ok(Value::Array(
+            items.iter().map(literal).collect::<Result<_, _>>()?,
+        )),
+        Json::Object(map) => match special(map)? {
+            Some(value) => Ok(value),
+            None => Ok(Value::Object(
+                map.iter()
+                    .map(|(key, value)| Ok((key.clone(), literal(value)?)))
+                    .collect::<Result<BTreeMap<_, _>, DatabaseError>>()?,
+            )),
+        },
+        _ => Ok(Value::from_json_value(json.clone())),
+    }
+}
+
+fn special(map: &Map<String, Json>) -> Result<Option<Value>, DatabaseError> {
+    if map.len() != 1 {
+        return Ok(None);
+    }
+    match map.iter().next().unwrap() {
+        (key, Json::String(hex)) if key == "$oid" => ObjectId::from_hex(hex)
+            .map(|id| Some(Value::ObjectId(id)))
+            .map_err(|e| query_error(format!("Invalid $oid {}: {}", hex, e))),
+        (key, Json::String(date)) if key == "$date" => DateTime::parse_from_rfc3339(date)
+            .map(|date| Some(Value::DateTime(date.with_timezone(&Utc))))
+            .map_err(|e| query_error(format!("Invalid $date {}: {}", date, e))),
+        _ => Ok(None),
+    }
+}
+
+fn query_error(message: String) -> DatabaseError {
+    DatabaseError::Query(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn person() -> Document {
+        Document::from_json(
+            r#"{"name": "Ada", "age": 36, "score": 9.5, "tags": ["a", "x"],
+                "address": {"city": "London", "zip": null}}"#,
+        )
+        .unwrap()
+    }
+
+    fn matches(filter: &str) -> bool {
+        Filter::parse(filter).unwrap().matches(&person())
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert!(matches(r#"{"age": 36}"#));
+        assert!(matches(r#"{"age": 36.0}"#));
+        assert!(matches(r#"{"age": {"$gte": 21, "$lt": 40}}"#));
+        assert!(!matches(r#"{"age": {"$gt": 36}}"#));
+        assert!(matches(r#"{"score": {"$gt": 9}}"#));
+        assert!(matches(r#"{"name": {"$ne": "Bob"}}"#));
+        // Range operators do not cross kinds
+        assert!(!matches(r#"{"name": {"$gt": 1}}"#));
+        assert!(!matches(r#"{"age": {"$lt": "z"}}"#));
+    }
+
+    #[test]
+    fn test_paths_and_missing_fields() {
+        assert!(matches(r#"{"address.city": "London"}"#));
+        assert!(matches(r#"{"address.zip": null}"#));
+        assert!(matches(r#"{"missing": null}"#));
+        assert!(matches(r#"{"missing": {"$exists": false}}"#));
+        assert!(matches(r#"{"address.zip": {"$exists": true}}"#));
+        assert!(!matches(r#"{"missing": {"$gt": 0}}"#));
+    }
+
+    #[test]
+    fn test_arrays() {
+        assert!(matches(r#"{"tags": "a"}"#));
+        assert!(matches(r#"{"tags": ["a", "x"]}"#));
+        assert!(matches(r#"{"tags": {"$in": ["b", "x"]}}"#));
+        assert!(!matches(r#"{"tags": {"$nin": ["a"]}}"#));
+        assert!(matches(r#"{"tags": {"$all": ["x", "a"]}}"#));
+        assert!(!matches(r#"{"tags": {"$all": ["x", "b"]}}"#));
+        assert!(matches(r#"{"tags": {"$size": 2}}"#));
+    }
+
+    #[test]
+    fn test_logical_operators() {
+        assert!(matches(r#"{"$or": [{"age": 1}, {"name": "Ada"}]}"#));
+        assert!(!matches(r#"{"$and": [{"age": 36}, {"name": "Bob"}]}"#));
+        assert!(matches(r#"{"$nor": [{"age": 1}, {"name": "Bob"}]}"#));
+        assert!(matches(r#"{"age": {"$not": {"$gt": 40}}}"#));
+        assert!(matches(r#"{}"#));
+    }
+
+    #[test]
+    fn test_id_and_special_literals() {
+        let document = person();
+        let hex = document.get_id().unwrap().to_hex();
+        let filter = Filter::parse(&format!(r#"{{"_id": {{"$oid": "{}"}}}}"#, hex)).unwrap();
+        assert!(filter.matches(&document));
+
+        let mut dated = Document::new();
+        dated.set(
+            "at",
+            Value::DateTime("2024-05-01T00:00:00Z".parse().unwrap()),
+        );
+        let filter =
+            Filter::parse(r#"{"at": {"$gte": {"$date": "2024-01-01T00:00:00Z"}}}"#).unwrap();
+        assert!(filter.matches(&dated));
+    }
+
+    #[test]
+    fn test_text_searches() {
+        assert!(matches(r#"{"$text": {"$search": "Londoners in LONDON"}}"#));
+        assert!(matches(r#"{"$text": {"$search": "x"}, "age": 36}"#));
+        assert!(!matches(r#"{"$text": {"$search": "Paris"}}"#));
+        // Stop words alone find nothing
+        assert!(!matches(r#"{"$text": {"$search": "the a"}}"#));
+
+        let filter = Filter::parse(r#"{"$text": {"$search": "a b"}, "age": 1}"#).unwrap();
+        assert_eq!(filter.text_search().unwrap().unwrap().terms(), ["b"]);
+        let nested = Filter::parse(r#"{"$or": [{"$text": {"$search": "b"}}, {"age": 1}]}"#);
+        assert!(nested.unwrap().text_search().is_err());
+        assert!(Filter::parse(r#"{"age": 1}"#).unwrap().text_search().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_geo_conditions() {
+        let mut place = Document::new();
+        place.set("loc", Value::Array(vec![Value::F64(13.4), Value::F64(52.5)]));
+        let matches = |filter: &str| Filter::parse(filter).unwrap().matches(&place);
+        assert!(matches(r#"{"loc": {"$geoWithin": {"$box": [[13, 52], [14, 53]]}}}"#));
+        assert!(!matches(r#"{"loc": {"$geoWithin": {"$box": [[0, 0], [1, 1]]}}}"#));
+        assert!(matches(r#"{"loc": {"$near": [0, 0]}}"#));
+        assert!(matches(
+            r#"{"loc": {"$near": {"$geometry": {"type": "Point", "coordinates": [13.41, 52.5]}, "$maxDistance": 1000}}}"#
+        ));
+        assert!(!matches(
+            r#"{"loc": {"$near": {"$geometry": [13.5, 52.5], "$maxDistance": 1000}}}"#
+        ));
+        assert!(!matches(r#"{"missing": {"$near": [0, 0]}}"#));
+
+        let filter = Filter::parse(r#"{"loc": {"$near": [1, 2]}, "a": 1}"#).unwrap();
+        assert_eq!(
+            filter.near().unwrap(),
+            Some(("loc", Point::new(1.0, 2.0).unwrap(), None))
+        );
+        let nested = Filter::parse(r#"{"$or": [{"loc": {"$near": [1, 2]}}, {"a": 1}]}"#);
+        assert!(nested.unwrap().near().is_err());
+    }
+
+    #[test]
+    fn test_implication() {
+        let implies = |filter: &str, other: &str| {
+            Filter::parse(filter)
+                .unwrap()
+                .implies(&Filter::parse(other).unwrap())
+        };
+        assert!(implies(r#"{"age": 40}"#, r#"{"age": {"$gte": 18}}"#));
+        assert!(implies(r#"{"age": {"$gt": 30}}"#, r#"{"age": {"$gte": 30}}"#));
+        assert!(!implies(r#"{"age": {"$gte": 30}}"#, r#"{"age": {"$gt": 30}}"#));
+        assert!(implies(r#"{"age": {"$lt": 10}, "x": 1}"#, r#"{"age": {"$lte": 10}}"#));
+        assert!(!implies(r#"{"age": {"$lt": 10}}"#, r#"{"age": {"$gt": 1}}"#));
+        assert!(implies(r#"{"status": {"$in": ["a", "b"]}}"#, r#"{"status": {"$exists": true}}"#));
+        assert!(!implies(r#"{"status": null}"#, r#"{"status": {"$exists": true}}"#));
+        assert!(!implies(r#"{"age": {"$gt": "30"}}"#, r#"{"age": {"$gt": 10}}"#));
+        assert!(implies(
+            r#"{"$or": [{"a": 1, "b": 2}, {"a": 2}]}"#,
+            r#"{"a": {"$in": [1, 2]}}"#
+        ));
+        assert!(implies(
+            r#"{"a": 5, "b": {"$exists": true}}"#,
+            r#"{"$and": [{"a": {"$gt": 1}}, {"b": {"$exists": true}}]}"#
+        ));
+        assert!(implies(r#"{"a": 5}"#, r#"{"$or": [{"a": 5}, {"b": 1}]}"#));
+        assert!(!implies(r#"{"$or": [{"a": 5}, {"b": 1}]}"#, r#"{"a": 5}"#));
+        assert!(!implies(r#"{"a": {"$ne": 5}}"#, r#"{"a": {"$exists": true}}"#));
+        assert!(implies(r#"{"x": 1}"#, r#"{}"#));
+        assert!(!implies(r#"{}"#, r#"{"x": 1}"#));
+    }
+
+    #[test]
+    fn test_fields() {
+        let filter =
+            Filter::parse(r#"{"a.b": 1, "$or": [{"c": 2}, {"$nor": [{"a": 3}, {"_id": 4}]}]}"#)
+                .unwrap();
+        assert_eq!(
+            filter.fields().into_iter().collect::<Vec<_>>(),
+            vec!["_id", "a", "c"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_filters_are_rejected() {
+        for filter in [
+            r#"[1]"#,
+            r#"{"$where": "x"}"#,
+            r#"{"age": {"$between": [1, 2]}}"#,
+            r#"{"age": {"$gt": 1, "plain": 2}}"#,
+            r#"{"tags": {"$in": "a"}}"#,
+            r#"{"$or": []}"#,
+            r#"{"tags": {"$size": -1}}"#,
+            r#"{"_id": {"$oid": "nothex"}}"#,
+            r#"{"$text": "shoes"}"#,
+            r#"{"$text": {"$search": 1}}"#,
+            r#"{"loc": {"$near": [200, 0]}}"#,
+            r#"{"loc": {"$near": {"$geometry": [0, 0], "$maxDistance": -1}}}"#,
+            r#"{"loc": {"$near": {"$geometry": [0, 0], "$minDistance": 1}}}"#,
+            r#"{"loc": {"$geoWithin": {"$box": 1}}}"#,
+        ] {
+            assert!(
+                matches!(Filter::parse(filter), Err(DatabaseError::Query(_))),
+                "{} should be rejected",
+                filter
+            );
+        }
+        assert!(matches!(Filter::parse("{"), Err(DatabaseError::Json(_))));
+    }
+}
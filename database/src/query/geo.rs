@@ -0,0 +1,327 @@
+// Points and shapes on the earth, for `$near` and `$geoWithin`.
+//
+// A point is stored either as a `[longitude, latitude]` array of two numbers
+// or as a GeoJSON point, `{"type": "Point", "coordinates": [longitude,
+// latitude]}`, with longitudes from -180 to 180 and latitudes from -90 to 90.
+// Queries write points the same way, as JSON.
+//
+// Distances are great-circle distances in meters on a sphere the size of the
+// earth. Shapes are:
+//
+//   {"$box": [[lng, lat], [lng, lat]]}          lower-left and upper-right corners
+//   {"$polygon": [[lng, lat], ...]}             at least three corners
+//   {"$center": [[lng, lat], meters]}           a circle around a point
+//   {"$geometry": {"type": "Polygon", "coordinates": [[[lng, lat], ...]]}}
+//
+// Boxes and polygons are flat in longitude and latitude and include their
+// edges; a GeoJSON polygon only uses its outer ring.
+
+use crate::{document::types::Value, error::DatabaseError};
+use serde_json::Value as Json;
+
+/// Mean radius of the earth, in meters.
+pub const EARTH_RADIUS: f64 = 6_371_008.8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub lng: f64,
+    pub lat: f64,
+}
+
+/// An area that `$geoWithin` looks for points in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Box { min: Point, max: Point },
+    Polygon(Vec<Point>),
+    Circle { center: Point, radius: f64 },
+}
+
+impl Point {
+    /// The point at `lng`, `lat`, if both are in range.
+    pub fn new(lng: f64, lat: f64) -> Option<Self> {
+        ((-180.0..=180.0).contains(&lng) && (-90.0..=90.0).contains(&lat))
+            .then_some(Self { lng, lat })
+    }
+
+    /// The point a document holds in `value`, if it holds one.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        let coordinates = match value {
+            Value::Array(items) => items,
+            Value::Object(fields) => match (fields.get("type"), fields.get("coordinates")) {
+                (Some(Value::String(kind)), Some(Value::Array(items))) if kind == "Point" => items,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        match coordinates.as_slice() {
+            [lng, lat] => Self::new(number(lng)?, number(lat)?),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn from_json(json: &Json) -> Result<Self, DatabaseError> {
+        let coordinates = match json {
+            Json::Object(map) if map.get("type") == Some(&Json::from("Point")) => {
+                map.get("coordinates")
+            }
+            json => Some(json),
+        };
+        let point = match coordinates {
+            Some(Json::Array(items)) if items.len() == 2 => items[0]
+                .as_f64()
+                .zip(items[1].as_f64())
+                .and_then(|(lng, lat)| Self::new(lng, lat)),
+            _ => None,
+        };
+        point.ok_or_else(|| query_error(format!("Invalid point {}", json)))
+    }
+
+    /// The great-circle distance to `other`, in meters.
+    pub fn distance(&self, other: &Point) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let half_lat = (lat2 - lat1) / 2.0;
+        let half_lng = (other.lng - self.lng).to_radians() / 2.0;
+        let a = half_lat.sin().powi(2) + lat1.cos() * lat2.cos() * half_lng.sin().powi(2);
+        2.0 * EARTH_RADIUS * a.sqrt().min(1.0).asin()
+    }
+
+    /// The corners of a box holding every point within `radius` meters.
+    pub(crate) fn bounds_within(&self, radius: f64) -> (Point, Point) {
+        let lat_delta = (radius / EARTH_RADIUS).to_degrees();
+        let min_lat = (self.lat - lat_delta).max(-90.0);
+        let max_lat = (self.lat + lat_delta).min(90.0);
+        // Near a pole, or across the antimeridian, any longitude may be close
+        let widest = min_lat.abs().max(max_lat.abs());
+        let (min_lng, max_lng) = if widest >= 90.0 {
+            (-180.0, 180.0)
+        } else {
+            let lng_delta = lat_delta / widest.to_radians().cos();
+            match (self.lng - lng_delta, self.lng + lng_delta) {
+                (min, max) if min < -180.0 || max > 180.0 => (-180.0, 180.0),
+                bounds => bounds,
+            }
+        };
+        (
+            Point {
+                lng: min_lng,
+                lat: min_lat,
+            },
+            Point {
+                lng: max_lng,
+                lat: max_lat,
+            },
+        )
+    }
+}
+
+impl Shape {
+    pub(crate) fn from_json(json: &Json) -> Result<Self, DatabaseError> {
+        let invalid = || query_error(format!("Invalid $geoWithin shape {}", json));
+        let Json::Object(map) = json else {
+            return Err(invalid());
+        };
+        let shape = match map.iter().next() {
+            Some((kind, Json::Array(items))) if map.len() == 1 => match kind.as_str() {
+                "$box" if items.len() == 2 => {
+                    let (a, b) = (Point::from_json(&items[0])?, Point::from_json(&items[1])?);
+                    Shape::Box {
+                        min: Point {
+                            lng: a.lng.min(b.lng),
+                            lat: a.lat.min(b.lat),
+                        },
+                        max: Point {
+                            lng: a.lng.max(b.lng),
+                            lat: a.lat.max(b.lat),
+                        },
+                    }
+                }
+                "$polygon" => polygon(items)?,
+                "$center" if items.len() == 2 => Shape::Circle {
+                    center: Point::from_json(&items[0])?,
+                    radius: items[1]
+                        .as_f64()
+                        .filter(|radius| *radius >= 0.0)
+                        .ok_or_else(invalid)?,
+                },
+                _ => return Err(invalid()),
+            },
+            Some((kind, Json::Object(geometry))) if map.len() == 1 && kind == "$geometry" => {
+                match (geometry.get("type"), geometry.get("coordinates")) {
+                    (Some(Json::String(kind)), Some(Json::Array(rings))) if kind == "Polygon" => {
+                        match rings.first() {
+                            Some(Json::Array(ring)) => polygon(ring)?,
+                            _ => return Err(invalid()),
+                        }
+                    }
+                    _ => return Err(invalid()),
+                }
+            }
+            _ => return Err(invalid()),
+        };
+        Ok(shape)
+    }
+
+    /// Whether `point` lies in the shape.
+    pub fn contains(&self, point: &Point) -> bool {
+        match self {
+            Shape::Box { min, max } => {
+                (min.lng..=max.lng).contains(&point.lng) && (min.lat..=max.lat).contains(&point.lat)
+            }
+            Shape::Circle { center, radius } => center.distance(point) <= *radius,
+            Shape::Polygon(corners) => {
+                // Count the edges a ray east from the point crosses, after
+                // checking the edges themselves
+                let mut inside = false;
+                let mut previous = corners[corners.len() - 1];
+                for &corner in corners {
+                    if on_segment(point, &previous, &corner) {
+                        return true;
+                    }
+                    if (corner.lat > point.lat) != (previous.lat > point.lat) {
+                        let lng = corner.lng
+                            + (point.lat - corner.lat) / (previous.lat - corner.lat)
+                                * (previous.lng - corner.lng);
+                        if point.lng < lng {
+                            inside = !inside;
+                        }
+                    }
+                    previous = corner;
+                }
+                inside
+            }
+        }
+    }
+
+    /// The lower-left and upper-right corners of a box around the shape.
+    pub(crate) fn bounds(&self) -> (Point, Point) {
+        match self {
+            Shape::Box { min, max } => (*min, *max),
+            Shape::Circle { center, radius } => center.bounds_within(*radius),
+            Shape::Polygon(corners) => {
+                corners
+                    .iter()
+                    .fold((corners[0], corners[0]), |(min, max), corner| {
+                        (
+                            Point {
+                                lng: min.lng.min(corner.lng),
+                                lat: min.lat.min(corner.lat),
+                            },
+                            Point {
+                                lng: max.lng.max(corner.lng),
+                                lat: max.lat.max(corner.lat),
+                            },
+                        )
+                    })
+            }
+        }
+    }
+}
+
+fn polygon(items: &[Json]) -> Result<Shape, DatabaseError> {
+    let mut corners = items
+        .iter()
+        .map(Point::from_json)
+        .collect::<Result<Vec<_>, _>>()?;
+    // GeoJSON rings repeat their first corner at the end
+    if corners.len() > 1 && corners.first() == corners.last() {
+        corners.pop();
+    }
+    if corners.len() < 3 {
+        return Err(query_error(
+            "A polygon needs at least three corners".to_string(),
+        ));
+    }
+    Ok(Shape::Polygon(corners))
+}
+
+/// Whether `point` lies on the segment from `a` to `b`.
+fn on_segment(point: &Point, a: &Point, b: &Point) -> bool {
+    let cross = (b.lng - a.lng) * (point.lat - a.lat) - (b.lat - a.lat) * (point.lng - a.lng);
+    cross.abs() <= f64::EPSILON * 1e3
+        && point.lng >= a.lng.min(b.lng)
+        && point.lng <= a.lng.max(b.lng)
+        && point.lat >= a.lat.min(b.lat)
+        && point.lat <= a.lat.max(b.lat)
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::I32(_) | Value::I64(_) | Value::F64(_) => value.as_f64(),
+        _ => None,
+    }
+}
+
+fn query_error(message: String) -> DatabaseError {
+    DatabaseError::Query(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(lng: f64, lat: f64) -> Point {
+        Point::new(lng, lat).unwrap()
+    }
+
+    fn shape(json: &str) -> Shape {
+        Shape::from_json(&serde_json::from_str(json).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_points_from_values() {
+        let array = Value::Array(vec![Value::F64(13.4), Value::I32(52)]);
+        assert_eq!(Point::from_value(&array), Some(point(13.4, 52.0)));
+        let geojson = Value::from_json_value(
+            serde_json::from_str(r#"{"type": "Point", "coordinates": [-0.1, 51.5]}"#).unwrap(),
+        );
+        assert_eq!(Point::from_value(&geojson), Some(point(-0.1, 51.5)));
+        for value in [
+            Value::Array(vec![Value::I32(200), Value::I32(0)]),
+            Value::Array(vec![Value::I32(1)]),
+            Value::Array(vec![Value::String("1".to_string()), Value::I32(1)]),
+            Value::String("1,1".to_string()),
+        ] {
+            assert_eq!(Point::from_value(&value), None);
+        }
+    }
+
+    #[test]
+    fn test_distances() {
+        // Berlin to Paris is about 878 km
+        let distance = point(13.405, 52.52).distance(&point(2.3522, 48.8566));
+        assert!((distance - 878_000.0).abs() < 5_000.0, "{}", distance);
+        assert_eq!(point(1.0, 1.0).distance(&point(1.0, 1.0)), 0.0);
+    }
+
+    #[test]
+    fn test_shapes_contain_points() {
+        let square = shape(r#"{"$box": [[10, 10], [0, 0]]}"#);
+        assert!(square.contains(&point(5.0, 5.0)));
+        assert!(square.contains(&point(10.0, 0.0)));
+        assert!(!square.contains(&point(10.5, 5.0)));
+
+        let triangle = shape(r#"{"$polygon": [[0, 0], [10, 0], [0, 10]]}"#);
+        assert!(triangle.contains(&point(2.0, 2.0)));
+        assert!(triangle.contains(&point(5.0, 0.0)));
+        assert!(!triangle.contains(&point(6.0, 6.0)));
+        let ring = shape(
+            r#"{"$geometry": {"type": "Polygon", "coordinates": [[[0, 0], [10, 0], [0, 10], [0, 0]]]}}"#,
+        );
+        assert_eq!(ring, triangle);
+
+        let circle = shape(r#"{"$center": [[0, 0], 200000]}"#);
+        assert!(circle.contains(&point(1.0, 1.0)));
+        assert!(!circle.contains(&point(2.0, 2.0)));
+        let (min, max) = circle.bounds();
+        assert!(min.lng < -1.79 && max.lat > 1.79);
+
+        for invalid in [
+            r#"{"$box": [[0, 0]]}"#,
+            r#"{"$polygon": [[0, 0], [1, 1]]}"#,
+            r#"{"$center": [[0, 0], -1]}"#,
+            r#"{"$circle": []}"#,
+        ] {
+            assert!(Shape::from_json(&serde_json::from_str(invalid).unwrap()).is_err());
+        }
+    }
+}
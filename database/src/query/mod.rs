@@ -0,0 +1,10 @@
+pub mod aggregate;
+pub mod compare;
+pub mod cursor;
+pub mod filter;
+pub mod geo;
+pub mod numeric;
+pub mod planner;
+pub mod projection;
+pub mod sort;
+pub mod update;
@@ -0,0 +1,92 @@
+// Arithmetic on numeric `Value`s.
+//
+// Integers stay integers while the result fits: `I32` widens to `I64` on
+// overflow, and `I64` to `F64`. Any `F64` operand makes the result `F64`.
+// Values that are not numbers give `None`.
+
+use crate::document::types::Value;
+
+/// `a + b`.
+pub fn add(a: &Value, b: &Value) -> Option<Value> {
+    match (a, b) {
+        (Value::I32(a), Value::I32(b)) => Some(
+            a.checked_add(*b)
+                .map_or(Value::I64(*a as i64 + *b as i64), Value::I32),
+        ),
+        _ => match (integer(a), integer(b)) {
+            (Some(a), Some(b)) => Some(
+                a.checked_add(b)
+                    .map_or(Value::F64(a as f64 + b as f64), Value::I64),
+            ),
+            _ => Some(Value::F64(float(a)? + float(b)?)),
+        },
+    }
+}
+
+/// `a * b`.
+pub fn multiply(a: &Value, b: &Value) -> Option<Value> {
+    match (a, b) {
+        (Value::I32(a), Value::I32(b)) => Some(
+            a.checked_mul(*b)
+                .map_or(Value::I64(*a as i64 * *b as i64), Value::I32),
+        ),
+        _ => match (integer(a), integer(b)) {
+            (Some(a), Some(b)) => Some(
+                a.checked_mul(b)
+                    .map_or(Value::F64(a as f64 * b as f64), Value::I64),
+            ),
+            _ => Some(Value::F64(float(a)? * float(b)?)),
+        },
+    }
+}
+
+fn integer(value: &Value) -> Option<i64> {
+    match value {
+        Value::I32(i) => Some(*i as i64),
+        Value::I64(i) => Some(*i),
+        _ => None,
+    }
+}
+
+fn float(value: &Value) -> Option<f64> {
+    match value {
+        Value::I32(_) | Value::I64(_) | Value::F64(_) => value.as_f64(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_promotes() {
+        assert_eq!(add(&Value::I32(1), &Value::I32(2)), Some(Value::I32(3)));
+        assert_eq!(
+            add(&Value::I32(i32::MAX), &Value::I32(1)),
+            Some(Value::I64(i32::MAX as i64 + 1))
+        );
+        assert_eq!(add(&Value::I64(5), &Value::I32(1)), Some(Value::I64(6)));
+        assert_eq!(
+            add(&Value::I64(i64::MAX), &Value::I32(1)),
+            Some(Value::F64(i64::MAX as f64 + 1.0))
+        );
+        assert_eq!(add(&Value::I32(1), &Value::F64(0.5)), Some(Value::F64(1.5)));
+        assert_eq!(add(&Value::I32(1), &Value::String("2".to_string())), None);
+    }
+
+    #[test]
+    fn test_multiply_promotes() {
+        assert_eq!(multiply(&Value::I32(3), &Value::I32(4)), Some(Value::I32(12)));
+        assert_eq!(
+            multiply(&Value::I32(i32::MAX), &Value::I32(2)),
+            Some(Value::I64(i32::MAX as i64 * 2))
+        );
+        assert_eq!(
+            multiply(&Value::I64(i64::MAX), &Value::I32(2)),
+            Some(Value::F64(i64::MAX as f64 * 2.0))
+        );
+        assert_eq!(multiply(&Value::I32(0), &Value::F64(2.5)), Some(Value::F64(0.0)));
+        assert_eq!(multiply(&Value::Null, &Value::I32(2)), None);
+    }
+}
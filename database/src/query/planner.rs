@@ -0,0 +1,577 @@
+// The query planner: how a query reads a collection.
+//
+// Every secondary index that can narrow down the documents or provide the sort
+// is a candidate, next to a scan of the whole collection. Each is given a cost
+// in page reads from the collection's statistics (its data pages and document
+// count) and those of its indexes (entries, pages, distinct keys and a sample
+// of keys spread evenly over the index):
+//
+//   collection scan  every data page, plus a little per document filtered
+//   index scan       the index pages and entries in the ranges, plus a
+//                    random read of each data page holding a document fetched
+//
+// An index scan reads the entries of as many samples as fall in its ranges.
+// Rare values fall between samples, so equality on the first n paths of an
+// index is taken to select at least entries / distinct(n) entries per value,
+// and a range at least half of what one sample stands for. A random page read
+// costs as much as a sequential one while the whole file fits in the buffer
+// pool and four times as much otherwise. A plan that does not return documents
+// in the requested order pays for sorting them. The cheapest plan wins.
+//
+// Neither a `$text` search nor a `$near` one is planned: the collection's text
+// index, or its geospatial index on the field, is the only way to run them,
+// and returns the matches most relevant or nearest first.
+//
+// Statistics are gathered by reading the indexes the first time a collection
+// with indexes is queried, and again once writes since then reach a tenth of
+// its documents. Chosen plans are cached by query shape (the filter's paths
+// and operators and the sort, without values) until the statistics are
+// refreshed or an index is created or dropped.
+
+use crate::{
+    index::secondary::{IndexLookup, IndexStats},
+    query::{
+        filter::{Condition, Filter},
+        sort::{Direction, Sort},
+    },
+};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Cost of reading one page in order.
+const SEQUENTIAL_PAGE_COST: f64 = 1.0;
+/// Cost of reading one page out of order once the file outgrows the buffer pool.
+const RANDOM_PAGE_COST: f64 = 4.0;
+/// Cost of decoding and filtering one document.
+const DOCUMENT_COST: f64 = 0.01;
+/// Cost of reading one index entry.
+const KEY_COST: f64 = 0.005;
+/// Cost per comparison of sorting the matches.
+const SORT_COST: f64 = 0.002;
+
+/// How a query reads a collection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Plan {
+    /// Every document of the collection, page by page.
+    CollectionScan,
+    /// The documents a secondary index finds, in index order or its reverse.
+    IndexScan { index: String, reverse: bool },
+    /// The documents a text index finds for a `$text` search, most relevant
+    /// first.
+    TextScan { index: String },
+    /// The documents a geospatial index finds for a `$near` search, nearest
+    /// first.
+    NearScan { index: String },
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Plan::CollectionScan => write!(f, "COLLSCAN"),
+            Plan::IndexScan {
+                index,
+                reverse: false,
+            } => write!(f, "IXSCAN {}", index),
+            Plan::IndexScan {
+                index,
+                reverse: true,
+            } => write!(f, "IXSCAN {} backwards", index),
+            Plan::TextScan { index } => write!(f, "TEXT {}", index),
+            Plan::NearScan { index } => write!(f, "GEO_NEAR {}", index),
+        }
+    }
+}
+
+/// A candidate plan and what the planner expects it to cost.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanEstimate {
+    pub plan: Plan,
+    /// Whether documents come out in the requested sort order.
+    pub sorted: bool,
+    pub estimated_documents: usize,
+    pub estimated_pages: usize,
+    pub cost: f64,
+}
+
+/// The plan a query ran with and what running it took, from `Cursor::explain`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explain {
+    pub plan: PlanEstimate,
+    /// The other candidates, cheapest first.
+    pub rejected: Vec<PlanEstimate>,
+    /// Whether the plan came from the plan cache.
+    pub cached: bool,
+    /// Documents read from the collection, matching or not.
+    pub documents_examined: usize,
+    /// Index entries read.
+    pub keys_examined: usize,
+    /// Distinct data pages documents were read from.
+    pub pages_examined: usize,
+    pub documents_returned: usize,
+}
+
+/// The plan chosen for one query, with the alternatives it beat.
+#[derive(Debug, Clone)]
+pub(crate) struct QueryPlan {
+    pub(crate) chosen: PlanEstimate,
+    pub(crate) rejected: Vec<PlanEstimate>,
+    pub(crate) cached: bool,
+}
+
+impl QueryPlan {
+    /// A collection scan, when there is nothing else to consider.
+    pub(crate) fn collection_scan() -> Self {
+        Self::only(Plan::CollectionScan)
+    }
+
+    /// A search of the text index called `index`.
+    pub(crate) fn text_scan(index: &str) -> Self {
+        Self::only(Plan::TextScan {
+            index: index.to_string(),
+        })
+    }
+
+    /// A `$near` search of the geospatial index called `index`.
+    pub(crate) fn near_scan(index: &str) -> Self {
+        Self::only(Plan::NearScan {
+            index: index.to_string(),
+        })
+    }
+
+    fn only(plan: Plan) -> Self {
+        Self {
+            chosen: PlanEstimate {
+                plan,
+                sorted: false,
+                estimated_documents: 0,
+                estimated_pages: 0,
+                cost: 0.0,
+            },
+            rejected: Vec::new(),
+            cached: false,
+        }
+    }
+}
+
+/// What the planner knows about a collection and its indexes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statistics {
+    pub documents: usize,
+    pub pages: usize,
+    pub indexes: HashMap<String, IndexStats>,
+}
+
+/// Statistics and cached plans of every collection queried so far.
+#[derive(Debug, Default)]
+pub(crate) struct Planner {
+    collections: Mutex<HashMap<String, CollectionState>>,
+}
+
+#[derive(Debug, Default)]
+struct CollectionState {
+    statistics: Option<Arc<Statistics>>,
+    /// Writes since the statistics were gathered.
+    writes: usize,
+    plans: HashMap<String, QueryPlan>,
+}
+
+impl Planner {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note a write to `collection`, which may leave its statistics stale.
+    pub(crate) fn record_write(&self, collection: &str) {
+        if let Some(state) = self.lock().get_mut(collection) {
+            state.writes += 1;
+        }
+    }
+
+    /// Drop everything known about `collection`, after its indexes change.
+    pub(crate) fn forget(&self, collection: &str) {
+        self.lock().remove(collection);
+    }
+
+    /// The statistics of `collection`, unless they are missing or stale.
+    pub(crate) fn statistics(&self, collection: &str) -> Option<Arc<Statistics>> {
+        let collections = self.lock();
+        let state = collections.get(collection)?;
+        let statistics = state.statistics.as_ref()?;
+        (state.writes <= statistics.documents / 10).then(|| statistics.clone())
+    }
+
+    /// Replace the statistics of `collection`, dropping plans chosen with the
+    /// old ones.
+    pub(crate) fn store_statistics(&self, collection: &str, statistics: Arc<Statistics>) {
+        self.lock().insert(
+            collection.to_string(),
+            CollectionState {
+                statistics: Some(statistics),
+                writes: 0,
+                plans: HashMap::new(),
+            },
+        );
+    }
+
+    pub(crate) fn cached_plan(&self, collection: &str, shape: &str) -> Option<QueryPlan> {
+        let collections = self.lock();
+        let plan = collections.get(collection)?.plans.get(shape)?;
+        Some(QueryPlan {
+            cached: true,
+            ..plan.clone()
+        })
+    }
+
+    pub(crate) fn cache_plan(&self, collection: &str, shape: String, plan: &QueryPlan) {
+        if let Some(state) = self.lock().get_mut(collection) {
+            state.plans.insert(shape, plan.clone());
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, CollectionState>> {
+        // Every change leaves the map consistent
+        self.collections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Estimate each candidate and a collection scan, and pick the cheapest.
+/// Returns the plan and the index of the chosen candidate, or None for the scan.
+pub(crate) fn choose(
+    statistics: &Statistics,
+    candidates: &[IndexLookup],
+    sort: Option<&Sort>,
+    file_fits_in_memory: bool,
+) -> (QueryPlan, Option<usize>) {
+    let random_page_cost = if file_fits_in_memory {
+        SEQUENTIAL_PAGE_COST
+    } else {
+        RANDOM_PAGE_COST
+    };
+    let sorting = sort.is_some_and(|sort| !sort.keys().is_empty());
+
+    let mut estimates: Vec<(Option<usize>, PlanEstimate)> = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, lookup)| {
+            (
+                Some(i),
+                estimate_index(statistics, lookup, random_page_cost),
+            )
+        })
+        .collect();
+    // The best guess at how many documents match, for the cost of sorting them
+    let matches = estimates
+        .iter()
+        .map(|(_, estimate)| estimate.estimated_documents)
+        .min()
+        .unwrap_or(statistics.documents);
+    estimates.push((
+        None,
+        PlanEstimate {
+            plan: Plan::CollectionScan,
+            sorted: false,
+            estimated_documents: statistics.documents,
+            estimated_pages: statistics.pages,
+            cost: statistics.pages as f64 * SEQUENTIAL_PAGE_COST
+                + statistics.documents as f64 * DOCUMENT_COST,
+        },
+    ));
+    for (_, estimate) in &mut estimates {
+        if sorting && !estimate.sorted {
+            let sorted = match estimate.plan {
+                Plan::CollectionScan => matches,
+                _ => estimate.estimated_documents,
+            } as f64;
+            estimate.cost += SORT_COST * sorted * sorted.max(2.0).log2();
+        }
+    }
+
+    // Ties go to the earlier candidate, and to any index over a scan
+    estimates.sort_by(|(_, a), (_, b)| a.cost.total_cmp(&b.cost));
+    let mut estimates = estimates.into_iter();
+    let (chosen_index, chosen) = estimates.next().unwrap();
+    let plan = QueryPlan {
+        chosen,
+        rejected: estimates.map(|(_, estimate)| estimate).collect(),
+        cached: false,
+    };
+    (plan, chosen_index)
+}
+
+fn estimate_index(
+    statistics: &Statistics,
+    lookup: &IndexLookup,
+    random_page_cost: f64,
+) -> PlanEstimate {
+    let index = statistics.indexes.get(lookup.index.name());
+    let entries = index.map_or(0, |index| index.entries) as f64;
+    let index_pages = index.map_or(1, |index| index.pages) as f64;
+
+    let ranges = lookup.ranges.len() as f64;
+    let keys = match index {
+        Some(index) if lookup.filtered => {
+            let sampled: f64 = lookup
+                .ranges
+                .iter()
+                .map(|range| index.entries_in(range))
+                .sum();
+            let least = if lookup.range {
+                ranges * entries / index.samples.len().max(1) as f64 / 2.0
+            } else {
+                let distinct = index
+                    .distinct
+                    .get(lookup.equality_fields.max(1) - 1)
+                    .copied()
+                    .unwrap_or(1)
+                    .max(1) as f64;
+                ranges * entries / distinct
+            };
+            sampled.max(least)
+        }
+        _ => entries,
+    }
+    .min(entries);
+
+    // A multikey index holds several entries for some documents
+    let documents = statistics.documents as f64;
+    let fetched = if entries > documents && documents > 0.0 {
+        keys * documents / entries
+    } else {
+        keys
+    }
+    .min(documents);
+    let index_pages_read = ranges + (index_pages * keys / entries.max(1.0)).ceil();
+    // Documents spread evenly over the pages share some of them
+    let pages = statistics.pages.max(1) as f64;
+    let data_pages_read = pages * (1.0 - (1.0 - 1.0 / pages).powf(fetched));
+
+    PlanEstimate {
+        plan: Plan::IndexScan {
+            index: lookup.index.name().to_string(),
+            reverse: lookup.reverse,
+        },
+        sorted: lookup.sorted,
+        estimated_documents: fetched.round() as usize,
+        estimated_pages: (index_pages_read + data_pages_read).round() as usize,
+        cost: index_pages_read * SEQUENTIAL_PAGE_COST
+            + keys * KEY_COST
+            + data_pages_read * random_page_cost
+            + fetched * DOCUMENT_COST,
+    }
+}
+
+/// The shape of a query: its filter's paths and operators and its sort,
+/// without the values compared against.
+pub(crate) fn shape(filter: &Filter, sort: Option<&Sort>) -> String {
+    let mut shape = String::new();
+    filter_shape(filter, &mut shape);
+    if let Some(sort) = sort {
+        shape.push_str(" sort");
+        for (path, direction) in sort.keys() {
+            let sign = match direction {
+                Direction::Ascending => '+',
+                Direction::Descending => '-',
+            };
+            shape.push_str(&format!(" {}{}", sign, path));
+        }
+    }
+    shape
+}
+
+fn filter_shape(filter: &Filter, shape: &mut String) {
+    let (name, filters) = match filter {
+        Filter::And(filters) => ("and", filters),
+        Filter::Or(filters) => ("or", filters),
+        Filter::Nor(filters) => ("nor", filters),
+        Filter::Field { path, conditions } => {
+            shape.push_str(&format!("{:?}(", path));
+            conditions_shape(conditions, shape);
+            shape.push(')');
+            return;
+        }
+        Filter::Text(_) => {
+            shape.push_str("text,");
+            return;
+        }
+    };
+    shape.push_str(name);
+    shape.push('(');
+    for filter in filters {
+        filter_shape(filter, shape);
+        shape.push(',');
+    }
+    shape.push(')');
+}
+
+fn conditions_shape(conditions: &[Condition], shape: &mut String) {
+    for condition in conditions {
+        let name = match condition {
+            Condition::Eq(_) => "eq",
+            Condition::Ne(_) => "ne",
+            Condition::Gt(_) => "gt",
+            Condition::Gte(_) => "gte",
+            Condition::Lt(_) => "lt",
+            Condition::Lte(_) => "lte",
+            Condition::In(_) => "in",
+            Condition::Nin(_) => "nin",
+            Condition::Exists(_) => "exists",
+            Condition::All(_) => "all",
+            Condition::Size(_) => "size",
+            Condition::Near { .. } => "near",
+            Condition::GeoWithin(_) => "geoWithin",
+            Condition::Not(conditions) => {
+                shape.push_str("not(");
+                conditions_shape(conditions, shape);
+                shape.push_str("),");
+                continue;
+            }
+        };
+        shape.push_str(name);
+        shape.push(',');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Value;
+    use crate::index::btree::BTree;
+    use crate::index::key;
+    use crate::index::secondary::{IndexDefinition, SecondaryIndex};
+
+    fn statistics(
+        documents: usize,
+        pages: usize,
+        indexes: &[(&str, usize, Vec<usize>)],
+    ) -> Statistics {
+        Statistics {
+            documents,
+            pages,
+            indexes: indexes
+                .iter()
+                .map(|(name, entries, distinct)| {
+                    let stats = IndexStats {
+                        entries: *entries,
+                        pages: entries / 100 + 1,
+                        distinct: distinct.clone(),
+                        // Keys spread evenly over the first path's values
+                        samples: (0..distinct[0] as i32)
+                            .map(|value| key::encode_value(&Value::I32(value)))
+                            .collect(),
+                    };
+                    (name.to_string(), stats)
+                })
+                .collect(),
+        }
+    }
+
+    fn candidates(filter: &str, sort: Option<&Sort>, paths: &[&str]) -> Vec<IndexLookup> {
+        let filter = Filter::parse(filter).unwrap();
+        paths
+            .iter()
+            .filter_map(|path| {
+                SecondaryIndex::new(IndexDefinition::new(path), BTree::open(0)).plan(&filter, sort)
+            })
+            .collect()
+    }
+
+    fn chosen(plan: &QueryPlan) -> String {
+        plan.chosen.plan.to_string()
+    }
+
+    #[test]
+    fn test_selective_index_beats_scan() {
+        let stats = statistics(
+            10_000,
+            200,
+            &[
+                ("email_1", 10_000, vec![10_000]),
+                ("status_1", 10_000, vec![2]),
+            ],
+        );
+        let lookups = candidates(
+            r#"{"email": "a@b", "status": "active"}"#,
+            None,
+            &["email", "status"],
+        );
+        let (plan, chosen_index) = choose(&stats, &lookups, None, false);
+        assert_eq!(chosen(&plan), "IXSCAN email_1");
+        assert_eq!(chosen_index, Some(0));
+        assert_eq!(plan.chosen.estimated_documents, 1);
+        assert_eq!(plan.rejected.len(), 2);
+        // Half the collection by random reads costs more than reading it all in order
+        assert_eq!(plan.rejected[0].plan, Plan::CollectionScan);
+
+        let lookups = candidates(r#"{"status": "active"}"#, None, &["status"]);
+        let (plan, chosen_index) = choose(&stats, &lookups, None, false);
+        assert_eq!(chosen(&plan), "COLLSCAN");
+        assert_eq!(chosen_index, None);
+    }
+
+    #[test]
+    fn test_ranges_and_sorts_shift_the_choice() {
+        let stats = statistics(10_000, 2_000, &[("age_1", 10_000, vec![80])]);
+        let sort = Sort::new().descending("age");
+        let lookups = candidates(r#"{"age": {"$gte": 30, "$lt": 50}}"#, Some(&sort), &["age"]);
+
+        // A narrow range read backwards saves both the scan and the sort
+        let (plan, _) = choose(&stats, &lookups, Some(&sort), true);
+        assert_eq!(chosen(&plan), "IXSCAN age_1 backwards");
+        assert!(plan.chosen.sorted);
+        assert_eq!(plan.chosen.estimated_documents, 2_625);
+
+        // unless every fetch may go to disk
+        let (plan, _) = choose(&stats, &lookups, Some(&sort), false);
+        assert_eq!(chosen(&plan), "COLLSCAN");
+
+        // Reading every document in index order beats sorting them only while
+        // the file is cached
+        let lookups = candidates(r#"{}"#, Some(&sort), &["age"]);
+        let (plan, _) = choose(&stats, &lookups, Some(&sort), true);
+        assert_eq!(chosen(&plan), "IXSCAN age_1 backwards");
+        let (plan, _) = choose(&stats, &lookups, Some(&sort), false);
+        assert_eq!(chosen(&plan), "COLLSCAN");
+        assert!(plan.rejected[0].sorted);
+    }
+
+    #[test]
+    fn test_shapes_ignore_values() {
+        let shape_of = |filter: &str| shape(&Filter::parse(filter).unwrap(), None);
+        assert_eq!(
+            shape_of(r#"{"a": 1, "b": {"$gt": 2}}"#),
+            shape_of(r#"{"a": "x", "b": {"$gt": 9}}"#)
+        );
+        assert_ne!(shape_of(r#"{"a": 1}"#), shape_of(r#"{"a": {"$in": [1]}}"#));
+        assert_ne!(shape_of(r#"{"a": 1}"#), shape_of(r#"{"b": 1}"#));
+        let filter = Filter::parse(r#"{"a": 1}"#).unwrap();
+        assert_ne!(
+            shape(&filter, Some(&Sort::new().ascending("a"))),
+            shape(&filter, Some(&Sort::new().descending("a")))
+        );
+    }
+
+    #[test]
+    fn test_plan_cache_follows_statistics() {
+        let planner = Planner::new();
+        let plan = QueryPlan::collection_scan();
+        planner.cache_plan("c", "shape".to_string(), &plan);
+        assert!(planner.cached_plan("c", "shape").is_none());
+
+        planner.store_statistics("c", Arc::new(statistics(100, 2, &[])));
+        planner.cache_plan("c", "shape".to_string(), &plan);
+        assert!(planner.cached_plan("c", "shape").unwrap().cached);
+        assert!(planner.statistics("c").is_some());
+
+        // A tenth of the collection rewritten makes the statistics stale
+        (0..11).for_each(|_| planner.record_write("c"));
+        assert!(planner.statistics("c").is_none());
+        planner.store_statistics("c", Arc::new(statistics(100, 2, &[])));
+        assert!(planner.cached_plan("c", "shape").is_none());
+
+        planner.forget("c");
+        assert!(planner.statistics("c").is_none());
+    }
+}
@@ -0,0 +1,287 @@
+// Projections trim the documents a cursor returns.
+//
+// A projection is parsed from JSON such as `{"name": 1, "address.city": 1}`
+// (keep only these fields) or `{"bio": 0}` (keep everything but these). The
+// two kinds cannot be mixed. Dotted paths reach into embedded objects, and
+// into the objects held by an array. A document's `_id` is always returned;
+// `{"_id": 1}` is accepted and changes nothing.
+
+use crate::{document::Document, document::types::Value, error::DatabaseError};
+use serde_json::Value as Json;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Projection {
+    /// Keep only the given paths.
+    Include(Vec<String>),
+    /// Keep everything except the given paths.
+    Exclude(Vec<String>),
+}
+
+/// The paths of a projection, arranged by their components.
+#[derive(Debug)]
+enum PathTree {
+    /// The whole value at this point is named.
+    Whole,
+    /// Only some fields of the object at this point are named.
+    Fields(BTreeMap<String, PathTree>),
+}
+
+impl Projection {
+    /// Parse a projection from its JSON text.
+    pub fn parse(json: &str) -> Result<Self, DatabaseError> {
+        let json: Json = serde_json::from_str(json).map_err(DatabaseError::Json)?;
+        Self::from_json(&json)
+    }
+
+    pub fn from_json(json: &Json) -> Result<Self, DatabaseError> {
+        let Json::Object(map) = json else {
+            return Err(query_error(format!(
+                "A projection must be an object, got {}",
+                json
+            )));
+        };
+
+        let mut included = Vec::new();
+        let mut excluded = Vec::new();
+        for (path, flag) in map {
+            let include = match flag {
+                Json::Bool(include) => *include,
+                Json::Number(n) if n.as_f64().is_some() => n.as_f64() != Some(0.0),
+                _ => {
+                    return Err(query_error(format!(
+                        "Projection of {} must be 1, 0, true or false, got {}",
+                        path, flag
+                    )));
+                }
+            };
+            if path == "_id" {
+                if !include {
+                    return Err(query_error(
+                        "The _id of a document cannot be excluded".to_string(),
+                    ));
+                }
+                continue;
+            }
+            if path.is_empty() || path.split('.').any(str::is_empty) || path.starts_with('$') {
+                return Err(query_error(format!("Invalid projection path '{}'", path)));
+            }
+            match include {
+                true => included.push(path.clone()),
+                false => excluded.push(path.clone()),
+            }
+        }
+
+        match (included.is_empty(), excluded.is_empty()) {
+            (false, false) => Err(query_error(
+                "A projection cannot both include and exclude fields".to_string(),
+            )),
+            (true, false) => Ok(Projection::Exclude(excluded)),
+            (false, true) => Ok(Projection::Include(included)),
+            // `{}` and `{"_id": 1}` keep every field
+            (true, true) => Ok(Projection::Exclude(Vec::new())),
+        }
+    }
+
+    /// The named paths.
+    pub fn paths(&self) -> &[String] {
+        match self {
+            Projection::Include(paths) | Projection::Exclude(paths) => paths,
+        }
+    }
+
+    /// Top-level fields a document needs for the projection to be applied,
+    /// given the fields it holds.
+    pub(crate) fn needed_fields<'a>(&self, present: &'a [String]) -> Vec<&'a str> {
+        let named = |field: &str| {
+            self.paths()
+                .iter()
+                .any(|path| path.split('.').next() == Some(field))
+        };
+        let whole = |field: &str| self.paths().iter().any(|path| path == field);
+        present
+            .iter()
+            .map(String::as_str)
+            .filter(|field| match self {
+                Projection::Include(_) => named(field),
+                Projection::Exclude(_) => !whole(field),
+            })
+            .collect()
+    }
+
+    /// Trim `document` to the projection.
+    pub fn apply(&self, mut document: Document) -> Document {
+        let tree = self.tree();
+        let fields: Vec<String> = document.keys().cloned().collect();
+        for field in fields {
+            let value = document.remove(&field).unwrap();
+            let kept = match (self, tree.get(&field)) {
+                (Projection::Include(_), Some(node)) => include(value, node),
+                (Projection::Include(_), None) => None,
+                (Projection::Exclude(_), Some(node)) => exclude(value, node),
+                (Projection::Exclude(_), None) => Some(value),
+            };
+            if let Some(value) = kept {
+                document.set(field, value);
+            }
+        }
+        document
+    }
+
+    fn tree(&self) -> BTreeMap<String, PathTree> {
+        let mut root = BTreeMap::new();
+        for path in self.paths() {
+            let mut fields = &mut root;
+            let mut components = path.split('.').peekable();
+            while let Some(component) = components.next() {
+                let node = fields
+                    .entry(component.to_string())
+                    .or_insert_with(|| PathTree::Fields(BTreeMap::new()));
+                if components.peek().is_none() {
+                    *node = PathTree::Whole;
+                    break;
+                }
+                match node {
+                    // A shorter path already names the whole value
+                    PathTree::Whole => break,
+                    PathTree::Fields(children) => fields = children,
+                }
+            }
+        }
+        root
+    }
+}
+
+/// The parts of `value` named by `node`, if any.
+fn include(value: Value, node: &PathTree) -> Option<Value> {
+    match (node, value) {
+        (PathTree::Whole, value) => Some(value),
+        (PathTree::Fields(children), Value::Object(map)) => Some(Value::Object(
+            map.into_iter()
+                .filter_map(|(key, value)| {
+                    let child = children.get(&key)?;
+                    Some((key, include(value, child)?))
+                })
+                .collect(),
+        )),
+        (PathTree::Fields(_), Value::Array(items)) => Some(Value::Array(
+            items
+                .into_iter()
+                .filter_map(|item| include(item, node))
+                .collect(),
+        )),
+        (PathTree::Fields(_), _) => None,
+    }
+}
+
+/// `value` without the parts named by `node`, or None if it is named whole.
+fn exclude(value: Value, node: &PathTree) -> Option<Value> {
+    match (node, value) {
+        (PathTree::Whole, _) => None,
+        (PathTree::Fields(children), Value::Object(map)) => Some(Value::Object(
+            map.into_iter()
+                .filter_map(|(key, value)| match children.get(&key) {
+                    Some(child) => Some((key, exclude(value, child)?)),
+                    None => Some((key, value)),
+                })
+                .collect(),
+        )),
+        (PathTree::Fields(_), Value::Array(items)) => Some(Value::Array(
+            items
+                .into_iter()
+                .filter_map(|item| exclude(item, node))
+                .collect(),
+        )),
+        (PathTree::Fields(_), value) => Some(value),
+    }
+}
+
+fn query_error(message: String) -> DatabaseError {
+    DatabaseError::Query(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn person() -> Document {
+        Document::from_json(
+            r#"{"name": "Ada", "age": 36, "bio": "...",
+                "address": {"city": "London", "zip": "N1"},
+                "jobs": [{"title": "analyst", "year": 1843}, 7]}"#,
+        )
+        .unwrap()
+    }
+
+    /// Apply `projection` to `person()` and check the result has `expected`'s fields.
+    fn assert_projects(projection: &str, expected: &str) {
+        let document = person();
+        let projected = Projection::parse(projection)
+            .unwrap()
+            .apply(document.clone());
+        assert_eq!(projected.id(), document.id());
+        let expected = Document::from_json(expected).unwrap();
+        assert_eq!(
+            projected.iter().collect::<Vec<_>>(),
+            expected.iter().collect::<Vec<_>>(),
+            "{}",
+            projection
+        );
+    }
+
+    #[test]
+    fn test_inclusion() {
+        assert_projects(
+            r#"{"name": 1, "address.city": 1, "_id": 1}"#,
+            r#"{"name": "Ada", "address": {"city": "London"}}"#,
+        );
+        assert_projects(
+            r#"{"jobs.title": true, "address": 1, "address.zip": 1}"#,
+            r#"{"address": {"city": "London", "zip": "N1"}, "jobs": [{"title": "analyst"}]}"#,
+        );
+    }
+
+    #[test]
+    fn test_exclusion() {
+        assert_projects(
+            r#"{"bio": 0, "address.zip": 0, "jobs.year": false}"#,
+            r#"{"name": "Ada", "age": 36, "address": {"city": "London"},
+                "jobs": [{"title": "analyst"}, 7]}"#,
+        );
+        let everything = r#"{"name": "Ada", "age": 36, "bio": "...",
+            "address": {"city": "London", "zip": "N1"},
+            "jobs": [{"title": "analyst", "year": 1843}, 7]}"#;
+        assert_projects("{}", everything);
+        assert_projects(r#"{"_id": 1}"#, everything);
+    }
+
+    #[test]
+    fn test_needed_fields() {
+        let present: Vec<String> = person().keys().cloned().collect();
+        let projection = Projection::parse(r#"{"address.city": 1, "name": 1}"#).unwrap();
+        assert_eq!(projection.needed_fields(&present), vec!["address", "name"]);
+        let projection = Projection::parse(r#"{"address.city": 0, "bio": 0}"#).unwrap();
+        assert_eq!(
+            projection.needed_fields(&present),
+            vec!["address", "age", "jobs", "name"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_projections_are_rejected() {
+        for projection in [
+            r#"[]"#,
+            r#"{"name": 1, "bio": 0}"#,
+            r#"{"_id": 0}"#,
+            r#"{"name": "yes"}"#,
+            r#"{"a..b": 1}"#,
+            r#"{"$slice": 1}"#,
+        ] {
+            assert!(
+                matches!(Projection::parse(projection), Err(DatabaseError::Query(_))),
+                "{} should be rejected",
+                projection
+            );
+        }
+    }
+}
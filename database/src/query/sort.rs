@@ -0,0 +1,355 @@
+// Sort orders for cursors, and the sorter that applies them.
+//
+// A sort is a list of dotted field paths, each ascending or descending; later
+// paths break ties left by earlier ones. Values are ordered by
+// `query::compare`, and a missing field sorts as null. Ties keep scan order.
+//
+// The sorter keeps documents in memory until their encoded size passes a
+// budget, then writes the sorted batch to a temporary file as a run and starts
+// a new batch. Reading the results merges the runs, so a sort of any size
+// needs memory for the budget plus one document per run.
+
+use crate::{
+    document::{
+        Document,
+        bson::{BsonDecoder, serialize_document},
+        types::Value,
+    },
+    error::DatabaseError,
+    query::compare::compare,
+    storage::storage_engine::DocumentId,
+};
+use anyhow::Result;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Seek, SeekFrom, Write};
+
+/// Memory a sort may use before spilling to disk, unless the cursor sets its own.
+pub const DEFAULT_SORT_MEMORY: usize = 32 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Ascending,
+    Descending,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sort {
+    keys: Vec<(String, Direction)>,
+}
+
+impl Sort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Order by `path`, smallest first, after any earlier keys.
+    pub fn ascending(mut self, path: &str) -> Self {
+        self.keys.push((path.to_string(), Direction::Ascending));
+        self
+    }
+
+    /// Order by `path`, largest first, after any earlier keys.
+    pub fn descending(mut self, path: &str) -> Self {
+        self.keys.push((path.to_string(), Direction::Descending));
+        self
+    }
+
+    /// Parse a sort from JSON: `{"age": -1}` for one key, or
+    /// `[{"age": -1}, {"name": 1}]` for several, since object keys are not
+    /// kept in order.
+    pub fn parse(json: &str) -> Result<Self, DatabaseError> {
+        let json: Json = serde_json::from_str(json).map_err(DatabaseError::Json)?;
+        Self::from_json(&json)
+    }
+
+    pub fn from_json(json: &Json) -> Result<Self, DatabaseError> {
+        let keys = match json {
+            Json::Array(keys) if !keys.is_empty() => keys.as_slice(),
+            Json::Object(_) => std::slice::from_ref(json),
+            _ => return Err(query_error(format!("Invalid sort {}", json))),
+        };
+
+        let mut sort = Sort::new();
+        for key in keys {
+            let (path, direction) = match key {
+                Json::Object(map) if map.len() == 1 => map.iter().next().unwrap(),
+                _ => {
+                    return Err(query_error(format!(
+                        "Each sort key must be an object with one field, got {}",
+                        key
+                    )));
+                }
+            };
+            sort = match direction.as_i64() {
+                Some(1) => sort.ascending(path),
+                Some(-1) => sort.descending(path),
+                _ => {
+                    return Err(query_error(format!(
+                        "Sort direction of {} must be 1 or -1, got {}",
+                        path, direction
+                    )));
+                }
+            };
+        }
+        Ok(sort)
+    }
+
+    pub fn keys(&self) -> &[(String, Direction)] {
+        &self.keys
+    }
+
+    /// Order `a` and `b` by the sort keys.
+    pub fn compare(&self, a: &Document, b: &Document) -> Ordering {
+        self.keys
+            .iter()
+            .map(|(path, direction)| {
+                let ordering = compare(sort_value(a, path), sort_value(b, path));
+                match direction {
+                    Direction::Ascending => ordering,
+                    Direction::Descending => ordering.reverse(),
+                }
+            })
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(Ordering::Equal)
+    }
+}
+
+fn sort_value<'a>(document: &'a Document, path: &str) -> &'a Value {
+    const NULL: &Value = &Value::Null;
+    match path {
+        "_id" => document.id(),
+        _ => document.get_path(path).unwrap_or(NULL),
+    }
+}
+
+/// Sorts documents within a memory budget, spilling sorted runs to disk.
+pub(crate) struct Sorter {
+    sort: Sort,
+    memory_budget: usize,
+    batch: Vec<(DocumentId, Document)>,
+    batch_bytes: usize,
+    runs: Vec<File>,
+}
+
+impl Sorter {
+    pub(crate) fn new(sort: Sort, memory_budget: usize) -> Self {
+        Self {
+            sort,
+            memory_budget,
+            batch: Vec::new(),
+            batch_bytes: 0,
+            runs: Vec::new(),
+        }
+    }
+
+    /// Add a document whose encoded size is `size` bytes.
+    pub(crate) fn push(
+        &mut self,
+        document_id: DocumentId,
+        document: Document,
+        size: usize,
+    ) -> Result<()> {
+        self.batch.push((document_id, document));
+        self.batch_bytes += size;
+        if self.batch_bytes > self.memory_budget {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// The documents pushed so far, in order.
+    pub(crate) fn finish(mut self) -> Result<Sorted> {
+        self.sort_batch();
+        if self.runs.is_empty() {
+            return Ok(Sorted::InMemory(self.batch.into_iter()));
+        }
+
+        if !self.batch.is_empty() {
+            self.spill()?;
+        }
+        let mut runs = Vec::with_capacity(self.runs.len());
+        for mut file in self.runs {
+            file.seek(SeekFrom::Start(0))?;
+            let mut run = Run {
+                reader: BufReader::new(file),
+                head: None,
+            };
+            run.advance()?;
+            runs.push(run);
+        }
+        Ok(Sorted::Merged {
+            sort: self.sort,
+            runs,
+        })
+    }
+
+    fn sort_batch(&mut self) {
+        let sort = &self.sort;
+        // Stable, so equal documents keep scan order
+        self.batch.sort_by(|(_, a), (_, b)| sort.compare(a, b));
+    }
+
+    /// Write the current batch to a new run file.
+    fn spill(&mut self) -> Result<()> {
+        self.sort_batch();
+        let mut writer = BufWriter::new(tempfile::tempfile()?);
+        for (document_id, document) in self.batch.drain(..) {
+            writer.write_u64::<LittleEndian>(document_id.page_id())?;
+            writer.write_u16::<LittleEndian>(document_id.slot_id())?;
+            writer.write_all(&serialize_document(&document)?)?;
+        }
+        self.runs
+            .push(writer.into_inner().map_err(|e| e.into_error())?);
+        self.batch_bytes = 0;
+        Ok(())
+    }
+}
+
+/// One sorted run on disk and its next document.
+pub(crate) struct Run {
+    reader: BufReader<File>,
+    head: Option<(DocumentId, Document)>,
+}
+
+impl Run {
+    fn advance(&mut self) -> Result<()> {
+        let page_id = match self.reader.read_u64::<LittleEndian>() {
+            Ok(page_id) => page_id,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                self.head = None;
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let slot_id = self.reader.read_u16::<LittleEndian>()?;
+        let document = BsonDecoder::new(&mut self.reader).decode_document()?;
+        self.head = Some((DocumentId::new(page_id, slot_id), document));
+        Ok(())
+    }
+}
+
+/// The output of a `Sorter`.
+pub(crate) enum Sorted {
+    InMemory(std::vec::IntoIter<(DocumentId, Document)>),
+    Merged { sort: Sort, runs: Vec<Run> },
+}
+
+impl Iterator for Sorted {
+    type Item = Result<(DocumentId, Document)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Sorted::InMemory(documents) => documents.next().map(Ok),
+            Sorted::Merged { sort, runs } => {
+                // The first run with the smallest head, so ties keep run order
+                let mut smallest: Option<usize> = None;
+                for (index, run) in runs.iter().enumerate() {
+                    let Some((_, document)) = &run.head else {
+                        continue;
+                    };
+                    let smaller = match smallest.and_then(|s| runs[s].head.as_ref()) {
+                        Some((_, best)) => sort.compare(document, best).is_lt(),
+                        None => true,
+                    };
+                    if smaller {
+                        smallest = Some(index);
+                    }
+                }
+
+                let run = &mut runs[smallest?];
+                let head = run.head.take();
+                if let Err(e) = run.advance() {
+                    runs.clear();
+                    return Some(Err(e));
+                }
+                head.map(Ok)
+            }
+        }
+    }
+}
+
+fn query_error(message: String) -> DatabaseError {
+    DatabaseError::Query(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(n: i32, name: &str) -> Document {
+        let mut document = Document::new();
+        document.set("n", Value::I32(n));
+        document.set("name", Value::String(name.to_string()));
+        document
+    }
+
+    /// Sort `documents` and report whether the sorter spilled to disk.
+    fn sorted(sort: Sort, memory_budget: usize, documents: &[Document]) -> (Vec<Document>, bool) {
+        let mut sorter = Sorter::new(sort, memory_budget);
+        for (slot, document) in documents.iter().enumerate() {
+            let size = serialize_document(document).unwrap().len();
+            sorter
+                .push(DocumentId::new(1, slot as u16), document.clone(), size)
+                .unwrap();
+        }
+        let sorted = sorter.finish().unwrap();
+        let spilled = matches!(&sorted, Sorted::Merged { runs, .. } if runs.len() > 1);
+        (sorted.map(|item| item.unwrap().1).collect(), spilled)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Sort::parse(r#"{"age": -1}"#).unwrap(),
+            Sort::new().descending("age")
+        );
+        assert_eq!(
+            Sort::parse(r#"[{"a.b": 1}, {"c": -1}]"#).unwrap(),
+            Sort::new().ascending("a.b").descending("c")
+        );
+        for sort in [
+            r#"{"a": 1, "b": 1}"#,
+            r#"{"a": 0}"#,
+            r#"[]"#,
+            r#"["a"]"#,
+            "1",
+        ] {
+            assert!(
+                matches!(Sort::parse(sort), Err(DatabaseError::Query(_))),
+                "{} should be rejected",
+                sort
+            );
+        }
+    }
+
+    #[test]
+    fn test_compare_by_several_keys() {
+        let sort = Sort::new().ascending("name").descending("n");
+        assert!(sort.compare(&document(1, "a"), &document(2, "b")).is_lt());
+        assert!(sort.compare(&document(1, "a"), &document(2, "a")).is_gt());
+        // A missing field sorts as null, before any value
+        assert!(sort.compare(&Document::new(), &document(0, "")).is_lt());
+    }
+
+    #[test]
+    fn test_spilled_sort_matches_in_memory_sort() {
+        let documents: Vec<_> = (0..200)
+            .map(|i| document((i * 37) % 50, if i % 2 == 0 { "even" } else { "odd" }))
+            .collect();
+        let sort = Sort::new().ascending("n").descending("name");
+
+        let (in_memory, spilled_to_disk) = sorted(sort.clone(), usize::MAX, &documents);
+        assert!(!spilled_to_disk);
+        let (spilled, spilled_to_disk) = sorted(sort.clone(), 512, &documents);
+        assert!(spilled_to_disk);
+        assert_eq!(in_memory, spilled);
+
+        let mut expected = documents.clone();
+        expected.sort_by(|a, b| sort.compare(a, b));
+        assert_eq!(spilled, expected);
+    }
+}
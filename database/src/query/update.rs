@@ -0,0 +1,513 @@
+// Update operators, applied in place to a stored document by `update_one`.
+//
+// An update is parsed from JSON such as
+//
+//   {"$set": {"address.city": "Paris"}, "$inc": {"visits": 1}, "$push": {"tags": "new"}}
+//
+// Supported operators:
+//
+//   $set       set fields, creating embedded objects along dotted paths
+//   $unset     remove fields
+//   $inc $mul  add to or multiply numbers, promoting as `query::numeric` does;
+//              a missing field counts as zero
+//   $push      append to an array, creating it if missing; `{"$each": [...]}`
+//              appends several values
+//   $addToSet  append values not already present, compared as filters compare
+//   $pull      remove elements equal to a value, or meeting a condition such
+//              as `{"$gte": 5}`
+//   $rename    move a field to a new path
+//
+// Operands are written as filter literals are, so object ids and dates are
+// `{"$oid": "<hex>"}` and `{"$date": "<RFC 3339>"}`.
+//
+// `_id` cannot be changed, and one update may not touch a path twice or both
+// a path and a field inside it. Every problem with an update is a
+// `DatabaseError::Validation`, and a failed update leaves the document as it
+// was.
+
+use crate::{
+    document::{Document, object_id::ObjectId, types::Value},
+    error::DatabaseError,
+    query::{
+        compare::equal,
+        filter::{Condition, Filter, literal, parse_conditions},
+        numeric::{add, multiply},
+    },
+};
+use serde_json::Value as Json;
+
+/// Which document `update_one` changes: the one with an `_id`, or the first
+/// to match a filter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selector {
+    Id(ObjectId),
+    Filter(Filter),
+}
+
+impl From<ObjectId> for Selector {
+    fn from(id: ObjectId) -> Self {
+        Selector::Id(id)
+    }
+}
+
+impl From<&ObjectId> for Selector {
+    fn from(id: &ObjectId) -> Self {
+        Selector::Id(id.clone())
+    }
+}
+
+impl From<Filter> for Selector {
+    fn from(filter: Filter) -> Self {
+        Selector::Filter(filter)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Update {
+    operations: Vec<Operation>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Set(String, Value),
+    Unset(String),
+    Inc(String, Value),
+    Mul(String, Value),
+    Push(String, Vec<Value>),
+    AddToSet(String, Vec<Value>),
+    Pull(String, Pull),
+    Rename(String, String),
+}
+
+/// What `$pull` removes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pull {
+    Equal(Value),
+    Matching(Vec<Condition>),
+}
+
+impl Update {
+    /// Parse an update from its JSON text.
+    pub fn parse(json: &str) -> Result<Self, DatabaseError> {
+        let json: Json = serde_json::from_str(json).map_err(DatabaseError::Json)?;
+        Self::from_json(&json)
+    }
+
+    pub fn from_json(json: &Json) -> Result<Self, DatabaseError> {
+        let map = match json {
+            Json::Object(map) if !map.is_empty() => map,
+            _ => {
+                return Err(validation_error(format!(
+                    "An update must be a non-empty object of operators, got {}",
+                    json
+                )));
+            }
+        };
+
+        let mut operations = Vec::new();
+        for (operator, fields) in map {
+            if !operator.starts_with('$') {
+                return Err(validation_error(format!(
+                    "Expected an update operator, got field '{}'; use update_document to replace a document",
+                    operator
+                )));
+            }
+            let Json::Object(fields) = fields else {
+                return Err(validation_error(format!(
+                    "{} needs an object of fields, got {}",
+                    operator, fields
+                )));
+            };
+            for (path, operand) in fields {
+                operations.push(Operation::parse(operator, path, operand)?);
+            }
+        }
+
+        let update = Self { operations };
+        update.check_paths()?;
+        Ok(update)
+    }
+
+    pub fn operations(&self) -> &[Operation] {
+        &self.operations
+    }
+
+    /// Apply every operation to `document`, or none of them if one fails.
+    pub fn apply(&self, document: &mut Document) -> Result<(), DatabaseError> {
+        let mut updated = document.clone();
+        for operation in &self.operations {
+            operation.apply(&mut updated)?;
+        }
+        *document = updated;
+        Ok(())
+    }
+
+    /// Reject updates that touch `_id`, or one path twice.
+    fn check_paths(&self) -> Result<(), DatabaseError> {
+        let paths: Vec<&str> = self
+            .operations
+            .iter()
+            .flat_map(|operation| operation.paths())
+            .collect();
+        for (i, path) in paths.iter().enumerate() {
+            if *path == "_id" || path.starts_with("_id.") {
+                return Err(validation_error(
+                    "The _id of a document cannot be updated".to_string(),
+                ));
+            }
+            if let Some(other) = paths[i + 1..].iter().find(|other| overlaps(path, other)) {
+                return Err(validation_error(format!(
+                    "Updating '{}' would conflict with updating '{}'",
+                    path, other
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether `a` and `b` are the same path or one lies inside the other.
+fn overlaps(a: &str, b: &str) -> bool {
+    let (shorter, longer) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    longer == shorter || longer.starts_with(&format!("{}.", shorter))
+}
+
+impl Operation {
+    fn parse(operator: &str, path: &str, operand: &Json) -> Result<Self, DatabaseError> {
+        if path.is_empty()
+            || path
+                .split('.')
+                .any(|key| key.is_empty() || key.starts_with('$'))
+        {
+            return Err(validation_error(format!("Invalid field path '{}'", path)));
+        }
+        let path = path.to_string();
+        let value = || operand_value(operand);
+        let number = || match value()? {
+            number if number.is_number() => Ok(number),
+            _ => Err(validation_error(format!(
+                "{} needs a number for '{}', got {}",
+                operator, path, operand
+            ))),
+        };
+
+        Ok(match operator {
+            "$set" => Operation::Set(path, value()?),
+            "$unset" => Operation::Unset(path),
+            "$inc" => Operation::Inc(path.clone(), number()?),
+            "$mul" => Operation::Mul(path.clone(), number()?),
+            "$push" => Operation::Push(path, each(operand)?),
+            "$addToSet" => Operation::AddToSet(path, each(operand)?),
+            "$pull" => match operand {
+                Json::Object(map) if map.keys().any(|key| key.starts_with('$')) => {
+                    let conditions = parse_conditions(operand)
+                        .map_err(|e| validation_error(format!("Invalid $pull condition: {}", e)))?;
+                    Operation::Pull(path, Pull::Matching(conditions))
+                }
+                _ => Operation::Pull(path, Pull::Equal(value()?)),
+            },
+            "$rename" => match operand {
+                Json::String(to) if !to.is_empty() && to.split('.').all(|key| !key.is_empty()) => {
+                    Operation::Rename(path, to.clone())
+                }
+                _ => {
+                    return Err(validation_error(format!(
+                        "$rename of '{}' needs a field path, got {}",
+                        path, operand
+                    )));
+                }
+            },
+            _ => {
+                return Err(validation_error(format!(
+                    "Unknown update operator {}",
+                    operator
+                )));
+            }
+        })
+    }
+
+    /// The paths the operation writes.
+    fn paths(&self) -> Vec<&str> {
+        match self {
+            Operation::Set(path, _)
+            | Operation::Unset(path)
+            | Operation::Inc(path, _)
+            | Operation::Mul(path, _)
+            | Operation::Push(path, _)
+            | Operation::AddToSet(path, _)
+            | Operation::Pull(path, _) => vec![path],
+            Operation::Rename(from, to) => vec![from, to],
+        }
+    }
+
+    fn apply(&self, document: &mut Document) -> Result<(), DatabaseError> {
+        match self {
+            Operation::Set(path, value) => set(document, path, value.clone()),
+            Operation::Unset(path) => {
+                document.remove_path(path);
+                Ok(())
+            }
+            Operation::Inc(path, operand) => arithmetic(document, path, operand, "$inc", add),
+            Operation::Mul(path, operand) => arithmetic(document, path, operand, "$mul", multiply),
+            Operation::Push(path, values) => {
+                let mut items = array(document, path, "$push")?;
+                items.extend(values.iter().cloned());
+                set(document, path, Value::Array(items))
+            }
+            Operation::AddToSet(path, values) => {
+                let mut items = array(document, path, "$addToSet")?;
+                for value in values {
+                    if !items.iter().any(|item| equal(item, value)) {
+                        items.push(value.clone());
+                    }
+                }
+                set(document, path, Value::Array(items))
+            }
+            Operation::Pull(path, pull) => {
+                if document.get_path(path).is_none() {
+                    return Ok(());
+                }
+                let mut items = array(document, path, "$pull")?;
+                items.retain(|item| match pull {
+                    Pull::Equal(value) => !equal(item, value),
+                    Pull::Matching(conditions) => !conditions
+                        .iter()
+                        .all(|condition| condition.matches(Some(item))),
+                });
+                set(document, path, Value::Array(items))
+            }
+            Operation::Rename(from, to) => match document.get_path(from).cloned() {
+                Some(value) => {
+                    set(document, to, value)?;
+                    document.remove_path(from);
+                    Ok(())
+                }
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+/// The values `$push` or `$addToSet` adds: the operand, or each of `{"$each": [...]}`.
+fn each(operand: &Json) -> Result<Vec<Value>, DatabaseError> {
+    match operand {
+        Json::Object(map) if map.len() == 1 => match map.get("$each") {
+            Some(Json::Array(items)) => items.iter().map(operand_value).collect(),
+            _ => Ok(vec![operand_value(operand)?]),
+        },
+        _ => Ok(vec![operand_value(operand)?]),
+    }
+}
+
+/// An operand as a `Value`, recognising `{"$oid": ...}` and `{"$date": ...}`
+/// the way filters do.
+fn operand_value(operand: &Json) -> Result<Value, DatabaseError> {
+    literal(operand).map_err(|e| validation_error(format!("Invalid operand {}: {}", operand, e)))
+}
+
+fn set(document: &mut Document, path: &str, value: Value) -> Result<(), DatabaseError> {
+    match document.set_path(path, value) {
+        true => Ok(()),
+        false => Err(validation_error(format!(
+            "Cannot set '{}': part of the path is not an object",
+            path
+        ))),
+    }
+}
+
+/// Apply `$inc` or `$mul` to the number at `path`, a missing field counting as zero.
+fn arithmetic(
+    document: &mut Document,
+    path: &str,
+    operand: &Value,
+    operator: &str,
+    apply: fn(&Value, &Value) -> Option<Value>,
+) -> Result<(), DatabaseError> {
+    // Zero times the operand also gives `$mul` of a missing field the operand's type
+    let current = document.get_path(path).cloned().unwrap_or(Value::I32(0));
+    match apply(&current, operand) {
+        Some(result) => set(document, path, result),
+        None => Err(validation_error(format!(
+            "{} needs '{}' to be a number, got {}",
+            operator, path, current
+        ))),
+    }
+}
+
+/// The array at `path` (empty if missing), for an operator to change.
+fn array(document: &Document, path: &str, operator: &str) -> Result<Vec<Value>, DatabaseError> {
+    match document.get_path(path) {
+        None => Ok(Vec::new()),
+        Some(Value::Array(items)) => Ok(items.clone()),
+        Some(other) => Err(validation_error(format!(
+            "{} needs '{}' to be an array, got {}",
+            operator, path, other
+        ))),
+    }
+}
+
+fn validation_error(message: String) -> DatabaseError {
+    DatabaseError::Validation(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn person() -> Document {
+        Document::from_json(
+            r#"{"name": "Ada", "age": 36, "score": 1.5, "tags": ["a", "b", "a"],
+                "nums": [1, 5, 9], "address": {"city": "London"}}"#,
+        )
+        .unwrap()
+    }
+
+    fn updated(update: &str) -> Document {
+        let mut document = person();
+        Update::parse(update).unwrap().apply(&mut document).unwrap();
+        document
+    }
+
+    fn strings(values: &[&str]) -> Value {
+        Value::Array(
+            values
+                .iter()
+                .map(|s| Value::String(s.to_string()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_set_unset_rename() {
+        let document = updated(
+            r#"{"$set": {"address.zip": "N1", "new.deep": true}, "$unset": {"score": 1},
+                "$rename": {"name": "names.first"}}"#,
+        );
+        assert_eq!(
+            document.get_path("address.city"),
+            Some(&Value::String("London".to_string()))
+        );
+        assert_eq!(
+            document.get_path("address.zip"),
+            Some(&Value::String("N1".to_string()))
+        );
+        assert_eq!(document.get_path("new.deep"), Some(&Value::Bool(true)));
+        assert_eq!(document.get("score"), None);
+        assert_eq!(document.get("name"), None);
+        assert_eq!(
+            document.get_path("names.first"),
+            Some(&Value::String("Ada".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_inc_and_mul_promote() {
+        let document =
+            updated(r#"{"$inc": {"age": 1, "score": 1, "visits": 2}, "$mul": {"missing": 2.0}}"#);
+        assert_eq!(document.get("age"), Some(&Value::I32(37)));
+        assert_eq!(document.get("score"), Some(&Value::F64(2.5)));
+        assert_eq!(document.get("visits"), Some(&Value::I32(2)));
+        assert_eq!(document.get("missing"), Some(&Value::F64(0.0)));
+
+        let document = updated(r#"{"$mul": {"age": 3000000000}}"#);
+        assert_eq!(document.get("age"), Some(&Value::I64(108_000_000_000)));
+    }
+
+    #[test]
+    fn test_array_operators() {
+        let document = updated(
+            r#"{"$push": {"tags": "c", "fresh": {"$each": [1, 2]}}, "$pull": {"nums": {"$gte": 5}},
+                "$addToSet": {"other": {"$each": ["x", "x", "y"]}}}"#,
+        );
+        assert_eq!(document.get("tags"), Some(&strings(&["a", "b", "a", "c"])));
+        assert_eq!(
+            document.get("fresh"),
+            Some(&Value::Array(vec![Value::I32(1), Value::I32(2)]))
+        );
+        assert_eq!(
+            document.get("nums"),
+            Some(&Value::Array(vec![Value::I32(1)]))
+        );
+        assert_eq!(document.get("other"), Some(&strings(&["x", "y"])));
+
+        let document = updated(r#"{"$pull": {"tags": "a"}, "$addToSet": {"nums": 5.0}}"#);
+        assert_eq!(document.get("tags"), Some(&strings(&["b"])));
+        assert_eq!(
+            document.get("nums"),
+            Some(&Value::Array(vec![
+                Value::I32(1),
+                Value::I32(5),
+                Value::I32(9)
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_extended_json_operands() {
+        let document = updated(r#"{"$set": {"at": {"$date": "2024-01-01T00:00:00Z"}}}"#);
+        assert_eq!(
+            document.get("at"),
+            Some(&Value::DateTime("2024-01-01T00:00:00Z".parse().unwrap()))
+        );
+
+        let id = ObjectId::new();
+        let push = format!(r#"{{"$push": {{"refs": {{"$oid": "{}"}}}}}}"#, id.to_hex());
+        let mut document = updated(&push);
+        assert_eq!(
+            document.get("refs"),
+            Some(&Value::Array(vec![Value::ObjectId(id.clone())]))
+        );
+        let pull = format!(r#"{{"$pull": {{"refs": {{"$oid": "{}"}}}}}}"#, id.to_hex());
+        Update::parse(&pull).unwrap().apply(&mut document).unwrap();
+        assert_eq!(document.get("refs"), Some(&Value::Array(Vec::new())));
+
+        assert!(Update::parse(r#"{"$set": {"at": {"$date": "yesterday"}}}"#).is_err());
+    }
+
+    #[test]
+    fn test_failed_update_changes_nothing() {
+        let mut document = person();
+        for update in [
+            r#"{"$inc": {"age": 1, "name": 1}}"#,
+            r#"{"$push": {"age": 1}}"#,
+            r#"{"$set": {"age.years": 1}}"#,
+        ] {
+            let result = Update::parse(update).unwrap().apply(&mut document);
+            assert!(
+                matches!(result, Err(DatabaseError::Validation(_))),
+                "{}",
+                update
+            );
+            assert_eq!(document, person_with_id(&document));
+        }
+    }
+
+    fn person_with_id(document: &Document) -> Document {
+        let mut expected = person();
+        expected.set_id_value(document.id().clone());
+        expected
+    }
+
+    #[test]
+    fn test_invalid_updates_are_rejected() {
+        for update in [
+            r#"{}"#,
+            r#"{"name": "Bob"}"#,
+            r#"{"$set": 1}"#,
+            r#"{"$inc": {"age": "1"}}"#,
+            r#"{"$set": {"_id": 1}}"#,
+            r#"{"$set": {"a": 1}, "$unset": {"a": 1}}"#,
+            r#"{"$set": {"a.b": 1, "a": 2}}"#,
+            r#"{"$rename": {"a": 1}}"#,
+            r#"{"$pull": {"a": {"$bogus": 1}}}"#,
+            r#"{"$max": {"a": 1}}"#,
+            r#"{"$set": {"a..b": 1}}"#,
+        ] {
+            assert!(
+                matches!(Update::parse(update), Err(DatabaseError::Validation(_))),
+                "{} should be rejected",
+                update
+            );
+        }
+    }
+}
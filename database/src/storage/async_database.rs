@@ -0,0 +1,174 @@
+// An async facade over `Database` for use inside tokio services.
+//
+// Every call runs the blocking engine work (page reads, writes, fsyncs) on
+// tokio's blocking thread pool with `spawn_blocking`, so the async worker
+// threads never wait on disk. The handle is cheap to clone; clones share one
+// `Database`.
+//
+// A scan is streamed one data page at a time: the blocking task reads a page's
+// documents under the shared latch, releases it and hands them to the stream
+// through a bounded channel, so writers are not held off for the whole scan and
+// a slow consumer does not buffer the whole collection. The scan reads through a
+// snapshot taken when it starts, so writes committed while it runs are not seen.
+
+use crate::{
+    document::{object_id::ObjectId, Document},
+    storage::{
+        collection::CollectionStats,
+        database::Database,
+        storage_engine::DocumentId,
+    },
+};
+use anyhow::Result;
+use futures_core::Stream;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+// Documents a scan may read ahead of its consumer
+const SCAN_BUFFER: usize = 64;
+
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    database: Arc<Database>,
+}
+
+impl AsyncDatabase {
+    /// Open (or create) the database at `path` with a buffer pool of `pool_size` pages.
+    pub async fn open(path: impl Into<PathBuf>, pool_size: usize) -> Result<Self> {
+        let path = path.into();
+        let database = tokio::task::spawn_blocking(move || Database::open(&path, pool_size)).await??;
+        Ok(Self::from_database(Arc::new(database)))
+    }
+
+    pub fn from_database(database: Arc<Database>) -> Self {
+        Self { database }
+    }
+
+    /// The shared blocking handle, for calls that have no async counterpart.
+    pub fn database(&self) -> &Arc<Database> {
+        &self.database
+    }
+
+    pub async fn create_collection(&self, name: &str) -> Result<()> {
+        let name = name.to_string();
+        self.run(move |database| database.create_collection(&name)).await
+    }
+
+    pub async fn collection_stats(&self, collection: &str) -> Result<CollectionStats> {
+        let collection = collection.to_string();
+        self.run(move |database| database.collection_stats(&collection)).await
+    }
+
+    pub async fn insert(&self, collection: &str, document: Document) -> Result<DocumentId> {
+        let collection = collection.to_string();
+        self.run(move |database| database.insert_document(&collection, &document))
+            .await
+    }
+
+    /// Fetch the document whose `_id` is `id`.
+    pub async fn get(&self, collection: &str, id: &ObjectId) -> Result<Option<Document>> {
+        let (collection, id) = (collection.to_string(), id.clone());
+        self.run(move |database| database.get_by_id(&collection, &id)).await
+    }
+
+    /// Replace the document whose `_id` is `id`.
+    pub async fn update(
+        &self,
+        collection: &str,
+        id: &ObjectId,
+        document: Document,
+    ) -> Result<DocumentId> {
+        let (collection, id) = (collection.to_string(), id.clone());
+        self.run(move |database| database.update_by_id(&collection, &id, &document))
+            .await
+    }
+
+    /// Delete the document whose `_id` is `id`. Returns false if there was none.
+    pub async fn delete(&self, collection: &str, id: &ObjectId) -> Result<bool> {
+        let (collection, id) = (collection.to_string(), id.clone());
+        self.run(move |database| database.delete_by_id(&collection, &id)).await
+    }
+
+    /// Stream every live document in `collection`. Must be called from within a
+    /// tokio runtime. Dropping the stream stops the scan.
+    pub fn scan(&self, collection: &str) -> DocumentStream {
+        let (sender, receiver) = mpsc::channel(SCAN_BUFFER);
+        let database = Arc::clone(&self.database);
+        let collection = collection.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            let started = database.read(|engine| {
+                Ok((engine.snapshot(), engine.collection_page_ids(&collection)?))
+            });
+            let (snapshot, page_ids) = match started {
+                Ok(started) => started,
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(e));
+                    return;
+                }
+            };
+
+            let mut seen = HashSet::new();
+            for page_id in page_ids {
+                let documents =
+                    database.read(|engine| engine.scan_page_at(page_id, &snapshot, &mut seen));
+                if !send_all(&sender, documents) {
+                    return;
+                }
+            }
+            // Documents changed since the snapshot are read from their old versions
+            let documents =
+                database.read(|engine| engine.scan_changed_at(&collection, &snapshot, &seen));
+            send_all(&sender, documents);
+        });
+
+        DocumentStream { receiver }
+    }
+
+    pub async fn flush(&self) -> Result<()> {
+        self.run(|database| database.flush()).await
+    }
+
+    async fn run<T: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&Database) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let database = Arc::clone(&self.database);
+        tokio::task::spawn_blocking(move || operation(&database)).await?
+    }
+}
+
+/// Hand a batch of scanned documents to the stream. Returns false once the
+/// consumer has hung up or an error has been reported.
+fn send_all(
+    sender: &mpsc::Sender<Result<(DocumentId, Document)>>,
+    documents: Result<Vec<(DocumentId, Document)>>,
+) -> bool {
+    match documents {
+        Ok(documents) => documents
+            .into_iter()
+            .all(|document| sender.blocking_send(Ok(document)).is_ok()),
+        Err(e) => {
+            let _ = sender.blocking_send(Err(e));
+            false
+        }
+    }
+}
+
+/// Documents of a collection as they are read, returned by [`AsyncDatabase::scan`].
+/// Ends after the first error.
+pub struct DocumentStream {
+    receiver: mpsc::Receiver<Result<(DocumentId, Document)>>,
+}
+
+impl Stream for DocumentStream {
+    type Item = Result<(DocumentId, Document)>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
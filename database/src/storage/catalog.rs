@@ -0,0 +1,690 @@
+// The collection catalog: which collections exist and which pages each one
+// owns.
+//
+// The catalog is a bincode-encoded list of `(name, primary index root,
+// directory head)` records kept in a chain of `PageType::Metadata` pages whose
+// head is registered as `RootPage::Catalog` in the file header. It is
+// followed by a list of `(collection, root, definition, multikey paths)`
+// records for the secondary indexes, absent in files written before they
+// existed. Definitions are stored as JSON so that options added to them later
+// can take defaults when read back. Each collection's directory is its own
+// Metadata page chain listing the data pages it owns (little-endian u64 page
+// ids, ascending), so scans, stats and drops only ever touch that collection's
+// pages.
+//
+// All catalog pages are written through the buffer pool, so catalog changes are
+// logged and rolled back together with the document writes that caused them.
+// The in-memory copy is rebuilt with `reload` after a rollback.
+//
+// Collections are held behind `Arc`s so a writer working alongside others can
+// clone the catalog cheaply and, when it commits, `rebase` the collections it
+// changed onto whatever the others committed in the meantime.
+
+use crate::error::DatabaseError;
+use crate::index::btree::BTree;
+use crate::index::primary::PrimaryIndex;
+use crate::index::secondary::{IndexDefinition, IndexKind, SecondaryIndex};
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::file::DatabaseFile;
+use crate::storage::page::PageType;
+use crate::storage::page_chain::PageChain;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+/// The collection used by the engine's collection-less methods. It always exists
+/// and cannot be dropped.
+pub const DEFAULT_COLLECTION: &str = "default";
+
+/// Longest collection name accepted, in bytes.
+pub const MAX_COLLECTION_NAME_LEN: usize = 120;
+
+#[derive(Serialize, Deserialize)]
+struct CatalogRecord {
+    name: String,
+    primary_root: u64,
+    directory_head: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IndexRecord {
+    collection: String,
+    root: u64,
+    definition: String,
+    multikey_paths: Vec<String>,
+}
+
+/// Everything the engine needs to know about one collection.
+#[derive(Debug, Clone)]
+pub struct CollectionInfo {
+    primary_index: PrimaryIndex,
+    directory: PageChain,
+    pages: BTreeSet<u64>,
+    indexes: Vec<SecondaryIndex>,
+}
+
+impl CollectionInfo {
+    pub fn primary_index(&self) -> PrimaryIndex {
+        self.primary_index
+    }
+
+    /// Data pages owned by the collection, in page-id order.
+    pub fn pages(&self) -> impl Iterator<Item = u64> + '_ {
+        self.pages.iter().copied()
+    }
+
+    pub fn owns_page(&self, page_id: u64) -> bool {
+        self.pages.contains(&page_id)
+    }
+
+    /// The page chain listing the collection's data pages.
+    pub fn directory(&self) -> PageChain {
+        self.directory
+    }
+
+    /// The collection's secondary indexes, in creation order.
+    pub fn indexes(&self) -> &[SecondaryIndex] {
+        &self.indexes
+    }
+
+    /// The collection's text index, if it has one. It cannot have more.
+    pub fn text_index(&self) -> Option<&SecondaryIndex> {
+        self.indexes
+            .iter()
+            .find(|index| index.definition().kind() == IndexKind::Text)
+    }
+
+    /// The collection's geospatial index on `path`, if it has one.
+    pub fn geo_index(&self, path: &str) -> Option<&SecondaryIndex> {
+        self.indexes.iter().find(|index| {
+            let definition = index.definition();
+            definition.kind() == IndexKind::Geo && definition.keys()[0].0 == path
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct Catalog {
+    chain: PageChain,
+    collections: BTreeMap<String, Arc<CollectionInfo>>,
+    // Bumped each time the catalog's own pages are rewritten
+    revision: u64,
+}
+
+impl Catalog {
+    /// Allocate an empty catalog.
+    pub fn create(
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+    ) -> Result<Self, DatabaseError> {
+        let chain = PageChain::create(buffer_pool, database_file, PageType::Metadata)?;
+        let mut catalog = Self {
+            chain,
+            collections: BTreeMap::new(),
+            revision: 0,
+        };
+        catalog.save(buffer_pool, database_file)?;
+        Ok(catalog)
+    }
+
+    /// Load the catalog whose chain starts at `head_page_id`.
+    pub fn load(
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        head_page_id: u64,
+    ) -> Result<Self, DatabaseError> {
+        let mut catalog = Self {
+            chain: PageChain::open(head_page_id),
+            collections: BTreeMap::new(),
+            revision: 0,
+        };
+        catalog.reload(buffer_pool, database_file)?;
+        Ok(catalog)
+    }
+
+    pub fn head_page_id(&self) -> u64 {
+        self.chain.head_page_id()
+    }
+
+    /// Re-read the catalog from its pages, discarding the in-memory copy.
+    pub fn reload(
+        &mut self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+    ) -> Result<(), DatabaseError> {
+        let bytes = self.chain.read(buffer_pool, database_file)?;
+        let mut reader = bytes.as_slice();
+        let records: Vec<CatalogRecord> =
+            bincode::deserialize_from(&mut reader).map_err(DatabaseError::Bincode)?;
+        let index_records: Vec<IndexRecord> = if reader.is_empty() {
+            Vec::new()
+        } else {
+            bincode::deserialize_from(&mut reader).map_err(DatabaseError::Bincode)?
+        };
+
+        self.collections.clear();
+        for record in records {
+            let directory = PageChain::open(record.directory_head);
+            let pages = directory
+                .read(buffer_pool, database_file)?
+                .chunks_exact(8)
+                .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                .collect();
+            self.collections.insert(
+                record.name,
+                Arc::new(CollectionInfo {
+                    primary_index: PrimaryIndex::new(BTree::open(record.primary_root)),
+                    directory,
+                    pages,
+                    indexes: Vec::new(),
+                }),
+            );
+        }
+        for record in index_records {
+            let definition: IndexDefinition =
+                serde_json::from_str(&record.definition).map_err(DatabaseError::Json)?;
+            let info = self
+                .collections
+                .get_mut(&record.collection)
+                .map(Arc::make_mut)
+                .ok_or_else(|| {
+                    DatabaseError::Storage(format!(
+                        "Index '{}' belongs to unknown collection '{}'",
+                        definition.name(),
+                        record.collection
+                    ))
+                })?;
+            let mut index = SecondaryIndex::new(definition, BTree::open(record.root));
+            for path in &record.multikey_paths {
+                index.set_multikey(path);
+            }
+            info.indexes.push(index);
+        }
+        Ok(())
+    }
+
+    /// Names of all collections, sorted.
+    pub fn names(&self) -> Vec<String> {
+        self.collections.keys().cloned().collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.collections.contains_key(name)
+    }
+
+    pub fn get(&self, name: &str) -> Result<&CollectionInfo, DatabaseError> {
+        self.collections
+            .get(name)
+            .map(Arc::as_ref)
+            .ok_or_else(|| DatabaseError::Query(format!("No collection named '{}'", name)))
+    }
+
+    /// Register a new, empty collection with its own primary index and directory.
+    pub fn create_collection(
+        &mut self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        name: &str,
+    ) -> Result<(), DatabaseError> {
+        Self::validate_name(name)?;
+        if self.contains(name) {
+            return Err(DatabaseError::Validation(format!(
+                "Collection '{}' already exists",
+                name
+            )));
+        }
+        let primary_index = PrimaryIndex::new(BTree::create(buffer_pool, database_file)?);
+        self.adopt_collection(buffer_pool, database_file, name, primary_index, Vec::new())
+    }
+
+    /// Register a collection made of pages that already exist, such as the data
+    /// written by files that predate the catalog.
+    pub fn adopt_collection(
+        &mut self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        name: &str,
+        primary_index: PrimaryIndex,
+        pages: Vec<u64>,
+    ) -> Result<(), DatabaseError> {
+        let directory = PageChain::create(buffer_pool, database_file, PageType::Metadata)?;
+        let info = CollectionInfo {
+            primary_index,
+            directory,
+            pages: pages.into_iter().collect(),
+            indexes: Vec::new(),
+        };
+        Self::save_directory(buffer_pool, database_file, &info)?;
+        self.collections.insert(name.to_string(), Arc::new(info));
+        self.save(buffer_pool, database_file)
+    }
+
+    /// Remove a collection from the catalog, returning what it owned.
+    pub fn drop_collection(
+        &mut self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        name: &str,
+    ) -> Result<Option<CollectionInfo>, DatabaseError> {
+        let removed = self.collections.remove(name);
+        if removed.is_some() {
+            self.save(buffer_pool, database_file)?;
+        }
+        Ok(removed.map(Arc::unwrap_or_clone))
+    }
+
+    /// Record that `page_id` now holds documents of collection `name`.
+    pub fn add_page(
+        &mut self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        name: &str,
+        page_id: u64,
+    ) -> Result<(), DatabaseError> {
+        let info = self
+            .collections
+            .get_mut(name)
+            .map(Arc::make_mut)
+            .ok_or_else(|| DatabaseError::Query(format!("No collection named '{}'", name)))?;
+        if info.pages.insert(page_id) {
+            Self::save_directory(buffer_pool, database_file, info)?;
+        }
+        Ok(())
+    }
+
+    /// Record that `page_id` no longer belongs to collection `name`.
+    pub fn remove_page(
+        &mut self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        name: &str,
+        page_id: u64,
+    ) -> Result<(), DatabaseError> {
+        let info = self
+            .collections
+            .get_mut(name)
+            .map(Arc::make_mut)
+            .ok_or_else(|| DatabaseError::Query(format!("No collection named '{}'", name)))?;
+        if info.pages.remove(&page_id) {
+            Self::save_directory(buffer_pool, database_file, info)?;
+        }
+        Ok(())
+    }
+
+    /// Register `index` as a secondary index of collection `name`.
+    pub fn add_index(
+        &mut self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        name: &str,
+        index: SecondaryIndex,
+    ) -> Result<(), DatabaseError> {
+        let info = self
+            .collections
+            .get_mut(name)
+            .map(Arc::make_mut)
+            .ok_or_else(|| DatabaseError::Query(format!("No collection named '{}'", name)))?;
+        if info
+            .indexes
+            .iter()
+            .any(|existing| existing.name() == index.name())
+        {
+            return Err(DatabaseError::Validation(format!(
+                "Index '{}' already exists on collection '{}'",
+                index.name(),
+                name
+            )));
+        }
+        info.indexes.push(index);
+        self.save(buffer_pool, database_file)
+    }
+
+    /// Remove the index called `index_name` from collection `name`, returning it.
+    pub fn remove_index(
+        &mut self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        name: &str,
+        index_name: &str,
+    ) -> Result<Option<SecondaryIndex>, DatabaseError> {
+        let info = self
+            .collections
+            .get_mut(name)
+            .map(Arc::make_mut)
+            .ok_or_else(|| DatabaseError::Query(format!("No collection named '{}'", name)))?;
+        let Some(position) = info
+            .indexes
+            .iter()
+            .position(|index| index.name() == index_name)
+        else {
+            return Ok(None);
+        };
+        let removed = info.indexes.remove(position);
+        self.save(buffer_pool, database_file)?;
+        Ok(Some(removed))
+    }
+
+    /// Record that index `index_name` of collection `name` has indexed an array
+    /// at `path`, which limits the sort orders it provides.
+    pub fn mark_multikey(
+        &mut self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        name: &str,
+        index_name: &str,
+        path: &str,
+    ) -> Result<(), DatabaseError> {
+        let index = self
+            .collections
+            .get_mut(name)
+            .map(Arc::make_mut)
+            .and_then(|info| {
+                info.indexes
+                    .iter_mut()
+                    .find(|index| index.name() == index_name)
+            })
+            .ok_or_else(|| {
+                DatabaseError::Query(format!(
+                    "No index named '{}' on collection '{}'",
+                    index_name, name
+                ))
+            })?;
+        if index.multikey_paths().contains(path) {
+            return Ok(());
+        }
+        index.set_multikey(path);
+        self.save(buffer_pool, database_file)
+    }
+
+    /// Apply the collection changes `changed` made since it was cloned from
+    /// `base` on top of this catalog. None if this catalog has changed any of
+    /// those collections since `base` too, or if both rewrote the catalog's
+    /// pages, in which case the changes have to be made again from this one.
+    pub fn rebase(&self, base: &Catalog, changed: &Catalog) -> Option<Catalog> {
+        fn same(a: Option<&Arc<CollectionInfo>>, b: Option<&Arc<CollectionInfo>>) -> bool {
+            match (a, b) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (a, b) => a.is_none() && b.is_none(),
+            }
+        }
+
+        let mut rebased = self.clone();
+        if changed.revision != base.revision {
+            if self.revision != base.revision {
+                return None;
+            }
+            rebased.revision = changed.revision;
+        }
+        let names: BTreeSet<&String> = base
+            .collections
+            .keys()
+            .chain(changed.collections.keys())
+            .collect();
+        for name in names {
+            let before = base.collections.get(name);
+            let after = changed.collections.get(name);
+            if same(before, after) {
+                continue;
+            }
+            if !same(before, self.collections.get(name)) {
+                return None;
+            }
+            match after {
+                Some(info) => rebased.collections.insert(name.clone(), Arc::clone(info)),
+                None => rebased.collections.remove(name),
+            };
+        }
+        Some(rebased)
+    }
+
+    fn validate_name(name: &str) -> Result<(), DatabaseError> {
+        if name.is_empty() {
+            return Err(DatabaseError::Validation(
+                "Collection name cannot be empty".to_string(),
+            ));
+        }
+        if name.len() > MAX_COLLECTION_NAME_LEN {
+            return Err(DatabaseError::Validation(format!(
+                "Collection name exceeds {} bytes",
+                MAX_COLLECTION_NAME_LEN
+            )));
+        }
+        if name.contains('\0') {
+            return Err(DatabaseError::Validation(
+                "Collection name cannot contain null characters".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn save(
+        &mut self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+    ) -> Result<(), DatabaseError> {
+        let records: Vec<CatalogRecord> = self
+            .collections
+            .iter()
+            .map(|(name, info)| CatalogRecord {
+                name: name.clone(),
+                primary_root: info.primary_index.root_page_id(),
+                directory_head: info.directory.head_page_id(),
+            })
+            .collect();
+        let mut index_records = Vec::new();
+        for (name, info) in &self.collections {
+            for index in &info.indexes {
+                index_records.push(IndexRecord {
+                    collection: name.clone(),
+                    root: index.root_page_id(),
+                    definition: serde_json::to_string(index.definition())
+                        .map_err(DatabaseError::Json)?,
+                    multikey_paths: index.multikey_paths().iter().cloned().collect(),
+                });
+            }
+        }
+        let mut bytes = bincode::serialize(&records).map_err(DatabaseError::Bincode)?;
+        bytes.extend(bincode::serialize(&index_records).map_err(DatabaseError::Bincode)?);
+        self.chain.write(buffer_pool, database_file, &bytes)?;
+        self.revision += 1;
+        Ok(())
+    }
+
+    fn save_directory(
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        info: &CollectionInfo,
+    ) -> Result<(), DatabaseError> {
+        let bytes: Vec<u8> = info.pages.iter().flat_map(|id| id.to_le_bytes()).collect();
+        info.directory.write(buffer_pool, database_file, &bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_roundtrip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut database_file = DatabaseFile::create(&temp_dir.path().join("test.db")).unwrap();
+        let mut buffer_pool = BufferPool::new(8);
+
+        let mut catalog = Catalog::create(&mut buffer_pool, &mut database_file).unwrap();
+        catalog
+            .create_collection(&mut buffer_pool, &mut database_file, "users")
+            .unwrap();
+        catalog
+            .create_collection(&mut buffer_pool, &mut database_file, "orders")
+            .unwrap();
+        catalog
+            .add_page(&mut buffer_pool, &mut database_file, "users", 42)
+            .unwrap();
+        assert!(
+            catalog
+                .create_collection(&mut buffer_pool, &mut database_file, "users")
+                .is_err()
+        );
+        assert!(
+            catalog
+                .create_collection(&mut buffer_pool, &mut database_file, "")
+                .is_err()
+        );
+
+        let loaded =
+            Catalog::load(&mut buffer_pool, &mut database_file, catalog.head_page_id()).unwrap();
+        assert_eq!(
+            loaded.names(),
+            vec!["orders".to_string(), "users".to_string()]
+        );
+        let users = loaded.get("users").unwrap();
+        assert_eq!(users.pages().collect::<Vec<_>>(), vec![42]);
+        assert_eq!(
+            users.primary_index().root_page_id(),
+            catalog.get("users").unwrap().primary_index().root_page_id()
+        );
+        assert!(loaded.get("missing").is_err());
+    }
+
+    #[test]
+    fn test_indexes_roundtrip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut database_file = DatabaseFile::create(&temp_dir.path().join("test.db")).unwrap();
+        let mut buffer_pool = BufferPool::new(8);
+
+        let mut catalog = Catalog::create(&mut buffer_pool, &mut database_file).unwrap();
+        catalog
+            .create_collection(&mut buffer_pool, &mut database_file, "users")
+            .unwrap();
+        let index = SecondaryIndex::new(
+            IndexDefinition::new("address.city"),
+            BTree::create(&mut buffer_pool, &mut database_file).unwrap(),
+        );
+        catalog
+            .add_index(&mut buffer_pool, &mut database_file, "users", index.clone())
+            .unwrap();
+        assert!(
+            catalog
+                .add_index(&mut buffer_pool, &mut database_file, "users", index.clone())
+                .is_err()
+        );
+
+        let mut loaded =
+            Catalog::load(&mut buffer_pool, &mut database_file, catalog.head_page_id()).unwrap();
+        let indexes = loaded.get("users").unwrap().indexes();
+        assert_eq!(indexes.len(), 1);
+        assert_eq!(indexes[0].definition(), index.definition());
+        assert_eq!(indexes[0].root_page_id(), index.root_page_id());
+        assert!(!indexes[0].is_multikey());
+
+        loaded
+            .mark_multikey(
+                &mut buffer_pool,
+                &mut database_file,
+                "users",
+                "address.city_1",
+                "address.city",
+            )
+            .unwrap();
+        let reloaded =
+            Catalog::load(&mut buffer_pool, &mut database_file, catalog.head_page_id()).unwrap();
+        let index = &reloaded.get("users").unwrap().indexes()[0];
+        assert!(index.is_multikey());
+        assert!(index.multikey_paths().contains("address.city"));
+
+        let removed = loaded
+            .remove_index(
+                &mut buffer_pool,
+                &mut database_file,
+                "users",
+                "address.city_1",
+            )
+            .unwrap();
+        assert_eq!(
+            removed.map(|index| index.root_page_id()),
+            Some(index.root_page_id())
+        );
+        catalog
+            .reload(&mut buffer_pool, &mut database_file)
+            .unwrap();
+        assert!(catalog.get("users").unwrap().indexes().is_empty());
+    }
+
+    #[test]
+    fn test_rebase_merges_changes_to_other_collections() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut database_file = DatabaseFile::create(&temp_dir.path().join("test.db")).unwrap();
+        let mut buffer_pool = BufferPool::new(8);
+
+        let mut base = Catalog::create(&mut buffer_pool, &mut database_file).unwrap();
+        base.create_collection(&mut buffer_pool, &mut database_file, "users")
+            .unwrap();
+        base.create_collection(&mut buffer_pool, &mut database_file, "orders")
+            .unwrap();
+
+        let mut committed = base.clone();
+        committed
+            .add_page(&mut buffer_pool, &mut database_file, "users", 42)
+            .unwrap();
+        let mut changed = base.clone();
+        changed
+            .add_page(&mut buffer_pool, &mut database_file, "orders", 43)
+            .unwrap();
+        let rebased = committed.rebase(&base, &changed).unwrap();
+        assert_eq!(
+            rebased.get("users").unwrap().pages().collect::<Vec<_>>(),
+            vec![42]
+        );
+        assert_eq!(
+            rebased.get("orders").unwrap().pages().collect::<Vec<_>>(),
+            vec![43]
+        );
+
+        // Both changed the same collection
+        let mut clashing = base.clone();
+        clashing
+            .add_page(&mut buffer_pool, &mut database_file, "users", 44)
+            .unwrap();
+        assert!(committed.rebase(&base, &clashing).is_none());
+
+        // Both rewrote the catalog's pages
+        let mut created = base.clone();
+        created
+            .create_collection(&mut buffer_pool, &mut database_file, "items")
+            .unwrap();
+        let mut dropped = base.clone();
+        dropped
+            .drop_collection(&mut buffer_pool, &mut database_file, "orders")
+            .unwrap();
+        assert!(dropped.rebase(&base, &created).is_none());
+    }
+
+    #[test]
+    fn test_catalog_without_index_records_loads() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut database_file = DatabaseFile::create(&temp_dir.path().join("test.db")).unwrap();
+        let mut buffer_pool = BufferPool::new(8);
+
+        // The layout written before secondary indexes existed
+        let chain =
+            PageChain::create(&mut buffer_pool, &mut database_file, PageType::Metadata).unwrap();
+        let directory =
+            PageChain::create(&mut buffer_pool, &mut database_file, PageType::Metadata).unwrap();
+        let records = vec![CatalogRecord {
+            name: DEFAULT_COLLECTION.to_string(),
+            primary_root: 0,
+            directory_head: directory.head_page_id(),
+        }];
+        chain
+            .write(
+                &mut buffer_pool,
+                &mut database_file,
+                &bincode::serialize(&records).unwrap(),
+            )
+            .unwrap();
+
+        let loaded =
+            Catalog::load(&mut buffer_pool, &mut database_file, chain.head_page_id()).unwrap();
+        assert!(loaded.get(DEFAULT_COLLECTION).unwrap().indexes().is_empty());
+    }
+}
@@ -0,0 +1,126 @@
+// A named collection of documents, obtained with `StorageEngine::collection`.
+//
+// The handle only remembers the collection's name; every call goes through the
+// engine so writes are logged, take part in any open transaction and keep the
+// collection's own primary index and page directory up to date.
+
+use crate::{
+    document::{object_id::ObjectId, Document},
+    index::secondary::IndexDefinition,
+    query::{
+        aggregate::{Documents, Pipeline},
+        cursor::Cursor,
+        filter::Filter,
+        update::{Selector, Update},
+    },
+    storage::storage_engine::{DocumentId, DocumentScan, StorageEngine},
+};
+use anyhow::Result;
+
+/// Document count and space usage of one collection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectionStats {
+    pub name: String,
+    pub document_count: usize,
+    /// Data pages owned by the collection.
+    pub page_count: usize,
+    /// Free bytes left across those pages.
+    pub free_space: usize,
+}
+
+pub struct Collection<'a> {
+    engine: &'a mut StorageEngine,
+    name: String,
+}
+
+impl<'a> Collection<'a> {
+    pub(crate) fn new(engine: &'a mut StorageEngine, name: &str) -> Self {
+        Self {
+            engine,
+            name: name.to_string(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn insert_document(&mut self, document: &Document) -> Result<DocumentId> {
+        self.engine.insert_document_in(&self.name, document)
+    }
+
+    pub fn get_document(&self, document_id: &DocumentId) -> Result<Document> {
+        self.engine.get_document_in(&self.name, document_id)
+    }
+
+    pub fn update_document(
+        &mut self,
+        document_id: &DocumentId,
+        new_document: &Document,
+    ) -> Result<DocumentId> {
+        self.engine
+            .update_document_in(&self.name, document_id, new_document)
+    }
+
+    pub fn delete_document(&mut self, document_id: &DocumentId) -> Result<()> {
+        self.engine.delete_document_in(&self.name, document_id)
+    }
+
+    pub fn locate(&self, id: &ObjectId) -> Result<Option<DocumentId>> {
+        self.engine.locate_in(&self.name, id)
+    }
+
+    pub fn get_by_id(&self, id: &ObjectId) -> Result<Option<Document>> {
+        self.engine.get_by_id_in(&self.name, id)
+    }
+
+    pub fn update_by_id(&mut self, id: &ObjectId, new_document: &Document) -> Result<DocumentId> {
+        self.engine.update_by_id_in(&self.name, id, new_document)
+    }
+
+    /// Apply `update` to the document with the given `_id`, or the first that
+    /// matches a filter.
+    pub fn update_one(
+        &mut self,
+        selector: impl Into<Selector>,
+        update: &Update,
+    ) -> Result<Option<DocumentId>> {
+        self.engine.update_one_in(&self.name, selector.into(), update)
+    }
+
+    pub fn delete_by_id(&mut self, id: &ObjectId) -> Result<bool> {
+        self.engine.delete_by_id_in(&self.name, id)
+    }
+
+    /// Scan every live document in this collection, in page order.
+    pub fn scan(&self) -> DocumentScan<'_> {
+        self.engine.scan_in(&self.name)
+    }
+
+    /// The documents in this collection that pass `filter`.
+    pub fn find(&self, filter: Filter) -> Cursor<'_> {
+        self.engine.find_in(&self.name, filter)
+    }
+
+    /// Run `pipeline` over the documents in this collection.
+    pub fn aggregate<'p>(&'p self, pipeline: &'p Pipeline) -> Documents<'p> {
+        self.engine.aggregate_in(&self.name, pipeline)
+    }
+
+    /// Index this collection's documents by `definition`.
+    pub fn create_index(&mut self, definition: IndexDefinition) -> Result<()> {
+        self.engine.create_index_in(&self.name, definition)
+    }
+
+    pub fn drop_index(&mut self, name: &str) -> Result<bool> {
+        self.engine.drop_index_in(&self.name, name)
+    }
+
+    pub fn list_indexes(&self) -> Result<Vec<IndexDefinition>> {
+        self.engine.list_indexes_in(&self.name)
+    }
+
+    pub fn stats(&mut self) -> Result<CollectionStats> {
+        self.engine.collection_stats(&self.name)
+    }
+}
@@ -0,0 +1,357 @@
+// A thread-safe handle to a storage engine, meant to be shared as `Arc<Database>`.
+//
+// Document writes run alongside each other and alongside reads. Each one runs on
+// its own writer (`StorageEngine::writer`), which latches the pages it changes
+// and works on private copies of them; pages latched by one writer are off
+// limits to the others until it commits, so writes to different pages, such
+// as writes to different collections, proceed at the same time. A commit
+// checks that nothing the writer read has been replaced since, logs its pages
+// and installs them all at once, so readers see each write either whole or not
+// at all. A write that loses a page to another writer is undone and run again,
+// and after a few attempts it takes the engine to itself instead.
+//
+// Reads run on a view of the last commit, underneath which the buffer pool
+// latches its frame table and each page frame while the file latches its I/O,
+// so any number of readers can load, evict and read pages at once. Creating or
+// dropping collections and indexes and flushing rewrite shared structures, so
+// they, and closures passed to `write`, have the engine to themselves. A
+// vacuum has it to itself one page at a time; a `VacuumScheduler` runs one in
+// the background, so that document writes never wait for a whole pass.
+//
+// A closure passed to `write` that panics may leave a change behind in memory,
+// so it poisons the handle: every later call fails with `DatabaseError::Storage`
+// instead.
+
+use crate::{
+    document::{Document, object_id::ObjectId},
+    error::DatabaseError,
+    index::secondary::IndexDefinition,
+    query::{
+        aggregate::Pipeline,
+        filter::Filter,
+        planner::Explain,
+        update::{Selector, Update},
+    },
+    storage::{
+        collection::CollectionStats,
+        mvcc::Snapshot,
+        storage_engine::{DocumentId, StorageEngine},
+        ttl::ExpiryReport,
+        vacuum::VacuumReport,
+    },
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::path::Path;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// How many times a document write that conflicts with other writers runs on
+/// a writer of its own before it takes the engine to itself.
+const MAX_WRITE_ATTEMPTS: usize = 8;
+
+pub struct Database {
+    engine: RwLock<StorageEngine>,
+}
+
+impl Database {
+    /// Open (or create) the database at `path` with a buffer pool of `pool_size` pages.
+    pub fn open(path: &Path, pool_size: usize) -> Result<Self> {
+        Ok(Self::from_engine(StorageEngine::new(path, pool_size)?))
+    }
+
+    pub fn from_engine(engine: StorageEngine) -> Self {
+        Self {
+            engine: RwLock::new(engine),
+        }
+    }
+
+    /// Run `read` against the last commit, alongside any other readers and
+    /// writers.
+    pub fn read<T>(&self, read: impl FnOnce(&StorageEngine) -> Result<T>) -> Result<T> {
+        self.read_guard()?.with_reader(read)
+    }
+
+    /// Run `write` with exclusive access to the engine, e.g. to use a transaction.
+    pub fn write<T>(&self, write: impl FnOnce(&mut StorageEngine) -> Result<T>) -> Result<T> {
+        write(&mut *self.exclusive()?)
+    }
+
+    pub fn create_collection(&self, name: &str) -> Result<()> {
+        self.exclusive()?.create_collection(name)
+    }
+
+    pub fn drop_collection(&self, name: &str) -> Result<bool> {
+        self.exclusive()?.drop_collection(name)
+    }
+
+    pub fn list_collections(&self) -> Result<Vec<String>> {
+        self.read(|engine| Ok(engine.list_collections()))
+    }
+
+    pub fn collection_stats(&self, collection: &str) -> Result<CollectionStats> {
+        self.read(|engine| engine.collection_stats(collection))
+    }
+
+    pub fn create_index(&self, collection: &str, definition: IndexDefinition) -> Result<()> {
+        self.exclusive()?.create_index_in(collection, definition)
+    }
+
+    pub fn drop_index(&self, collection: &str, name: &str) -> Result<bool> {
+        self.exclusive()?.drop_index_in(collection, name)
+    }
+
+    pub fn list_indexes(&self, collection: &str) -> Result<Vec<IndexDefinition>> {
+        self.read(|engine| engine.list_indexes_in(collection))
+    }
+
+    pub fn insert_document(&self, collection: &str, document: &Document) -> Result<DocumentId> {
+        self.write_concurrently(|engine| engine.collection(collection)?.insert_document(document))
+    }
+
+    pub fn get_document(&self, collection: &str, document_id: &DocumentId) -> Result<Document> {
+        self.read(|engine| engine.get_document_in(collection, document_id))
+    }
+
+    pub fn update_document(
+        &self,
+        collection: &str,
+        document_id: &DocumentId,
+        new_document: &Document,
+    ) -> Result<DocumentId> {
+        self.write_concurrently(|engine| {
+            engine
+                .collection(collection)?
+                .update_document(document_id, new_document)
+        })
+    }
+
+    pub fn delete_document(&self, collection: &str, document_id: &DocumentId) -> Result<()> {
+        self.write_concurrently(|engine| {
+            engine.collection(collection)?.delete_document(document_id)
+        })
+    }
+
+    pub fn locate(&self, collection: &str, id: &ObjectId) -> Result<Option<DocumentId>> {
+        self.read(|engine| engine.locate_in(collection, id))
+    }
+
+    pub fn get_by_id(&self, collection: &str, id: &ObjectId) -> Result<Option<Document>> {
+        self.read(|engine| engine.get_by_id_in(collection, id))
+    }
+
+    pub fn update_by_id(
+        &self,
+        collection: &str,
+        id: &ObjectId,
+        new_document: &Document,
+    ) -> Result<DocumentId> {
+        self.write_concurrently(|engine| {
+            engine
+                .collection(collection)?
+                .update_by_id(id, new_document)
+        })
+    }
+
+    pub fn update_one(
+        &self,
+        collection: &str,
+        selector: impl Into<Selector>,
+        update: &Update,
+    ) -> Result<Option<DocumentId>> {
+        let selector = selector.into();
+        self.write_concurrently(|engine| engine.update_one_in(collection, selector.clone(), update))
+    }
+
+    pub fn delete_by_id(&self, collection: &str, id: &ObjectId) -> Result<bool> {
+        self.write_concurrently(|engine| engine.collection(collection)?.delete_by_id(id))
+    }
+
+    /// Every live document in `collection`, read from one commit so the
+    /// result is a consistent view of the collection.
+    pub fn scan(&self, collection: &str) -> Result<Vec<(DocumentId, Document)>> {
+        self.read(|engine| engine.scan_in(collection).collect())
+    }
+
+    /// The documents of `collection` that pass `filter`, read from one commit.
+    pub fn find(&self, collection: &str, filter: Filter) -> Result<Vec<(DocumentId, Document)>> {
+        self.read(|engine| engine.find_in(collection, filter).collect())
+    }
+
+    /// How the planner runs `filter` over `collection`, and what running it
+    /// took.
+    pub fn explain(&self, collection: &str, filter: Filter) -> Result<Explain> {
+        self.read(|engine| engine.find_in(collection, filter).explain())
+    }
+
+    /// The results of running `pipeline` over `collection`, read from one
+    /// commit.
+    pub fn aggregate(&self, collection: &str, pipeline: &Pipeline) -> Result<Vec<Document>> {
+        self.read(|engine| engine.aggregate_in(collection, pipeline).collect())
+    }
+
+    /// A consistent view of the database as of the last commit, for reads that
+    /// span several calls while writers keep committing.
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        self.read(|engine| Ok(engine.snapshot()))
+    }
+
+    pub fn get_by_id_at(
+        &self,
+        collection: &str,
+        id: &ObjectId,
+        snapshot: &Snapshot,
+    ) -> Result<Option<Document>> {
+        self.read(|engine| engine.get_by_id_at_in(collection, id, snapshot))
+    }
+
+    /// Every document of `collection` as `snapshot` sees it.
+    pub fn scan_at(
+        &self,
+        collection: &str,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<(DocumentId, Document)>> {
+        self.read(|engine| engine.scan_at_in(collection, snapshot))
+    }
+
+    /// Vacuum the database as `StorageEngine::vacuum` does, but with the engine
+    /// to itself one page at a time, so reads and writes get in between pages.
+    pub fn vacuum(&self) -> Result<VacuumReport> {
+        let targets = self.exclusive()?.vacuum_targets()?;
+        let mut report = VacuumReport::default();
+        for (collection, page_id) in targets {
+            self.exclusive()?
+                .vacuum_step(&collection, page_id, &mut report)?;
+        }
+        self.exclusive()?.finish_vacuum(report)
+    }
+
+    /// Delete up to `limit` expired documents in one write. See `storage::ttl`.
+    pub fn expire_documents(&self, now: DateTime<Utc>, limit: usize) -> Result<ExpiryReport> {
+        self.exclusive()?.expire_documents(now, limit)
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.exclusive()?.flush()
+    }
+
+    /// Run a document write on a writer of its own, alongside reads and other
+    /// writes. A write that conflicts with another writer runs again on a
+    /// new writer of the same age, so that it eventually wins over younger
+    /// ones; after `MAX_WRITE_ATTEMPTS` it runs with the engine to itself.
+    fn write_concurrently<T>(&self, write: impl Fn(&mut StorageEngine) -> Result<T>) -> Result<T> {
+        let mut id = None;
+        for _ in 0..MAX_WRITE_ATTEMPTS {
+            let engine = self.read_guard()?;
+            let mut writer = engine.writer(id);
+            id = writer.writer_id();
+            match write(&mut writer) {
+                Err(e) if writer.must_retry() || is_write_conflict(&e) => continue,
+                result => return result,
+            }
+        }
+        write(&mut *self.exclusive()?)
+    }
+
+    /// The engine to this caller alone, up to date with every writer's commits.
+    fn exclusive(&self) -> Result<RwLockWriteGuard<'_, StorageEngine>> {
+        let mut engine = self.write_guard()?;
+        engine.refresh();
+        Ok(engine)
+    }
+
+    fn read_guard(&self) -> Result<RwLockReadGuard<'_, StorageEngine>> {
+        self.engine.read().map_err(|_| poisoned())
+    }
+
+    fn write_guard(&self) -> Result<RwLockWriteGuard<'_, StorageEngine>> {
+        self.engine.write().map_err(|_| poisoned())
+    }
+}
+
+fn is_write_conflict(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<DatabaseError>(),
+        Some(DatabaseError::WriteConflict)
+    )
+}
+
+fn poisoned() -> anyhow::Error {
+    DatabaseError::Storage("Database poisoned by a panicked write".to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_database_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Database>();
+        assert_send_sync::<StorageEngine>();
+    }
+
+    fn numbered(n: i32) -> Document {
+        let mut doc = Document::new();
+        doc.set("n", crate::Value::I32(n));
+        doc
+    }
+
+    #[test]
+    fn test_writers_latch_pages_instead_of_the_engine() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("writers.db");
+        crate::storage::file::DatabaseFile::create(&path).unwrap();
+        let database = Database::open(&path, 16).unwrap();
+        database.create_collection("a").unwrap();
+        database.create_collection("b").unwrap();
+        let engine = database.read_guard().unwrap();
+
+        // Writes to different collections are open at the same time
+        let mut older = engine.writer(None);
+        let mut younger = engine.writer(None);
+        let mut first = older.begin_transaction().unwrap();
+        first
+            .collection("a")
+            .unwrap()
+            .insert_document(&numbered(1))
+            .unwrap();
+        let mut second = younger.begin_transaction().unwrap();
+        second
+            .collection("b")
+            .unwrap()
+            .insert_document(&numbered(2))
+            .unwrap();
+        assert!(engine.with_reader(|reader| reader.scan_in("a").next().is_none()));
+        second.commit().unwrap();
+        first.commit().unwrap();
+        engine.with_reader(|reader| {
+            assert_eq!(reader.scan_in("a").count(), 1);
+            assert_eq!(reader.scan_in("b").count(), 1);
+        });
+
+        // A younger writer gives up on a page an older one has latched
+        let mut older = engine.writer(None);
+        let mut younger = engine.writer(None);
+        let mut first = older.begin_transaction().unwrap();
+        first
+            .collection("a")
+            .unwrap()
+            .insert_document(&numbered(3))
+            .unwrap();
+        let error = younger
+            .collection("a")
+            .unwrap()
+            .insert_document(&numbered(4))
+            .unwrap_err();
+        assert!(is_write_conflict(&error));
+        assert!(younger.must_retry());
+        drop(younger);
+        first.commit().unwrap();
+        drop(older);
+        drop(engine);
+
+        assert_eq!(database.scan("a").unwrap().len(), 2);
+        // The writer that gave up left nothing behind
+        assert_eq!(database.collection_stats("b").unwrap().document_count, 1);
+    }
+}
@@ -1,13 +1,35 @@
 use crate::error::DatabaseError;
-use crate::storage::page::{Page, PAGE_SIZE};
+use crate::storage::page::{Page, PageType, PAGE_SIZE};
 use fs2::FileExt;
 use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
+use std::collections::BTreeSet;
 use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
 const DATABASE_VERSION: u8 = 1;
 
+/// Well-known pages whose ids are recorded in the header's metadata area.
+///
+/// Each root occupies one 8-byte slot of `FileHeader::metadata`, so at most
+/// eight roots can be registered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RootPage {
+    /// Root of the `_id` -> `DocumentId` B+tree of files written before the
+    /// catalog existed. Only read when such a file is first opened.
+    PrimaryIndex = 0,
+    /// Head of the collection catalog's page chain.
+    Catalog = 1,
+    /// Head of the free-space map's page chain.
+    FreeSpaceMap = 2,
+    /// First page of the freelist.
+    Freelist = 3,
+}
+
+/// Marks the end of the freelist.
+const NO_PAGE: u64 = u64::MAX;
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 struct FileHeader {
     version: u8,
@@ -77,8 +99,25 @@ impl FileHeader {
 }
 
 pub struct DatabaseFile {
-    file: File,
-    header: FileHeader,
+    // Shared by every handle on the file
+    shared: Arc<SharedFile>,
+    // Pages allocated inside each open allocation scope, innermost last
+    allocation_scopes: Vec<AllocationScope>,
+}
+
+struct SharedFile {
+    // Latched so pages can be read and written through a shared reference; the
+    // seek and the read or write that follows it must not interleave
+    file: Mutex<File>,
+    // Held for the whole of an allocation or free, so handles used by
+    // different writers never hand out the same page or lose a freelist link
+    header: Mutex<FileHeader>,
+}
+
+struct AllocationScope {
+    // Pages in the file when the scope began
+    page_count: u64,
+    page_ids: Vec<u64>,
 }
 
 impl DatabaseFile {
@@ -93,10 +132,8 @@ impl DatabaseFile {
         file.try_lock_exclusive()
             .map_err(|e| DatabaseError::Io(e.into()))?;
 
-        let header = FileHeader::new();
-        let mut db_file = Self { file, header };
-
-        db_file.write_header()?;
+        let db_file = Self::with_file(file, FileHeader::new());
+        db_file.shared.write_header(&db_file.header())?;
         db_file.sync()?;
 
         Ok(db_file)
@@ -113,115 +150,459 @@ impl DatabaseFile {
         file.try_lock_exclusive()
             .map_err(|e| DatabaseError::Io(e.into()))?;
 
-        let mut db_file = Self {
-            file,
-            // Header will be read from file.
-            header: FileHeader::new(),
-        };
-
-        db_file.read_header()?;
+        // Header will be read from file.
+        let db_file = Self::with_file(file, FileHeader::new());
+        let header = db_file.shared.read_header()?;
 
-        if db_file.header.version != DATABASE_VERSION {
+        if header.version != DATABASE_VERSION {
             return Err(DatabaseError::Storage(format!(
                 "Incompatible database version. Expected {}, found {}",
-                DATABASE_VERSION, db_file.header.version
+                DATABASE_VERSION, header.version
             )));
         }
+        *db_file.header() = header;
 
         Ok(db_file)
     }
 
+    fn with_file(file: File, header: FileHeader) -> Self {
+        Self {
+            shared: Arc::new(SharedFile {
+                file: Mutex::new(file),
+                header: Mutex::new(header),
+            }),
+            allocation_scopes: Vec::new(),
+        }
+    }
+
+    /// Another handle on the same file, with allocation scopes of its own, for a
+    /// writer working alongside the holder of this one.
+    pub(crate) fn handle(&self) -> Self {
+        Self {
+            shared: Arc::clone(&self.shared),
+            allocation_scopes: Vec::new(),
+        }
+    }
+
+    /// Reads a specific page from the disk.
+    pub fn read_page(&self, page_id: u64) -> Result<Page, DatabaseError> {
+        let page_count = self.page_count();
+        self.shared.read_page(page_count, page_id)
+    }
+
+    /// Writes a page to the disk at a specific page ID.
+    pub fn write_page(&self, page_id: u64, page: &Page) -> Result<(), DatabaseError> {
+        let page_count = self.page_count();
+        self.shared.write_page(page_count, page_id, page)
+    }
+
+    /// Allocates a new page in the database file.
+    ///
+    /// This creates a new page with proper headers and checksum, writes it to disk,
+    /// and increments the page count in the header.
+    /// Returns the new page ID.
+    pub fn allocate_page(&mut self) -> Result<u64, DatabaseError> {
+        self.allocate_page_with_type(PageType::Data)
+    }
+
+    /// Allocates a new page of the given type. See [`DatabaseFile::allocate_page`].
+    ///
+    /// Pages on the freelist are reused before the file is extended.
+    pub fn allocate_page_with_type(&mut self, page_type: PageType) -> Result<u64, DatabaseError> {
+        let mut header = self.shared.lock_header();
+        let page_id = match header.root_page(RootPage::Freelist) {
+            Some(page_id) => {
+                let next = self.shared.read_free_link(&header, page_id)?;
+                // Unlink the page before reformatting it. Neither write is synced, so a
+                // crash may keep the new page and lose the new head; `check_freelist`
+                // catches that on the next open
+                self.shared.set_root_page(&mut header, RootPage::Freelist, next)?;
+                self.shared
+                    .write_page(header.page_count, page_id, &Page::new(page_id, page_type))?;
+                page_id
+            }
+            None => self.shared.append_page(&mut header, page_type)?,
+        };
+        drop(header);
+        self.record_allocation(page_id);
+        Ok(page_id)
+    }
+
+    /// Start recording the pages allocated from now on, so they can be given
+    /// back if the writes using them are undone. Scopes nest.
+    pub fn begin_allocation_scope(&mut self) {
+        let page_count = self.page_count();
+        self.allocation_scopes.push(AllocationScope {
+            page_count,
+            page_ids: Vec::new(),
+        });
+    }
+
+    /// Keep the pages allocated in the innermost scope. An enclosing scope can
+    /// still give them back.
+    pub fn release_allocation_scope(&mut self) {
+        if let Some(scope) = self.allocation_scopes.pop()
+            && let Some(parent) = self.allocation_scopes.last_mut()
+        {
+            parent.page_ids.extend(scope.page_ids);
+        }
+    }
+
+    /// The pages allocated so far in the innermost scope.
+    pub fn allocated_in_scope(&self) -> &[u64] {
+        self.allocation_scopes
+            .last()
+            .map_or(&[], |scope| &scope.page_ids)
+    }
+
+    /// Give back every page allocated in the innermost scope and return their
+    /// ids. The file shrinks back to its size when the scope began if all the
+    /// pages added since are being given back; other pages go on the freelist.
+    ///
+    /// As with [`DatabaseFile::free_page`], nothing may still refer to them.
+    pub fn rollback_allocation_scope(&mut self) -> Result<Vec<u64>, DatabaseError> {
+        let Some(scope) = self.allocation_scopes.pop() else {
+            return Ok(Vec::new());
+        };
+        let mut header = self.shared.lock_header();
+        let appended = scope
+            .page_ids
+            .iter()
+            .filter(|&&page_id| page_id >= scope.page_count)
+            .count() as u64;
+        // Another handle may have extended the file since, in which case the
+        // pages appended here go on the freelist like the rest, or shrunk it
+        // back past where the scope began, which it only does if nothing was
+        // appended here
+        let shrink = header.page_count.checked_sub(scope.page_count) == Some(appended);
+        for &page_id in &scope.page_ids {
+            if !(shrink && page_id >= scope.page_count) {
+                self.shared.free_page(&mut header, page_id)?;
+            }
+        }
+        if shrink && appended > 0 {
+            header.page_count = scope.page_count;
+            self.shared.write_header(&header)?;
+            self.shared
+                .lock_file()
+                .set_len(FileHeader::size() + scope.page_count * PAGE_SIZE as u64)?;
+        }
+        Ok(scope.page_ids)
+    }
+
+    fn record_allocation(&mut self, page_id: u64) {
+        if let Some(scope) = self.allocation_scopes.last_mut() {
+            scope.page_ids.push(page_id);
+        }
+    }
+
+    /// Returns `page_id` to the freelist so a later allocation can reuse it.
+    ///
+    /// The page is overwritten on disk straight away, so callers must make sure
+    /// nothing (including the buffer pool and the write-ahead log) still refers to it.
+    ///
+    /// The freelist lives outside the write-ahead log: the link and the header are
+    /// written in place, unsynced. A crash can therefore persist one without the
+    /// other. A head or link left pointing at a page that is not free is repaired
+    /// by [`DatabaseFile::check_freelist`]; a freed page that never got linked
+    /// stays unused until the freelist is next rebuilt.
+    pub fn free_page(&mut self, page_id: u64) -> Result<(), DatabaseError> {
+        let mut header = self.shared.lock_header();
+        self.shared.free_page(&mut header, page_id)
+    }
+
+    /// Ids of the pages on the freelist, in the order they will be reused.
+    pub fn free_pages(&mut self) -> Result<Vec<u64>, DatabaseError> {
+        let header = self.shared.lock_header();
+        self.shared.free_pages(&header)
+    }
+
+    /// Walks the freelist and, if a crash left it pointing at a page that is
+    /// not free (or out of the file, or back into itself), rebuilds it from
+    /// every page of the file marked free. Returns whether it was rebuilt.
+    ///
+    /// Must run before anything is allocated, once the write-ahead log has been
+    /// replayed so that every page in use carries its latest contents.
+    pub fn check_freelist(&mut self) -> Result<bool, DatabaseError> {
+        let mut header = self.shared.lock_header();
+        let mut seen = BTreeSet::new();
+        let mut next = header.root_page(RootPage::Freelist);
+        while let Some(page_id) = next {
+            if page_id >= header.page_count || !seen.insert(page_id) {
+                break;
+            }
+            match self.shared.read_free_link(&header, page_id) {
+                Ok(link) => next = link,
+                Err(_) => break,
+            }
+        }
+        if next.is_none() {
+            return Ok(false);
+        }
+
+        let mut free = BTreeSet::new();
+        for page_id in 0..header.page_count {
+            let page = self.shared.read_page(header.page_count, page_id);
+            if matches!(page, Ok(page) if page.get_page_type() == PageType::Free) {
+                free.insert(page_id);
+            }
+        }
+        self.shared.relink_free_pages(&mut header, &free)?;
+        Ok(true)
+    }
+
+    /// Shrinks the file by dropping the free pages at its end and returns how many
+    /// pages were released.
+    ///
+    /// The remaining free pages are relinked in ascending order so that later
+    /// allocations fill the front of the file first. Must only be called while
+    /// the write-ahead log is empty.
+    pub fn truncate(&mut self) -> Result<u64, DatabaseError> {
+        let mut header = self.shared.lock_header();
+        let mut free: BTreeSet<u64> = self.shared.free_pages(&header)?.into_iter().collect();
+        let mut page_count = header.page_count;
+        while page_count > 0 && free.remove(&(page_count - 1)) {
+            page_count -= 1;
+        }
+        let released = header.page_count - page_count;
+        if released == 0 {
+            return Ok(0);
+        }
+
+        header.page_count = page_count;
+        self.shared.relink_free_pages(&mut header, &free)?;
+        self.shared
+            .lock_file()
+            .set_len(FileHeader::size() + page_count * PAGE_SIZE as u64)?;
+        self.sync()?;
+        Ok(released)
+    }
+
+    /// Grows the file with fresh data pages until it holds at least `page_count` pages.
+    pub fn ensure_page_count(&mut self, page_count: u64) -> Result<(), DatabaseError> {
+        let mut header = self.shared.lock_header();
+        while header.page_count < page_count {
+            self.shared.append_page(&mut header, PageType::Data)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes all in-memory changes to the disk.
+    pub fn sync(&self) -> Result<(), DatabaseError> {
+        self.shared.lock_file().sync_all()?;
+        Ok(())
+    }
+
+    /// Returns the number of pages in the file.
+    pub fn page_count(&self) -> u64 {
+        self.header().page_count
+    }
+
+    /// Returns the page id registered for `root`, if any.
+    pub fn root_page(&self, root: RootPage) -> Option<u64> {
+        self.header().root_page(root)
+    }
+
+    /// Registers (or clears) the page id for `root` and persists the header.
+    pub fn set_root_page(&mut self, root: RootPage, page_id: Option<u64>) -> Result<(), DatabaseError> {
+        let mut header = self.shared.lock_header();
+        self.shared.set_root_page(&mut header, root, page_id)
+    }
+
+    fn header(&self) -> MutexGuard<'_, FileHeader> {
+        self.shared.lock_header()
+    }
+}
+
+impl FileHeader {
+    fn root_page(&self, root: RootPage) -> Option<u64> {
+        let offset = root as usize * 8;
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&self.metadata[offset..offset + 8]);
+        // Slots are stored as page_id + 1 so that the zeroed metadata of older
+        // files reads back as "unset" rather than page 0.
+        match u64::from_le_bytes(bytes) {
+            0 => None,
+            stored => Some(stored - 1),
+        }
+    }
+}
+
+// The operations behind `DatabaseFile`, run by a handle holding the header latch
+impl SharedFile {
     /// Reads the file header from disk.
-    fn read_header(&mut self) -> Result<(), DatabaseError> {
+    fn read_header(&self) -> Result<FileHeader, DatabaseError> {
         let mut buffer = vec![0; FileHeader::size() as usize];
-        self.file.seek(SeekFrom::Start(0))?;
-        self.file.read_exact(&mut buffer)?;
-        self.header = bincode::deserialize(&buffer).map_err(DatabaseError::Bincode)?;
-        Ok(())
+        let mut file = self.lock_file();
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut buffer)?;
+        bincode::deserialize(&buffer).map_err(DatabaseError::Bincode)
     }
 
     /// Writes the file header to disk.
-    fn write_header(&mut self) -> Result<(), DatabaseError> {
-        let buffer = bincode::serialize(&self.header).map_err(DatabaseError::Bincode)?;
-        self.file.seek(SeekFrom::Start(0))?;
-        self.file.write_all(&buffer)?;
+    fn write_header(&self, header: &FileHeader) -> Result<(), DatabaseError> {
+        let buffer = bincode::serialize(header).map_err(DatabaseError::Bincode)?;
+        let mut file = self.lock_file();
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&buffer)?;
         Ok(())
     }
 
-    /// Reads a specific page from the disk.
-    pub fn read_page(&mut self, page_id: u64) -> Result<Page, DatabaseError> {
-        if page_id >= self.header.page_count {
+    fn read_page(&self, page_count: u64, page_id: u64) -> Result<Page, DatabaseError> {
+        if page_id >= page_count {
             return Err(DatabaseError::Storage(format!(
                 "Attempted to read non-existent page {}",
                 page_id
             )));
         }
         let offset = FileHeader::size() + page_id * PAGE_SIZE as u64;
-        self.file.seek(SeekFrom::Start(offset))?;
-
         let mut buffer = [0u8; PAGE_SIZE];
-        self.file.read_exact(&mut buffer)?;
+        {
+            let mut file = self.lock_file();
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut buffer)?;
+        }
 
         Page::from_bytes(buffer)
     }
 
-    /// Writes a page to the disk at a specific page ID.
-    pub fn write_page(&mut self, page_id: u64, page: &Page) -> Result<(), DatabaseError> {
-        if page_id >= self.header.page_count {
+    fn write_page(&self, page_count: u64, page_id: u64, page: &Page) -> Result<(), DatabaseError> {
+        if page_id >= page_count {
             return Err(DatabaseError::Storage(format!(
                 "Attempted to write to non-existent page {}",
                 page_id
             )));
         }
         let offset = FileHeader::size() + page_id * PAGE_SIZE as u64;
-        self.file.seek(SeekFrom::Start(offset))?;
-        self.file.write_all(&page.to_bytes())?;
+        let mut file = self.lock_file();
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&page.to_bytes())?;
         Ok(())
     }
 
-    /// Allocates a new page in the database file.
-    ///
-    /// This creates a new page with proper headers and checksum, writes it to disk,
-    /// and increments the page count in the header.
-    /// Returns the new page ID.
-    pub fn allocate_page(&mut self) -> Result<u64, DatabaseError> {
-        use crate::storage::page::PageType;
-        
-        let new_page_id = self.header.page_count;
+    /// Extends the file by one page of the given type, bypassing the freelist.
+    fn append_page(&self, header: &mut FileHeader, page_type: PageType) -> Result<u64, DatabaseError> {
+        let new_page_id = header.page_count;
         
         // Create a new, properly initialized page with valid headers and checksum
-        let new_page = Page::new(new_page_id, PageType::Data);
-        
-        // Update header first to reflect the new page count
-        self.header.page_count += 1;
-        self.write_header()?;
+        let new_page = Page::new(new_page_id, page_type);
         
         // Calculate the offset for the new page
         let offset = FileHeader::size() + new_page_id * PAGE_SIZE as u64;
         
-        // Write the new page to the correct file offset
-        self.file.seek(SeekFrom::Start(offset))?;
-        self.file.write_all(&new_page.to_bytes())?;
+        // Write the page before the header so the header never counts a page
+        // that is not on disk yet
+        {
+            let mut file = self.lock_file();
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&new_page.to_bytes())?;
+        }
+        
+        header.page_count += 1;
+        self.write_header(header)?;
         
         Ok(new_page_id)
     }
 
-    /// Flushes all in-memory changes to the disk.
-    pub fn sync(&self) -> Result<(), DatabaseError> {
-        self.file.sync_all()?;
+    fn free_page(&self, header: &mut FileHeader, page_id: u64) -> Result<(), DatabaseError> {
+        if self.read_page(header.page_count, page_id)?.get_page_type() == PageType::Free {
+            return Err(DatabaseError::Storage(format!(
+                "Page {} is already free",
+                page_id
+            )));
+        }
+        let head = header.root_page(RootPage::Freelist);
+        self.write_free_link(header, page_id, head)?;
+        self.set_root_page(header, RootPage::Freelist, Some(page_id))
+    }
+
+    fn free_pages(&self, header: &FileHeader) -> Result<Vec<u64>, DatabaseError> {
+        let mut page_ids = Vec::new();
+        let mut next = header.root_page(RootPage::Freelist);
+        while let Some(page_id) = next {
+            if page_ids.len() as u64 >= header.page_count {
+                return Err(DatabaseError::Storage(
+                    "The freelist contains a cycle".to_string(),
+                ));
+            }
+            page_ids.push(page_id);
+            next = self.read_free_link(header, page_id)?;
+        }
+        Ok(page_ids)
+    }
+
+    /// Makes `free` the freelist, lowest id first, syncing the links before the
+    /// header that points at them.
+    fn relink_free_pages(
+        &self,
+        header: &mut FileHeader,
+        free: &BTreeSet<u64>,
+    ) -> Result<(), DatabaseError> {
+        // Link the pages back to front so the lowest id ends up first
+        let mut head = None;
+        for &page_id in free.iter().rev() {
+            self.write_free_link(header, page_id, head)?;
+            head = Some(page_id);
+        }
+        self.lock_file().sync_all()?;
+        self.set_root_page(header, RootPage::Freelist, head)?;
+        self.lock_file().sync_all()?;
         Ok(())
     }
 
-    /// Returns the number of pages in the file.
-    pub fn page_count(&self) -> u64 {
-        self.header.page_count
+    fn read_free_link(&self, header: &FileHeader, page_id: u64) -> Result<Option<u64>, DatabaseError> {
+        let page = self.read_page(header.page_count, page_id)?;
+        if page.get_page_type() != PageType::Free {
+            return Err(DatabaseError::Storage(format!(
+                "Freelist page {} is not a free page",
+                page_id
+            )));
+        }
+        match u64::from_le_bytes(page.payload()[0..8].try_into().unwrap()) {
+            NO_PAGE => Ok(None),
+            next => Ok(Some(next)),
+        }
+    }
+
+    fn write_free_link(
+        &self,
+        header: &FileHeader,
+        page_id: u64,
+        next: Option<u64>,
+    ) -> Result<(), DatabaseError> {
+        let mut page = Page::new(page_id, PageType::Free);
+        page.payload_mut()[0..8].copy_from_slice(&next.unwrap_or(NO_PAGE).to_le_bytes());
+        let checksum = page.calculate_checksum();
+        page.set_checksum(checksum);
+        self.write_page(header.page_count, page_id, &page)
+    }
+
+    fn set_root_page(
+        &self,
+        header: &mut FileHeader,
+        root: RootPage,
+        page_id: Option<u64>,
+    ) -> Result<(), DatabaseError> {
+        let offset = root as usize * 8;
+        let stored = page_id.map_or(0, |id| id + 1);
+        header.metadata[offset..offset + 8].copy_from_slice(&stored.to_le_bytes());
+        self.write_header(header)
+    }
+
+    fn lock_header(&self) -> MutexGuard<'_, FileHeader> {
+        // Every header change is written out before the latch is released
+        self.header.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_file(&self) -> MutexGuard<'_, File> {
+        // A panic mid-write leaves nothing in the handle itself to repair
+        self.file.lock().unwrap_or_else(PoisonError::into_inner)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::storage::page::PageType;
     use tempfile;
 
     #[test]
@@ -238,7 +619,7 @@ mod tests {
         // Open
         {
             let db_file = DatabaseFile::open(&path).unwrap();
-            assert_eq!(db_file.header.version, DATABASE_VERSION);
+            assert_eq!(db_file.header().version, DATABASE_VERSION);
             assert_eq!(db_file.page_count(), 0);
         }
     }
@@ -295,12 +676,110 @@ mod tests {
     fn test_read_non_existent_page() {
         let temp_dir = tempfile::tempdir().unwrap();
         let path = temp_dir.path().join("test.db");
-        let mut db_file = DatabaseFile::create(&path).unwrap();
+        let db_file = DatabaseFile::create(&path).unwrap();
 
         let result = db_file.read_page(0);
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_root_pages_persist() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("test.db");
+
+        {
+            let mut db_file = DatabaseFile::create(&path).unwrap();
+            assert_eq!(db_file.root_page(RootPage::PrimaryIndex), None);
+
+            let page_id = db_file.allocate_page_with_type(PageType::Index).unwrap();
+            assert_eq!(page_id, 0);
+            db_file.set_root_page(RootPage::PrimaryIndex, Some(page_id)).unwrap();
+        }
+
+        let db_file = DatabaseFile::open(&path).unwrap();
+        assert_eq!(db_file.root_page(RootPage::PrimaryIndex), Some(0));
+        assert_eq!(db_file.read_page(0).unwrap().get_page_type(), PageType::Index);
+    }
+
+    #[test]
+    fn test_freed_pages_are_reused() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("test.db");
+        let mut db_file = DatabaseFile::create(&path).unwrap();
+
+        for _ in 0..4 {
+            db_file.allocate_page().unwrap();
+        }
+        db_file.free_page(1).unwrap();
+        db_file.free_page(2).unwrap();
+        assert!(db_file.free_page(2).is_err());
+        assert_eq!(db_file.free_pages().unwrap(), vec![2, 1]);
+
+        let page_id = db_file.allocate_page_with_type(PageType::Index).unwrap();
+        assert_eq!(page_id, 2);
+        assert_eq!(db_file.read_page(2).unwrap().get_page_type(), PageType::Index);
+        drop(db_file);
+
+        let mut db_file = DatabaseFile::open(&path).unwrap();
+        assert_eq!(db_file.free_pages().unwrap(), vec![1]);
+        assert_eq!(db_file.allocate_page().unwrap(), 1);
+        assert_eq!(db_file.allocate_page().unwrap(), 4);
+        assert_eq!(db_file.page_count(), 5);
+    }
+
+    #[test]
+    fn test_truncate_releases_trailing_free_pages() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("test.db");
+        let mut db_file = DatabaseFile::create(&path).unwrap();
+
+        for _ in 0..6 {
+            db_file.allocate_page().unwrap();
+        }
+        for page_id in [5, 1, 4, 2] {
+            db_file.free_page(page_id).unwrap();
+        }
+
+        assert_eq!(db_file.truncate().unwrap(), 2);
+        assert_eq!(db_file.page_count(), 4);
+        assert_eq!(db_file.free_pages().unwrap(), vec![1, 2]);
+        assert_eq!(db_file.truncate().unwrap(), 0);
+        drop(db_file);
+
+        let length = std::fs::metadata(&path).unwrap().len();
+        assert_eq!(length, FileHeader::size() + 4 * PAGE_SIZE as u64);
+        let mut db_file = DatabaseFile::open(&path).unwrap();
+        assert_eq!(db_file.page_count(), 4);
+        assert_eq!(db_file.allocate_page().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_check_freelist_drops_pages_in_use() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("test.db");
+        let mut db_file = DatabaseFile::create(&path).unwrap();
+
+        for _ in 0..6 {
+            db_file.allocate_page().unwrap();
+        }
+        for page_id in [1, 2, 4] {
+            db_file.free_page(page_id).unwrap();
+        }
+        assert!(!db_file.check_freelist().unwrap());
+
+        // A crash after the head page was reformatted but before the header moved past it
+        db_file.write_page(4, &Page::new(4, PageType::Data)).unwrap();
+        assert!(db_file.check_freelist().unwrap());
+        assert_eq!(db_file.free_pages().unwrap(), vec![1, 2]);
+
+        // A head past the end of the file, e.g. after a lost append
+        db_file.set_root_page(RootPage::Freelist, Some(9)).unwrap();
+        assert!(db_file.check_freelist().unwrap());
+        assert_eq!(db_file.allocate_page().unwrap(), 1);
+        assert_eq!(db_file.allocate_page().unwrap(), 2);
+        assert_eq!(db_file.allocate_page().unwrap(), 6);
+    }
+
     #[test]
     fn test_sync() {
         let temp_dir = tempfile::tempdir().unwrap();
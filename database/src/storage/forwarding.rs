@@ -0,0 +1,140 @@
+// Records that keep a `DocumentId` valid after its document moves to another page.
+//
+// When an update no longer fits in the document's page, the document is written
+// elsewhere and its original ("home") slot is overwritten with a forwarding
+// pointer to the new location:
+//
+//   marker (u32 = 0xFFFF_FFFE) | target page (u64) | target slot (u16) | unused (u16)
+//
+// The relocated record carries a header naming its home slot, so scans can
+// report the stable id and skip the pointer:
+//
+//   marker (u32 = 0xFFFF_FFFD) | home page (u64) | home slot (u16) | record...
+//
+// where the record is the inline document or its overflow stub. Pointers are
+// never chained: moving a relocated document again repoints its home slot, and
+// a document that fits back in its home slot returns there.
+
+use crate::storage::storage_engine::DocumentId;
+
+const FORWARDING_MARKER: u32 = 0xFFFF_FFFE;
+const MOVED_MARKER: u32 = 0xFFFF_FFFD;
+
+/// Size of the pointer left in the home slot.
+pub const FORWARDING_POINTER_SIZE: usize = 16;
+
+/// Bytes a relocated record adds in front of the document.
+pub const MOVED_HEADER_SIZE: usize = 14;
+
+/// Stored in a document's home slot once the document lives elsewhere.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ForwardingPointer {
+    target: DocumentId,
+}
+
+impl ForwardingPointer {
+    pub fn new(target: DocumentId) -> Self {
+        Self { target }
+    }
+
+    /// Where the document currently lives.
+    pub fn target(&self) -> DocumentId {
+        self.target
+    }
+
+    pub fn encode(&self) -> [u8; FORWARDING_POINTER_SIZE] {
+        let mut bytes = [0u8; FORWARDING_POINTER_SIZE];
+        bytes[0..4].copy_from_slice(&FORWARDING_MARKER.to_le_bytes());
+        bytes[4..12].copy_from_slice(&self.target.page_id().to_le_bytes());
+        bytes[12..14].copy_from_slice(&self.target.slot_id().to_le_bytes());
+        bytes
+    }
+
+    /// Returns the pointer stored in a slot, or None if the slot holds a record.
+    pub fn decode(record: &[u8]) -> Option<Self> {
+        if record.len() != FORWARDING_POINTER_SIZE
+            || u32::from_le_bytes(record[0..4].try_into().unwrap()) != FORWARDING_MARKER
+        {
+            return None;
+        }
+        let page_id = u64::from_le_bytes(record[4..12].try_into().unwrap());
+        let slot_id = u16::from_le_bytes([record[12], record[13]]);
+        Some(Self::new(DocumentId::new(page_id, slot_id)))
+    }
+}
+
+/// A record stored away from its home slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovedRecord<'a> {
+    home: DocumentId,
+    record: &'a [u8],
+}
+
+impl<'a> MovedRecord<'a> {
+    pub fn new(home: DocumentId, record: &'a [u8]) -> Self {
+        Self { home, record }
+    }
+
+    /// The slot holding the forwarding pointer to this record.
+    pub fn home(&self) -> DocumentId {
+        self.home
+    }
+
+    /// The inline document or overflow stub.
+    pub fn record(&self) -> &'a [u8] {
+        self.record
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(MOVED_HEADER_SIZE + self.record.len());
+        bytes.extend_from_slice(&MOVED_MARKER.to_le_bytes());
+        bytes.extend_from_slice(&self.home.page_id().to_le_bytes());
+        bytes.extend_from_slice(&self.home.slot_id().to_le_bytes());
+        bytes.extend_from_slice(self.record);
+        bytes
+    }
+
+    /// Returns the relocated record stored in a slot, or None for anything else.
+    pub fn decode(record: &'a [u8]) -> Option<Self> {
+        if record.len() < MOVED_HEADER_SIZE
+            || u32::from_le_bytes(record[0..4].try_into().unwrap()) != MOVED_MARKER
+        {
+            return None;
+        }
+        let page_id = u64::from_le_bytes(record[4..12].try_into().unwrap());
+        let slot_id = u16::from_le_bytes([record[12], record[13]]);
+        Some(Self::new(
+            DocumentId::new(page_id, slot_id),
+            &record[MOVED_HEADER_SIZE..],
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::overflow::OverflowStub;
+
+    #[test]
+    fn test_pointer_roundtrip() {
+        let pointer = ForwardingPointer::new(DocumentId::new(0x0102_0304_0506_0708, 77));
+        let encoded = pointer.encode();
+        assert_eq!(ForwardingPointer::decode(&encoded), Some(pointer));
+        assert!(MovedRecord::decode(&encoded).is_none());
+        assert!(OverflowStub::decode(&encoded).is_none());
+    }
+
+    #[test]
+    fn test_moved_record_roundtrip() {
+        let home = DocumentId::new(12, 3);
+        let moved = MovedRecord::new(home, b"document bytes");
+        let encoded = moved.encode();
+
+        let decoded = MovedRecord::decode(&encoded).unwrap();
+        assert_eq!(decoded.home(), home);
+        assert_eq!(decoded.record(), b"document bytes");
+        assert!(ForwardingPointer::decode(&encoded).is_none());
+        // An inline BSON document starts with its (small) length
+        assert!(MovedRecord::decode(&[22, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_none());
+    }
+}
@@ -0,0 +1,189 @@
+// Free-space map: roughly how many bytes every data page has left.
+//
+// One byte per page id holds the page's free space divided by `BUCKET_SIZE`,
+// rounded down, so the map never promises more room than a page has. Inserts
+// consult it to find a page of the right collection with enough space without
+// reading any page, and every change the engine makes to a data page records the
+// page's new free space here.
+//
+// The map lives in a chain of `PageType::Metadata` pages registered as
+// `RootPage::FreeSpaceMap`. Like the catalog it is written through the buffer
+// pool, so it is logged and rolled back together with the pages it describes.
+//
+// The buckets as last saved are shared by every map made with `share`, so
+// writers working alongside each other see each other's committed changes;
+// each map keeps the buckets it recorded since its last save to itself.
+
+use crate::error::DatabaseError;
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::file::DatabaseFile;
+use crate::storage::page::PageType;
+use crate::storage::page_chain::PageChain;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Bytes of free space represented by one bucket step.
+pub const BUCKET_SIZE: usize = 32;
+
+pub struct FreeSpaceMap {
+    chain: PageChain,
+    // Buckets as last saved
+    buckets: Arc<Mutex<Vec<u8>>>,
+    // Buckets recorded since the last save: page id -> bucket
+    changes: BTreeMap<u64, u8>,
+}
+
+impl FreeSpaceMap {
+    /// Allocate an empty map.
+    pub fn create(
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+    ) -> Result<Self, DatabaseError> {
+        let chain = PageChain::create(buffer_pool, database_file, PageType::Metadata)?;
+        Ok(Self {
+            chain,
+            buckets: Arc::default(),
+            changes: BTreeMap::new(),
+        })
+    }
+
+    /// Load the map whose chain starts at `head_page_id`.
+    pub fn load(
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        head_page_id: u64,
+    ) -> Result<Self, DatabaseError> {
+        let mut map = Self {
+            chain: PageChain::open(head_page_id),
+            buckets: Arc::default(),
+            changes: BTreeMap::new(),
+        };
+        map.reload(buffer_pool, database_file)?;
+        Ok(map)
+    }
+
+    /// A map over the same saved buckets with no unsaved changes, for a
+    /// writer working alongside the holder of this one.
+    pub fn share(&self) -> Self {
+        Self {
+            chain: self.chain,
+            buckets: Arc::clone(&self.buckets),
+            changes: BTreeMap::new(),
+        }
+    }
+
+    pub fn head_page_id(&self) -> u64 {
+        self.chain.head_page_id()
+    }
+
+    /// Re-read the map from its pages, discarding unsaved changes.
+    pub fn reload(
+        &mut self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+    ) -> Result<(), DatabaseError> {
+        *self.lock_buckets() = self.chain.read(buffer_pool, database_file)?;
+        self.changes.clear();
+        Ok(())
+    }
+
+    /// The changes recorded since the last save, to put back with
+    /// `restore_unsaved_changes` if the writes that made later ones are undone.
+    pub fn unsaved_changes(&self) -> BTreeMap<u64, u8> {
+        self.changes.clone()
+    }
+
+    pub fn restore_unsaved_changes(&mut self, changes: BTreeMap<u64, u8>) {
+        self.changes = changes;
+    }
+
+    /// Record that `page_id` now has `free_space` bytes free.
+    pub fn record(&mut self, page_id: u64, free_space: usize) {
+        let bucket = (free_space / BUCKET_SIZE).min(u8::MAX as usize) as u8;
+        if self.bucket(page_id) != bucket {
+            self.changes.insert(page_id, bucket);
+        }
+    }
+
+    /// Free bytes `page_id` is known to have, at most `BUCKET_SIZE - 1` short of
+    /// the real figure. Pages never recorded have none.
+    pub fn free_space(&self, page_id: u64) -> usize {
+        self.bucket(page_id) as usize * BUCKET_SIZE
+    }
+
+    fn bucket(&self, page_id: u64) -> u8 {
+        match self.changes.get(&page_id) {
+            Some(&bucket) => bucket,
+            None => self.lock_buckets().get(page_id as usize).copied().unwrap_or(0),
+        }
+    }
+
+    /// Write the map back to its pages if it changed since the last save.
+    pub fn save(
+        &mut self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+    ) -> Result<(), DatabaseError> {
+        if self.changes.is_empty() {
+            return Ok(());
+        }
+        // Not held while writing, which may wait for another writer's latch
+        let mut buckets = self.lock_buckets().clone();
+        Self::apply(&mut buckets, &self.changes);
+        self.chain.write(buffer_pool, database_file, &buckets)?;
+        let changes = std::mem::take(&mut self.changes);
+        Self::apply(&mut self.lock_buckets(), &changes);
+        Ok(())
+    }
+
+    fn apply(buckets: &mut Vec<u8>, changes: &BTreeMap<u64, u8>) {
+        for (&page_id, &bucket) in changes {
+            let index = page_id as usize;
+            if index >= buckets.len() {
+                buckets.resize(index + 1, 0);
+            }
+            buckets[index] = bucket;
+        }
+    }
+
+    fn lock_buckets(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.buckets.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buckets_round_down() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut database_file = DatabaseFile::create(&temp_dir.path().join("test.db")).unwrap();
+        let mut buffer_pool = BufferPool::new(4);
+        let mut map = FreeSpaceMap::create(&mut buffer_pool, &mut database_file).unwrap();
+
+        map.record(3, 100);
+        map.record(5, 8000);
+        assert_eq!(map.free_space(3), 96);
+        assert_eq!(map.free_space(4), 0);
+        assert_eq!(map.free_space(5), 8000);
+        assert_eq!(map.free_space(99), 0);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut database_file = DatabaseFile::create(&temp_dir.path().join("test.db")).unwrap();
+        let mut buffer_pool = BufferPool::new(4);
+        let mut map = FreeSpaceMap::create(&mut buffer_pool, &mut database_file).unwrap();
+
+        map.record(20_000, 4096);
+        map.save(&mut buffer_pool, &mut database_file).unwrap();
+        map.record(1, 64);
+
+        let loaded =
+            FreeSpaceMap::load(&mut buffer_pool, &mut database_file, map.head_page_id()).unwrap();
+        assert_eq!(loaded.free_space(20_000), 4096);
+        assert_eq!(loaded.free_space(1), 0);
+    }
+}
@@ -1,5 +1,18 @@
+pub mod async_database;
 pub mod buffer_pool;
+pub mod catalog;
+pub mod collection;
+pub mod database;
 pub mod file;
+pub mod forwarding;
+pub mod free_space_map;
+pub mod mvcc;
+pub mod overflow;
 pub mod page;
+pub mod page_chain;
 pub mod page_layout;
-pub mod storage_engine;
\ No newline at end of file
+pub mod storage_engine;
+pub mod transaction;
+pub mod ttl;
+pub mod vacuum;
+pub mod wal;
@@ -0,0 +1,368 @@
+// Snapshot reads through an in-memory undo store.
+//
+// Every commit that changes documents gets the next commit sequence number. A
+// snapshot remembers the sequence number current when it was taken and sees
+// each document as it was at that point. While any snapshot is open, writes
+// record the previous version of every home slot they change (the encoded
+// document, or nothing if the slot was empty) and the commit that superseded
+// it. A snapshot reading a home slot then uses the oldest version superseded
+// after it was taken, or the slot's current contents if there is none.
+//
+// Versions are keyed by home slot, which a document keeps for its whole life,
+// so relocations do not break the chain; a slot reused by a later insert just
+// continues its chain. Nothing is recorded while no snapshot is open. Versions
+// no open snapshot can see any more are dropped as soon as the oldest snapshot
+// is, and by commits made while none is open; vacuum drops any left over.
+//
+// Stores made with `VersionStore::share` belong to writers working alongside
+// each other and record versions whether or not a snapshot is open, since one
+// may be taken before their write commits.
+
+use crate::{
+    document::object_id::ObjectId, error::DatabaseError, storage::storage_engine::DocumentId,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+// Shared with the snapshots, so the last one to go can drop what it kept
+type Registry = Arc<Mutex<Versions>>;
+
+#[derive(Default)]
+struct Versions {
+    // Sequence number of the last commit that changed documents
+    sequence: u64,
+    // Open snapshots: sequence number -> how many are open at it
+    open: BTreeMap<u64, usize>,
+    // Superseded versions of each home slot, oldest first
+    chains: HashMap<DocumentId, Vec<Arc<Version>>>,
+}
+
+impl Versions {
+    /// Drop the versions no open snapshot can see. Returns how many were dropped.
+    fn prune(&mut self) -> usize {
+        // A version superseded at or before the oldest snapshot is older than
+        // anything any snapshot reads
+        let horizon = self.open.keys().next().copied().unwrap_or(u64::MAX);
+        let mut pruned = 0;
+        self.chains.retain(|_, chain| {
+            let before = chain.len();
+            chain.retain(|version| version.superseded_at > horizon);
+            pruned += before - chain.len();
+            !chain.is_empty()
+        });
+        pruned
+    }
+}
+
+/// A consistent point-in-time view of the database, taken with
+/// [`StorageEngine::snapshot`](crate::storage::storage_engine::StorageEngine::snapshot).
+/// The versions it needs are kept until it is dropped.
+pub struct Snapshot {
+    sequence: u64,
+    // Collections that existed when it was taken
+    collections: HashSet<String>,
+    registry: Registry,
+}
+
+impl Snapshot {
+    /// The commit sequence number the snapshot sees the database at.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// An error if there was no collection named `collection` when the
+    /// snapshot was taken.
+    pub(crate) fn ensure_collection(&self, collection: &str) -> Result<(), DatabaseError> {
+        if self.collections.contains(collection) {
+            Ok(())
+        } else {
+            Err(DatabaseError::Query(format!(
+                "No collection named '{}'",
+                collection
+            )))
+        }
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let mut versions = self.registry.lock().unwrap_or_else(PoisonError::into_inner);
+        let oldest = versions.open.keys().next() == Some(&self.sequence);
+        if let Some(count) = versions.open.get_mut(&self.sequence) {
+            *count -= 1;
+            if *count == 0 {
+                versions.open.remove(&self.sequence);
+                if oldest {
+                    versions.prune();
+                }
+            }
+        }
+    }
+}
+
+/// The contents of a home slot before a commit changed it.
+#[derive(Debug, Clone)]
+pub(crate) struct Version {
+    superseded_at: u64,
+    collection: String,
+    id: Option<ObjectId>,
+    document: Option<Vec<u8>>,
+}
+
+impl Version {
+    pub(crate) fn collection(&self) -> &str {
+        &self.collection
+    }
+
+    pub(crate) fn id(&self) -> Option<&ObjectId> {
+        self.id.as_ref()
+    }
+
+    /// The encoded document, or None if the slot held no document.
+    pub(crate) fn document(&self) -> Option<&[u8]> {
+        self.document.as_deref()
+    }
+}
+
+pub(crate) struct VersionStore {
+    versions: Registry,
+    // Versions recorded by writes that are not committed yet
+    pending: Vec<(DocumentId, Version)>,
+    // Record versions even while no snapshot is open
+    always_track: bool,
+}
+
+impl VersionStore {
+    pub(crate) fn new() -> Self {
+        Self {
+            versions: Arc::new(Mutex::new(Versions::default())),
+            pending: Vec::new(),
+            always_track: false,
+        }
+    }
+
+    /// A store over the same versions for a writer working alongside others,
+    /// with nothing pending.
+    pub(crate) fn share(&self) -> Self {
+        Self {
+            versions: Arc::clone(&self.versions),
+            pending: Vec::new(),
+            always_track: true,
+        }
+    }
+
+    /// A snapshot at the last commit, of a database holding `collections`.
+    pub(crate) fn snapshot(&self, collections: HashSet<String>) -> Snapshot {
+        let mut versions = self.lock();
+        let sequence = versions.sequence;
+        *versions.open.entry(sequence).or_insert(0) += 1;
+        Snapshot {
+            sequence,
+            collections,
+            registry: Arc::clone(&self.versions),
+        }
+    }
+
+    /// Whether writes need to record what they replace.
+    pub(crate) fn is_tracking(&self) -> bool {
+        self.always_track || !self.lock().open.is_empty()
+    }
+
+    /// How many superseded versions are kept.
+    pub(crate) fn len(&self) -> usize {
+        self.lock().chains.values().map(Vec::len).sum()
+    }
+
+    /// Remember what `home` held before the current write changed it.
+    pub(crate) fn record(
+        &mut self,
+        home: DocumentId,
+        collection: &str,
+        id: Option<ObjectId>,
+        document: Option<Vec<u8>>,
+    ) {
+        self.pending.push((
+            home,
+            Version {
+                superseded_at: 0,
+                collection: collection.to_string(),
+                id,
+                document,
+            },
+        ));
+    }
+
+    pub(crate) fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Forget the versions recorded since `len` by a write that was undone.
+    pub(crate) fn truncate_pending(&mut self, len: usize) {
+        self.pending.truncate(len);
+    }
+
+    /// Stamp the pending versions with a new commit sequence number.
+    pub(crate) fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let pending = std::mem::take(&mut self.pending);
+        let mut versions = self.lock();
+        versions.sequence += 1;
+        let sequence = versions.sequence;
+        // Only the first change to a slot in a commit holds what it replaced
+        let mut seen = HashSet::new();
+        for (home, mut version) in pending {
+            if seen.insert(home) {
+                version.superseded_at = sequence;
+                versions
+                    .chains
+                    .entry(home)
+                    .or_default()
+                    .push(Arc::new(version));
+            }
+        }
+        // The snapshots these were recorded for may have closed in the meantime
+        if versions.open.is_empty() {
+            versions.prune();
+        }
+    }
+
+    /// What `home` held when `snapshot` was taken, if a later commit changed it.
+    pub(crate) fn visible(&self, home: &DocumentId, snapshot: &Snapshot) -> Option<Arc<Version>> {
+        Self::visible_in(&self.lock(), home, snapshot)
+    }
+
+    /// Every home slot changed since `snapshot` was taken, with what it held then.
+    pub(crate) fn changed_since(&self, snapshot: &Snapshot) -> Vec<(DocumentId, Arc<Version>)> {
+        let versions = self.lock();
+        versions
+            .chains
+            .keys()
+            .filter_map(|home| Some((*home, Self::visible_in(&versions, home, snapshot)?)))
+            .collect()
+    }
+
+    /// Drop the versions no open snapshot can see. Returns how many were dropped.
+    pub(crate) fn prune(&mut self) -> usize {
+        self.lock().prune()
+    }
+
+    fn visible_in(
+        versions: &Versions,
+        home: &DocumentId,
+        snapshot: &Snapshot,
+    ) -> Option<Arc<Version>> {
+        versions
+            .chains
+            .get(home)?
+            .iter()
+            .find(|version| version.superseded_at > snapshot.sequence)
+            .cloned()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Versions> {
+        self.versions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(byte: u8) -> Option<Vec<u8>> {
+        Some(vec![byte])
+    }
+
+    #[test]
+    fn test_snapshot_sees_oldest_later_version() {
+        let mut store = VersionStore::new();
+        let home = DocumentId::new(1, 0);
+
+        let before_any = store.snapshot(HashSet::new());
+        store.record(home, "c", None, None);
+        store.commit();
+        let after_insert = store.snapshot(HashSet::new());
+        store.record(home, "c", None, document(1));
+        store.commit();
+        store.record(home, "c", None, document(2));
+        store.commit();
+
+        assert_eq!(store.visible(&home, &before_any).unwrap().document(), None);
+        assert_eq!(
+            store.visible(&home, &after_insert).unwrap().document(),
+            Some(&[1][..])
+        );
+        assert!(
+            store
+                .visible(&home, &store.snapshot(HashSet::new()))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_first_change_in_a_commit_wins() {
+        let mut store = VersionStore::new();
+        let home = DocumentId::new(1, 0);
+        let snapshot = store.snapshot(HashSet::new());
+
+        store.record(home, "c", None, document(1));
+        store.record(home, "c", None, document(2));
+        store.commit();
+
+        assert_eq!(
+            store.visible(&home, &snapshot).unwrap().document(),
+            Some(&[1][..])
+        );
+    }
+
+    #[test]
+    fn test_prune_keeps_versions_open_snapshots_need() {
+        let mut store = VersionStore::new();
+        let home = DocumentId::new(1, 0);
+
+        let old = store.snapshot(HashSet::new());
+        store.record(home, "c", None, document(1));
+        store.commit();
+        let newer = store.snapshot(HashSet::new());
+        store.record(home, "c", None, document(2));
+        store.commit();
+
+        assert_eq!(store.prune(), 0);
+        assert_eq!(store.len(), 2);
+        // Dropping the oldest snapshot drops what only it could see
+        drop(old);
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.prune(), 0);
+        assert_eq!(
+            store.visible(&home, &newer).unwrap().document(),
+            Some(&[2][..])
+        );
+        drop(newer);
+        assert!(!store.is_tracking());
+        assert_eq!(store.len(), 0);
+    }
+
+    #[test]
+    fn test_store_empties_when_snapshots_close() {
+        let mut store = VersionStore::new();
+        let home = DocumentId::new(1, 0);
+
+        // A newer snapshot closing first keeps what the older one needs
+        let old = store.snapshot(HashSet::new());
+        store.record(home, "c", None, document(1));
+        store.commit();
+        let newer = store.snapshot(HashSet::new());
+        drop(newer);
+        assert_eq!(store.len(), 1);
+        drop(old);
+        assert_eq!(store.len(), 0);
+
+        // Versions recorded for a snapshot that closed before the commit
+        let snapshot = store.snapshot(HashSet::new());
+        store.record(home, "c", None, document(2));
+        drop(snapshot);
+        store.commit();
+        assert_eq!(store.len(), 0);
+    }
+}
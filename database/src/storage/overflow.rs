@@ -0,0 +1,149 @@
+// Storage for documents too large to fit in a single data page.
+//
+// The encoded document is written to a page chain of `PageType::Overflow` pages
+// and the document's slot in the data page holds a fixed-size stub instead:
+//
+//   marker (u32 = 0xFFFF_FFFF) | document length (u32) | first overflow page (u64)
+//
+// A BSON document starts with its own length, which is never 0xFFFF_FFFF, so a
+// slot holding a stub cannot be mistaken for an inline document.
+
+use crate::document::bson::{BsonError, MAX_DOCUMENT_SIZE};
+use crate::error::DatabaseError;
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::file::DatabaseFile;
+use crate::storage::page::PageType;
+use crate::storage::page_chain::PageChain;
+
+const OVERFLOW_MARKER: u32 = u32::MAX;
+
+/// Size of the stub left in the data page.
+pub const OVERFLOW_STUB_SIZE: usize = 16;
+
+/// Points from a data page slot to the overflow chain holding the document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OverflowStub {
+    length: u32,
+    chain: PageChain,
+}
+
+impl OverflowStub {
+    /// Write `document_bytes` to a new overflow chain.
+    pub fn store(
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        document_bytes: &[u8],
+    ) -> Result<Self, DatabaseError> {
+        Self::check_size(document_bytes)?;
+        let chain = PageChain::create(buffer_pool, database_file, PageType::Overflow)?;
+        Self::write_chain(buffer_pool, database_file, chain, document_bytes)
+    }
+
+    /// Replace the document in this stub's chain, reusing its pages.
+    pub fn rewrite(
+        &self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        document_bytes: &[u8],
+    ) -> Result<Self, DatabaseError> {
+        Self::check_size(document_bytes)?;
+        Self::write_chain(buffer_pool, database_file, self.chain, document_bytes)
+    }
+
+    /// Reassemble the document from its overflow chain.
+    pub fn load(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        let bytes = self.chain.read(buffer_pool, database_file)?;
+        if bytes.len() != self.length as usize {
+            return Err(DatabaseError::Storage(format!(
+                "Overflow chain at page {} holds {} of {} bytes",
+                self.chain.head_page_id(),
+                bytes.len(),
+                self.length
+            )));
+        }
+        Ok(bytes)
+    }
+
+    /// The overflow chain holding the document.
+    pub fn chain(&self) -> PageChain {
+        self.chain
+    }
+
+    pub fn encode(&self) -> [u8; OVERFLOW_STUB_SIZE] {
+        let mut bytes = [0u8; OVERFLOW_STUB_SIZE];
+        bytes[0..4].copy_from_slice(&OVERFLOW_MARKER.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.length.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.chain.head_page_id().to_le_bytes());
+        bytes
+    }
+
+    /// Returns the stub stored in a slot, or None if the slot holds an inline document.
+    pub fn decode(record: &[u8]) -> Option<Self> {
+        if record.len() != OVERFLOW_STUB_SIZE
+            || u32::from_le_bytes(record[0..4].try_into().unwrap()) != OVERFLOW_MARKER
+        {
+            return None;
+        }
+        Some(Self {
+            length: u32::from_le_bytes(record[4..8].try_into().unwrap()),
+            chain: PageChain::open(u64::from_le_bytes(record[8..16].try_into().unwrap())),
+        })
+    }
+
+    fn check_size(document_bytes: &[u8]) -> Result<(), DatabaseError> {
+        if document_bytes.len() > MAX_DOCUMENT_SIZE {
+            return Err(DatabaseError::Document(
+                BsonError::DocumentTooLarge(document_bytes.len()).to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn write_chain(
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        chain: PageChain,
+        document_bytes: &[u8],
+    ) -> Result<Self, DatabaseError> {
+        chain.write(buffer_pool, database_file, document_bytes)?;
+        Ok(Self {
+            length: document_bytes.len() as u32,
+            chain,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store_and_load() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut database_file = DatabaseFile::create(&temp_dir.path().join("test.db")).unwrap();
+        let mut buffer_pool = BufferPool::new(4);
+
+        let document: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let stub = OverflowStub::store(&mut buffer_pool, &mut database_file, &document).unwrap();
+        let decoded = OverflowStub::decode(&stub.encode()).unwrap();
+        assert_eq!(decoded, stub);
+        assert_eq!(decoded.load(&mut buffer_pool, &mut database_file).unwrap(), document);
+
+        let shorter = vec![7u8; 9_000];
+        let stub = stub.rewrite(&mut buffer_pool, &mut database_file, &shorter).unwrap();
+        assert_eq!(stub.load(&mut buffer_pool, &mut database_file).unwrap(), shorter);
+    }
+
+    #[test]
+    fn test_inline_documents_are_not_stubs() {
+        // A 16 byte BSON document starts with its length, 16
+        let mut inline = [0u8; OVERFLOW_STUB_SIZE];
+        inline[0] = 16;
+        assert!(OverflowStub::decode(&inline).is_none());
+        assert!(OverflowStub::decode(&[0xFF; 8]).is_none());
+    }
+}
@@ -15,6 +15,7 @@ pub enum PageType {
     Index = 1,
     Metadata = 2,
     Free = 3,
+    Overflow = 4,
 }
 
 impl From<u8> for PageType {
@@ -24,6 +25,7 @@ impl From<u8> for PageType {
             1 => PageType::Index,
             2 => PageType::Metadata,
             3 => PageType::Free,
+            4 => PageType::Overflow,
             // It's good practice to handle invalid values.
             _ => panic!("Invalid value for PageType: {}", value),
         }
@@ -38,6 +40,7 @@ impl From<PageType> for u8 {
 
 // A Page is a fixed-size block of data as it would be on disk.
 // The layout is a PageHeader followed by the page's content.
+#[derive(Clone)]
 #[repr(C, align(8))]
 pub struct Page {
     data: [u8; PAGE_SIZE],
@@ -164,6 +167,21 @@ impl Page {
         self.get_header().page_id()
     }
 
+    /// Returns the type of data stored on this page.
+    pub fn get_page_type(&self) -> PageType {
+        self.header().page_type
+    }
+
+    /// Returns the bytes following the page header.
+    pub fn payload(&self) -> &[u8] {
+        &self.data[PAGE_HEADER_SIZE..]
+    }
+
+    /// Returns mutable access to the bytes following the page header.
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        &mut self.data[PAGE_HEADER_SIZE..]
+    }
+
     #[allow(dead_code)]
     fn set_header(&mut self, header: PageHeader) {
         let header_bytes = header.to_bytes();
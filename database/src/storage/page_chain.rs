@@ -0,0 +1,176 @@
+// A byte string spread over a singly linked list of pages.
+//
+// Used for structures that outgrow a single page: the collection catalog, each
+// collection's page directory, the free-space map and overflowed documents.
+// Every page in the chain stores
+//
+//   next page (u64, u64::MAX at the end) | used length (u16) | bytes...
+//
+// in its payload. Rewriting the chain reuses its pages, only dirties the pages
+// whose bytes changed and only allocates when the bytes no longer fit; pages left
+// over after a shrink stay linked with a used length of 0 so a later rewrite can
+// grow into them again.
+
+use crate::error::DatabaseError;
+use crate::storage::buffer_pool::BufferPool;
+use crate::storage::file::DatabaseFile;
+use crate::storage::page::{PageType, PAGE_HEADER_SIZE, PAGE_SIZE};
+
+const NO_PAGE: u64 = u64::MAX;
+const CHAIN_HEADER_SIZE: usize = 10; // next page (8) + used length (2)
+const CHAIN_CAPACITY: usize = PAGE_SIZE - PAGE_HEADER_SIZE - CHAIN_HEADER_SIZE;
+
+/// Handle to a page chain identified by its (fixed) head page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageChain {
+    head_page_id: u64,
+}
+
+impl PageChain {
+    /// Allocate a head page of `page_type` holding an empty byte string.
+    pub fn create(
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        page_type: PageType,
+    ) -> Result<Self, DatabaseError> {
+        let head_page_id = database_file.allocate_page_with_type(page_type)?;
+        Self::write_link(buffer_pool, database_file, head_page_id, NO_PAGE, &[])?;
+        Ok(Self { head_page_id })
+    }
+
+    /// Open an existing chain starting at `head_page_id`.
+    pub fn open(head_page_id: u64) -> Self {
+        Self { head_page_id }
+    }
+
+    pub fn head_page_id(&self) -> u64 {
+        self.head_page_id
+    }
+
+    /// Read the whole byte string.
+    pub fn read(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+    ) -> Result<Vec<u8>, DatabaseError> {
+        let mut bytes = Vec::new();
+        let mut page_id = self.head_page_id;
+        while page_id != NO_PAGE {
+            let (next, chunk) = Self::read_link(buffer_pool, database_file, page_id)?;
+            bytes.extend_from_slice(&chunk);
+            page_id = next;
+        }
+        Ok(bytes)
+    }
+
+    /// Replace the byte string with `bytes`, extending the chain if needed.
+    pub fn write(
+        &self,
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        bytes: &[u8],
+    ) -> Result<(), DatabaseError> {
+        let page_type =
+            buffer_pool.read_page(self.head_page_id, database_file, |page| page.get_page_type())?;
+
+        let mut chunks = bytes.chunks(CHAIN_CAPACITY);
+        let mut page_id = self.head_page_id;
+        loop {
+            let chunk = chunks.next().unwrap_or(&[]);
+            let (mut next, current) = Self::read_link(buffer_pool, database_file, page_id)?;
+            if next == NO_PAGE && chunks.len() > 0 {
+                next = database_file.allocate_page_with_type(page_type)?;
+                Self::write_link(buffer_pool, database_file, next, NO_PAGE, &[])?;
+                Self::write_link(buffer_pool, database_file, page_id, next, chunk)?;
+            } else if current != chunk {
+                // Links whose bytes did not change are left clean
+                Self::write_link(buffer_pool, database_file, page_id, next, chunk)?;
+            }
+
+            if next == NO_PAGE {
+                return Ok(());
+            }
+            page_id = next;
+        }
+    }
+
+    /// Every page in the chain, head first.
+    pub fn page_ids(
+        &self,
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+    ) -> Result<Vec<u64>, DatabaseError> {
+        let mut page_ids = Vec::new();
+        let mut page_id = self.head_page_id;
+        while page_id != NO_PAGE {
+            page_ids.push(page_id);
+            page_id = Self::read_link(buffer_pool, database_file, page_id)?.0;
+        }
+        Ok(page_ids)
+    }
+
+    fn read_link(
+        buffer_pool: &BufferPool,
+        database_file: &DatabaseFile,
+        page_id: u64,
+    ) -> Result<(u64, Vec<u8>), DatabaseError> {
+        buffer_pool.read_page(page_id, database_file, |page| {
+            let payload = page.payload();
+            let next = u64::from_le_bytes(payload[0..8].try_into().unwrap());
+            let len = u16::from_le_bytes([payload[8], payload[9]]) as usize;
+            if len > CHAIN_CAPACITY {
+                return Err(DatabaseError::Storage(format!(
+                    "Corrupt page chain link {}: length {}",
+                    page_id, len
+                )));
+            }
+            Ok((next, payload[CHAIN_HEADER_SIZE..CHAIN_HEADER_SIZE + len].to_vec()))
+        })?
+    }
+
+    fn write_link(
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+        page_id: u64,
+        next: u64,
+        chunk: &[u8],
+    ) -> Result<(), DatabaseError> {
+        let page = buffer_pool.pin_page(page_id, database_file)?;
+        let payload = page.payload_mut();
+        payload[0..8].copy_from_slice(&next.to_le_bytes());
+        payload[8..10].copy_from_slice(&(chunk.len() as u16).to_le_bytes());
+        payload[CHAIN_HEADER_SIZE..CHAIN_HEADER_SIZE + chunk.len()].copy_from_slice(chunk);
+        buffer_pool.unpin_page(page_id, true);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_grows_and_shrinks() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut database_file = DatabaseFile::create(&temp_dir.path().join("test.db")).unwrap();
+        let mut buffer_pool = BufferPool::new(4);
+
+        let chain = PageChain::create(&mut buffer_pool, &mut database_file, PageType::Metadata).unwrap();
+        assert!(chain.read(&mut buffer_pool, &mut database_file).unwrap().is_empty());
+
+        let long: Vec<u8> = (0..3 * CHAIN_CAPACITY + 5).map(|i| i as u8).collect();
+        chain.write(&mut buffer_pool, &mut database_file, &long).unwrap();
+        assert_eq!(chain.read(&mut buffer_pool, &mut database_file).unwrap(), long);
+        let page_ids = chain.page_ids(&mut buffer_pool, &mut database_file).unwrap();
+        assert_eq!(page_ids.len(), 4);
+
+        // Shrinking keeps the spare pages for later
+        chain.write(&mut buffer_pool, &mut database_file, b"short").unwrap();
+        assert_eq!(chain.read(&mut buffer_pool, &mut database_file).unwrap(), b"short");
+        assert_eq!(chain.page_ids(&mut buffer_pool, &mut database_file).unwrap(), page_ids);
+
+        chain.write(&mut buffer_pool, &mut database_file, &long).unwrap();
+        assert_eq!(chain.read(&mut buffer_pool, &mut database_file).unwrap(), long);
+        assert_eq!(database_file.page_count(), 4);
+    }
+}
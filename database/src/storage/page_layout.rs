@@ -239,6 +239,30 @@ impl PageLayout {
         Ok(true)
     }
     
+    /// Replace the document in `slot_id`, keeping its slot id even when the page
+    /// has to be compacted to make room. Returns false, leaving the page
+    /// untouched, if the new document does not fit in the page at all.
+    pub fn replace_document(page: &mut Page, slot_id: SlotId, new_data: &[u8]) -> Result<bool, DatabaseError> {
+        let old_length = Self::get_document(page, slot_id)?.len();
+        if new_data.len() <= old_length || Self::find_free_space(page, new_data.len()).is_ok() {
+            return Self::update_document(page, slot_id, new_data);
+        }
+        if !Self::has_sufficient_space(page, new_data.len() - old_length)? {
+            return Ok(false);
+        }
+        
+        // Drop the old bytes and squeeze out every hole; the slot itself stays
+        // in the directory (as an empty entry) for the new bytes to take over
+        Self::write_slot_entry(page, slot_id, &SlotEntry::tombstone())?;
+        Self::compact_page(page)?;
+        let offset = Self::find_free_space(page, new_data.len())?;
+        Self::write_document_data(page, offset, new_data)?;
+        Self::write_slot_entry(page, slot_id, &SlotEntry::new(offset, new_data.len() as u16))?;
+        
+        Self::update_page_free_space(page)?;
+        Ok(true)
+    }
+    
     /// Compact the page by removing fragmentation
     pub fn compact_page(page: &mut Page) -> Result<(), DatabaseError> {
         let header = Self::read_slot_directory_header(page)?;
@@ -297,6 +321,35 @@ impl PageLayout {
         Ok((used_space as f32 / usable_space as f32) * 100.0)
     }
     
+    /// Bytes of the data area held by deleted or replaced documents, which only
+    /// compaction gives back as contiguous free space
+    pub fn get_fragmented_space(page: &Page) -> Result<usize, DatabaseError> {
+        let header = Self::read_slot_directory_header(page)?;
+        let mut data_end = Self::get_header_size();
+        let mut used_space = 0;
+        
+        for slot_id in 0..header.slot_count {
+            let slot_entry = Self::read_slot_entry(page, slot_id)?;
+            if !slot_entry.is_tombstone() && !slot_entry.is_empty() {
+                data_end = data_end.max((slot_entry.offset + slot_entry.length) as usize);
+                used_space += slot_entry.length as usize;
+            }
+        }
+        
+        // New documents are placed after the last live one, so every gap before it is lost
+        Ok(data_end - Self::get_header_size() - used_space)
+    }
+    
+    /// Free space an insert of `document_size` bytes needs, counting a new slot
+    pub fn space_needed(document_size: usize) -> usize {
+        document_size + SLOT_SIZE
+    }
+    
+    /// Largest document that fits in an otherwise empty page
+    pub fn max_document_size() -> usize {
+        Self::get_usable_page_size(1) - SLOT_SIZE
+    }
+    
     /// Get the number of documents stored in the page
     pub fn get_document_count(page: &Page) -> Result<u16, DatabaseError> {
         let header = Self::read_slot_directory_header(page)?;
@@ -312,6 +365,21 @@ impl PageLayout {
         Ok(count)
     }
     
+    /// Get the slot IDs of all live (non-deleted, non-empty) documents, in slot order
+    pub fn get_live_slots(page: &Page) -> Result<Vec<SlotId>, DatabaseError> {
+        let header = Self::read_slot_directory_header(page)?;
+        let mut slots = Vec::new();
+        
+        for slot_id in 0..header.slot_count {
+            let slot_entry = Self::read_slot_entry(page, slot_id)?;
+            if !slot_entry.is_tombstone() && !slot_entry.is_empty() {
+                slots.push(slot_id);
+            }
+        }
+        
+        Ok(slots)
+    }
+    
     // Helper methods
     
     fn get_header_size() -> usize {
@@ -741,9 +809,11 @@ mod tests {
         PageLayout::delete_document(&mut page, slot4).unwrap(); // Delete "Document4"
         
         assert_eq!(PageLayout::get_document_count(&page).unwrap(), 3);
+        assert_eq!(PageLayout::get_fragmented_space(&page).unwrap(), 18);
         
         // Compact the page
         PageLayout::compact_page(&mut page).unwrap();
+        assert_eq!(PageLayout::get_fragmented_space(&page).unwrap(), 0);
         
         // Verify remaining documents are still accessible
         assert_eq!(PageLayout::get_document(&page, slot1).unwrap(), doc1);
@@ -752,6 +822,41 @@ mod tests {
         assert_eq!(PageLayout::get_document_count(&page).unwrap(), 3);
     }
 
+    #[test]
+    fn test_replace_document_keeps_slot() {
+        let mut page = create_test_page();
+        let filler = vec![7u8; 1000];
+        let slots: Vec<_> = (0..8)
+            .map(|_| PageLayout::insert_document(&mut page, &filler).unwrap())
+            .collect();
+        
+        // The freed space is scattered, so growing slot 3 needs a compaction
+        PageLayout::delete_document(&mut page, slots[1]).unwrap();
+        PageLayout::delete_document(&mut page, slots[5]).unwrap();
+        let grown = vec![9u8; 2500];
+        assert!(PageLayout::replace_document(&mut page, slots[3], &grown).unwrap());
+        assert_eq!(PageLayout::get_document(&page, slots[3]).unwrap(), grown);
+        for &slot_id in &[slots[0], slots[2], slots[4], slots[6], slots[7]] {
+            assert_eq!(PageLayout::get_document(&page, slot_id).unwrap(), filler);
+        }
+        
+        // Too large for the page: nothing changes
+        let huge = vec![1u8; 4000];
+        assert!(!PageLayout::replace_document(&mut page, slots[3], &huge).unwrap());
+        assert_eq!(PageLayout::get_document(&page, slots[3]).unwrap(), grown);
+    }
+
+    #[test]
+    fn test_max_document_size() {
+        let mut page = create_test_page();
+        let too_large = vec![1u8; PageLayout::max_document_size() + 1];
+        assert!(PageLayout::insert_document(&mut page, &too_large).is_err());
+
+        let largest = vec![1u8; PageLayout::max_document_size()];
+        let slot_id = PageLayout::insert_document(&mut page, &largest).unwrap();
+        assert_eq!(PageLayout::get_document(&page, slot_id).unwrap(), largest);
+    }
+
     #[test]
     fn test_utilization_percentage() {
         let mut page = create_test_page();
@@ -769,6 +874,20 @@ mod tests {
         assert!(utilization <= 100.0);
     }
 
+    #[test]
+    fn test_get_live_slots_skips_tombstones() {
+        let mut page = create_test_page();
+        assert!(PageLayout::get_live_slots(&page).unwrap().is_empty());
+        
+        let slot1 = PageLayout::insert_document(&mut page, b"Doc1").unwrap();
+        let slot2 = PageLayout::insert_document(&mut page, b"Doc2").unwrap();
+        let slot3 = PageLayout::insert_document(&mut page, b"Doc3").unwrap();
+        
+        PageLayout::delete_document(&mut page, slot2).unwrap();
+        
+        assert_eq!(PageLayout::get_live_slots(&page).unwrap(), vec![slot1, slot3]);
+    }
+
     #[test]
     fn test_large_document_storage() {
         let mut page = create_test_page();
@@ -7,17 +7,57 @@
 // Slots = Page numbers within each book
 // Dirty = You wrote notes in the margins (needs to be saved)
 // Unpinning = Returning the book (clean or with notes to be filed)
-// TODO: Consider adding a tombstone Vacuum
 
 use crate::{
-    document::bson::{deserialize_document, serialize_document},
-    storage::{buffer_pool::BufferPool, file::DatabaseFile, page_layout::PageLayout},
-    Document,
+    Document, Value,
+    document::{
+        bson::{deserialize_document, serialize_document},
+        object_id::ObjectId,
+    },
+    error::DatabaseError,
+    index::{
+        btree::BTree,
+        primary::PrimaryIndex,
+        secondary::{IndexDefinition, IndexKind, IndexLookup, SecondaryIndex},
+    },
+    query::{
+        aggregate::{Documents, Pipeline},
+        cursor::Cursor,
+        filter::{Condition, Filter, TextSearch},
+        geo::Point,
+        planner::{self, Plan, Planner, QueryPlan, Statistics},
+        sort::Sort,
+        update::{Selector, Update},
+    },
+    storage::{
+        buffer_pool::BufferPool,
+        catalog::{Catalog, DEFAULT_COLLECTION},
+        collection::{Collection, CollectionStats},
+        file::{DatabaseFile, RootPage},
+        forwarding::{ForwardingPointer, MOVED_HEADER_SIZE, MovedRecord},
+        free_space_map::FreeSpaceMap,
+        mvcc::{Snapshot, VersionStore},
+        overflow::OverflowStub,
+        page::{PAGE_SIZE, Page, PageType},
+        page_layout::{PageLayout, SlotId},
+        transaction::Transaction,
+        ttl::ExpiryReport,
+        vacuum::{VacuumOptions, VacuumReport},
+        wal::WriteAheadLog,
+    },
 };
 use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Instant;
 
-#[derive(Debug, Clone, Copy)]
+/// Once the write-ahead log grows past this many bytes the next commit
+/// checkpoints: dirty pages are written to the data file and the log is emptied.
+const WAL_CHECKPOINT_BYTES: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct DocumentId {
     page_id: u64,
     slot_id: u16,
@@ -43,181 +83,2157 @@ impl DocumentId {
 pub struct StorageEngine {
     pub database_file: DatabaseFile,
     buffer_pool: BufferPool,
+    catalog: Arc<Catalog>,
+    free_space_map: FreeSpaceMap,
+    shared: Arc<Shared>,
+    view: View,
+    in_transaction: bool,
+    /// Pages released by writes that are not committed yet.
+    uncommitted_frees: Vec<u64>,
+    versions: VersionStore,
+    vacuum_options: VacuumOptions,
+    last_vacuum: Instant,
+    vacuum_running: bool,
+    last_vacuum_report: Option<VacuumReport>,
+    planner: Arc<Planner>,
+}
+
+/// State every view of an engine works on.
+struct Shared {
+    /// Also taken for the whole of a commit, so commits happen one at a time.
+    wal: Mutex<WriteAheadLog>,
+    /// The catalog as of the last commit.
+    catalog: Mutex<Arc<Catalog>>,
+    /// Pages released by committed writes, returned to the freelist at the next
+    /// checkpoint once no log record can bring their old contents back.
+    committed_frees: Mutex<Vec<u64>>,
+}
+
+impl Shared {
+    fn lock_wal(&self) -> MutexGuard<'_, WriteAheadLog> {
+        // A commit that panicked halfway has not installed anything
+        self.wal.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn catalog(&self) -> Arc<Catalog> {
+        Arc::clone(&self.catalog.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    fn publish_catalog(&self, catalog: Arc<Catalog>) {
+        *self.catalog.lock().unwrap_or_else(PoisonError::into_inner) = catalog;
+    }
+
+    fn lock_committed_frees(&self) -> MutexGuard<'_, Vec<u64>> {
+        self.committed_frees
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// What an engine is to the database it works on.
+enum View {
+    /// The engine that opened the database. It changes pages in place and so
+    /// must have the database to itself while it writes.
+    Owner,
+    /// Made by `reader`; only reads.
+    Reader,
+    /// Made by `writer`: latches the pages it changes and commits them
+    /// alongside other writers.
+    Writer {
+        /// The committed catalog this writer's changes are based on
+        base: Arc<Catalog>,
+        /// The catalog and unsaved free-space map changes as each open undo
+        /// scope began, innermost last
+        scopes: Vec<(Arc<Catalog>, BTreeMap<u64, u8>)>,
+    },
 }
 
 impl StorageEngine {
     pub fn new(database_path: &Path, buffer_pool_size: usize) -> Result<Self> {
-        let database_file = DatabaseFile::open(database_path)?;
-        let buffer_pool = BufferPool::new(buffer_pool_size);
-        Ok(Self {
+        let mut database_file = DatabaseFile::open(database_path)?;
+
+        // Bring the data file up to date with anything committed before a crash
+        let mut wal = WriteAheadLog::open(&WriteAheadLog::path_for(database_path))?;
+        wal.recover(&mut database_file)?;
+        // The freelist is written outside the log, so a crash can leave it stale
+        database_file.check_freelist()?;
+
+        let mut buffer_pool = BufferPool::new(buffer_pool_size);
+        buffer_pool.enable_no_steal();
+
+        let catalog_head = database_file.root_page(RootPage::Catalog);
+        let (catalog, needs_backfill) = match catalog_head {
+            Some(head_page_id) => (
+                Catalog::load(&mut buffer_pool, &mut database_file, head_page_id)?,
+                false,
+            ),
+            None => Self::create_catalog(&mut buffer_pool, &mut database_file)?,
+        };
+
+        let free_space_map_head = database_file.root_page(RootPage::FreeSpaceMap);
+        let free_space_map = match free_space_map_head {
+            Some(head_page_id) => {
+                FreeSpaceMap::load(&mut buffer_pool, &mut database_file, head_page_id)?
+            }
+            None => Self::create_free_space_map(&mut buffer_pool, &mut database_file)?,
+        };
+
+        let catalog = Arc::new(catalog);
+        let mut engine = Self {
             database_file,
             buffer_pool,
+            catalog: Arc::clone(&catalog),
+            free_space_map,
+            shared: Arc::new(Shared {
+                wal: Mutex::new(wal),
+                catalog: Mutex::new(catalog),
+                committed_frees: Mutex::new(Vec::new()),
+            }),
+            view: View::Owner,
+            in_transaction: false,
+            uncommitted_frees: Vec::new(),
+            versions: VersionStore::new(),
+            vacuum_options: VacuumOptions::default(),
+            last_vacuum: Instant::now(),
+            vacuum_running: false,
+            last_vacuum_report: None,
+            planner: Arc::new(Planner::new()),
+        };
+
+        // Files written before the primary index existed need their documents indexed
+        if needs_backfill {
+            engine.rebuild_primary_index()?;
+        }
+        engine.commit()?;
+
+        // Only point the header at new structures once every page they reference is on disk
+        if catalog_head.is_none() || free_space_map_head.is_none() {
+            engine.checkpoint()?;
+            let catalog_head = engine.catalog.head_page_id();
+            let free_space_map_head = engine.free_space_map.head_page_id();
+            engine
+                .database_file
+                .set_root_page(RootPage::Catalog, Some(catalog_head))?;
+            engine
+                .database_file
+                .set_root_page(RootPage::FreeSpaceMap, Some(free_space_map_head))?;
+        }
+
+        Ok(engine)
+    }
+
+    /// An engine over the same database for one write that runs alongside
+    /// reads and other such writes. Instead of changing pages in place it
+    /// latches each page it changes and works on a copy, which its commit
+    /// installs for everyone at once. A commit or write that would build on a
+    /// page another writer latched or has changed since fails with
+    /// `DatabaseError::WriteConflict`, leaving nothing behind; the write can
+    /// then be run again on a new writer, passing `writer_id` of the old one as
+    /// `id` so that it keeps its age. `None` makes it younger than every other.
+    ///
+    /// Uncommitted changes are rolled back when the writer is dropped. While
+    /// writers exist, this engine may only read, through `with_reader`.
+    pub(crate) fn writer(&self, id: Option<u64>) -> Self {
+        let catalog = self.shared.catalog();
+        let mut writer = Self {
+            database_file: self.database_file.handle(),
+            buffer_pool: self.buffer_pool.writer(id),
+            catalog: Arc::clone(&catalog),
+            free_space_map: self.free_space_map.share(),
+            shared: Arc::clone(&self.shared),
+            view: View::Writer {
+                base: catalog,
+                scopes: Vec::new(),
+            },
+            in_transaction: false,
+            uncommitted_frees: Vec::new(),
+            versions: self.versions.share(),
+            vacuum_options: self.vacuum_options,
+            last_vacuum: self.last_vacuum,
+            vacuum_running: false,
+            last_vacuum_report: None,
+            planner: Arc::clone(&self.planner),
+        };
+        // Holds everything allocated since the last commit, to give back if
+        // the commit never happens
+        writer.begin_scope();
+        writer
+    }
+
+    /// An engine over the same database that reads what was last committed.
+    fn reader(&self) -> Self {
+        Self {
+            database_file: self.database_file.handle(),
+            buffer_pool: self.buffer_pool.reader(),
+            catalog: self.shared.catalog(),
+            free_space_map: self.free_space_map.share(),
+            shared: Arc::clone(&self.shared),
+            view: View::Reader,
+            in_transaction: false,
+            uncommitted_frees: Vec::new(),
+            versions: self.versions.share(),
+            vacuum_options: self.vacuum_options,
+            last_vacuum: self.last_vacuum,
+            vacuum_running: false,
+            last_vacuum_report: None,
+            planner: Arc::clone(&self.planner),
+        }
+    }
+
+    /// Run `read` on an engine that sees the writes of every writer committed
+    /// so far, and none that commit while it runs.
+    pub(crate) fn with_reader<R>(&self, read: impl FnOnce(&Self) -> R) -> R {
+        self.buffer_pool.read_committed(|| read(&self.reader()))
+    }
+
+    /// Catch up with what writers made by `writer` have committed, before
+    /// writing to this engine directly again.
+    pub(crate) fn refresh(&mut self) {
+        self.catalog = self.shared.catalog();
+    }
+
+    /// The age of a writer, to pass to `writer` when running its write again.
+    pub(crate) fn writer_id(&self) -> Option<u64> {
+        self.buffer_pool.writer_id()
+    }
+
+    /// Whether a writer's changes can no longer commit because of what other
+    /// writers did, so its write has to run again on a new writer.
+    pub(crate) fn must_retry(&self) -> bool {
+        self.buffer_pool.is_stale()
+    }
+
+    /// Build the catalog of a file that has none yet. Documents written before
+    /// collections existed become the default collection, keeping their primary
+    /// index if the file has one. Returns whether that index still has to be built.
+    fn create_catalog(
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+    ) -> Result<(Catalog, bool)> {
+        let mut data_pages = Vec::new();
+        for page_id in 0..database_file.page_count() {
+            if database_file.read_page(page_id)?.get_page_type() == PageType::Data {
+                data_pages.push(page_id);
+            }
+        }
+
+        let (primary_index, needs_backfill) = match database_file.root_page(RootPage::PrimaryIndex)
+        {
+            Some(root_page_id) => (PrimaryIndex::new(BTree::open(root_page_id)), false),
+            None => (
+                PrimaryIndex::new(BTree::create(buffer_pool, database_file)?),
+                !data_pages.is_empty(),
+            ),
+        };
+
+        let mut catalog = Catalog::create(buffer_pool, database_file)?;
+        catalog.adopt_collection(
+            buffer_pool,
+            database_file,
+            DEFAULT_COLLECTION,
+            primary_index,
+            data_pages,
+        )?;
+        Ok((catalog, needs_backfill))
+    }
+
+    /// Build the free-space map of a file that has none yet from its data pages.
+    fn create_free_space_map(
+        buffer_pool: &mut BufferPool,
+        database_file: &mut DatabaseFile,
+    ) -> Result<FreeSpaceMap> {
+        let mut free_space_map = FreeSpaceMap::create(buffer_pool, database_file)?;
+        for page_id in 0..database_file.page_count() {
+            let page = buffer_pool.get_page(page_id, database_file)?;
+            if page.get_page_type() == PageType::Data {
+                free_space_map.record(page_id, page.get_free_space() as usize);
+            }
+        }
+        free_space_map.save(buffer_pool, database_file)?;
+        Ok(free_space_map)
+    }
+
+    /// Create an empty collection called `name`.
+    pub fn create_collection(&mut self, name: &str) -> Result<()> {
+        self.atomically(|engine| {
+            Arc::make_mut(&mut engine.catalog).create_collection(
+                &mut engine.buffer_pool,
+                &mut engine.database_file,
+                name,
+            )?;
+            Ok(())
         })
     }
 
-    pub fn insert_document(&mut self, document: &Document) -> Result<DocumentId> {
-        // 1. Serialize the document to BSON bytes
-        let document_bytes = serialize_document(document)
-            .map_err(|e| anyhow::anyhow!("Failed to serialize document: {}", e))?;
-        let document_size = document_bytes.len();
+    /// Remove the collection called `name` together with its documents. Returns
+    /// false if there was no such collection. The default collection cannot be
+    /// dropped.
+    pub fn drop_collection(&mut self, name: &str) -> Result<bool> {
+        if name == DEFAULT_COLLECTION {
+            return Err(DatabaseError::Validation(
+                "The default collection cannot be dropped".to_string(),
+            )
+            .into());
+        }
+        self.atomically(|engine| {
+            if engine.versions.is_tracking() && engine.catalog.get(name).is_ok() {
+                let homes: Vec<DocumentId> = engine
+                    .scan_in(name)
+                    .map(|item| item.map(|(home, _)| home))
+                    .collect::<Result<_>>()?;
+                for home in homes {
+                    engine.preserve_version(name, &home)?;
+                }
+            }
+            engine.planner.forget(name);
+            let Some(dropped) = Arc::make_mut(&mut engine.catalog).drop_collection(
+                &mut engine.buffer_pool,
+                &mut engine.database_file,
+                name,
+            )?
+            else {
+                return Ok(false);
+            };
+
+            let mut page_ids = dropped
+                .directory()
+                .page_ids(&engine.buffer_pool, &engine.database_file)?;
+            page_ids.extend(
+                dropped
+                    .primary_index()
+                    .page_ids(&engine.buffer_pool, &engine.database_file)?,
+            );
+            for index in dropped.indexes() {
+                page_ids.extend(index.page_ids(&engine.buffer_pool, &engine.database_file)?);
+            }
+            for page_id in dropped.pages() {
+                page_ids.extend(engine.overflow_pages_in(page_id)?);
+                page_ids.push(page_id);
+            }
+            engine.free_pages_on_commit(page_ids);
+            Ok(true)
+        })
+    }
+
+    /// Names of every collection, sorted.
+    pub fn list_collections(&self) -> Vec<String> {
+        self.catalog.names()
+    }
+
+    /// Index the documents of the default collection by `definition`. The index
+    /// is built from the documents already stored and kept up to date by every
+    /// later write; `find` uses it for equality and range conditions. Creating
+    /// a unique index fails if stored documents already share a key.
+    pub fn create_index(&mut self, definition: IndexDefinition) -> Result<()> {
+        self.create_index_in(DEFAULT_COLLECTION, definition)
+    }
+
+    pub(crate) fn create_index_in(
+        &mut self,
+        collection: &str,
+        definition: IndexDefinition,
+    ) -> Result<()> {
+        definition.validate()?;
+        if definition.kind() == IndexKind::Text {
+            let existing = self
+                .catalog
+                .get(collection)
+                .ok()
+                .and_then(|info| info.text_index());
+            if let Some(existing) = existing {
+                return Err(DatabaseError::Validation(format!(
+                    "Collection '{}' already has a text index '{}'",
+                    collection,
+                    existing.name()
+                ))
+                .into());
+            }
+        }
+        self.planner.forget(collection);
+        self.atomically(|engine| {
+            let page_ids = engine.collection_page_ids(collection)?;
+            let tree = BTree::create(&mut engine.buffer_pool, &mut engine.database_file)?;
+            let index = SecondaryIndex::new(definition, tree);
+            Arc::make_mut(&mut engine.catalog).add_index(
+                &mut engine.buffer_pool,
+                &mut engine.database_file,
+                collection,
+                index.clone(),
+            )?;
+
+            // One page at a time, so the collection never has to fit in memory
+            let mut multikey = BTreeSet::new();
+            for page_id in page_ids {
+                let documents = engine
+                    .scan_pages(vec![page_id])
+                    .collect::<Result<Vec<_>>>()?;
+                for (document_id, document) in documents {
+                    engine.ensure_unique(&index, None, &document, document_id)?;
+                    multikey.extend(index.insert(
+                        &mut engine.buffer_pool,
+                        &mut engine.database_file,
+                        &document,
+                        document_id,
+                    )?);
+                }
+            }
+            let multikey = multikey
+                .into_iter()
+                .map(|path| (index.name().to_string(), path))
+                .collect();
+            engine.mark_multikey(collection, multikey)?;
+            Ok(())
+        })
+    }
+
+    /// Remove the index called `name` from the default collection. Returns false
+    /// if there was no such index.
+    pub fn drop_index(&mut self, name: &str) -> Result<bool> {
+        self.drop_index_in(DEFAULT_COLLECTION, name)
+    }
 
-        // 2. Try to find an existing page with enough free space
-        let page_ids = self.buffer_pool.get_all_page_ids();
+    pub(crate) fn drop_index_in(&mut self, collection: &str, name: &str) -> Result<bool> {
+        self.planner.forget(collection);
+        self.atomically(|engine| {
+            let Some(dropped) = Arc::make_mut(&mut engine.catalog).remove_index(
+                &mut engine.buffer_pool,
+                &mut engine.database_file,
+                collection,
+                name,
+            )?
+            else {
+                return Ok(false);
+            };
+            let page_ids = dropped.page_ids(&engine.buffer_pool, &engine.database_file)?;
+            engine.free_pages_on_commit(page_ids);
+            Ok(true)
+        })
+    }
+
+    /// Definitions of the default collection's secondary indexes, in creation order.
+    pub fn list_indexes(&self) -> Result<Vec<IndexDefinition>> {
+        self.list_indexes_in(DEFAULT_COLLECTION)
+    }
+
+    pub(crate) fn list_indexes_in(&self, collection: &str) -> Result<Vec<IndexDefinition>> {
+        Ok(self
+            .catalog
+            .get(collection)?
+            .indexes()
+            .iter()
+            .map(|index| index.definition().clone())
+            .collect())
+    }
+
+    /// A handle for reading and writing the documents of collection `name`.
+    pub fn collection(&mut self, name: &str) -> Result<Collection<'_>> {
+        self.catalog.get(name)?;
+        Ok(Collection::new(self, name))
+    }
+
+    /// Document count and space usage of collection `name`.
+    pub fn collection_stats(&self, name: &str) -> Result<CollectionStats> {
+        let page_ids: Vec<u64> = self.catalog.get(name)?.pages().collect();
+        let mut stats = CollectionStats {
+            name: name.to_string(),
+            document_count: 0,
+            page_count: page_ids.len(),
+            free_space: 0,
+        };
         for page_id in page_ids {
-            // Pin the page to get mutable access
-            if let Ok(page) = self.buffer_pool.pin_page(page_id, &mut self.database_file) {
-                let free_space = page.get_free_space() as usize;
-
-                // Check if document can fit in this page
-                if document_size <= free_space {
-                    // Insert the document using PageLayout
-                    match PageLayout::insert_document(page, &document_bytes) {
-                        Ok(slot_id) => {
-                            // Mark the page as dirty and unpin it
-                            self.buffer_pool.unpin_page(page_id, true); // true = is_dirty
-                            return Ok(DocumentId {
-                                page_id: page_id,
-                                slot_id,
-                            });
-                        }
-                        Err(_) => {
-                            // Failed to insert, unpin the page without marking dirty
-                            self.buffer_pool.unpin_page(page_id, false);
-                            continue;
+            let (document_count, free_space) = self.buffer_pool.read_page(
+                page_id,
+                &self.database_file,
+                |page| -> Result<_> {
+                    let mut document_count = 0;
+                    for slot_id in PageLayout::get_live_slots(page)? {
+                        // A moved document is counted where it lives, not at its pointer
+                        let record = PageLayout::get_document(page, slot_id)?;
+                        if ForwardingPointer::decode(&record).is_none() {
+                            document_count += 1;
                         }
                     }
-                }
-                // Page doesn't have enough space, unpin it
-                self.buffer_pool.unpin_page(page_id, false);
-            }
+                    Ok((document_count, page.get_free_space() as usize))
+                },
+            )??;
+            stats.document_count += document_count;
+            stats.free_space += free_space;
         }
+        Ok(stats)
+    }
 
-        // Page doesen't exist, or not enough space? Allocate more space and insert a fresh page.
-        let new_page_id = self.database_file.allocate_page()?;
+    pub fn insert_document(&mut self, document: &Document) -> Result<DocumentId> {
+        self.insert_document_in(DEFAULT_COLLECTION, document)
+    }
 
-        let page = self
-            .buffer_pool
-            .pin_page(new_page_id, &mut self.database_file)?;
+    pub(crate) fn insert_document_in(
+        &mut self,
+        collection: &str,
+        document: &Document,
+    ) -> Result<DocumentId> {
+        self.atomically(|engine| engine.insert_document_in_scope(collection, document))
+    }
+
+    fn insert_document_in_scope(
+        &mut self,
+        collection: &str,
+        document: &Document,
+    ) -> Result<DocumentId> {
+        let primary_index = self.catalog.get(collection)?.primary_index();
+        self.planner.record_write(collection);
 
-        let slot_id = PageLayout::insert_document(page, &document_bytes)?;
+        // 1. Serialize the document to BSON bytes
+        let document_bytes = serialize_document(document)
+            .map_err(|e| anyhow::anyhow!("Failed to serialize document: {}", e))?;
 
-        self.buffer_pool.unpin_page(new_page_id, true);
+        // 2. Refuse a second document with the same _id
+        if let Some(id) = document.get_id() {
+            self.ensure_id_is_free(collection, id)?;
+        }
 
-        Ok(DocumentId {
-            page_id: new_page_id,
-            slot_id: slot_id,
-        })
+        // 3. Store it (spilling to overflow pages if needed) and remember where it went
+        let record = self.encode_record(&document_bytes, None)?;
+        let document_id = self.insert_document_internal(collection, &record)?;
+        if self.versions.is_tracking() {
+            self.versions.record(document_id, collection, None, None);
+        }
+        if let Some(id) = document.get_id() {
+            primary_index.insert(
+                &mut self.buffer_pool,
+                &mut self.database_file,
+                id,
+                document_id,
+            )?;
+        }
+        let mut multikey = Vec::new();
+        for index in self.catalog.get(collection)?.indexes() {
+            self.ensure_unique(index, None, document, document_id)?;
+            let array = index.insert(
+                &mut self.buffer_pool,
+                &mut self.database_file,
+                document,
+                document_id,
+            )?;
+            if let Some(path) = array.filter(|path| !index.multikey_paths().contains(path)) {
+                multikey.push((index.name().to_string(), path));
+            }
+        }
+        self.mark_multikey(collection, multikey)?;
+
+        Ok(document_id)
     }
 
-    pub fn get_document(&mut self, document_id: &DocumentId) -> Result<Document> {
-        let page = self
-            .buffer_pool
-            .pin_page(document_id.page_id, &mut self.database_file)?;
-        let document_bytes = PageLayout::get_document(page, document_id.slot_id)?;
-        self.buffer_pool.unpin_page(document_id.page_id(), false);
+    /// Fetch the document stored under `document_id`, following its forwarding
+    /// pointer if it has moved.
+    pub fn get_document(&self, document_id: &DocumentId) -> Result<Document> {
+        let (_, record) = self.resolve(document_id)?;
+        let document_bytes = self.decode_record(record)?;
 
         Ok(deserialize_document(&document_bytes)?)
     }
 
+    pub(crate) fn get_document_in(
+        &self,
+        collection: &str,
+        document_id: &DocumentId,
+    ) -> Result<Document> {
+        self.ensure_owned(collection, document_id)?;
+        self.get_document(document_id)
+    }
+
     pub fn update_document(
         &mut self,
         document_id: &DocumentId,
         new_document: &Document,
     ) -> Result<DocumentId> {
-        // 1. Serialize the new document
+        self.update_document_in(DEFAULT_COLLECTION, document_id, new_document)
+    }
+
+    pub(crate) fn update_document_in(
+        &mut self,
+        collection: &str,
+        document_id: &DocumentId,
+        new_document: &Document,
+    ) -> Result<DocumentId> {
+        self.atomically(|engine| {
+            engine.update_document_in_scope(collection, document_id, new_document)
+        })
+    }
+
+    fn update_document_in_scope(
+        &mut self,
+        collection: &str,
+        document_id: &DocumentId,
+        new_document: &Document,
+    ) -> Result<DocumentId> {
+        self.ensure_owned(collection, document_id)?;
+        self.planner.record_write(collection);
+        let primary_index = self.catalog.get(collection)?.primary_index();
+        self.preserve_version(collection, document_id)?;
+
+        let old_document = self.get_document(document_id)?;
+        let old_id = old_document.get_id().cloned();
+        let new_id = new_document.get_id();
+        if let Some(id) = new_id.filter(|id| old_id.as_ref() != Some(*id)) {
+            self.ensure_id_is_free(collection, id)?;
+        }
+
         let new_document_bytes = serialize_document(new_document)
             .map_err(|e| anyhow::anyhow!("Failed to serialize document: {}", e))?;
-        let new_size = new_document_bytes.len();
+        let (location, old_record) = self.resolve(document_id)?;
+        // A document that already spilled over reuses its overflow pages
+        let previous = OverflowStub::decode(&old_record);
+        let new_record = self.encode_record(&new_document_bytes, previous)?;
+        if let Some(stub) = previous.filter(|_| OverflowStub::decode(&new_record).is_none()) {
+            let page_ids = stub
+                .chain()
+                .page_ids(&self.buffer_pool, &self.database_file)?;
+            self.free_pages_on_commit(page_ids);
+        }
+        self.write_record(collection, document_id, &location, &new_record)?;
+
+        // The document keeps its DocumentId, so only a changed _id needs re-keying
+        if old_id.as_ref() != new_id {
+            if let Some(id) = &old_id {
+                primary_index.remove(&mut self.buffer_pool, &mut self.database_file, id)?;
+            }
+            if let Some(id) = new_id {
+                primary_index.insert(
+                    &mut self.buffer_pool,
+                    &mut self.database_file,
+                    id,
+                    *document_id,
+                )?;
+            }
+        }
+        let mut multikey = Vec::new();
+        for index in self.catalog.get(collection)?.indexes() {
+            self.ensure_unique(index, Some(&old_document), new_document, *document_id)?;
+            let array = index.update(
+                &mut self.buffer_pool,
+                &mut self.database_file,
+                &old_document,
+                new_document,
+                *document_id,
+            )?;
+            if let Some(path) = array.filter(|path| !index.multikey_paths().contains(path)) {
+                multikey.push((index.name().to_string(), path));
+            }
+        }
+        self.mark_multikey(collection, multikey)?;
+
+        Ok(*document_id)
+    }
+
+    /// Record that the named indexes of `collection` now hold array elements,
+    /// each at the given path.
+    fn mark_multikey(&mut self, collection: &str, paths: Vec<(String, String)>) -> Result<()> {
+        for (name, path) in paths {
+            Arc::make_mut(&mut self.catalog).mark_multikey(
+                &mut self.buffer_pool,
+                &mut self.database_file,
+                collection,
+                &name,
+                &path,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Store `record` as the document whose home slot is `home` and which
+    /// currently lives at `location`. The record goes back to its home slot if
+    /// it fits there, otherwise it stays where it is or moves to a page with
+    /// room, and the home slot points at it.
+    fn write_record(
+        &mut self,
+        collection: &str,
+        home: &DocumentId,
+        location: &DocumentId,
+        record: &[u8],
+    ) -> Result<()> {
+        let moved = location != home;
+        if self.replace_in_slot(home, record)? {
+            if moved {
+                self.delete_slot(location)?;
+            }
+            return Ok(());
+        }
+
+        let moved_record = MovedRecord::new(*home, record).encode();
+        if moved && self.replace_in_slot(location, &moved_record)? {
+            return Ok(());
+        }
 
-        // 2. Pin the original page
+        let target = self.insert_document_internal(collection, &moved_record)?;
+        if moved {
+            self.delete_slot(location)?;
+        }
+        // Every record is at least as large as a pointer, so this always fits
+        let pointer = ForwardingPointer::new(target).encode();
+        if !self.replace_in_slot(home, &pointer)? {
+            return Err(DatabaseError::Storage(format!(
+                "No room for a forwarding pointer at page {} slot {}",
+                home.page_id, home.slot_id
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Overwrite the contents of an existing slot. Returns false if its page
+    /// cannot hold `record`.
+    fn replace_in_slot(&mut self, location: &DocumentId, record: &[u8]) -> Result<bool> {
+        let page = self
+            .buffer_pool
+            .pin_page(location.page_id, &mut self.database_file)?;
+        let replaced = match PageLayout::replace_document(page, location.slot_id, record) {
+            Ok(replaced) => replaced,
+            Err(e) => {
+                self.buffer_pool.unpin_page(location.page_id, false);
+                return Err(e.into());
+            }
+        };
+        if replaced {
+            self.free_space_map
+                .record(location.page_id, page.get_free_space() as usize);
+        }
+        self.buffer_pool.unpin_page(location.page_id, replaced);
+        Ok(replaced)
+    }
+
+    /// Tombstone a slot.
+    fn delete_slot(&mut self, location: &DocumentId) -> Result<()> {
         let page = self
             .buffer_pool
-            .pin_page(document_id.page_id, &mut self.database_file)?;
+            .pin_page(location.page_id, &mut self.database_file)?;
+        PageLayout::delete_document(page, location.slot_id)?;
+        self.free_space_map
+            .record(location.page_id, page.get_free_space() as usize);
+        self.buffer_pool.unpin_page(location.page_id, true);
+        Ok(())
+    }
+
+    /// Scan every live document in the default collection.
+    ///
+    /// The collection's pages are visited in page-id order through the buffer
+    /// pool, one page at a time, so the whole file never has to be resident.
+    /// Tombstoned slots are skipped.
+    pub fn scan(&self) -> DocumentScan<'_> {
+        self.scan_in(DEFAULT_COLLECTION)
+    }
 
-        // 3. Get the old document size for comparison
-        let old_document_bytes = PageLayout::get_document(page, document_id.slot_id)?;
-        let old_size = old_document_bytes.len();
+    /// Scan every live document in `collection`, failing if there is no such
+    /// collection.
+    pub(crate) fn scan_in(&self, collection: &str) -> DocumentScan<'_> {
+        match self.collection_page_ids(collection) {
+            Ok(page_ids) => self.scan_pages(page_ids),
+            Err(e) => self.failed_scan(e),
+        }
+    }
+
+    /// Data pages of `collection`, in the order a scan visits them.
+    pub(crate) fn collection_page_ids(&self, collection: &str) -> Result<Vec<u64>> {
+        Ok(self.catalog.get(collection)?.pages().collect())
+    }
+
+    /// Scan the live documents of the given data pages only, so a caller can
+    /// visit a collection a few pages at a time.
+    pub(crate) fn scan_pages(&self, page_ids: Vec<u64>) -> DocumentScan<'_> {
+        DocumentScan {
+            engine: self,
+            source: ScanSource::Pages(page_ids.into_iter()),
+            current_page_id: 0,
+            pending: VecDeque::new(),
+            scores: HashMap::new(),
+            documents_examined: 0,
+            keys_examined: 0,
+            pages_examined: HashSet::new(),
+        }
+    }
+
+    /// A scan that yields `error` and nothing else.
+    fn failed_scan(&self, error: anyhow::Error) -> DocumentScan<'_> {
+        let mut scan = self.scan_pages(Vec::new());
+        scan.source = ScanSource::Failed(Some(error));
+        scan
+    }
+
+    /// Read only the documents `lookup` finds, in index order.
+    fn scan_index(&self, lookup: IndexLookup) -> DocumentScan<'_> {
+        DocumentScan {
+            engine: self,
+            source: ScanSource::Index {
+                lookup: Box::new(lookup),
+                locations: None,
+            },
+            current_page_id: 0,
+            pending: VecDeque::new(),
+            scores: HashMap::new(),
+            documents_examined: 0,
+            keys_examined: 0,
+            pages_examined: HashSet::new(),
+        }
+    }
+
+    /// The documents of the default collection that pass `filter`.
+    pub fn find(&self, filter: Filter) -> Cursor<'_> {
+        self.find_in(DEFAULT_COLLECTION, filter)
+    }
+
+    pub(crate) fn find_in(&self, collection: &str, filter: Filter) -> Cursor<'_> {
+        Cursor::new(self, collection, filter)
+    }
+
+    /// The scan a query of `collection` for `filter` ordered by `sort` reads,
+    /// as the query planner picks it, and the plan it follows.
+    pub(crate) fn plan_scan(
+        &self,
+        collection: &str,
+        filter: &Filter,
+        sort: Option<&Sort>,
+    ) -> (DocumentScan<'_>, QueryPlan) {
+        let near = match filter.near() {
+            Ok(near) => near,
+            Err(e) => return (self.failed_scan(e.into()), QueryPlan::collection_scan()),
+        };
+        match filter.text_search() {
+            Ok(None) => {}
+            Ok(Some(_)) if near.is_some() => {
+                let error = DatabaseError::Query("$text and $near cannot be combined".to_string());
+                return (self.failed_scan(error.into()), QueryPlan::collection_scan());
+            }
+            Ok(Some(search)) => return self.plan_text(collection, search),
+            Err(e) => return (self.failed_scan(e.into()), QueryPlan::collection_scan()),
+        }
+        if let Some((path, point, max_distance)) = near {
+            return self.plan_near(collection, path, point, max_distance);
+        }
+        let mut candidates: Vec<IndexLookup> = match self.catalog.get(collection) {
+            Ok(info) => info
+                .indexes()
+                .iter()
+                .filter_map(|index| index.plan(filter, sort))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        if candidates.is_empty() {
+            return (self.scan_in(collection), QueryPlan::collection_scan());
+        }
 
-        // 4. Check if new document fits in the same slot
-        if new_size <= old_size {
-            // Case 1: New document fits in same slot (in-place update)
-            PageLayout::update_document(page, document_id.slot_id, &new_document_bytes)?;
-            self.buffer_pool.unpin_page(document_id.page_id, true); // Mark as dirty
-            Ok(*document_id) // Return same DocumentId
-        } else {
-            // Case 2: New document doesn't fit, need to relocate
+        let statistics = match self.planner.statistics(collection) {
+            Some(statistics) => statistics,
+            // A scan reports whatever keeps the statistics from being read
+            None => match self.gather_statistics(collection) {
+                Ok(statistics) => {
+                    let statistics = Arc::new(statistics);
+                    self.planner
+                        .store_statistics(collection, statistics.clone());
+                    statistics
+                }
+                Err(_) => return (self.scan_in(collection), QueryPlan::collection_scan()),
+            },
+        };
 
-            // First, check if the same page has enough free space
-            let available_space = page.get_free_space() as usize;
-            if new_size <= available_space + old_size {
-                // Can fit on same page after deleting old document
-                PageLayout::delete_document(page, document_id.slot_id)?;
-                let new_slot_id = PageLayout::insert_document(page, &new_document_bytes)?;
-                self.buffer_pool.unpin_page(document_id.page_id, true);
+        let shape = planner::shape(filter, sort);
+        if let Some(plan) = self.planner.cached_plan(collection, &shape) {
+            let chosen = match &plan.chosen.plan {
+                Plan::CollectionScan => Some(None),
+                Plan::TextScan { .. } | Plan::NearScan { .. } => None,
+                Plan::IndexScan { index, reverse } => candidates
+                    .iter()
+                    .position(|lookup| {
+                        lookup.index.name() == index
+                            && lookup.reverse == *reverse
+                            && lookup.sorted == plan.chosen.sorted
+                    })
+                    .map(Some),
+            };
+            // An index that no longer serves the query as it did (dropped, or
+            // multikey since) is planned afresh
+            match chosen {
+                Some(None) => return (self.scan_in(collection), plan),
+                Some(Some(i)) => return (self.scan_index(candidates.swap_remove(i)), plan),
+                None => {}
+            }
+        }
 
-                Ok(DocumentId::new(document_id.page_id, new_slot_id))
-            } else {
-                // Need to move to different page
+        let fits_in_memory =
+            self.database_file.page_count() as usize <= self.buffer_pool.capacity();
+        let (plan, chosen) = planner::choose(&statistics, &candidates, sort, fits_in_memory);
+        self.planner.cache_plan(collection, shape, &plan);
+        match chosen {
+            Some(i) => (self.scan_index(candidates.swap_remove(i)), plan),
+            None => (self.scan_in(collection), plan),
+        }
+    }
 
-                // Mark old slot as deleted (tombstone)
-                PageLayout::delete_document(page, document_id.slot_id)?;
-                self.buffer_pool.unpin_page(document_id.page_id, true);
+    /// The scan of `collection`'s text index for `search`.
+    fn plan_text(&self, collection: &str, search: &TextSearch) -> (DocumentScan<'_>, QueryPlan) {
+        let Ok(info) = self.catalog.get(collection) else {
+            return (self.scan_in(collection), QueryPlan::collection_scan());
+        };
+        match info.text_index() {
+            Some(index) => (
+                self.scan_index(index.plan_text(search)),
+                QueryPlan::text_scan(index.name()),
+            ),
+            None => {
+                let error = DatabaseError::Query(format!(
+                    "$text needs a text index on collection '{}'",
+                    collection
+                ));
+                (self.failed_scan(error.into()), QueryPlan::collection_scan())
+            }
+        }
+    }
 
-                // Insert into new location (reuse insert_document logic)
-                self.insert_document_internal(&new_document_bytes)
+    /// The scan of `collection`'s geospatial index on `path` for the
+    /// documents nearest `point`.
+    fn plan_near(
+        &self,
+        collection: &str,
+        path: &str,
+        point: Point,
+        max_distance: Option<f64>,
+    ) -> (DocumentScan<'_>, QueryPlan) {
+        let Ok(info) = self.catalog.get(collection) else {
+            return (self.scan_in(collection), QueryPlan::collection_scan());
+        };
+        match info.geo_index(path) {
+            Some(index) => (
+                self.scan_index(index.plan_near(point, max_distance)),
+                QueryPlan::near_scan(index.name()),
+            ),
+            None => {
+                let error = DatabaseError::Query(format!(
+                    "$near needs a geospatial index on '{}' in collection '{}'",
+                    path, collection
+                ));
+                (self.failed_scan(error.into()), QueryPlan::collection_scan())
             }
         }
     }
 
+    /// Read the statistics the planner needs about `collection` and its
+    /// indexes.
+    fn gather_statistics(&self, collection: &str) -> Result<Statistics> {
+        let stats = self.collection_stats(collection)?;
+        let mut indexes = HashMap::new();
+        for index in self.catalog.get(collection)?.indexes() {
+            let index_stats = index.statistics(&self.buffer_pool, &self.database_file)?;
+            indexes.insert(index.name().to_string(), index_stats);
+        }
+        Ok(Statistics {
+            documents: stats.document_count,
+            pages: stats.page_count,
+            indexes,
+        })
+    }
+
+    /// Run `pipeline` over the documents of the default collection.
+    pub fn aggregate<'a>(&'a self, pipeline: &'a Pipeline) -> Documents<'a> {
+        self.aggregate_in(DEFAULT_COLLECTION, pipeline)
+    }
+
+    pub(crate) fn aggregate_in<'a>(
+        &'a self,
+        collection: &str,
+        pipeline: &'a Pipeline,
+    ) -> Documents<'a> {
+        let documents = self
+            .scan_in(collection)
+            .map(|item| item.map(|(_, document)| document));
+        pipeline.run(documents)
+    }
+
+    /// Write all dirty pages back to the database file, sync it to disk and
+    /// truncate the write-ahead log.
+    pub fn flush(&mut self) -> Result<()> {
+        self.commit()?;
+        self.checkpoint()
+    }
+
     pub fn delete_document(&mut self, document_id: &DocumentId) -> Result<()> {
-        // 1. Pin the page containing the document
+        self.delete_document_in(DEFAULT_COLLECTION, document_id)
+    }
+
+    pub(crate) fn delete_document_in(
+        &mut self,
+        collection: &str,
+        document_id: &DocumentId,
+    ) -> Result<()> {
+        self.atomically(|engine| engine.delete_document_in_scope(collection, document_id))
+    }
+
+    fn delete_document_in_scope(
+        &mut self,
+        collection: &str,
+        document_id: &DocumentId,
+    ) -> Result<()> {
+        self.ensure_owned(collection, document_id)?;
+        self.planner.record_write(collection);
+        let primary_index = self.catalog.get(collection)?.primary_index();
+        self.preserve_version(collection, document_id)?;
+
+        let document = self.get_document(document_id)?;
+        let (location, record) = self.resolve(document_id)?;
+        if let Some(stub) = OverflowStub::decode(&record) {
+            let page_ids = stub
+                .chain()
+                .page_ids(&self.buffer_pool, &self.database_file)?;
+            self.free_pages_on_commit(page_ids);
+        }
+
+        // Tombstone the record and, if it moved, the pointer to it
+        if location != *document_id {
+            self.delete_slot(&location)?;
+        }
+        self.delete_slot(document_id)?;
+
+        if let Some(id) = document.get_id() {
+            primary_index.remove(&mut self.buffer_pool, &mut self.database_file, id)?;
+        }
+        for index in self.catalog.get(collection)?.indexes() {
+            index.remove(
+                &mut self.buffer_pool,
+                &mut self.database_file,
+                &document,
+                *document_id,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Start a transaction. Writes made through it become visible to the engine
+    /// immediately but are only made durable by [`Transaction::commit`], and are
+    /// undone by [`Transaction::rollback`] or by dropping the handle.
+    pub fn begin_transaction(&mut self) -> Result<Transaction<'_>> {
+        // Anything still pending belongs to earlier operations, not to this transaction
+        self.commit()?;
+        self.in_transaction = true;
+        self.begin_scope();
+        Ok(Transaction::new(self))
+    }
+
+    pub(crate) fn commit_transaction(&mut self) -> Result<()> {
+        self.release_scope();
+        self.in_transaction = false;
+        self.commit()?;
+        self.vacuum_if_due()
+    }
+
+    pub(crate) fn rollback_transaction(&mut self) -> Result<()> {
+        self.in_transaction = false;
+        // The transaction started with nothing uncommitted, so every pending free is its own
+        self.uncommitted_frees.clear();
+        self.versions.truncate_pending(0);
+        self.rollback_scope()
+    }
+
+    /// Run a write so that it either fully applies or leaves no trace. Outside a
+    /// transaction the write is committed to the log before returning.
+    fn atomically<T>(&mut self, operation: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        self.begin_scope();
+        let frees_before = self.uncommitted_frees.len();
+        let versions_before = self.versions.pending_len();
+        let result = operation(self).and_then(|value| {
+            // The free-space map is logged and undone with the pages it
+            // describes. A writer saves it when it commits, since latching its
+            // pages any earlier would hold up every other writer
+            if !matches!(self.view, View::Writer { .. }) {
+                self.free_space_map
+                    .save(&mut self.buffer_pool, &mut self.database_file)?;
+            }
+            Ok(value)
+        });
+        match result {
+            Ok(value) => {
+                self.release_scope();
+                if !self.in_transaction {
+                    self.commit()?;
+                    self.vacuum_if_due()?;
+                }
+                Ok(value)
+            }
+            Err(e) => {
+                self.uncommitted_frees.truncate(frees_before);
+                self.versions.truncate_pending(versions_before);
+                self.rollback_scope()?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Open an undo scope: page changes and page allocations made from now on
+    /// can be undone together.
+    fn begin_scope(&mut self) {
+        self.buffer_pool.begin_undo_scope();
+        self.database_file.begin_allocation_scope();
+        if let View::Writer { scopes, .. } = &mut self.view {
+            scopes.push((
+                Arc::clone(&self.catalog),
+                self.free_space_map.unsaved_changes(),
+            ));
+        }
+    }
+
+    fn release_scope(&mut self) {
+        self.buffer_pool.release_undo_scope();
+        self.database_file.release_allocation_scope();
+        if let View::Writer { scopes, .. } = &mut self.view {
+            scopes.pop();
+        }
+    }
+
+    /// Undo the innermost undo scope, including any catalog or free-space map
+    /// change made in it, and give back the pages it allocated.
+    fn rollback_scope(&mut self) -> Result<()> {
+        self.buffer_pool
+            .rollback_undo_scope(&mut self.database_file)?;
+        // Drop the pages from the pool before giving them back, since another
+        // writer may allocate one again and commit it before this returns
+        for &page_id in self.database_file.allocated_in_scope() {
+            self.buffer_pool.discard_page(page_id);
+        }
+        self.database_file.rollback_allocation_scope()?;
+        if let View::Writer { scopes, .. } = &mut self.view {
+            // Its pages are only ever changed by the writer's commit
+            if let Some((catalog, changes)) = scopes.pop() {
+                self.catalog = catalog;
+                self.free_space_map.restore_unsaved_changes(changes);
+            }
+            return Ok(());
+        }
+        Arc::make_mut(&mut self.catalog).reload(&mut self.buffer_pool, &mut self.database_file)?;
+        self.free_space_map
+            .reload(&mut self.buffer_pool, &mut self.database_file)?;
+        Ok(())
+    }
+
+    /// Make every page changed since the last commit durable by logging it.
+    /// Called at the end of each write; the change is acknowledged once this returns.
+    fn commit(&mut self) -> Result<()> {
+        if let View::Writer { .. } = self.view {
+            return self.commit_writer();
+        }
+        let shared = Arc::clone(&self.shared);
+        let mut wal = shared.lock_wal();
+        self.versions.commit();
+        shared.publish_catalog(Arc::clone(&self.catalog));
+        let page_ids = self.buffer_pool.take_uncommitted_pages();
+        if page_ids.is_empty() {
+            return Ok(());
+        }
+
+        for page_id in page_ids {
+            if let Some(image) = self.buffer_pool.page_image(page_id) {
+                wal.log_page(page_id, &image)?;
+            }
+        }
+        wal.commit(self.database_file.page_count())?;
+        shared
+            .lock_committed_frees()
+            .append(&mut self.uncommitted_frees);
+
+        // Pages held back while uncommitted may have pushed the pool over capacity
+        self.buffer_pool
+            .shrink_to_capacity(&mut self.database_file)?;
+
+        if wal.size() >= WAL_CHECKPOINT_BYTES {
+            self.checkpoint_with(&mut wal)?;
+        }
+        Ok(())
+    }
+
+    /// Commit a writer: check that no other writer changed what it built on,
+    /// log its pages, then install them together with its catalog and
+    /// document versions.
+    fn commit_writer(&mut self) -> Result<()> {
+        let shared = Arc::clone(&self.shared);
+        let mut wal = shared.lock_wal();
+        let View::Writer { base, .. } = &self.view else {
+            unreachable!("only writers commit this way");
+        };
+        if self.buffer_pool.is_stale() {
+            return Err(DatabaseError::WriteConflict.into());
+        }
+        let catalog = shared
+            .catalog()
+            .rebase(base, &self.catalog)
+            .ok_or(DatabaseError::WriteConflict)?;
+        self.free_space_map
+            .save(&mut self.buffer_pool, &mut self.database_file)?;
+
+        let page_ids = self.buffer_pool.take_uncommitted_pages();
+        for &page_id in &page_ids {
+            if let Some(image) = self.buffer_pool.page_image(page_id) {
+                wal.log_page(page_id, &image)?;
+            }
+        }
+        if !page_ids.is_empty() {
+            wal.commit(self.database_file.page_count())?;
+        }
+
+        let catalog = Arc::new(catalog);
+        let versions = &mut self.versions;
+        self.buffer_pool.install(&page_ids, || {
+            shared.publish_catalog(Arc::clone(&catalog));
+            versions.commit();
+        });
+        shared
+            .lock_committed_frees()
+            .append(&mut self.uncommitted_frees);
+        self.buffer_pool.release_latches();
+        self.catalog = Arc::clone(&catalog);
+        self.view = View::Writer {
+            base: catalog,
+            scopes: Vec::new(),
+        };
+        // The outermost scope began with the writer; what it allocated is now in use
+        self.buffer_pool.release_undo_scope();
+        self.database_file.release_allocation_scope();
+        self.begin_scope();
+
+        self.buffer_pool
+            .shrink_to_capacity(&mut self.database_file)?;
+        if wal.size() >= WAL_CHECKPOINT_BYTES {
+            self.checkpoint_with(&mut wal)?;
+        }
+        Ok(())
+    }
+
+    /// Write every dirty page to the data file, sync it and empty the log.
+    fn checkpoint(&mut self) -> Result<()> {
+        let shared = Arc::clone(&self.shared);
+        let mut wal = shared.lock_wal();
+        self.checkpoint_with(&mut wal)
+    }
+
+    fn checkpoint_with(&mut self, wal: &mut WriteAheadLog) -> Result<()> {
+        self.buffer_pool.flush_all(&mut self.database_file)?;
+        self.database_file.sync()?;
+        wal.reset()?;
+        self.release_committed_frees()
+    }
+
+    /// Delete up to `limit` documents that TTL indexes mark expired as of
+    /// `now`, in one atomic write.
+    pub fn expire_documents(&mut self, now: DateTime<Utc>, limit: usize) -> Result<ExpiryReport> {
+        let mut report = ExpiryReport::default();
+        let mut expired: Vec<(String, DocumentId)> = Vec::new();
+        let mut seen = HashSet::new();
+        for collection in self.catalog.names() {
+            for definition in self.list_indexes_in(&collection)? {
+                let Some(expire_after) = definition.expires_after() else {
+                    continue;
+                };
+                report.indexes_checked += 1;
+                // A duration too long to subtract never runs out
+                let Some(cutoff) = chrono::Duration::from_std(expire_after)
+                    .ok()
+                    .and_then(|duration| now.checked_sub_signed(duration))
+                else {
+                    continue;
+                };
+                let mut filter = Filter::Field {
+                    path: definition.keys()[0].0.clone(),
+                    conditions: vec![Condition::Lt(Value::DateTime(cutoff))],
+                };
+                // A partial index only expires the documents it holds
+                if let Some(partial) = definition.partial_filter() {
+                    filter = Filter::And(vec![filter, Filter::parse(partial)?]);
+                }
+                for item in self.find_in(&collection, filter) {
+                    if expired.len() >= limit {
+                        break;
+                    }
+                    let (document_id, _) = item?;
+                    if seen.insert(document_id) {
+                        expired.push((collection.clone(), document_id));
+                    }
+                }
+            }
+        }
+
+        self.atomically(|engine| {
+            for (collection, document_id) in &expired {
+                engine.delete_document_in_scope(collection, document_id)?;
+            }
+            Ok(())
+        })?;
+        for (collection, _) in expired {
+            report.documents_removed += 1;
+            *report.removed_by_collection.entry(collection).or_default() += 1;
+        }
+        Ok(report)
+    }
+
+    /// Checkpoint and give the free pages at the end of the database file back to
+    /// the filesystem. Returns how many pages the file shrank by.
+    pub fn truncate(&mut self) -> Result<u64> {
+        self.flush()?;
+        Ok(self.database_file.truncate()?)
+    }
+
+    /// Compact fragmented data pages and free empty ones in every collection,
+    /// and drop the document versions no open snapshot needs any more.
+    ///
+    /// Each page is handled in its own atomic write, so a failure part way
+    /// leaves the pages already vacuumed in their new state. Freed pages are put
+    /// on the freelist before this returns.
+    pub fn vacuum(&mut self) -> Result<VacuumReport> {
+        let mut report = VacuumReport::default();
+        let result = self.vacuum_targets().and_then(|targets| {
+            targets.into_iter().try_for_each(|(collection, page_id)| {
+                self.vacuum_step(&collection, page_id, &mut report)
+            })
+        });
+        self.last_vacuum = Instant::now();
+        result?;
+        self.finish_vacuum(report)
+    }
+
+    /// The data pages a vacuum pass visits, with the collections holding them.
+    pub(crate) fn vacuum_targets(&self) -> Result<Vec<(String, u64)>> {
+        let mut targets = Vec::new();
+        for name in self.catalog.names() {
+            for page_id in self.collection_page_ids(&name)? {
+                targets.push((name.clone(), page_id));
+            }
+        }
+        Ok(targets)
+    }
+
+    /// Vacuum one data page of `collection` in an atomic write of its own,
+    /// adding what it reclaimed to `report`. A page the collection no longer
+    /// holds is skipped, so a pass can let other writes in between pages.
+    pub(crate) fn vacuum_step(
+        &mut self,
+        collection: &str,
+        page_id: u64,
+        report: &mut VacuumReport,
+    ) -> Result<()> {
+        let held = self
+            .catalog
+            .get(collection)
+            .is_ok_and(|info| info.owns_page(page_id));
+        if !held {
+            return Ok(());
+        }
+        let max_utilization = self.vacuum_options.max_utilization;
+        // The pass's own writes must not trigger a scheduled vacuum
+        self.vacuum_running = true;
+        let result = self.atomically(|engine| {
+            engine.vacuum_page(collection, page_id, max_utilization, report)
+        });
+        self.vacuum_running = false;
+        result
+    }
+
+    /// End a vacuum pass that reclaimed `report`: put the pages it freed on
+    /// the freelist and drop the versions no open snapshot needs any more.
+    pub(crate) fn finish_vacuum(&mut self, mut report: VacuumReport) -> Result<VacuumReport> {
+        if report.pages_freed > 0 {
+            self.checkpoint()?;
+        }
+        report.versions_reclaimed = self.versions.prune();
+        self.last_vacuum = Instant::now();
+        self.last_vacuum_report = Some(report.clone());
+        Ok(report)
+    }
+
+    pub fn vacuum_options(&self) -> VacuumOptions {
+        self.vacuum_options
+    }
+
+    /// Change the compaction threshold and the schedule of automatic vacuums.
+    pub fn set_vacuum_options(&mut self, options: VacuumOptions) {
+        self.vacuum_options = options;
+    }
+
+    /// The result of the most recent vacuum, whether run on demand or on schedule.
+    pub fn last_vacuum_report(&self) -> Option<&VacuumReport> {
+        self.last_vacuum_report.as_ref()
+    }
+
+    /// Run the scheduled vacuum if its interval has passed.
+    pub(crate) fn vacuum_if_due(&mut self) -> Result<()> {
+        if self.vacuum_due() {
+            self.vacuum()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the scheduled vacuum should run. Only the engine that opened the
+    /// database runs it, since it rewrites pages of every collection.
+    fn vacuum_due(&self) -> bool {
+        match self.vacuum_options.interval {
+            Some(interval) => {
+                matches!(self.view, View::Owner)
+                    && !self.vacuum_running
+                    && self.last_vacuum.elapsed() >= interval
+            }
+            None => false,
+        }
+    }
+
+    fn vacuum_page(
+        &mut self,
+        collection: &str,
+        page_id: u64,
+        max_utilization: f32,
+        report: &mut VacuumReport,
+    ) -> Result<()> {
+        report.forwarding_pointers_collapsed +=
+            self.collapse_forwarding_pointers(collection, page_id)?;
+
+        let page = self
+            .buffer_pool
+            .get_page(page_id, &mut self.database_file)?;
+        let document_count = PageLayout::get_document_count(page)?;
+        let fragmented_space = PageLayout::get_fragmented_space(page)?;
+        let utilization = PageLayout::get_utilization_percentage(page)?;
+        report.pages_scanned += 1;
+
+        if document_count == 0 {
+            Arc::make_mut(&mut self.catalog).remove_page(
+                &mut self.buffer_pool,
+                &mut self.database_file,
+                collection,
+                page_id,
+            )?;
+            self.free_pages_on_commit(vec![page_id]);
+            report.pages_freed += 1;
+            report.bytes_reclaimed += PAGE_SIZE;
+        } else if fragmented_space > 0 && utilization <= max_utilization {
+            let page = self
+                .buffer_pool
+                .pin_page(page_id, &mut self.database_file)?;
+            PageLayout::compact_page(page)?;
+            self.free_space_map
+                .record(page_id, page.get_free_space() as usize);
+            self.buffer_pool.unpin_page(page_id, true);
+            report.pages_compacted += 1;
+            report.bytes_reclaimed += fragmented_space;
+        }
+        Ok(())
+    }
+
+    /// Move documents whose home slot is on `page_id` back home where the page
+    /// now has room for them. Returns how many came back.
+    fn collapse_forwarding_pointers(&mut self, collection: &str, page_id: u64) -> Result<usize> {
         let page = self
             .buffer_pool
-            .pin_page(document_id.page_id, &mut self.database_file)?;
+            .get_page(page_id, &mut self.database_file)?;
+        let mut pointers = Vec::new();
+        for slot_id in PageLayout::get_live_slots(page)? {
+            if let Some(pointer) =
+                ForwardingPointer::decode(&PageLayout::get_document(page, slot_id)?)
+            {
+                pointers.push((DocumentId::new(page_id, slot_id), pointer.target()));
+            }
+        }
 
-        // 2. Mark the document slot as deleted (tombstone)
-        PageLayout::delete_document(page, document_id.slot_id)?;
+        let mut collapsed = 0;
+        for (home, target) in pointers {
+            // The document itself does not change, but a snapshot scan must not
+            // miss it moving onto a page it has already visited
+            self.preserve_version(collection, &home)?;
+            let (_, record) = self.resolve(&home)?;
+            if self.replace_in_slot(&home, &record)? {
+                self.delete_slot(&target)?;
+                collapsed += 1;
+            }
+        }
+        Ok(collapsed)
+    }
 
-        // 3. Mark page as dirty and unpin
-        self.buffer_pool.unpin_page(document_id.page_id, true);
+    /// Hand `page_ids` to the freelist once the current write commits. They are
+    /// removed from the free-space map now so no insert picks them in the meantime.
+    fn free_pages_on_commit(&mut self, page_ids: Vec<u64>) {
+        for &page_id in &page_ids {
+            self.free_space_map.record(page_id, 0);
+        }
+        self.uncommitted_frees.extend(page_ids);
+    }
 
+    /// Put the pages freed by committed writes on the freelist. Only safe right
+    /// after a checkpoint: a crash before this leaks the pages, never reuses them
+    /// while a log record still describes them.
+    fn release_committed_frees(&mut self) -> Result<()> {
+        let committed_frees = std::mem::take(&mut *self.shared.lock_committed_frees());
+        if committed_frees.is_empty() {
+            return Ok(());
+        }
+        for page_id in committed_frees {
+            if self.buffer_pool.contains_page(page_id) {
+                self.buffer_pool
+                    .force_evict_page(page_id, &mut self.database_file)?;
+            }
+            self.database_file.free_page(page_id)?;
+            // Read while being freed, by a writer bound to find it changed
+            self.buffer_pool.discard_page(page_id);
+        }
+        self.database_file.sync()?;
         Ok(())
     }
 
-    // Helper function to avoid code duplication
-    fn insert_document_internal(&mut self, document_bytes: &[u8]) -> Result<DocumentId> {
-        let document_size = document_bytes.len();
+    /// A consistent view of the database as of the last commit. Reads through
+    /// it see no write committed later, for as long as it is kept.
+    pub fn snapshot(&self) -> Snapshot {
+        self.versions
+            .snapshot(self.catalog.names().into_iter().collect())
+    }
+
+    /// How many old document versions are kept for open snapshots.
+    pub fn retained_versions(&self) -> usize {
+        self.versions.len()
+    }
+
+    /// The document whose home slot is `document_id` as `snapshot` sees it, or
+    /// None if the slot held no document then.
+    pub fn get_document_at(
+        &self,
+        document_id: &DocumentId,
+        snapshot: &Snapshot,
+    ) -> Result<Option<Document>> {
+        match self.versions.visible(document_id, snapshot) {
+            Some(version) => Ok(version.document().map(deserialize_document).transpose()?),
+            None => Ok(Some(self.get_document(document_id)?)),
+        }
+    }
 
-        // Try to find an existing page with enough free space
-        let page_ids = self.buffer_pool.get_all_page_ids();
+    /// Fetch a document by its `_id` as `snapshot` sees it.
+    pub fn get_by_id_at(&self, id: &ObjectId, snapshot: &Snapshot) -> Result<Option<Document>> {
+        self.get_by_id_at_in(DEFAULT_COLLECTION, id, snapshot)
+    }
+
+    pub(crate) fn get_by_id_at_in(
+        &self,
+        collection: &str,
+        id: &ObjectId,
+        snapshot: &Snapshot,
+    ) -> Result<Option<Document>> {
+        snapshot.ensure_collection(collection)?;
+        // Changed since the snapshot: only its old version can say where it was
+        for (_, version) in self.versions.changed_since(snapshot) {
+            if version.collection() == collection && version.id() == Some(id) {
+                return Ok(version.document().map(deserialize_document).transpose()?);
+            }
+        }
+        // Otherwise it is where it is now, in a slot nothing has changed since.
+        // A collection dropped since has none left.
+        if !self.catalog.contains(collection) {
+            return Ok(None);
+        }
+        match self.locate_in(collection, id)? {
+            Some(home) if self.versions.visible(&home, snapshot).is_none() => {
+                Ok(Some(self.get_document(&home)?))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Every document of the default collection as `snapshot` sees it.
+    pub fn scan_at(&self, snapshot: &Snapshot) -> Result<Vec<(DocumentId, Document)>> {
+        self.scan_at_in(DEFAULT_COLLECTION, snapshot)
+    }
+
+    pub(crate) fn scan_at_in(
+        &self,
+        collection: &str,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<(DocumentId, Document)>> {
+        snapshot.ensure_collection(collection)?;
+        let mut seen = HashSet::new();
+        let mut documents = Vec::new();
+        // A collection dropped since has no pages left
+        let page_ids = match self.catalog.get(collection) {
+            Ok(info) => info.pages().collect(),
+            Err(_) => Vec::new(),
+        };
         for page_id in page_ids {
-            if let Ok(page) = self.buffer_pool.pin_page(page_id, &mut self.database_file) {
-                let free_space = page.get_free_space() as usize;
-
-                if document_size <= free_space {
-                    match PageLayout::insert_document(page, document_bytes) {
-                        Ok(slot_id) => {
-                            self.buffer_pool.unpin_page(page_id, true);
-                            return Ok(DocumentId::new(page_id, slot_id));
-                        }
-                        Err(_) => {
-                            self.buffer_pool.unpin_page(page_id, false);
-                            continue;
-                        }
+            documents.extend(self.scan_page_at(page_id, snapshot, &mut seen)?);
+        }
+        documents.extend(self.scan_changed_at(collection, snapshot, &seen)?);
+        Ok(documents)
+    }
+
+    /// The documents on data page `page_id` whose slots have not changed since
+    /// `snapshot`, skipping those already in `seen` and adding the rest to it.
+    /// Together with [`Self::scan_changed_at`] this lets a caller scan a
+    /// snapshot a page at a time, letting writers in between pages.
+    pub(crate) fn scan_page_at(
+        &self,
+        page_id: u64,
+        snapshot: &Snapshot,
+        seen: &mut HashSet<DocumentId>,
+    ) -> Result<Vec<(DocumentId, Document)>> {
+        let mut documents = Vec::new();
+        for item in self.scan_pages(vec![page_id]) {
+            let (home, document) = item?;
+            if self.versions.visible(&home, snapshot).is_none() && seen.insert(home) {
+                documents.push((home, document));
+            }
+        }
+        Ok(documents)
+    }
+
+    /// The documents of `collection` that `snapshot` sees in slots changed
+    /// since, apart from those in `seen`.
+    pub(crate) fn scan_changed_at(
+        &self,
+        collection: &str,
+        snapshot: &Snapshot,
+        seen: &HashSet<DocumentId>,
+    ) -> Result<Vec<(DocumentId, Document)>> {
+        let mut documents = Vec::new();
+        for (home, version) in self.versions.changed_since(snapshot) {
+            if version.collection() != collection || seen.contains(&home) {
+                continue;
+            }
+            if let Some(document_bytes) = version.document() {
+                documents.push((home, deserialize_document(document_bytes)?));
+            }
+        }
+        documents.sort_by_key(|(home, _)| (home.page_id, home.slot_id));
+        Ok(documents)
+    }
+
+    /// Keep what `home` holds now for the open snapshots, before the current
+    /// write changes it.
+    fn preserve_version(&mut self, collection: &str, home: &DocumentId) -> Result<()> {
+        if !self.versions.is_tracking() {
+            return Ok(());
+        }
+        let (_, record) = self.resolve(home)?;
+        let document_bytes = self.decode_record(record)?;
+        let id = deserialize_document(&document_bytes)?.get_id().cloned();
+        self.versions
+            .record(*home, collection, id, Some(document_bytes));
+        Ok(())
+    }
+
+    /// Find the current location of the document whose `_id` is `id`.
+    pub fn locate(&self, id: &ObjectId) -> Result<Option<DocumentId>> {
+        self.locate_in(DEFAULT_COLLECTION, id)
+    }
+
+    pub(crate) fn locate_in(&self, collection: &str, id: &ObjectId) -> Result<Option<DocumentId>> {
+        let primary_index = self.catalog.get(collection)?.primary_index();
+        Ok(primary_index.get(&self.buffer_pool, &self.database_file, id)?)
+    }
+
+    /// Fetch a document by its `_id`.
+    pub fn get_by_id(&self, id: &ObjectId) -> Result<Option<Document>> {
+        self.get_by_id_in(DEFAULT_COLLECTION, id)
+    }
+
+    pub(crate) fn get_by_id_in(&self, collection: &str, id: &ObjectId) -> Result<Option<Document>> {
+        match self.locate_in(collection, id)? {
+            Some(document_id) => Ok(Some(self.get_document(&document_id)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replace the document whose `_id` is `id`. The stored document keeps `id`
+    /// as its `_id` whatever `new_document` carries, so the key stays stable.
+    pub fn update_by_id(&mut self, id: &ObjectId, new_document: &Document) -> Result<DocumentId> {
+        self.update_by_id_in(DEFAULT_COLLECTION, id, new_document)
+    }
+
+    pub(crate) fn update_by_id_in(
+        &mut self,
+        collection: &str,
+        id: &ObjectId,
+        new_document: &Document,
+    ) -> Result<DocumentId> {
+        let document_id = self
+            .locate_in(collection, id)?
+            .ok_or_else(|| DatabaseError::Document(format!("No document with _id {}", id)))?;
+
+        let mut document = new_document.clone();
+        document.set_id(id.clone());
+        self.update_document_in(collection, &document_id, &document)
+    }
+
+    /// Apply `update` to the document with the given `_id`, or to the first
+    /// document that matches a filter. Returns the updated document's id, or
+    /// None if nothing matched.
+    pub fn update_one(
+        &mut self,
+        selector: impl Into<Selector>,
+        update: &Update,
+    ) -> Result<Option<DocumentId>> {
+        self.update_one_in(DEFAULT_COLLECTION, selector.into(), update)
+    }
+
+    pub(crate) fn update_one_in(
+        &mut self,
+        collection: &str,
+        selector: Selector,
+        update: &Update,
+    ) -> Result<Option<DocumentId>> {
+        let found = match selector {
+            Selector::Id(id) => match self.locate_in(collection, &id)? {
+                Some(document_id) => Some((document_id, self.get_document(&document_id)?)),
+                None => None,
+            },
+            Selector::Filter(filter) => self.find_in(collection, filter).next().transpose()?,
+        };
+        let Some((document_id, mut document)) = found else {
+            return Ok(None);
+        };
+
+        update.apply(&mut document)?;
+        self.update_document_in(collection, &document_id, &document)?;
+        Ok(Some(document_id))
+    }
+
+    /// Delete the document whose `_id` is `id`. Returns false if there was none.
+    pub fn delete_by_id(&mut self, id: &ObjectId) -> Result<bool> {
+        self.delete_by_id_in(DEFAULT_COLLECTION, id)
+    }
+
+    pub(crate) fn delete_by_id_in(&mut self, collection: &str, id: &ObjectId) -> Result<bool> {
+        match self.locate_in(collection, id)? {
+            Some(document_id) => {
+                self.delete_document_in(collection, &document_id)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn ensure_id_is_free(&self, collection: &str, id: &ObjectId) -> Result<()> {
+        if self.locate_in(collection, id)?.is_some() {
+            return Err(DatabaseError::Index(format!("Duplicate _id {}", id)).into());
+        }
+        Ok(())
+    }
+
+    /// Refuse `new`, about to be stored at `location` in place of `old`, if a
+    /// unique `index` already holds one of its values for another document.
+    /// Keys widen numbers to f64, so the documents sharing a key are read back
+    /// to compare the values themselves.
+    fn ensure_unique(
+        &self,
+        index: &SecondaryIndex,
+        old: Option<&Document>,
+        new: &Document,
+        location: DocumentId,
+    ) -> Result<()> {
+        let candidates =
+            index.unique_candidates(&self.buffer_pool, &self.database_file, old, new, location)?;
+        for (value, others) in candidates {
+            for other in others {
+                if index.holds(&self.get_document(&other)?, &value)? {
+                    return Err(DatabaseError::DuplicateKey {
+                        index: index.name().to_string(),
+                        key: value,
                     }
+                    .into());
                 }
-                self.buffer_pool.unpin_page(page_id, false);
+            }
+        }
+        Ok(())
+    }
+
+    /// Raw contents of a document's slot: the encoded document or an overflow stub.
+    fn read_record(&self, document_id: &DocumentId) -> Result<Vec<u8>> {
+        let record =
+            self.buffer_pool
+                .read_page(document_id.page_id, &self.database_file, |page| {
+                    PageLayout::get_document(page, document_id.slot_id)
+                })?;
+        Ok(record?)
+    }
+
+    /// Where the document with home slot `document_id` lives and its record
+    /// there (an inline document or an overflow stub).
+    fn resolve(&self, document_id: &DocumentId) -> Result<(DocumentId, Vec<u8>)> {
+        let record = self.read_record(document_id)?;
+        let Some(pointer) = ForwardingPointer::decode(&record) else {
+            let record = match MovedRecord::decode(&record) {
+                Some(moved) => moved.record().to_vec(),
+                None => record,
+            };
+            return Ok((*document_id, record));
+        };
+
+        let target = pointer.target();
+        let moved_record = self.read_record(&target)?;
+        match MovedRecord::decode(&moved_record) {
+            Some(moved) if moved.home() == *document_id => Ok((target, moved.record().to_vec())),
+            _ => Err(DatabaseError::Storage(format!(
+                "Broken forwarding pointer from page {} slot {} to page {} slot {}",
+                document_id.page_id, document_id.slot_id, target.page_id, target.slot_id
+            ))
+            .into()),
+        }
+    }
+
+    /// The slot contents for `document_bytes`: the document itself if it fits in
+    /// a page, otherwise a stub pointing at an overflow chain. `previous` is the
+    /// stub of the record being replaced, whose chain is rewritten in place.
+    fn encode_record(
+        &mut self,
+        document_bytes: &[u8],
+        previous: Option<OverflowStub>,
+    ) -> Result<Vec<u8>> {
+        // Inline records leave room for the header they get if they ever move
+        if document_bytes.len() <= PageLayout::max_document_size() - MOVED_HEADER_SIZE {
+            return Ok(document_bytes.to_vec());
+        }
+        let stub = match previous {
+            Some(stub) => stub.rewrite(
+                &mut self.buffer_pool,
+                &mut self.database_file,
+                document_bytes,
+            )?,
+            None => OverflowStub::store(
+                &mut self.buffer_pool,
+                &mut self.database_file,
+                document_bytes,
+            )?,
+        };
+        Ok(stub.encode().to_vec())
+    }
+
+    /// The encoded document behind a slot's contents, reassembled from its
+    /// overflow chain if it has one.
+    fn decode_record(&self, record: Vec<u8>) -> Result<Vec<u8>> {
+        match OverflowStub::decode(&record) {
+            Some(stub) => Ok(stub.load(&self.buffer_pool, &self.database_file)?),
+            None => Ok(record),
+        }
+    }
+
+    /// Pages of every overflow chain referenced from data page `page_id`.
+    fn overflow_pages_in(&mut self, page_id: u64) -> Result<Vec<u64>> {
+        let page = self
+            .buffer_pool
+            .pin_page(page_id, &mut self.database_file)?;
+        let stubs: Result<Vec<OverflowStub>, DatabaseError> = PageLayout::get_live_slots(page)
+            .and_then(|slots| {
+                let mut stubs = Vec::new();
+                for slot_id in slots {
+                    let record = PageLayout::get_document(page, slot_id)?;
+                    let record =
+                        MovedRecord::decode(&record).map_or(&record[..], |moved| moved.record());
+                    stubs.extend(OverflowStub::decode(record));
+                }
+                Ok(stubs)
+            });
+        self.buffer_pool.unpin_page(page_id, false);
+
+        let mut page_ids = Vec::new();
+        for stub in stubs? {
+            page_ids.extend(
+                stub.chain()
+                    .page_ids(&self.buffer_pool, &self.database_file)?,
+            );
+        }
+        Ok(page_ids)
+    }
+
+    /// Refuse to touch a document location outside `collection`.
+    fn ensure_owned(&self, collection: &str, document_id: &DocumentId) -> Result<()> {
+        if !self
+            .catalog
+            .get(collection)?
+            .owns_page(document_id.page_id())
+        {
+            return Err(DatabaseError::Document(format!(
+                "Page {} does not belong to collection '{}'",
+                document_id.page_id(),
+                collection
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Re-index every stored document by `_id`.
+    fn rebuild_primary_index(&mut self) -> Result<()> {
+        let entries = self
+            .scan()
+            .map(|entry| {
+                entry.map(|(document_id, document)| (document_id, document.get_id().cloned()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let primary_index = self.catalog.get(DEFAULT_COLLECTION)?.primary_index();
+        for (document_id, id) in entries {
+            if let Some(id) = id {
+                primary_index.insert(
+                    &mut self.buffer_pool,
+                    &mut self.database_file,
+                    &id,
+                    document_id,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // Helper function to avoid code duplication
+    fn insert_document_internal(
+        &mut self,
+        collection: &str,
+        document_bytes: &[u8],
+    ) -> Result<DocumentId> {
+        let needed = PageLayout::space_needed(document_bytes.len());
+
+        // Try the collection's pages that the free-space map says have room
+        let candidates: Vec<u64> = self
+            .catalog
+            .get(collection)?
+            .pages()
+            .filter(|&page_id| self.free_space_map.free_space(page_id) >= needed)
+            // A page another writer latched would only hold this write up
+            .filter(|&page_id| !self.buffer_pool.is_latched_elsewhere(page_id))
+            .collect();
+        for page_id in candidates {
+            let page = self
+                .buffer_pool
+                .pin_page(page_id, &mut self.database_file)?;
+            let inserted = Self::insert_into_page(page, document_bytes);
+            self.free_space_map
+                .record(page_id, page.get_free_space() as usize);
+            // Dirty even on failure: the page may have been compacted
+            self.buffer_pool.unpin_page(page_id, true);
+
+            if let Ok(slot_id) = inserted {
+                return Ok(DocumentId::new(page_id, slot_id));
             }
         }
 
         // Need a new page
         let new_page_id = self.database_file.allocate_page()?;
+        Arc::make_mut(&mut self.catalog).add_page(
+            &mut self.buffer_pool,
+            &mut self.database_file,
+            collection,
+            new_page_id,
+        )?;
         let page = self
             .buffer_pool
             .pin_page(new_page_id, &mut self.database_file)?;
         let slot_id = PageLayout::insert_document(page, document_bytes)?;
+        self.free_space_map
+            .record(new_page_id, page.get_free_space() as usize);
         self.buffer_pool.unpin_page(new_page_id, true);
 
         Ok(DocumentId::new(new_page_id, slot_id))
     }
+
+    /// Insert into `page`, compacting it first if deletes left its free space
+    /// scattered in holes too small for the document.
+    fn insert_into_page(page: &mut Page, document_bytes: &[u8]) -> Result<SlotId, DatabaseError> {
+        PageLayout::insert_document(page, document_bytes).or_else(|_| {
+            PageLayout::compact_page(page)?;
+            PageLayout::insert_document(page, document_bytes)
+        })
+    }
+}
+
+impl Drop for StorageEngine {
+    fn drop(&mut self) {
+        match &self.view {
+            // Best effort: errors cannot be reported from drop, callers that care
+            // should call `flush` themselves.
+            View::Owner => {
+                let _ = self.flush();
+            }
+            // Give back what an uncommitted write allocated; its page latches
+            // go with the buffer pool
+            View::Writer { scopes, .. } => {
+                for _ in 0..scopes.len() {
+                    if self.rollback_scope().is_err() {
+                        break;
+                    }
+                }
+            }
+            View::Reader => {}
+        }
+    }
+}
+
+/// Lazy iterator over every live document of a collection, returned by
+/// [`StorageEngine::scan`] and [`Collection::scan`].
+pub struct DocumentScan<'a> {
+    engine: &'a StorageEngine,
+    source: ScanSource,
+    current_page_id: u64,
+    // Slot contents read from the current page but not yet yielded
+    pending: VecDeque<(SlotId, Vec<u8>)>,
+    // Relevance of the documents a text search found
+    scores: HashMap<DocumentId, f64>,
+    documents_examined: usize,
+    keys_examined: usize,
+    pages_examined: HashSet<u64>,
+}
+
+enum ScanSource {
+    /// Every live document of these data pages.
+    Pages(std::vec::IntoIter<u64>),
+    /// The documents an index lookup finds. The lookup runs on the first read.
+    Index {
+        lookup: Box<IndexLookup>,
+        locations: Option<std::vec::IntoIter<DocumentId>>,
+    },
+    /// Nothing but an error, reported once.
+    Failed(Option<anyhow::Error>),
+}
+
+impl DocumentScan<'_> {
+    /// The name of the index the scan reads from, if it does not visit every page.
+    pub fn index_name(&self) -> Option<&str> {
+        match &self.source {
+            ScanSource::Pages(_) | ScanSource::Failed(_) => None,
+            ScanSource::Index { lookup, .. } => Some(lookup.index.name()),
+        }
+    }
+
+    /// How relevant the document at `document_id` is to the `$text` search
+    /// the scan runs, if it runs one.
+    pub(crate) fn score(&self, document_id: &DocumentId) -> Option<f64> {
+        self.scores.get(document_id).copied()
+    }
+
+    /// Documents read so far, matching or not.
+    pub(crate) fn documents_examined(&self) -> usize {
+        self.documents_examined
+    }
+
+    /// Index entries read so far.
+    pub(crate) fn keys_examined(&self) -> usize {
+        self.keys_examined
+    }
+
+    /// Distinct data pages read so far.
+    pub(crate) fn pages_examined(&self) -> usize {
+        self.pages_examined.len()
+    }
+
+    /// Load the live documents of the next data page into `pending`.
+    /// Returns false once every page has been visited.
+    fn load_next_page(&mut self) -> Result<bool> {
+        let ScanSource::Pages(page_ids) = &mut self.source else {
+            return Ok(false);
+        };
+        for page_id in page_ids.by_ref() {
+            let engine = self.engine;
+            self.pages_examined.insert(page_id);
+            let documents = engine.buffer_pool.read_page(
+                page_id,
+                &engine.database_file,
+                |page| -> Result<Vec<_>> {
+                    if page.get_page_type() != PageType::Data {
+                        return Ok(Vec::new());
+                    }
+                    let mut documents = Vec::new();
+                    for slot_id in PageLayout::get_live_slots(page)? {
+                        let record = PageLayout::get_document(page, slot_id)?;
+                        // Moved documents are yielded where they live
+                        if ForwardingPointer::decode(&record).is_none() {
+                            documents.push((slot_id, record));
+                        }
+                    }
+                    Ok(documents)
+                },
+            )??;
+            if !documents.is_empty() {
+                self.current_page_id = page_id;
+                self.pending.extend(documents);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// The next live document as its BSON bytes, for callers that decode only
+    /// some of its fields.
+    pub(crate) fn next_bytes(&mut self) -> Option<Result<(DocumentId, Vec<u8>)>> {
+        match &mut self.source {
+            ScanSource::Index { .. } => return self.next_indexed(),
+            ScanSource::Failed(error) => return error.take().map(Err),
+            ScanSource::Pages(_) => {}
+        }
+        if self.pending.is_empty() {
+            match self.load_next_page() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    // Stop after reporting the error rather than skipping past a damaged page
+                    self.source = ScanSource::Pages(Vec::new().into_iter());
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        let (slot_id, record) = self.pending.pop_front()?;
+        self.documents_examined += 1;
+        // A moved document is reported under its home slot, the id callers hold
+        let (document_id, record) = match MovedRecord::decode(&record) {
+            Some(moved) => (moved.home(), moved.record().to_vec()),
+            None => (DocumentId::new(self.current_page_id, slot_id), record),
+        };
+        Some(
+            self.engine
+                .decode_record(record)
+                .map(|document_bytes| (document_id, document_bytes)),
+        )
+    }
+
+    fn next_indexed(&mut self) -> Option<Result<(DocumentId, Vec<u8>)>> {
+        let engine = self.engine;
+        let ScanSource::Index { lookup, locations } = &mut self.source else {
+            return None;
+        };
+        if locations.is_none() {
+            match lookup.run(&engine.buffer_pool, &engine.database_file) {
+                Ok(found) => {
+                    self.keys_examined += found.keys;
+                    self.scores = found.locations.iter().copied().zip(found.scores).collect();
+                    *locations = Some(found.locations.into_iter());
+                }
+                Err(e) => {
+                    *locations = Some(Vec::new().into_iter());
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+
+        let document_id = locations.as_mut()?.next()?;
+        self.documents_examined += 1;
+        self.pages_examined.insert(document_id.page_id());
+        let pages_examined = &mut self.pages_examined;
+        let result = engine
+            .resolve(&document_id)
+            .and_then(|(location, record)| {
+                pages_examined.insert(location.page_id());
+                engine.decode_record(record)
+            })
+            .map(|document_bytes| (document_id, document_bytes));
+        if result.is_err() {
+            *locations = Some(Vec::new().into_iter());
+        }
+        Some(result)
+    }
+}
+
+impl Iterator for DocumentScan<'_> {
+    type Item = Result<(DocumentId, Document)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(
+            self.next_bytes()?
+                .and_then(|(document_id, document_bytes)| {
+                    Ok((document_id, deserialize_document(&document_bytes)?))
+                }),
+        )
+    }
 }
@@ -0,0 +1,120 @@
+// Groups several writes into one all-or-nothing unit.
+//
+// While a transaction is open every page it touches keeps its before-image in
+// the buffer pool's undo scope, and nothing it dirties reaches the log until
+// `commit`, which writes all of it as a single WAL commit group. Rolling back
+// (explicitly or by dropping the handle) restores the before-images, so a crash
+// or an abandoned transaction never leaves half of its writes behind.
+
+use crate::{
+    document::{object_id::ObjectId, Document},
+    query::{
+        cursor::Cursor,
+        filter::Filter,
+        update::{Selector, Update},
+    },
+    storage::{
+        collection::Collection,
+        storage_engine::{DocumentId, DocumentScan, StorageEngine},
+    },
+};
+use anyhow::Result;
+
+pub struct Transaction<'a> {
+    engine: &'a mut StorageEngine,
+    finished: bool,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(engine: &'a mut StorageEngine) -> Self {
+        Self {
+            engine,
+            finished: false,
+        }
+    }
+
+    pub fn insert_document(&mut self, document: &Document) -> Result<DocumentId> {
+        self.engine.insert_document(document)
+    }
+
+    pub fn get_document(&self, document_id: &DocumentId) -> Result<Document> {
+        self.engine.get_document(document_id)
+    }
+
+    pub fn update_document(
+        &mut self,
+        document_id: &DocumentId,
+        new_document: &Document,
+    ) -> Result<DocumentId> {
+        self.engine.update_document(document_id, new_document)
+    }
+
+    pub fn delete_document(&mut self, document_id: &DocumentId) -> Result<()> {
+        self.engine.delete_document(document_id)
+    }
+
+    pub fn get_by_id(&self, id: &ObjectId) -> Result<Option<Document>> {
+        self.engine.get_by_id(id)
+    }
+
+    pub fn update_by_id(&mut self, id: &ObjectId, new_document: &Document) -> Result<DocumentId> {
+        self.engine.update_by_id(id, new_document)
+    }
+
+    pub fn update_one(
+        &mut self,
+        selector: impl Into<Selector>,
+        update: &Update,
+    ) -> Result<Option<DocumentId>> {
+        self.engine.update_one(selector, update)
+    }
+
+    pub fn delete_by_id(&mut self, id: &ObjectId) -> Result<bool> {
+        self.engine.delete_by_id(id)
+    }
+
+    /// Scan the default collection, including this transaction's own uncommitted writes.
+    pub fn scan(&self) -> DocumentScan<'_> {
+        self.engine.scan()
+    }
+
+    /// Filter the default collection, including this transaction's own uncommitted writes.
+    pub fn find(&self, filter: Filter) -> Cursor<'_> {
+        self.engine.find(filter)
+    }
+
+    /// Create a collection as part of the transaction.
+    pub fn create_collection(&mut self, name: &str) -> Result<()> {
+        self.engine.create_collection(name)
+    }
+
+    /// Drop a collection as part of the transaction.
+    pub fn drop_collection(&mut self, name: &str) -> Result<bool> {
+        self.engine.drop_collection(name)
+    }
+
+    /// A handle whose writes to collection `name` belong to this transaction.
+    pub fn collection(&mut self, name: &str) -> Result<Collection<'_>> {
+        self.engine.collection(name)
+    }
+
+    /// Make every write in the transaction durable at once.
+    pub fn commit(mut self) -> Result<()> {
+        self.finished = true;
+        self.engine.commit_transaction()
+    }
+
+    /// Undo every write made in the transaction.
+    pub fn rollback(mut self) -> Result<()> {
+        self.finished = true;
+        self.engine.rollback_transaction()
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.engine.rollback_transaction();
+        }
+    }
+}
@@ -0,0 +1,160 @@
+// Expiring documents through TTL indexes.
+//
+// An index built with `IndexDefinition::expire_after` marks a document expired
+// once the date at its path, plus the index's duration, has passed. When the
+// path holds an array, the earliest date counts; documents with no date there
+// never expire, and neither do those a partial index leaves out. Nothing hides
+// expired documents from reads: they stay until they are deleted, either by
+// calling `StorageEngine::expire_documents` or by a `TtlReaper`.
+//
+// A reaper is a background thread that wakes every `interval`, and deletes
+// expired documents through the engine in batches of `batch_size`, each batch
+// one atomic write under the database's write latch, so readers and writers
+// get in between batches. What to expire is kept with the index definitions
+// in the catalog, so a reaper started after the database is reopened carries
+// on where the last one stopped.
+
+use crate::storage::database::Database;
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How often a reaper runs and how much it deletes at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TtlOptions {
+    /// Time between passes.
+    pub interval: Duration,
+    /// Most documents deleted in one write.
+    pub batch_size: usize,
+}
+
+impl Default for TtlOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            batch_size: 1000,
+        }
+    }
+}
+
+/// What one call to `StorageEngine::expire_documents` deleted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExpiryReport {
+    /// TTL indexes looked through.
+    pub indexes_checked: usize,
+    /// Documents deleted.
+    pub documents_removed: usize,
+    /// Documents deleted from each collection that lost any.
+    pub removed_by_collection: BTreeMap<String, usize>,
+}
+
+/// Counts kept by a reaper's thread.
+#[derive(Debug, Default)]
+struct ReaperCounts {
+    passes: AtomicU64,
+    removed: AtomicU64,
+}
+
+/// A background thread deleting expired documents. It stops when dropped, or
+/// on its own once the database it reaps is dropped.
+pub struct TtlReaper {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    counts: Arc<ReaperCounts>,
+}
+
+impl TtlReaper {
+    /// Start reaping `database`, the first pass right away.
+    pub fn start(database: &Arc<Database>, options: TtlOptions) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let counts = Arc::new(ReaperCounts::default());
+        let database = Arc::downgrade(database);
+        let thread_counts = Arc::clone(&counts);
+        let thread = thread::Builder::new()
+            .name("ttl-reaper".to_string())
+            .spawn(move || {
+                loop {
+                    if !reap(&database, options.batch_size, &thread_counts) {
+                        return;
+                    }
+                    match stopped.recv_timeout(options.interval) {
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        _ => return,
+                    }
+                }
+            })
+            .expect("failed to spawn the TTL reaper thread");
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+            counts,
+        }
+    }
+
+    /// Passes completed so far.
+    pub fn passes(&self) -> u64 {
+        self.counts.passes.load(Ordering::Acquire)
+    }
+
+    /// Documents deleted so far.
+    pub fn removed(&self) -> u64 {
+        self.counts.removed.load(Ordering::Acquire)
+    }
+
+    /// Stop the thread, waiting for a batch in progress to finish. Returns how
+    /// many documents it deleted.
+    pub fn stop(mut self) -> u64 {
+        self.shut_down();
+        self.removed()
+    }
+
+    fn shut_down(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for TtlReaper {
+    fn drop(&mut self) {
+        self.shut_down();
+    }
+}
+
+/// Delete batches of expired documents until one comes back short. Returns
+/// false once the database is gone.
+fn reap(database: &Weak<Database>, batch_size: usize, counts: &ReaperCounts) -> bool {
+    let mut removed = 0;
+    loop {
+        // Only hold the database for one batch, so dropping it ends the thread
+        let Some(database) = database.upgrade() else {
+            return false;
+        };
+        match database.expire_documents(Utc::now(), batch_size) {
+            Ok(report) => {
+                removed += report.documents_removed;
+                counts
+                    .removed
+                    .fetch_add(report.documents_removed as u64, Ordering::AcqRel);
+                if report.documents_removed < batch_size.max(1) {
+                    break;
+                }
+            }
+            Err(e) => {
+                warn!("TTL reaper failed to expire documents: {}", e);
+                break;
+            }
+        }
+    }
+    if removed > 0 {
+        info!("TTL reaper removed {} expired documents", removed);
+    }
+    counts.passes.fetch_add(1, Ordering::AcqRel);
+    true
+}
@@ -0,0 +1,145 @@
+// Settings and results of `StorageEngine::vacuum`.
+//
+// Deleting or shrinking a document leaves a hole in its data page that new
+// documents can only use once the page is compacted, and a page whose documents
+// are all gone still belongs to its collection. A vacuum pass visits every data
+// page: empty pages are handed back to the freelist, and sparsely used pages
+// with holes are compacted so their free space is contiguous again. Documents
+// that moved away from their home slot are brought back when it has room,
+// dropping their forwarding pointer. Slot ids never change, so index entries
+// stay valid.
+//
+// An engine used on its own runs a scheduled vacuum after a write once
+// `VacuumOptions::interval` has passed. A shared `Database` is vacuumed by a
+// `VacuumScheduler` instead: a background thread that wakes every interval and
+// runs `Database::vacuum`, which lets reads and writes in between pages, so an
+// idle database still gets its space back after a bulk delete.
+
+use crate::storage::database::Database;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Controls which pages a vacuum compacts and how often it runs on its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VacuumOptions {
+    /// Pages with holes are compacted when at most this percentage of their
+    /// usable space holds live documents (see `PageLayout::get_utilization_percentage`).
+    pub max_utilization: f32,
+    /// Run a vacuum after a committed write once this much time has passed since
+    /// the previous one. `None` only vacuums on demand. Writes through a
+    /// `Database` leave this to a `VacuumScheduler`.
+    pub interval: Option<Duration>,
+}
+
+impl Default for VacuumOptions {
+    fn default() -> Self {
+        Self {
+            max_utilization: 75.0,
+            interval: None,
+        }
+    }
+}
+
+/// What a vacuum pass reclaimed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VacuumReport {
+    /// Data pages examined.
+    pub pages_scanned: usize,
+    /// Pages whose holes were squeezed out.
+    pub pages_compacted: usize,
+    /// Empty pages removed from their collection and put on the freelist.
+    pub pages_freed: usize,
+    /// Moved documents returned to their home slot.
+    pub forwarding_pointers_collapsed: usize,
+    /// Old document versions dropped because no open snapshot can see them.
+    pub versions_reclaimed: usize,
+    /// Bytes made usable again, counting a whole page for every page freed.
+    pub bytes_reclaimed: usize,
+}
+
+/// A background thread vacuuming a database on a schedule. It stops when
+/// dropped, or on its own once the database it vacuums is dropped.
+pub struct VacuumScheduler {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    passes: Arc<AtomicU64>,
+}
+
+impl VacuumScheduler {
+    /// Start vacuuming `database` every `interval`, the first pass one
+    /// interval from now.
+    pub fn start(database: &Arc<Database>, interval: Duration) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let passes = Arc::new(AtomicU64::new(0));
+        let database = Arc::downgrade(database);
+        let thread_passes = Arc::clone(&passes);
+        let thread = thread::Builder::new()
+            .name("vacuum-scheduler".to_string())
+            .spawn(move || {
+                loop {
+                    match stopped.recv_timeout(interval) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        _ => return,
+                    }
+                    if !vacuum(&database, &thread_passes) {
+                        return;
+                    }
+                }
+            })
+            .expect("failed to spawn the vacuum scheduler thread");
+        Self {
+            stop: Some(stop),
+            thread: Some(thread),
+            passes,
+        }
+    }
+
+    /// Passes completed so far.
+    pub fn passes(&self) -> u64 {
+        self.passes.load(Ordering::Acquire)
+    }
+
+    /// Stop the thread, waiting for a pass in progress to finish. Returns how
+    /// many passes it completed.
+    pub fn stop(mut self) -> u64 {
+        self.shut_down();
+        self.passes()
+    }
+
+    fn shut_down(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for VacuumScheduler {
+    fn drop(&mut self) {
+        self.shut_down();
+    }
+}
+
+/// Run one vacuum pass. Returns false once the database is gone.
+fn vacuum(database: &Weak<Database>, passes: &AtomicU64) -> bool {
+    let Some(database) = database.upgrade() else {
+        return false;
+    };
+    match database.vacuum() {
+        Ok(report) => {
+            if report.pages_freed > 0 || report.pages_compacted > 0 {
+                info!(
+                    "Scheduled vacuum freed {} pages and compacted {}",
+                    report.pages_freed, report.pages_compacted
+                );
+            }
+            passes.fetch_add(1, Ordering::AcqRel);
+        }
+        Err(e) => warn!("Scheduled vacuum failed: {}", e),
+    }
+    true
+}
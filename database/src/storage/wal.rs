@@ -0,0 +1,259 @@
+// Write-ahead log for crash recovery.
+//
+// The log is a sequence of physical redo records appended to `<database>-wal`:
+//
+//   kind (u8) | payload length (u32) | payload | crc32 of everything before it (u32)
+//
+// A PAGE record carries a page id followed by the full page image, and a COMMIT
+// record carries the database page count at commit time. Every page changed by an
+// operation is logged before the operation's COMMIT record is synced, and the
+// buffer pool keeps those pages out of the data file until then, so after a crash
+// replaying the committed records brings the data file back to the last
+// acknowledged state. A torn or corrupt tail simply ends the replay.
+//
+// A checkpoint writes every dirty page to the data file, syncs it and truncates
+// the log.
+
+use crate::error::DatabaseError;
+use crate::storage::file::DatabaseFile;
+use crate::storage::page::{Page, PAGE_SIZE};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const RECORD_PAGE: u8 = 1;
+const RECORD_COMMIT: u8 = 2;
+const RECORD_HEADER_SIZE: usize = 5; // kind (1) + payload length (4)
+const RECORD_TRAILER_SIZE: usize = 4; // crc32
+
+/// Page images belonging to one committed operation.
+#[derive(Debug)]
+struct CommittedBatch {
+    page_count: u64,
+    pages: Vec<(u64, [u8; PAGE_SIZE])>,
+}
+
+pub struct WriteAheadLog {
+    file: File,
+    size: u64,
+}
+
+impl WriteAheadLog {
+    /// The log file used for the database at `database_path`.
+    pub fn path_for(database_path: &Path) -> PathBuf {
+        let mut file_name = database_path
+            .file_name()
+            .map(|name| name.to_os_string())
+            .unwrap_or_default();
+        file_name.push("-wal");
+        database_path.with_file_name(file_name)
+    }
+
+    /// Opens the log at `path`, creating an empty one if it does not exist.
+    pub fn open(path: &Path) -> Result<Self, DatabaseError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let size = file.seek(SeekFrom::End(0))?;
+        Ok(Self { file, size })
+    }
+
+    /// Current size of the log in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Appends the after-image of a page changed by the current operation.
+    pub fn log_page(&mut self, page_id: u64, page: &[u8; PAGE_SIZE]) -> Result<(), DatabaseError> {
+        let mut payload = Vec::with_capacity(8 + PAGE_SIZE);
+        payload.extend_from_slice(&page_id.to_le_bytes());
+        payload.extend_from_slice(page);
+        self.append(RECORD_PAGE, &payload)
+    }
+
+    /// Appends a commit record and syncs the log. Once this returns, every page
+    /// logged since the previous commit survives a crash.
+    pub fn commit(&mut self, page_count: u64) -> Result<(), DatabaseError> {
+        self.append(RECORD_COMMIT, &page_count.to_le_bytes())?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Discards the whole log. Only safe once the data file holds every change.
+    pub fn reset(&mut self) -> Result<(), DatabaseError> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.sync_all()?;
+        self.size = 0;
+        Ok(())
+    }
+
+    /// Replays every committed operation into `database_file`, syncs it and
+    /// empties the log. Returns the number of operations replayed.
+    pub fn recover(&mut self, database_file: &mut DatabaseFile) -> Result<usize, DatabaseError> {
+        let batches = self.read_committed()?;
+
+        for batch in &batches {
+            database_file.ensure_page_count(batch.page_count)?;
+            for (page_id, image) in &batch.pages {
+                database_file.ensure_page_count(page_id + 1)?;
+                let page = Page::from_bytes(*image)?;
+                database_file.write_page(*page_id, &page)?;
+            }
+        }
+
+        if !batches.is_empty() {
+            database_file.sync()?;
+        }
+        self.reset()?;
+        Ok(batches.len())
+    }
+
+    fn append(&mut self, kind: u8, payload: &[u8]) -> Result<(), DatabaseError> {
+        let mut record = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len() + RECORD_TRAILER_SIZE);
+        record.push(kind);
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(payload);
+        let crc = crc32fast::hash(&record);
+        record.extend_from_slice(&crc.to_le_bytes());
+
+        self.file.seek(SeekFrom::Start(self.size))?;
+        self.file.write_all(&record)?;
+        self.size += record.len() as u64;
+        Ok(())
+    }
+
+    /// Reads all records up to the last intact commit. Page records after it
+    /// belong to an operation that never committed and are ignored.
+    fn read_committed(&mut self) -> Result<Vec<CommittedBatch>, DatabaseError> {
+        let mut log = Vec::new();
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.read_to_end(&mut log)?;
+
+        let mut batches = Vec::new();
+        let mut pending = Vec::new();
+        let mut pos = 0;
+
+        while let Some((kind, payload, next)) = Self::parse_record(&log, pos) {
+            match kind {
+                RECORD_PAGE if payload.len() == 8 + PAGE_SIZE => {
+                    let page_id = u64::from_le_bytes(payload[..8].try_into().unwrap());
+                    let mut image = [0u8; PAGE_SIZE];
+                    image.copy_from_slice(&payload[8..]);
+                    pending.push((page_id, image));
+                }
+                RECORD_COMMIT if payload.len() == 8 => {
+                    batches.push(CommittedBatch {
+                        page_count: u64::from_le_bytes(payload.try_into().unwrap()),
+                        pages: std::mem::take(&mut pending),
+                    });
+                }
+                _ => break,
+            }
+            pos = next;
+        }
+
+        Ok(batches)
+    }
+
+    /// Parses the record at `pos`, returning None for a truncated or corrupt record.
+    fn parse_record(log: &[u8], pos: usize) -> Option<(u8, &[u8], usize)> {
+        let header = log.get(pos..pos + RECORD_HEADER_SIZE)?;
+        let payload_len = u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize;
+        let body_end = pos + RECORD_HEADER_SIZE + payload_len;
+        let body = log.get(pos..body_end)?;
+        let crc = log.get(body_end..body_end + RECORD_TRAILER_SIZE)?;
+
+        if crc32fast::hash(body) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            return None;
+        }
+        Some((header[0], &body[RECORD_HEADER_SIZE..], body_end + RECORD_TRAILER_SIZE))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::page::PageType;
+
+    fn page_image(page_id: u64, fill: u8) -> [u8; PAGE_SIZE] {
+        let mut page = Page::new(page_id, PageType::Data);
+        page.payload_mut()[..100].fill(fill);
+        let checksum = page.calculate_checksum();
+        page.set_checksum(checksum);
+        page.to_bytes()
+    }
+
+    #[test]
+    fn test_path_for() {
+        assert_eq!(
+            WriteAheadLog::path_for(Path::new("/tmp/data/app.db")),
+            PathBuf::from("/tmp/data/app.db-wal")
+        );
+    }
+
+    #[test]
+    fn test_only_committed_batches_are_read() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut wal = WriteAheadLog::open(&temp_dir.path().join("test.db-wal")).unwrap();
+
+        let image = page_image(0, 3);
+        wal.log_page(0, &page_image(0, 1)).unwrap();
+        wal.commit(1).unwrap();
+        wal.log_page(1, &page_image(1, 2)).unwrap();
+        wal.log_page(0, &image).unwrap();
+        wal.commit(2).unwrap();
+        // Never committed
+        wal.log_page(2, &page_image(2, 4)).unwrap();
+
+        let batches = wal.read_committed().unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].pages.len(), 1);
+        assert_eq!(batches[1].page_count, 2);
+        assert_eq!(batches[1].pages[1].1, image);
+    }
+
+    #[test]
+    fn test_corrupt_tail_is_ignored() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("test.db-wal");
+        let image = page_image(0, 1);
+        {
+            let mut wal = WriteAheadLog::open(&path).unwrap();
+            wal.log_page(0, &image).unwrap();
+            wal.commit(1).unwrap();
+            wal.log_page(0, &page_image(0, 2)).unwrap();
+            wal.commit(1).unwrap();
+        }
+
+        // Flip a byte inside the second page record
+        let mut bytes = std::fs::read(&path).unwrap();
+        let second_record = RECORD_HEADER_SIZE + 8 + PAGE_SIZE + RECORD_TRAILER_SIZE + 17;
+        bytes[second_record + 100] ^= 0xFF;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut wal = WriteAheadLog::open(&path).unwrap();
+        let batches = wal.read_committed().unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].pages[0].1, image);
+    }
+
+    #[test]
+    fn test_recover_replays_and_resets() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let mut database_file = DatabaseFile::create(&temp_dir.path().join("test.db")).unwrap();
+        let mut wal = WriteAheadLog::open(&temp_dir.path().join("test.db-wal")).unwrap();
+
+        let image = page_image(2, 9);
+        wal.log_page(2, &image).unwrap();
+        wal.commit(3).unwrap();
+
+        assert_eq!(wal.recover(&mut database_file).unwrap(), 1);
+        assert_eq!(database_file.page_count(), 3);
+        assert_eq!(database_file.read_page(2).unwrap().to_bytes(), image);
+        assert_eq!(wal.size(), 0);
+    }
+}
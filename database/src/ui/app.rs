@@ -102,9 +102,18 @@ impl DatabaseApp {
     }
     
     fn refresh_documents(&mut self) {
-        // Note: In a real implementation, you'd need to add a method to StorageEngine
-        // to iterate over all documents. For now, we'll keep the current list.
-        self.set_status("📋 Document list refreshed", egui::Color32::LIGHT_BLUE);
+        if let Some(ref mut storage_engine) = self.storage_engine {
+            match storage_engine.scan().collect::<Result<Vec<_>, _>>() {
+                Ok(documents) => {
+                    self.documents = documents;
+                    self.selected_doc_index = None;
+                    self.set_status("📋 Document list refreshed", egui::Color32::LIGHT_BLUE);
+                }
+                Err(e) => {
+                    self.set_status(&format!("❌ Failed to load documents: {}", e), egui::Color32::RED);
+                }
+            }
+        }
     }
     
     fn insert_document_from_json(&mut self) {
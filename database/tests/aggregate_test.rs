@@ -0,0 +1,111 @@
+mod common;
+
+use common::create_engine;
+use database::{
+    Document, Value,
+    query::aggregate::Pipeline,
+    storage::database::Database,
+};
+use tempfile::tempdir;
+
+fn order(customer: &str, amount: i32, items: &[&str]) -> Document {
+    let mut doc = Document::new();
+    doc.set("customer", Value::String(customer.to_string()));
+    doc.set("amount", Value::I32(amount));
+    doc.set(
+        "items",
+        Value::Array(
+            items
+                .iter()
+                .map(|item| Value::String(item.to_string()))
+                .collect(),
+        ),
+    );
+    doc
+}
+
+#[test]
+fn test_aggregate_default_collection() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("aggregate.db"), 10);
+    for n in 0..60 {
+        let customer = ["ann", "ben", "cat"][n % 3];
+        storage_engine
+            .insert_document(&order(customer, n as i32, &["pen"]))
+            .unwrap();
+    }
+
+    let pipeline = Pipeline::parse(
+        r#"[{"$match": {"amount": {"$gte": 30}}},
+            {"$group": {"_id": "$customer", "total": {"$sum": "$amount"}, "orders": {"$count": {}}}},
+            {"$sort": {"total": -1}},
+            {"$limit": 2}]"#,
+    )
+    .unwrap();
+    let results: Vec<Document> = storage_engine
+        .aggregate(&pipeline)
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+
+    // Amounts 30..60 split by n % 3: cat has 32, 35, ..., 59
+    let summary: Vec<_> = results
+        .iter()
+        .map(|doc| {
+            (
+                doc.id().clone(),
+                doc.get("total").cloned().unwrap(),
+                doc.get("orders").cloned().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (
+                Value::String("cat".to_string()),
+                Value::I32(455),
+                Value::I32(10)
+            ),
+            (
+                Value::String("ben".to_string()),
+                Value::I32(445),
+                Value::I32(10)
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_aggregate_collection_through_database() {
+    let temp_dir = tempdir().unwrap();
+    let database = Database::from_engine(create_engine(&temp_dir.path().join("agg_db.db"), 10));
+    database.create_collection("orders").unwrap();
+    database
+        .insert_document("orders", &order("ann", 5, &["pen", "ink"]))
+        .unwrap();
+    database
+        .insert_document("orders", &order("ben", 7, &["ink"]))
+        .unwrap();
+
+    let pipeline = Pipeline::parse(
+        r#"[{"$unwind": "$items"},
+            {"$group": {"_id": "$items", "buyers": {"$push": "$customer"}}},
+            {"$project": {"item": "$_id", "buyers": 1}}]"#,
+    )
+    .unwrap();
+    let results = database.aggregate("orders", &pipeline).unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(
+        results[0].get("item"),
+        Some(&Value::String("ink".to_string()))
+    );
+    assert_eq!(
+        results[0].get("buyers"),
+        Some(&Value::Array(vec![
+            Value::String("ann".to_string()),
+            Value::String("ben".to_string())
+        ]))
+    );
+
+    assert!(database.aggregate("missing", &pipeline).is_err());
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use common::{create_async_database, numbered};
+use database::Value;
+use futures_util::StreamExt;
+use tempfile::tempdir;
+
+#[tokio::test]
+async fn test_insert_get_update_delete() {
+    let temp_dir = tempdir().unwrap();
+    let database = create_async_database(&temp_dir.path().join("async_crud.db"), 8).await;
+    database.create_collection("items").await.unwrap();
+
+    let doc = numbered(1, 500);
+    let id = doc.get_id().unwrap().clone();
+    database.insert("items", doc.clone()).await.unwrap();
+    assert_eq!(database.get("items", &id).await.unwrap(), Some(doc));
+
+    database.update("items", &id, numbered(2, 500)).await.unwrap();
+    let updated = database.get("items", &id).await.unwrap().unwrap();
+    assert_eq!(updated.get("n"), Some(&Value::I32(2)));
+    assert_eq!(updated.get_id(), Some(&id));
+
+    assert!(database.delete("items", &id).await.unwrap());
+    assert!(!database.delete("items", &id).await.unwrap());
+    assert_eq!(database.get("items", &id).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_scan_streams_every_document() {
+    let temp_dir = tempdir().unwrap();
+    let database = create_async_database(&temp_dir.path().join("async_scan.db"), 4).await;
+    database.create_collection("items").await.unwrap();
+
+    // More documents than the stream buffers, over more pages than the pool holds
+    for i in 0..150 {
+        database.insert("items", numbered(i, 500)).await.unwrap();
+    }
+
+    let mut seen: Vec<i32> = database
+        .scan("items")
+        .map(|item| match item.unwrap().1.get("n") {
+            Some(Value::I32(n)) => *n,
+            other => panic!("unexpected n: {:?}", other),
+        })
+        .collect()
+        .await;
+    seen.sort_unstable();
+    assert_eq!(seen, (0..150).collect::<Vec<_>>());
+
+    // Dropping a stream part way through stops its scan without blocking writers
+    let mut partial = database.scan("items");
+    assert!(partial.next().await.unwrap().is_ok());
+    drop(partial);
+    database.insert("items", numbered(150, 500)).await.unwrap();
+    assert_eq!(database.collection_stats("items").await.unwrap().document_count, 151);
+}
+
+#[tokio::test]
+async fn test_scan_of_missing_collection_yields_error() {
+    let temp_dir = tempdir().unwrap();
+    let database = create_async_database(&temp_dir.path().join("async_missing.db"), 4).await;
+
+    let results: Vec<_> = database.scan("nope").collect().await;
+    assert_eq!(results.len(), 1);
+    assert!(results[0].is_err());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_tasks_share_a_handle() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("async_tasks.db");
+    let database = create_async_database(&db_path, 8).await;
+    database.create_collection("items").await.unwrap();
+
+    let tasks: Vec<_> = (0..8)
+        .map(|t| {
+            let database = database.clone();
+            tokio::spawn(async move {
+                for i in 0..20 {
+                    let doc = numbered(t * 100 + i, 500);
+                    let id = doc.get_id().unwrap().clone();
+                    database.insert("items", doc).await.unwrap();
+                    assert!(database.get("items", &id).await.unwrap().is_some());
+                }
+            })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap();
+    }
+
+    assert_eq!(database.scan("items").count().await, 160);
+    database.flush().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_scan_sees_snapshot_despite_concurrent_writes() {
+    let temp_dir = tempdir().unwrap();
+    let database = create_async_database(&temp_dir.path().join("async_snapshot.db"), 8).await;
+    database.create_collection("items").await.unwrap();
+
+    let mut ids = Vec::new();
+    for i in 0..100 {
+        let doc = numbered(i, 500);
+        ids.push(doc.get_id().unwrap().clone());
+        database.insert("items", doc).await.unwrap();
+    }
+
+    let mut stream = database.scan("items");
+    let mut seen = vec![stream.next().await.unwrap().unwrap()];
+
+    // Rewrite, delete and add documents while the scan is still running
+    for (i, id) in ids.iter().enumerate() {
+        if i % 2 == 0 {
+            database.update("items", id, numbered(1000 + i as i32, 500)).await.unwrap();
+        } else {
+            database.delete("items", id).await.unwrap();
+        }
+        database.insert("items", numbered(2000 + i as i32, 500)).await.unwrap();
+    }
+
+    while let Some(item) = stream.next().await {
+        seen.push(item.unwrap());
+    }
+    let mut numbers: Vec<i32> = seen
+        .iter()
+        .map(|(_, doc)| match doc.get("n") {
+            Some(Value::I32(n)) => *n,
+            other => panic!("unexpected n: {:?}", other),
+        })
+        .collect();
+    numbers.sort_unstable();
+    assert_eq!(numbers, (0..100).collect::<Vec<_>>());
+}
@@ -1,9 +1,11 @@
+mod common;
+
+use common::remove_database;
 use database::storage::buffer_pool::BufferPool;
 use database::storage::file::DatabaseFile;
 use database::storage::storage_engine::StorageEngine;
 use database::{Document, Value};
 use std::path::Path;
-use std::fs;
 
 #[cfg(test)]
 mod tests {
@@ -18,7 +20,7 @@ mod tests {
         let temp_path = format!("test_db_{}_{}_{}.db", std::process::id(), timestamp, thread_id.chars().filter(|c| c.is_numeric()).collect::<String>());
         
         // Clean up any existing file first
-        let _ = fs::remove_file(&temp_path);
+        remove_database(Path::new(&temp_path));
         
         // Create the database file
         let db_file = DatabaseFile::create(Path::new(&temp_path))?;
@@ -34,7 +36,7 @@ mod tests {
         let temp_path = format!("test_storage_{}_{}_{}.db", std::process::id(), timestamp, thread_id.chars().filter(|c| c.is_numeric()).collect::<String>());
         
         // Clean up any existing file first
-        let _ = fs::remove_file(&temp_path);
+        remove_database(Path::new(&temp_path));
         
         // Follow the correct workflow: create file only if it doesn't exist
         let path = Path::new(&temp_path);
@@ -48,10 +50,6 @@ mod tests {
         Ok((temp_path, storage_engine))
     }
 
-    fn cleanup_file(path: &str) {
-        let _ = fs::remove_file(path); // Ignore errors if file doesn't exist
-    }
-
     #[test]
     fn test_buffer_pool_basic_stats() -> Result<(), Box<dyn std::error::Error>> {
         let (temp_path, _db_file) = create_temp_database()?;
@@ -83,7 +81,7 @@ mod tests {
         // Test debug print (just ensure it doesn't panic)
         pool.debug_print();
 
-        cleanup_file(&temp_path);
+        remove_database(Path::new(&temp_path));
         Ok(())
     }
 
@@ -105,7 +103,7 @@ mod tests {
         assert_eq!(stats.dirty_pages, 0);
         assert_eq!(stats.pinned_pages, 0);
 
-        cleanup_file(&temp_path);
+        remove_database(Path::new(&temp_path));
         Ok(())
     }
 
@@ -121,7 +119,7 @@ mod tests {
         // Test consistency
         assert!(pool.validate_consistency().is_ok());
 
-        cleanup_file(&temp_path);
+        remove_database(Path::new(&temp_path));
         Ok(())
     }
 
@@ -153,7 +151,7 @@ mod tests {
         // Test that these operations maintain consistency
         assert!(pool.validate_consistency().is_ok());
 
-        cleanup_file(&temp_path);
+        remove_database(Path::new(&temp_path));
         Ok(())
     }
 
@@ -193,7 +191,7 @@ mod tests {
         assert_eq!(product_doc.get("price"), retrieved_product.get("price"));
         assert_eq!(product_doc.get("stock"), retrieved_product.get("stock"));
 
-        cleanup_file(&temp_path);
+        remove_database(Path::new(&temp_path));
         Ok(())
     }
 
@@ -217,7 +215,7 @@ mod tests {
 
         println!("✅ Buffer pool successfully handled real page operations");
         
-        cleanup_file(&temp_path);
+        remove_database(Path::new(&temp_path));
         Ok(())
     }
 }
@@ -0,0 +1,166 @@
+mod common;
+
+use common::create_engine;
+use database::{
+    bson::serialize_document,
+    page_layout::PageLayout,
+    storage::{file::DatabaseFile, storage_engine::StorageEngine},
+    Document, Value,
+};
+use tempfile::tempdir;
+
+fn named(name: &str) -> Document {
+    let mut doc = Document::new();
+    doc.set("name", Value::String(name.to_string()));
+    doc.set("bio", Value::String("b".repeat(400)));
+    doc
+}
+
+#[test]
+fn test_create_list_drop() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("collections.db"), 10);
+    assert_eq!(storage_engine.list_collections(), vec!["default".to_string()]);
+
+    storage_engine.create_collection("users").unwrap();
+    storage_engine.create_collection("orders").unwrap();
+    assert!(storage_engine.create_collection("users").is_err());
+    assert!(storage_engine.create_collection("").is_err());
+    assert_eq!(
+        storage_engine.list_collections(),
+        vec!["default".to_string(), "orders".to_string(), "users".to_string()]
+    );
+
+    assert!(storage_engine.drop_collection("orders").unwrap());
+    assert!(!storage_engine.drop_collection("orders").unwrap());
+    assert!(storage_engine.drop_collection("default").is_err());
+    assert!(storage_engine.collection("orders").is_err());
+    assert_eq!(
+        storage_engine.list_collections(),
+        vec!["default".to_string(), "users".to_string()]
+    );
+}
+
+#[test]
+fn test_documents_are_scoped_to_their_collection() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("scoped.db"), 10);
+    storage_engine.create_collection("users").unwrap();
+    storage_engine.create_collection("orders").unwrap();
+
+    let alice = named("alice");
+    let alice_location = storage_engine
+        .collection("users")
+        .unwrap()
+        .insert_document(&alice)
+        .unwrap();
+    {
+        let mut orders = storage_engine.collection("orders").unwrap();
+        for i in 0..30 {
+            orders.insert_document(&named(&format!("order{}", i))).unwrap();
+        }
+        // The same _id may live in different collections
+        orders.insert_document(&alice).unwrap();
+
+        // A location from another collection is refused
+        assert!(orders.get_document(&alice_location).is_err());
+        assert!(orders.delete_document(&alice_location).is_err());
+    }
+    storage_engine.insert_document(&named("loose")).unwrap();
+
+    let mut users = storage_engine.collection("users").unwrap();
+    assert_eq!(users.scan().count(), 1);
+    assert_eq!(users.get_by_id(alice.get_id().unwrap()).unwrap(), Some(alice.clone()));
+    let user_stats = users.stats().unwrap();
+    assert_eq!(user_stats.document_count, 1);
+    assert_eq!(user_stats.page_count, 1);
+
+    let order_stats = storage_engine.collection_stats("orders").unwrap();
+    assert_eq!(order_stats.document_count, 31);
+    assert!(order_stats.page_count > 1);
+    assert_eq!(storage_engine.scan().count(), 1);
+
+    // Dropping one collection leaves the others alone
+    assert!(storage_engine.drop_collection("orders").unwrap());
+    let mut users = storage_engine.collection("users").unwrap();
+    assert!(users.delete_by_id(alice.get_id().unwrap()).unwrap());
+    assert_eq!(users.scan().count(), 0);
+    assert_eq!(storage_engine.scan().count(), 1);
+}
+
+#[test]
+fn test_catalog_persists_across_reopen() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("catalog_reopen.db");
+
+    let doc = named("bob");
+    {
+        let mut storage_engine = create_engine(&db_path, 4);
+        storage_engine.create_collection("people").unwrap();
+        let mut people = storage_engine.collection("people").unwrap();
+        people.insert_document(&doc).unwrap();
+        for i in 0..100 {
+            people.insert_document(&named(&format!("person{}", i))).unwrap();
+        }
+    }
+
+    let mut storage_engine = StorageEngine::new(&db_path, 4).unwrap();
+    assert_eq!(
+        storage_engine.list_collections(),
+        vec!["default".to_string(), "people".to_string()]
+    );
+    let people = storage_engine.collection("people").unwrap();
+    assert_eq!(people.get_by_id(doc.get_id().unwrap()).unwrap(), Some(doc));
+    assert_eq!(people.scan().count(), 101);
+    assert_eq!(storage_engine.scan().count(), 0);
+}
+
+#[test]
+fn test_collection_changes_roll_back() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("catalog_rollback.db"), 10);
+    storage_engine.create_collection("kept").unwrap();
+    storage_engine
+        .collection("kept")
+        .unwrap()
+        .insert_document(&named("survivor"))
+        .unwrap();
+
+    let mut transaction = storage_engine.begin_transaction().unwrap();
+    transaction.create_collection("temporary").unwrap();
+    transaction
+        .collection("temporary")
+        .unwrap()
+        .insert_document(&named("ghost"))
+        .unwrap();
+    assert!(transaction.drop_collection("kept").unwrap());
+    transaction.rollback().unwrap();
+
+    assert_eq!(
+        storage_engine.list_collections(),
+        vec!["default".to_string(), "kept".to_string()]
+    );
+    assert_eq!(storage_engine.collection("kept").unwrap().scan().count(), 1);
+}
+
+#[test]
+fn test_existing_documents_join_default_collection() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("legacy.db");
+
+    // A file laid out before collections existed: bare data pages, no catalog
+    let doc = named("legacy");
+    {
+        let mut database_file = DatabaseFile::create(&db_path).unwrap();
+        let page_id = database_file.allocate_page().unwrap();
+        let mut page = database_file.read_page(page_id).unwrap();
+        PageLayout::insert_document(&mut page, &serialize_document(&doc).unwrap()).unwrap();
+        let checksum = page.calculate_checksum();
+        page.set_checksum(checksum);
+        database_file.write_page(page_id, &page).unwrap();
+    }
+
+    let storage_engine = StorageEngine::new(&db_path, 10).unwrap();
+    assert_eq!(storage_engine.list_collections(), vec!["default".to_string()]);
+    assert_eq!(storage_engine.get_by_id(doc.get_id().unwrap()).unwrap(), Some(doc));
+}
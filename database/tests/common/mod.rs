@@ -0,0 +1,61 @@
+// Setup shared by the integration tests. Each test file compiles this module
+// on its own and uses only some of it.
+#![allow(dead_code)]
+
+use database::{
+    Document, Value,
+    storage::{
+        async_database::AsyncDatabase, database::Database, file::DatabaseFile,
+        storage_engine::StorageEngine, wal::WriteAheadLog,
+    },
+};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// A storage engine over a new, empty database file at `path`.
+pub fn create_engine(path: &Path, buffer_pool_size: usize) -> StorageEngine {
+    let db_file = DatabaseFile::create(path).expect("Failed to create database file");
+    drop(db_file);
+    StorageEngine::new(path, buffer_pool_size).expect("Failed to create storage engine")
+}
+
+/// A shareable handle to a new, empty database file at `path`.
+pub fn create_database(path: &Path, buffer_pool_size: usize) -> Arc<Database> {
+    let db_file = DatabaseFile::create(path).expect("Failed to create database file");
+    drop(db_file);
+    Arc::new(Database::open(path, buffer_pool_size).expect("Failed to open database"))
+}
+
+/// An async handle to a new, empty database file at `path`.
+pub async fn create_async_database(path: &Path, buffer_pool_size: usize) -> AsyncDatabase {
+    let db_file = DatabaseFile::create(path).expect("Failed to create database file");
+    drop(db_file);
+    AsyncDatabase::open(path, buffer_pool_size)
+        .await
+        .expect("Failed to open database")
+}
+
+/// Delete the database file at `path` and the write-ahead log beside it,
+/// skipping whichever is not there.
+pub fn remove_database(path: &Path) {
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(WriteAheadLog::path_for(path));
+}
+
+/// A document numbered `i` in its `n` field, padded with a `payload_len` byte
+/// string.
+pub fn numbered(i: i32, payload_len: usize) -> Document {
+    let mut doc = Document::new();
+    doc.set("n", Value::I32(i));
+    doc.set("payload", Value::String("x".repeat(payload_len)));
+    doc
+}
+
+/// A document named `name`, padded with a `payload_len` byte string.
+pub fn sized(name: &str, payload_len: usize) -> Document {
+    let mut doc = Document::new();
+    doc.set("name", Value::String(name.to_string()));
+    doc.set("payload", Value::String("x".repeat(payload_len)));
+    doc
+}
@@ -0,0 +1,204 @@
+mod common;
+
+use common::create_engine;
+use database::{
+    Document, Value,
+    index::secondary::IndexDefinition,
+    query::{filter::Filter, sort::Sort},
+    storage::storage_engine::StorageEngine,
+};
+use tempfile::tempdir;
+
+fn post(n: i32, tag: Value) -> Document {
+    let mut doc = Document::new();
+    doc.set("n", Value::I32(n));
+    doc.set("tag", tag);
+    // Out of insertion order, so page order never happens to be sort order
+    doc.set("created", Value::I32((n * 37) % 101));
+    // Four documents to a page, so reading a third of them beats a scan
+    doc.set("body", Value::String("b".repeat(2000)));
+    doc
+}
+
+fn tag(tag: &str) -> Value {
+    Value::String(tag.to_string())
+}
+
+fn created(engine: &StorageEngine, filter: &str, sort: Sort) -> (Vec<i32>, bool) {
+    let cursor = engine.find(Filter::parse(filter).unwrap()).sort(sort);
+    let presorted = cursor.is_presorted();
+    let created = cursor
+        .map(|item| match item.unwrap().1.get("created") {
+            Some(Value::I32(created)) => *created,
+            other => panic!("unexpected created {:?}", other),
+        })
+        .collect();
+    (created, presorted)
+}
+
+fn tag_created() -> IndexDefinition {
+    IndexDefinition::compound(Sort::new().ascending("tag").descending("created"))
+}
+
+#[test]
+fn test_compound_index_serves_filter_and_sort() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("compound.db"), 64);
+    for n in 0..101 {
+        let name = ["x", "y", "z"][n as usize % 3];
+        storage_engine.insert_document(&post(n, tag(name))).unwrap();
+    }
+    storage_engine.create_index(tag_created()).unwrap();
+    let mut expected: Vec<i32> = (0..101)
+        .filter(|n| n % 3 == 1)
+        .map(|n| (n * 37) % 101)
+        .collect();
+    expected.sort_by(|a, b| b.cmp(a));
+
+    let cursor = storage_engine
+        .find(Filter::parse(r#"{"tag": "y"}"#).unwrap())
+        .sort(Sort::new().descending("created"));
+    assert_eq!(cursor.index(), Some("tag_1_created_-1"));
+    let (newest, presorted) = created(
+        &storage_engine,
+        r#"{"tag": "y"}"#,
+        Sort::new().descending("created"),
+    );
+    assert!(presorted);
+    assert_eq!(newest, expected);
+
+    // The opposite order walks the index backwards
+    let (oldest, presorted) = created(
+        &storage_engine,
+        r#"{"tag": "y"}"#,
+        Sort::new().ascending("created"),
+    );
+    assert!(presorted);
+    assert_eq!(oldest, expected.iter().rev().copied().collect::<Vec<_>>());
+
+    // A range on the second path narrows the walk
+    let (recent, presorted) = created(
+        &storage_engine,
+        r#"{"tag": "y", "created": {"$gte": 50, "$lt": 80}}"#,
+        Sort::new().descending("created"),
+    );
+    assert!(presorted);
+    let in_range: Vec<i32> = expected
+        .iter()
+        .copied()
+        .filter(|created| (50..80).contains(created))
+        .collect();
+    assert_eq!(recent, in_range);
+
+    // Limits stop the walk early without changing the order
+    let first: Vec<Document> = storage_engine
+        .find(Filter::parse(r#"{"tag": "y"}"#).unwrap())
+        .sort(Sort::new().descending("created"))
+        .limit(3)
+        .map(|item| item.unwrap().1)
+        .collect();
+    assert_eq!(first.len(), 3);
+    assert_eq!(first[0].get("created"), Some(&Value::I32(expected[0])));
+
+    // Several tags interleave, so the cursor sorts them itself
+    let (both, presorted) = created(
+        &storage_engine,
+        r#"{"tag": {"$in": ["x", "y"]}}"#,
+        Sort::new().descending("created"),
+    );
+    assert!(!presorted);
+    assert_eq!(both.len(), 68);
+    assert!(both.windows(2).all(|pair| pair[0] >= pair[1]));
+}
+
+#[test]
+fn test_multikey_index_serves_sorts_with_the_array_pinned() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("multikey.db");
+    let mut storage_engine = create_engine(&path, 64);
+    storage_engine.create_index(tag_created()).unwrap();
+    for n in 0..60 {
+        let name = ["x", "y", "z"][n as usize % 3];
+        storage_engine.insert_document(&post(n, tag(name))).unwrap();
+    }
+
+    // One document with several tags is found under each of them, once
+    storage_engine
+        .insert_document(&post(60, Value::Array(vec![tag("x"), tag("w")])))
+        .unwrap();
+    let (tagged, presorted) = created(
+        &storage_engine,
+        r#"{"tag": "x"}"#,
+        Sort::new().descending("created"),
+    );
+    assert!(presorted);
+    assert_eq!(tagged.len(), 21);
+    assert!(tagged.windows(2).all(|pair| pair[0] > pair[1]));
+    let (w, _) = created(&storage_engine, r#"{"tag": "w"}"#, Sort::new());
+    assert_eq!(w, vec![(60 * 37) % 101]);
+
+    // Several tags would repeat it, and the tags themselves are arrays to sort by
+    let (both, presorted) = created(
+        &storage_engine,
+        r#"{"tag": {"$in": ["x", "w"]}}"#,
+        Sort::new().descending("created"),
+    );
+    assert!(!presorted);
+    assert_eq!(both.len(), 21);
+    assert!(
+        !created(
+            &storage_engine,
+            r#"{"tag": "x"}"#,
+            Sort::new().ascending("tag").descending("created")
+        )
+        .1
+    );
+
+    // Which paths held arrays survives a restart
+    drop(storage_engine);
+    let mut storage_engine = StorageEngine::new(&path, 64).unwrap();
+    let (tagged, presorted) = created(
+        &storage_engine,
+        r#"{"tag": "x"}"#,
+        Sort::new().descending("created"),
+    );
+    assert!(presorted);
+    assert!(tagged.windows(2).all(|pair| pair[0] > pair[1]));
+
+    // Once the sorted path holds an array, the cursor sorts by itself
+    let mut dated = post(61, tag("x"));
+    dated.set("created", Value::Array(vec![Value::I32(5), Value::I32(500)]));
+    storage_engine.insert_document(&dated).unwrap();
+    drop(storage_engine);
+    let storage_engine = StorageEngine::new(&path, 64).unwrap();
+    assert!(
+        !storage_engine
+            .find(Filter::parse(r#"{"tag": "x"}"#).unwrap())
+            .sort(Sort::new().descending("created"))
+            .is_presorted()
+    );
+}
+
+#[test]
+fn test_parallel_arrays_are_rejected() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("parallel.db"), 10);
+    storage_engine
+        .create_index(IndexDefinition::compound(
+            Sort::new().ascending("tag").ascending("created"),
+        ))
+        .unwrap();
+
+    let mut doc = post(1, Value::Array(vec![tag("x"), tag("y")]));
+    storage_engine.insert_document(&doc).unwrap();
+    doc.set("created", Value::Array(vec![Value::I32(1), Value::I32(2)]));
+    doc.set("n", Value::I32(2));
+    assert!(storage_engine.insert_document(&doc).is_err());
+
+    // The failed insert left nothing behind
+    let all: Vec<_> = storage_engine
+        .find(Filter::parse("{}").unwrap())
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(all.len(), 1);
+}
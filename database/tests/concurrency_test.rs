@@ -0,0 +1,174 @@
+mod common;
+
+use common::{create_database, numbered};
+use database::{
+    document::object_id::ObjectId,
+    error::DatabaseError,
+    storage::database::Database,
+    Value,
+};
+use std::sync::Arc;
+use std::thread;
+use tempfile::tempdir;
+
+#[test]
+fn test_concurrent_readers_share_a_small_pool() {
+    let temp_dir = tempdir().unwrap();
+    // Far fewer frames than pages, so readers keep evicting each other's pages
+    let database = create_database(&temp_dir.path().join("concurrent_read.db"), 4);
+    database.create_collection("items").unwrap();
+
+    let ids: Vec<(ObjectId, i32)> = (0..200)
+        .map(|i| {
+            let doc = numbered(i, 500);
+            let id = doc.get_id().unwrap().clone();
+            database.insert_document("items", &doc).unwrap();
+            (id, i)
+        })
+        .collect();
+    let ids = Arc::new(ids);
+
+    let readers: Vec<_> = (0..8)
+        .map(|t| {
+            let database = Arc::clone(&database);
+            let ids = Arc::clone(&ids);
+            thread::spawn(move || {
+                for (id, n) in ids.iter().skip(t).step_by(3) {
+                    let doc = database.get_by_id("items", id).unwrap().unwrap();
+                    assert_eq!(doc.get("n"), Some(&Value::I32(*n)));
+                }
+                assert_eq!(database.scan("items").unwrap().len(), 200);
+            })
+        })
+        .collect();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+}
+
+#[test]
+fn test_readers_and_writers_interleave() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("concurrent_write.db");
+    let database = create_database(&db_path, 8);
+    database.create_collection("items").unwrap();
+
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let database = Arc::clone(&database);
+            thread::spawn(move || {
+                for i in 0..50 {
+                    let doc = numbered(t * 1000 + i, 500);
+                    let id = doc.get_id().unwrap().clone();
+                    database.insert_document("items", &doc).unwrap();
+                    // Our own writes are visible as soon as they return
+                    assert!(database.get_by_id("items", &id).unwrap().is_some());
+                    if i % 5 == 0 {
+                        assert!(database.delete_by_id("items", &id).unwrap());
+                    }
+                }
+            })
+        })
+        .collect();
+    let reader = {
+        let database = Arc::clone(&database);
+        thread::spawn(move || {
+            for _ in 0..20 {
+                // Every scan sees whole documents, never a half-applied write
+                for (_, doc) in database.scan("items").unwrap() {
+                    assert!(matches!(doc.get("n"), Some(Value::I32(_))));
+                }
+            }
+        })
+    };
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    reader.join().unwrap();
+
+    assert_eq!(database.collection_stats("items").unwrap().document_count, 160);
+
+    // Everything written from every thread survives a reopen
+    drop(database);
+    let database = Database::open(&db_path, 8).unwrap();
+    assert_eq!(database.scan("items").unwrap().len(), 160);
+}
+
+#[test]
+fn test_writers_to_different_collections_run_side_by_side() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("concurrent_collections.db");
+    let database = create_database(&db_path, 8);
+    for t in 0..4 {
+        database.create_collection(&format!("items_{}", t)).unwrap();
+    }
+
+    let writers: Vec<_> = (0..4)
+        .map(|t| {
+            let database = Arc::clone(&database);
+            thread::spawn(move || {
+                let collection = format!("items_{}", t);
+                for i in 0..50 {
+                    let doc = numbered(i, 500);
+                    let id = doc.get_id().unwrap().clone();
+                    database.insert_document(&collection, &doc).unwrap();
+                    let mut updated = doc.clone();
+                    updated.set("n", Value::I32(i + 1000));
+                    database.update_by_id(&collection, &id, &updated).unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    for t in 0..4 {
+        let documents = database.scan(&format!("items_{}", t)).unwrap();
+        assert_eq!(documents.len(), 50);
+        assert!(documents
+            .iter()
+            .all(|(_, doc)| matches!(doc.get("n"), Some(Value::I32(n)) if *n >= 1000)));
+    }
+
+    drop(database);
+    let database = Database::open(&db_path, 8).unwrap();
+    for t in 0..4 {
+        assert_eq!(database.scan(&format!("items_{}", t)).unwrap().len(), 50);
+    }
+}
+
+#[test]
+fn test_scan_of_missing_collection_fails() {
+    let temp_dir = tempdir().unwrap();
+    let database = create_database(&temp_dir.path().join("concurrent_missing.db"), 4);
+
+    assert!(database.scan("nope").is_err());
+    assert!(database.list_collections().unwrap().contains(&"default".to_string()));
+}
+
+#[test]
+fn test_panicked_write_poisons_the_database() {
+    let temp_dir = tempdir().unwrap();
+    let database = create_database(&temp_dir.path().join("concurrent_poison.db"), 4);
+    database.insert_document("default", &numbered(0, 500)).unwrap();
+
+    let panicking = Arc::clone(&database);
+    let result = thread::spawn(move || {
+        panicking.write(|engine| -> anyhow::Result<()> {
+            engine.insert_document(&numbered(1, 500))?;
+            panic!("writer died halfway");
+        })
+    })
+    .join();
+    assert!(result.is_err());
+
+    // Later calls report the poisoned handle instead of panicking themselves
+    let error = database.scan("default").unwrap_err();
+    assert!(matches!(
+        error.downcast::<DatabaseError>(),
+        Ok(DatabaseError::Storage(_))
+    ));
+    assert!(database.insert_document("default", &numbered(2, 500)).is_err());
+    assert!(database.read(|engine| Ok(engine.list_collections())).is_err());
+}
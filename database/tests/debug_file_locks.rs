@@ -1,3 +1,6 @@
+mod common;
+
+use common::remove_database;
 use database::storage::file::DatabaseFile;
 use database::storage::storage_engine::StorageEngine;
 use std::path::Path;
@@ -8,7 +11,7 @@ fn test_minimal_file_lock_debug() {
     println!("🔍 Starting minimal file lock debug test");
     
     let test_file = "debug_minimal.db";
-    let _ = fs::remove_file(test_file);
+    remove_database(Path::new(test_file));
     
     println!("📁 Step 1: Creating DatabaseFile");
     let db_file_result = DatabaseFile::create(Path::new(test_file));
@@ -35,6 +38,7 @@ fn test_minimal_file_lock_debug() {
             // Try to clean up and see if file is still locked
             println!("📁 Step 4: Attempting file cleanup");
             let remove_result = fs::remove_file(test_file);
+            remove_database(Path::new(test_file));
             match remove_result {
                 Ok(_) => println!("✅ File removed successfully"),
                 Err(e) => println!("❌ File removal failed (still locked): {}", e),
@@ -47,7 +51,7 @@ fn test_minimal_file_lock_debug() {
     drop(storage_result);
     
     println!("📁 Step 5: Final cleanup");
-    let _ = fs::remove_file(test_file);
+    remove_database(Path::new(test_file));
     println!("✅ Test completed successfully");
 }
 
@@ -56,7 +60,7 @@ fn test_database_file_only() {
     println!("🔍 Testing DatabaseFile creation/destruction only");
     
     let test_file = "debug_dbfile_only.db";
-    let _ = fs::remove_file(test_file);
+    remove_database(Path::new(test_file));
     
     {
         println!("📁 Creating DatabaseFile in scope");
@@ -73,7 +77,7 @@ fn test_database_file_only() {
         Err(e) => println!("❌ Second DatabaseFile creation failed: {}", e),
     }
     
-    let _ = fs::remove_file(test_file);
+    remove_database(Path::new(test_file));
 }
 
 #[test]
@@ -81,7 +85,7 @@ fn test_storage_engine_only() {
     println!("🔍 Testing StorageEngine creation after manual file creation");
     
     let test_file = "debug_storage_only.db";
-    let _ = fs::remove_file(test_file);
+    remove_database(Path::new(test_file));
     
     // Create database file and immediately drop it
     {
@@ -96,5 +100,5 @@ fn test_storage_engine_only() {
         Err(e) => println!("❌ StorageEngine creation failed: {}", e),
     }
     
-    let _ = fs::remove_file(test_file);
+    remove_database(Path::new(test_file));
 }
\ No newline at end of file
@@ -0,0 +1,108 @@
+mod common;
+
+use common::{create_engine, sized};
+use database::storage::storage_engine::{DocumentId, StorageEngine};
+use tempfile::tempdir;
+
+/// Insert a small document and fill the rest of its page, returning the
+/// document's id and the fillers' ids.
+fn insert_crowded(storage_engine: &mut StorageEngine) -> (DocumentId, Vec<DocumentId>) {
+    let document_id = storage_engine.insert_document(&sized("doc", 10)).unwrap();
+    let fillers = (0..7)
+        .map(|i| storage_engine.insert_document(&sized(&format!("filler{}", i), 1000)).unwrap())
+        .collect();
+    (document_id, fillers)
+}
+
+#[test]
+fn test_document_id_survives_relocation() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("forward_relocate.db");
+    let mut storage_engine = create_engine(&db_path, 8);
+
+    let (document_id, _) = insert_crowded(&mut storage_engine);
+    let pages_before = storage_engine.collection_stats("default").unwrap().page_count;
+
+    // Too large for the crowded page, and then larger still
+    for payload_len in [3000, 5000, 2000] {
+        let replacement = sized("doc", payload_len);
+        assert_eq!(storage_engine.update_document(&document_id, &replacement).unwrap(), document_id);
+        let stored = storage_engine.get_document(&document_id).unwrap();
+        assert_eq!(stored.get("payload"), replacement.get("payload"));
+    }
+
+    let stats = storage_engine.collection_stats("default").unwrap();
+    assert!(stats.page_count > pages_before);
+    assert_eq!(stats.document_count, 8);
+
+    let scanned: Vec<_> = storage_engine.scan().collect::<Result<_, _>>().unwrap();
+    assert_eq!(scanned.len(), 8);
+    assert_eq!(scanned.iter().filter(|(id, _)| *id == document_id).count(), 1);
+    drop(storage_engine);
+
+    let storage_engine = StorageEngine::new(&db_path, 8).unwrap();
+    let stored = storage_engine.get_document(&document_id).unwrap();
+    assert_eq!(stored.get("payload"), sized("doc", 2000).get("payload"));
+}
+
+#[test]
+fn test_delete_removes_pointer_and_record() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("forward_delete.db"), 8);
+
+    let (document_id, _) = insert_crowded(&mut storage_engine);
+    let doc = sized("doc", 3000);
+    storage_engine.update_document(&document_id, &doc).unwrap();
+
+    storage_engine.delete_document(&document_id).unwrap();
+    assert!(storage_engine.get_document(&document_id).is_err());
+    assert_eq!(storage_engine.get_by_id(doc.get_id().unwrap()).unwrap(), None);
+    assert_eq!(storage_engine.scan().count(), 7);
+    assert_eq!(storage_engine.collection_stats("default").unwrap().document_count, 7);
+}
+
+#[test]
+fn test_document_moves_home_when_room_frees_up() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("forward_home.db"), 8);
+
+    let (document_id, fillers) = insert_crowded(&mut storage_engine);
+    storage_engine.update_document(&document_id, &sized("doc", 3000)).unwrap();
+    for filler in &fillers[..4] {
+        storage_engine.delete_document(filler).unwrap();
+    }
+
+    // The next update fits in the home page again
+    let replacement = sized("doc", 3500);
+    storage_engine.update_document(&document_id, &replacement).unwrap();
+    let report = storage_engine.vacuum().unwrap();
+    assert_eq!(report.forwarding_pointers_collapsed, 0);
+
+    let scanned: Vec<_> = storage_engine.scan().collect::<Result<_, _>>().unwrap();
+    let (_, stored) = scanned.iter().find(|(id, _)| *id == document_id).unwrap();
+    assert_eq!(stored.get("payload"), replacement.get("payload"));
+    assert_eq!(storage_engine.collection_stats("default").unwrap().page_count, 1);
+}
+
+#[test]
+fn test_vacuum_collapses_forwarding_pointers() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("forward_vacuum.db"), 8);
+
+    let (document_id, fillers) = insert_crowded(&mut storage_engine);
+    let doc = sized("doc", 3000);
+    storage_engine.update_document(&document_id, &doc).unwrap();
+    for filler in &fillers[..4] {
+        storage_engine.delete_document(filler).unwrap();
+    }
+
+    let report = storage_engine.vacuum().unwrap();
+    assert_eq!(report.forwarding_pointers_collapsed, 1);
+    // The page the document had moved to is now empty and gets freed
+    assert_eq!(report.pages_freed, 1);
+
+    assert_eq!(storage_engine.get_document(&document_id).unwrap(), doc);
+    assert_eq!(storage_engine.locate(doc.get_id().unwrap()).unwrap(), Some(document_id));
+    assert_eq!(storage_engine.collection_stats("default").unwrap().document_count, 4);
+    assert_eq!(storage_engine.vacuum().unwrap().forwarding_pointers_collapsed, 0);
+}
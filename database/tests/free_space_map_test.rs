@@ -0,0 +1,75 @@
+mod common;
+
+use common::{create_engine, numbered};
+use database::storage::storage_engine::StorageEngine;
+use tempfile::tempdir;
+
+#[test]
+fn test_deleted_space_is_reused() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("fsm_reuse.db"), 4);
+
+    let ids: Vec<_> = (0..200)
+        .map(|i| {
+            let doc = numbered(i, 500);
+            storage_engine.insert_document(&doc).unwrap();
+            doc.get_id().unwrap().clone()
+        })
+        .collect();
+    let page_count = storage_engine.database_file.page_count();
+
+    for id in &ids {
+        assert!(storage_engine.delete_by_id(id).unwrap());
+    }
+    for i in 0..200 {
+        storage_engine.insert_document(&numbered(i, 500)).unwrap();
+    }
+
+    assert_eq!(storage_engine.database_file.page_count(), page_count);
+    assert_eq!(storage_engine.scan().count(), 200);
+}
+
+#[test]
+fn test_free_space_is_found_after_reopen() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("fsm_reopen.db");
+
+    let page_count = {
+        let mut storage_engine = create_engine(&db_path, 4);
+        let mut ids = Vec::new();
+        for i in 0..300 {
+            let doc = numbered(i, 500);
+            storage_engine.insert_document(&doc).unwrap();
+            ids.push(doc.get_id().unwrap().clone());
+        }
+        // Punch holes all over the file
+        for id in ids.iter().step_by(2) {
+            storage_engine.delete_by_id(id).unwrap();
+        }
+        storage_engine.database_file.page_count()
+    };
+
+    // None of the holey pages are buffered after a restart
+    let mut storage_engine = StorageEngine::new(&db_path, 2).unwrap();
+    for i in 0..150 {
+        storage_engine.insert_document(&numbered(i, 500)).unwrap();
+    }
+    assert_eq!(storage_engine.database_file.page_count(), page_count);
+    assert_eq!(storage_engine.scan().count(), 300);
+}
+
+#[test]
+fn test_packing_does_not_depend_on_pool_size() {
+    let temp_dir = tempdir().unwrap();
+
+    let data_pages = |pool_size: usize| {
+        let path = temp_dir.path().join(format!("fsm_pool_{}.db", pool_size));
+        let mut storage_engine = create_engine(&path, pool_size);
+        for i in 0..400 {
+            storage_engine.insert_document(&numbered(i, 500)).unwrap();
+        }
+        storage_engine.collection_stats("default").unwrap().page_count
+    };
+
+    assert_eq!(data_pages(2), data_pages(128));
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use common::{create_engine, numbered};
+use database::{
+    index::secondary::IndexDefinition,
+    storage::storage_engine::StorageEngine,
+};
+use tempfile::tempdir;
+
+#[test]
+fn test_dropped_collection_pages_are_reused() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("freelist_drop.db"), 8);
+
+    storage_engine.create_collection("scratch").unwrap();
+    {
+        let mut scratch = storage_engine.collection("scratch").unwrap();
+        for i in 0..200 {
+            scratch.insert_document(&numbered(i, 500)).unwrap();
+        }
+        scratch.insert_document(&numbered(-1, 100_000)).unwrap();
+    }
+    assert!(storage_engine.drop_collection("scratch").unwrap());
+    storage_engine.flush().unwrap();
+    let page_count = storage_engine.database_file.page_count();
+    assert!(!storage_engine.database_file.free_pages().unwrap().is_empty());
+
+    storage_engine.create_collection("again").unwrap();
+    let mut again = storage_engine.collection("again").unwrap();
+    for i in 0..200 {
+        again.insert_document(&numbered(i, 500)).unwrap();
+    }
+    assert_eq!(again.scan().count(), 200);
+    assert_eq!(storage_engine.database_file.page_count(), page_count);
+}
+
+#[test]
+fn test_rolled_back_drop_frees_nothing() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("freelist_rollback.db"), 8);
+
+    storage_engine.create_collection("kept").unwrap();
+    for i in 0..50 {
+        storage_engine
+            .collection("kept")
+            .unwrap()
+            .insert_document(&numbered(i, 500))
+            .unwrap();
+    }
+
+    let mut transaction = storage_engine.begin_transaction().unwrap();
+    assert!(transaction.drop_collection("kept").unwrap());
+    transaction.rollback().unwrap();
+    storage_engine.flush().unwrap();
+
+    assert!(storage_engine.database_file.free_pages().unwrap().is_empty());
+    assert_eq!(storage_engine.collection("kept").unwrap().scan().count(), 50);
+}
+
+#[test]
+fn test_truncate_shrinks_the_file() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("freelist_truncate.db");
+
+    let mut storage_engine = create_engine(&db_path, 8);
+    storage_engine.insert_document(&numbered(0, 100)).unwrap();
+    storage_engine.create_collection("bulk").unwrap();
+    {
+        let mut bulk = storage_engine.collection("bulk").unwrap();
+        for i in 0..100 {
+            bulk.insert_document(&numbered(i, 20_000)).unwrap();
+        }
+    }
+    storage_engine.flush().unwrap();
+    let grown = std::fs::metadata(&db_path).unwrap().len();
+
+    storage_engine.drop_collection("bulk").unwrap();
+    let released = storage_engine.truncate().unwrap();
+    assert!(released > 0);
+    let shrunk = std::fs::metadata(&db_path).unwrap().len();
+    assert!(shrunk < grown / 2);
+
+    // Everything still works against the smaller file
+    storage_engine.insert_document(&numbered(1, 20_000)).unwrap();
+    drop(storage_engine);
+    let storage_engine = StorageEngine::new(&db_path, 8).unwrap();
+    assert_eq!(storage_engine.scan().count(), 2);
+    assert!(!storage_engine.list_collections().contains(&"bulk".to_string()));
+}
+
+#[test]
+fn test_deleted_overflow_chains_are_reused() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("freelist_overflow.db"), 8);
+
+    let large = numbered(0, 200_000);
+    storage_engine.insert_document(&large).unwrap();
+    storage_engine.delete_by_id(large.get_id().unwrap()).unwrap();
+    storage_engine.flush().unwrap();
+    let page_count = storage_engine.database_file.page_count();
+
+    storage_engine.insert_document(&numbered(1, 200_000)).unwrap();
+    assert_eq!(storage_engine.database_file.page_count(), page_count);
+    assert_eq!(storage_engine.scan().count(), 1);
+}
+
+#[test]
+fn test_rolled_back_allocations_are_given_back() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("freelist_undo.db"), 8);
+    let page_count = storage_engine.database_file.page_count();
+
+    for _ in 0..5 {
+        let mut transaction = storage_engine.begin_transaction().unwrap();
+        for i in 0..20 {
+            transaction.insert_document(&numbered(i, 1000)).unwrap();
+        }
+        transaction.rollback().unwrap();
+    }
+    assert_eq!(storage_engine.database_file.page_count(), page_count);
+    assert!(storage_engine.database_file.free_pages().unwrap().is_empty());
+
+    // A failed write gives back the overflow pages it took
+    storage_engine
+        .create_index(IndexDefinition::new("n").unique())
+        .unwrap();
+    storage_engine.insert_document(&numbered(0, 100)).unwrap();
+    let page_count = storage_engine.database_file.page_count();
+    assert!(storage_engine.insert_document(&numbered(0, 200_000)).is_err());
+    assert_eq!(storage_engine.database_file.page_count(), page_count);
+
+    // Pages taken from the freelist go back on it
+    storage_engine.create_collection("scratch").unwrap();
+    for i in 0..50 {
+        storage_engine
+            .collection("scratch")
+            .unwrap()
+            .insert_document(&numbered(i, 1000))
+            .unwrap();
+    }
+    assert!(storage_engine.drop_collection("scratch").unwrap());
+    storage_engine.flush().unwrap();
+    let page_count = storage_engine.database_file.page_count();
+    let free = storage_engine.database_file.free_pages().unwrap().len();
+    assert!(free > 0);
+    let mut transaction = storage_engine.begin_transaction().unwrap();
+    for i in 100..200 {
+        transaction.insert_document(&numbered(i, 1000)).unwrap();
+    }
+    transaction.rollback().unwrap();
+    assert_eq!(storage_engine.database_file.page_count(), page_count);
+    assert_eq!(storage_engine.database_file.free_pages().unwrap().len(), free);
+
+    // What is left still works
+    storage_engine.insert_document(&numbered(1, 200_000)).unwrap();
+    assert_eq!(storage_engine.scan().count(), 2);
+}
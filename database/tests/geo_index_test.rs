@@ -0,0 +1,212 @@
+mod common;
+
+use common::create_engine;
+use database::{
+    Document, Value,
+    error::DatabaseError,
+    index::secondary::IndexDefinition,
+    query::{filter::Filter, planner::Plan},
+    storage::storage_engine::StorageEngine,
+};
+use tempfile::tempdir;
+
+fn place(name: &str, lng: i32, lat: i32) -> Document {
+    let mut doc = Document::new();
+    doc.set("name", Value::String(name.to_string()));
+    doc.set("loc", Value::Array(vec![Value::I32(lng), Value::I32(lat)]));
+    // Two documents to a page
+    doc.set("notes", Value::String("n".repeat(1500)));
+    doc
+}
+
+/// A place at every whole degree from 0 to 19 east and north.
+fn grid(engine: &mut StorageEngine) {
+    for lng in 0..20 {
+        for lat in 0..20 {
+            engine
+                .insert_document(&place(&format!("{}:{}", lng, lat), lng, lat))
+                .unwrap();
+        }
+    }
+}
+
+fn names(engine: &StorageEngine, filter: &str) -> Vec<String> {
+    engine
+        .find(Filter::parse(filter).unwrap())
+        .map(|item| match item.unwrap().1.get("name") {
+            Some(Value::String(name)) => name.clone(),
+            other => panic!("unexpected name {:?}", other),
+        })
+        .collect()
+}
+
+fn sorted(mut names: Vec<String>) -> Vec<String> {
+    names.sort();
+    names
+}
+
+#[test]
+fn test_near_returns_the_nearest_first() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("near.db"), 64);
+    grid(&mut storage_engine);
+    storage_engine
+        .create_index(IndexDefinition::geo("loc"))
+        .unwrap();
+
+    // One degree is about 111 km, so 120 km reaches the four neighbours
+    let filter = r#"{"loc": {"$near": {"$geometry": {"type": "Point", "coordinates": [5, 5.1]}, "$maxDistance": 120000}}}"#;
+    let found = names(&storage_engine, filter);
+    assert_eq!(found[0], "5:5");
+    assert_eq!(found[1], "5:6");
+    assert_eq!(sorted(found[2..].to_vec()), vec!["4:5", "6:5"]);
+
+    let distances: Vec<f64> = storage_engine
+        .find(Filter::parse(filter).unwrap())
+        .near_distance("distance")
+        .map(|item| match item.unwrap().1.get("distance") {
+            Some(Value::F64(distance)) => *distance,
+            other => panic!("unexpected distance {:?}", other),
+        })
+        .collect();
+    assert_eq!(distances.len(), 4);
+    assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert!((distances[0] - 11_100.0).abs() < 200.0);
+
+    // Without a limit on the distance every place comes back, the corner last
+    let found = names(&storage_engine, r#"{"loc": {"$near": [0, 0]}}"#);
+    assert_eq!(found.len(), 400);
+    assert_eq!(found[0], "0:0");
+    assert_eq!(found[399], "19:19");
+
+    let explain = storage_engine
+        .find(Filter::parse(filter).unwrap())
+        .explain()
+        .unwrap();
+    assert_eq!(explain.plan.plan.to_string(), "GEO_NEAR loc_2d");
+    assert_eq!(explain.documents_returned, 4);
+}
+
+#[test]
+fn test_geo_within_uses_the_index_when_selective() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("within.db");
+    let mut storage_engine = create_engine(&path, 64);
+    grid(&mut storage_engine);
+    storage_engine
+        .create_index(IndexDefinition::geo("loc"))
+        .unwrap();
+
+    let boxed = r#"{"loc": {"$geoWithin": {"$box": [[2, 2], [4, 4]]}}}"#;
+    assert_eq!(names(&storage_engine, boxed).len(), 9);
+    let explain = storage_engine
+        .find(Filter::parse(boxed).unwrap())
+        .explain()
+        .unwrap();
+    assert_eq!(
+        explain.plan.plan,
+        Plan::IndexScan {
+            index: "loc_2d".to_string(),
+            reverse: false,
+        }
+    );
+    assert!(explain.documents_examined < 40);
+
+    let triangle = r#"{"loc": {"$geoWithin": {"$polygon": [[0, 0], [3, 0], [0, 3]]}}}"#;
+    assert_eq!(
+        sorted(names(&storage_engine, triangle)),
+        vec![
+            "0:0", "0:1", "0:2", "0:3", "1:0", "1:1", "1:2", "2:0", "2:1", "3:0"
+        ]
+    );
+    let circle = r#"{"loc": {"$geoWithin": {"$center": [[10, 10], 120000]}}}"#;
+    assert_eq!(
+        sorted(names(&storage_engine, circle)),
+        vec!["10:10", "10:11", "10:9", "11:10", "9:10"]
+    );
+
+    // A box over most of the grid is cheaper to scan. Plans are cached by
+    // the shape of the query, so this needs an engine that hasn't seen one
+    drop(storage_engine);
+    let storage_engine = StorageEngine::new(&path, 64).unwrap();
+    let wide = r#"{"loc": {"$geoWithin": {"$box": [[0, 0], [18, 18]]}}}"#;
+    let explain = storage_engine
+        .find(Filter::parse(wide).unwrap())
+        .explain()
+        .unwrap();
+    assert_eq!(explain.plan.plan, Plan::CollectionScan);
+    assert_eq!(explain.documents_returned, 361);
+}
+
+#[test]
+fn test_geo_index_is_maintained_and_persists() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("geo_writes.db");
+    let mut storage_engine = create_engine(&path, 16);
+    storage_engine
+        .create_index(IndexDefinition::geo("loc"))
+        .unwrap();
+    let cafe = storage_engine
+        .insert_document(&place("cafe", 13, 52))
+        .unwrap();
+    let mut geojson = Document::new();
+    geojson.set("name", Value::String("park".to_string()));
+    geojson.set(
+        "loc",
+        Value::from_json_value(
+            serde_json::from_str(r#"{"type": "Point", "coordinates": [13.1, 52]}"#).unwrap(),
+        ),
+    );
+    storage_engine.insert_document(&geojson).unwrap();
+    // Documents without a point are left out
+    let mut nowhere = Document::new();
+    nowhere.set("name", Value::String("nowhere".to_string()));
+    storage_engine.insert_document(&nowhere).unwrap();
+
+    let near = r#"{"loc": {"$near": [13, 52]}}"#;
+    assert_eq!(names(&storage_engine, near), vec!["cafe", "park"]);
+
+    storage_engine
+        .update_document(&cafe, &place("cafe", 14, 52))
+        .unwrap();
+    assert_eq!(names(&storage_engine, near), vec!["park", "cafe"]);
+
+    drop(storage_engine);
+    let mut storage_engine = StorageEngine::new(&path, 16).unwrap();
+    assert_eq!(names(&storage_engine, near), vec!["park", "cafe"]);
+    storage_engine.delete_document(&cafe).unwrap();
+    assert_eq!(names(&storage_engine, near), vec!["park"]);
+}
+
+#[test]
+fn test_near_needs_a_geo_index() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("geo_errors.db"), 16);
+    storage_engine
+        .insert_document(&place("cafe", 13, 52))
+        .unwrap();
+
+    let error = storage_engine
+        .find(Filter::parse(r#"{"loc": {"$near": [13, 52]}}"#).unwrap())
+        .next()
+        .unwrap()
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast::<DatabaseError>(),
+        Ok(DatabaseError::Query(_))
+    ));
+    // $geoWithin works without one
+    assert_eq!(
+        names(
+            &storage_engine,
+            r#"{"loc": {"$geoWithin": {"$box": [[12, 51], [14, 53]]}}}"#
+        ),
+        vec!["cafe"]
+    );
+
+    assert!(
+        storage_engine
+            .create_index(IndexDefinition::geo("loc").unique())
+            .is_err()
+    );
+}
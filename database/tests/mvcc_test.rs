@@ -0,0 +1,186 @@
+mod common;
+
+use common::{create_engine, numbered};
+use database::{
+    storage::{
+        database::Database,
+        file::DatabaseFile,
+        storage_engine::DocumentId,
+    },
+    Document, Value,
+};
+use tempfile::tempdir;
+
+fn numbers(documents: &[(DocumentId, Document)]) -> Vec<i32> {
+    let mut numbers: Vec<i32> = documents
+        .iter()
+        .map(|(_, doc)| match doc.get("n") {
+            Some(Value::I32(n)) => *n,
+            other => panic!("unexpected n: {:?}", other),
+        })
+        .collect();
+    numbers.sort_unstable();
+    numbers
+}
+
+#[test]
+fn test_snapshot_ignores_later_writes() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("mvcc_writes.db"), 8);
+
+    let ids: Vec<DocumentId> = (0..10)
+        .map(|i| storage_engine.insert_document(&numbered(i, 100)).unwrap())
+        .collect();
+    let snapshot = storage_engine.snapshot();
+
+    storage_engine.insert_document(&numbered(100, 100)).unwrap();
+    storage_engine.update_document(&ids[0], &numbered(50, 100)).unwrap();
+    storage_engine.delete_document(&ids[1]).unwrap();
+
+    assert_eq!(numbers(&storage_engine.scan_at(&snapshot).unwrap()), (0..10).collect::<Vec<_>>());
+    let now: Vec<_> = storage_engine.scan().collect::<Result<_, _>>().unwrap();
+    assert_eq!(numbers(&now), [2, 3, 4, 5, 6, 7, 8, 9, 50, 100]);
+
+    assert_eq!(
+        storage_engine.get_document_at(&ids[0], &snapshot).unwrap().unwrap().get("n"),
+        Some(&Value::I32(0))
+    );
+    assert!(storage_engine.get_document_at(&ids[1], &snapshot).unwrap().is_some());
+}
+
+#[test]
+fn test_get_by_id_at_sees_old_versions() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("mvcc_by_id.db"), 8);
+
+    let kept = numbered(1, 10);
+    let kept_id = kept.get_id().unwrap().clone();
+    let deleted = numbered(2, 10);
+    let deleted_id = deleted.get_id().unwrap().clone();
+    storage_engine.insert_document(&kept).unwrap();
+    storage_engine.insert_document(&deleted).unwrap();
+
+    let snapshot = storage_engine.snapshot();
+    storage_engine.update_by_id(&kept_id, &numbered(10, 3000)).unwrap();
+    storage_engine.delete_by_id(&deleted_id).unwrap();
+    let added = numbered(3, 10);
+    let added_id = added.get_id().unwrap().clone();
+    storage_engine.insert_document(&added).unwrap();
+
+    assert_eq!(storage_engine.get_by_id_at(&kept_id, &snapshot).unwrap(), Some(kept));
+    assert_eq!(storage_engine.get_by_id_at(&deleted_id, &snapshot).unwrap(), Some(deleted));
+    assert_eq!(storage_engine.get_by_id_at(&added_id, &snapshot).unwrap(), None);
+    assert_eq!(storage_engine.get_by_id_at(&added_id, &storage_engine.snapshot()).unwrap(), Some(added));
+}
+
+#[test]
+fn test_snapshot_survives_relocation_and_vacuum() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("mvcc_vacuum.db"), 8);
+
+    // A small document on a page filled up by the others
+    let ids: Vec<DocumentId> = (0..8)
+        .map(|i| {
+            let payload_len = if i == 0 { 10 } else { 1000 };
+            storage_engine.insert_document(&numbered(i, payload_len)).unwrap()
+        })
+        .collect();
+    // Relocate it, then make room for it to come home
+    storage_engine.update_document(&ids[0], &numbered(0, 3000)).unwrap();
+    for id in &ids[1..4] {
+        storage_engine.delete_document(id).unwrap();
+    }
+
+    let snapshot = storage_engine.snapshot();
+    let report = storage_engine.vacuum().unwrap();
+    assert!(report.forwarding_pointers_collapsed > 0);
+
+    assert_eq!(numbers(&storage_engine.scan_at(&snapshot).unwrap()), [0, 4, 5, 6, 7]);
+}
+
+#[test]
+fn test_rolled_back_writes_leave_no_versions() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("mvcc_rollback.db"), 8);
+
+    let document_id = storage_engine.insert_document(&numbered(1, 10)).unwrap();
+    let snapshot = storage_engine.snapshot();
+
+    let mut transaction = storage_engine.begin_transaction().unwrap();
+    transaction.update_document(&document_id, &numbered(2, 10)).unwrap();
+    transaction.rollback().unwrap();
+
+    assert_eq!(numbers(&storage_engine.scan_at(&snapshot).unwrap()), [1]);
+    drop(snapshot);
+    assert_eq!(storage_engine.vacuum().unwrap().versions_reclaimed, 0);
+}
+
+#[test]
+fn test_versions_are_reclaimed_once_snapshots_close() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("mvcc_reclaim.db"), 8);
+
+    let document_id = storage_engine.insert_document(&numbered(1, 10)).unwrap();
+    let snapshot = storage_engine.snapshot();
+    storage_engine.update_document(&document_id, &numbered(2, 10)).unwrap();
+    storage_engine.update_document(&document_id, &numbered(3, 10)).unwrap();
+
+    // Still needed by the open snapshot
+    assert_eq!(storage_engine.vacuum().unwrap().versions_reclaimed, 0);
+    assert_eq!(storage_engine.retained_versions(), 2);
+    assert_eq!(numbers(&storage_engine.scan_at(&snapshot).unwrap()), [1]);
+
+    // Dropped with the snapshot, without waiting for a vacuum
+    drop(snapshot);
+    assert_eq!(storage_engine.retained_versions(), 0);
+    assert_eq!(storage_engine.vacuum().unwrap().versions_reclaimed, 0);
+
+    // With no snapshot open nothing is recorded
+    storage_engine.update_document(&document_id, &numbered(4, 10)).unwrap();
+    assert_eq!(storage_engine.retained_versions(), 0);
+
+    // A snapshot closing mid-transaction leaves nothing behind either
+    let snapshot = storage_engine.snapshot();
+    let mut transaction = storage_engine.begin_transaction().unwrap();
+    transaction.update_document(&document_id, &numbered(5, 10)).unwrap();
+    drop(snapshot);
+    transaction.commit().unwrap();
+    assert_eq!(storage_engine.retained_versions(), 0);
+}
+
+#[test]
+fn test_snapshot_keeps_dropped_collection() {
+    let temp_dir = tempdir().unwrap();
+    let db_file = DatabaseFile::create(&temp_dir.path().join("mvcc_drop.db")).unwrap();
+    drop(db_file);
+    let database = Database::open(&temp_dir.path().join("mvcc_drop.db"), 8).unwrap();
+    database.create_collection("items").unwrap();
+    for i in 0..5 {
+        database.insert_document("items", &numbered(i, 100)).unwrap();
+    }
+
+    let snapshot = database.snapshot().unwrap();
+    database.drop_collection("items").unwrap();
+
+    assert_eq!(numbers(&database.scan_at("items", &snapshot).unwrap()), [0, 1, 2, 3, 4]);
+    assert!(database.scan("items").is_err());
+}
+
+#[test]
+fn test_snapshot_reads_of_a_missing_collection_fail() {
+    let temp_dir = tempdir().unwrap();
+    let db_file = DatabaseFile::create(&temp_dir.path().join("mvcc_missing.db")).unwrap();
+    drop(db_file);
+    let database = Database::open(&temp_dir.path().join("mvcc_missing.db"), 8).unwrap();
+    let document = numbered(0, 100);
+
+    let snapshot = database.snapshot().unwrap();
+    assert!(database.scan_at("missing", &snapshot).is_err());
+    assert!(database.get_by_id_at("missing", document.get_id().unwrap(), &snapshot).is_err());
+
+    // Nor can a snapshot see a collection created after it was taken
+    database.create_collection("later").unwrap();
+    database.insert_document("later", &document).unwrap();
+    assert!(database.scan_at("later", &snapshot).is_err());
+    assert!(database.scan_at("later", &database.snapshot().unwrap()).is_ok());
+}
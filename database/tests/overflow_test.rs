@@ -0,0 +1,74 @@
+mod common;
+
+use common::{create_engine, sized};
+use database::{
+    bson::MAX_DOCUMENT_SIZE,
+    storage::storage_engine::StorageEngine,
+};
+use tempfile::tempdir;
+
+#[test]
+fn test_large_document_roundtrip() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("overflow.db");
+
+    let large = sized("large", 1024 * 1024);
+    let small = sized("small", 100);
+    {
+        let mut storage_engine = create_engine(&db_path, 16);
+        let location = storage_engine.insert_document(&large).unwrap();
+        storage_engine.insert_document(&small).unwrap();
+
+        assert_eq!(storage_engine.get_document(&location).unwrap(), large);
+        assert_eq!(
+            storage_engine.get_by_id(large.get_id().unwrap()).unwrap().as_ref(),
+            Some(&large)
+        );
+        assert_eq!(storage_engine.scan().count(), 2);
+    }
+
+    let storage_engine = StorageEngine::new(&db_path, 16).unwrap();
+    assert_eq!(
+        storage_engine.get_by_id(large.get_id().unwrap()).unwrap(),
+        Some(large)
+    );
+    assert_eq!(storage_engine.get_by_id(small.get_id().unwrap()).unwrap(), Some(small));
+}
+
+#[test]
+fn test_updates_move_documents_in_and_out_of_overflow() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("overflow_update.db"), 16);
+
+    let doc = sized("doc", 10);
+    let id = doc.get_id().unwrap().clone();
+    storage_engine.insert_document(&doc).unwrap();
+
+    for payload_len in [50_000, 200_000, 20_000, 10, 30_000] {
+        let replacement = sized("doc", payload_len);
+        storage_engine.update_by_id(&id, &replacement).unwrap();
+
+        let stored = storage_engine.get_by_id(&id).unwrap().unwrap();
+        assert_eq!(stored.get("payload"), replacement.get("payload"));
+    }
+    assert_eq!(storage_engine.scan().count(), 1);
+}
+
+#[test]
+fn test_document_size_limit() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("overflow_limit.db"), 16);
+
+    // Just under the limit once the _id and field names are encoded
+    let largest = sized("largest", MAX_DOCUMENT_SIZE - 1024);
+    let location = storage_engine.insert_document(&largest).unwrap();
+    assert_eq!(storage_engine.get_document(&location).unwrap(), largest);
+
+    let too_large = sized("too large", MAX_DOCUMENT_SIZE);
+    assert!(storage_engine.insert_document(&too_large).is_err());
+    assert_eq!(storage_engine.get_by_id(too_large.get_id().unwrap()).unwrap(), None);
+    assert!(storage_engine
+        .update_document(&location, &too_large)
+        .is_err());
+    assert_eq!(storage_engine.get_document(&location).unwrap(), largest);
+}
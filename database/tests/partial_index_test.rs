@@ -0,0 +1,187 @@
+mod common;
+
+use common::create_engine;
+use database::{
+    Document, Value,
+    error::DatabaseError,
+    index::secondary::IndexDefinition,
+    query::{filter::Filter, planner::Plan, sort::Sort},
+    storage::storage_engine::StorageEngine,
+};
+use tempfile::tempdir;
+
+fn order(n: i32, status: &str) -> Document {
+    let mut doc = Document::new();
+    doc.set("n", Value::I32(n));
+    doc.set("status", Value::String(status.to_string()));
+    doc.set("due", Value::I32(n % 50));
+    doc.set("notes", Value::String("n".repeat(1000)));
+    doc
+}
+
+/// 400 orders, every tenth one open.
+fn orders(engine: &mut StorageEngine) {
+    for n in 0..400 {
+        let status = if n % 10 == 0 { "open" } else { "closed" };
+        engine.insert_document(&order(n, status)).unwrap();
+    }
+}
+
+fn numbers(engine: &StorageEngine, filter: &str) -> Vec<i32> {
+    let mut numbers: Vec<i32> = engine
+        .find(Filter::parse(filter).unwrap())
+        .map(|item| match item.unwrap().1.get("n") {
+            Some(Value::I32(n)) => *n,
+            other => panic!("unexpected n {:?}", other),
+        })
+        .collect();
+    numbers.sort();
+    numbers
+}
+
+fn plan(engine: &StorageEngine, filter: &str) -> Plan {
+    engine
+        .find(Filter::parse(filter).unwrap())
+        .explain()
+        .unwrap()
+        .plan
+        .plan
+}
+
+fn index_scan(name: &str) -> Plan {
+    Plan::IndexScan {
+        index: name.to_string(),
+        reverse: false,
+    }
+}
+
+#[test]
+fn test_partial_index_serves_queries_implying_its_filter() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("partial.db");
+    let mut storage_engine = create_engine(&path, 64);
+    orders(&mut storage_engine);
+    storage_engine
+        .create_index(IndexDefinition::new("due").partial(r#"{"status": "open"}"#))
+        .unwrap();
+
+    let open_soon = r#"{"status": "open", "due": {"$lt": 5}}"#;
+    assert_eq!(plan(&storage_engine, open_soon), index_scan("due_1"));
+    assert_eq!(
+        numbers(&storage_engine, open_soon),
+        vec![0, 50, 100, 150, 200, 250, 300, 350]
+    );
+
+    // Closed orders are not in the index, so it can't answer for them
+    let soon = r#"{"due": {"$lt": 1}}"#;
+    assert_eq!(plan(&storage_engine, soon), Plan::CollectionScan);
+    assert_eq!(numbers(&storage_engine, soon).len(), 8);
+    let closed = r#"{"status": "closed", "due": 1}"#;
+    assert_eq!(plan(&storage_engine, closed), Plan::CollectionScan);
+    assert_eq!(numbers(&storage_engine, closed).len(), 8);
+
+    // Documents move in and out of the index as they change
+    let (closing, _) = storage_engine
+        .find(Filter::parse(r#"{"n": 100}"#).unwrap())
+        .next()
+        .unwrap()
+        .unwrap();
+    storage_engine
+        .update_document(&closing, &order(100, "closed"))
+        .unwrap();
+    let (opening, _) = storage_engine
+        .find(Filter::parse(r#"{"n": 101}"#).unwrap())
+        .next()
+        .unwrap()
+        .unwrap();
+    storage_engine
+        .update_document(&opening, &order(101, "open"))
+        .unwrap();
+    assert_eq!(
+        numbers(&storage_engine, open_soon),
+        vec![0, 50, 101, 150, 200, 250, 300, 350]
+    );
+
+    drop(storage_engine);
+    let storage_engine = StorageEngine::new(&path, 64).unwrap();
+    let definition = &storage_engine.list_indexes().unwrap()[0];
+    assert_eq!(definition.partial_filter(), Some(r#"{"status": "open"}"#));
+    assert_eq!(plan(&storage_engine, open_soon), index_scan("due_1"));
+    assert_eq!(numbers(&storage_engine, open_soon).len(), 8);
+}
+
+#[test]
+fn test_sparse_index_skips_documents_missing_the_field() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("sparse.db"), 64);
+    orders(&mut storage_engine);
+    // Only some orders have a coupon, each a different one
+    for n in 0..5 {
+        let mut doc = order(1000 + n, "open");
+        doc.set("coupon", Value::String(format!("SAVE{}", n)));
+        storage_engine.insert_document(&doc).unwrap();
+    }
+    let mut nulled = order(2000, "open");
+    nulled.set("coupon", Value::Null);
+    storage_engine.insert_document(&nulled).unwrap();
+
+    // Without sparse, the 400 orders missing a coupon would all collide as null
+    storage_engine
+        .create_index(IndexDefinition::new("coupon").sparse().unique())
+        .unwrap();
+    let mut repeat = order(3000, "open");
+    repeat.set("coupon", Value::String("SAVE1".to_string()));
+    let error = storage_engine.insert_document(&repeat).unwrap_err();
+    assert!(matches!(
+        error.downcast::<DatabaseError>(),
+        Ok(DatabaseError::DuplicateKey { .. })
+    ));
+    storage_engine
+        .insert_document(&order(3001, "open"))
+        .unwrap();
+
+    let coupon = r#"{"coupon": "SAVE3"}"#;
+    assert_eq!(plan(&storage_engine, coupon), index_scan("coupon_1"));
+    assert_eq!(numbers(&storage_engine, coupon), vec![1003]);
+
+    // Null matches the documents left out as well
+    let none = r#"{"coupon": null}"#;
+    assert_eq!(plan(&storage_engine, none), Plan::CollectionScan);
+    assert_eq!(numbers(&storage_engine, none).len(), 402);
+
+    // Sorts see every document, whether the index holds it or not
+    let sorted: Vec<i32> = storage_engine
+        .find(Filter::parse(r#"{"coupon": {"$exists": true}}"#).unwrap())
+        .sort(Sort::new().descending("coupon"))
+        .map(|item| match item.unwrap().1.get("n") {
+            Some(Value::I32(n)) => *n,
+            other => panic!("unexpected n {:?}", other),
+        })
+        .collect();
+    assert_eq!(sorted, vec![1004, 1003, 1002, 1001, 1000, 2000]);
+    assert_eq!(
+        storage_engine
+            .find(Filter::all())
+            .sort(Sort::new().ascending("coupon"))
+            .count(),
+        407
+    );
+}
+
+#[test]
+fn test_invalid_sparse_and_partial_indexes_are_refused() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("partial_invalid.db"), 16);
+    for definition in [
+        IndexDefinition::text(&["notes"]).sparse(),
+        IndexDefinition::geo("loc").partial(r#"{"status": "open"}"#),
+        IndexDefinition::new("due").partial(r#"{"status": {"$bogus": 1}}"#),
+        IndexDefinition::new("due").partial(r#"{"$text": {"$search": "x"}}"#),
+    ] {
+        let error = storage_engine.create_index(definition).unwrap_err();
+        assert!(matches!(
+            error.downcast::<DatabaseError>(),
+            Ok(DatabaseError::Validation(_))
+        ));
+    }
+}
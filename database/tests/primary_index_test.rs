@@ -0,0 +1,105 @@
+mod common;
+
+use common::create_engine;
+use database::{
+    document::object_id::ObjectId,
+    storage::storage_engine::StorageEngine,
+    Document, Value,
+};
+use tempfile::tempdir;
+
+fn user(name: &str, bio_len: usize) -> Document {
+    let mut doc = Document::new();
+    doc.set("name", Value::String(name.to_string()));
+    doc.set("bio", Value::String("b".repeat(bio_len)));
+    doc
+}
+
+#[test]
+fn test_get_by_id() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("pk_get.db"), 10);
+
+    let doc = user("Alice", 10);
+    let id = doc.get_id().unwrap().clone();
+    let location = storage_engine.insert_document(&doc).unwrap();
+
+    assert_eq!(storage_engine.locate(&id).unwrap(), Some(location));
+    assert_eq!(storage_engine.get_by_id(&id).unwrap(), Some(doc));
+    assert_eq!(storage_engine.get_by_id(&ObjectId::new()).unwrap(), None);
+}
+
+#[test]
+fn test_duplicate_id_rejected() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("pk_dup.db"), 10);
+
+    let doc = user("Alice", 10);
+    storage_engine.insert_document(&doc).unwrap();
+    assert!(storage_engine.insert_document(&doc).is_err());
+    assert_eq!(storage_engine.scan().count(), 1);
+}
+
+#[test]
+fn test_update_by_id_follows_relocation() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("pk_update.db"), 10);
+
+    // Fill the first data page so the growing document has to move elsewhere
+    let doc = user("Alice", 10);
+    let id = doc.get_id().unwrap().clone();
+    let original_location = storage_engine.insert_document(&doc).unwrap();
+    for i in 0..7 {
+        storage_engine.insert_document(&user(&format!("filler{}", i), 1000)).unwrap();
+    }
+
+    // The replacement carries its own fresh _id, but update_by_id keeps ours
+    let replacement = user("Alice", 3000);
+    // The document moves, but a forwarding pointer keeps its DocumentId valid
+    let new_location = storage_engine.update_by_id(&id, &replacement).unwrap();
+    assert_eq!(new_location, original_location);
+    assert_eq!(storage_engine.locate(&id).unwrap(), Some(original_location));
+
+    let stored = storage_engine.get_by_id(&id).unwrap().unwrap();
+    assert_eq!(stored.get_id(), Some(&id));
+    assert_eq!(stored.get("bio"), replacement.get("bio"));
+    assert_eq!(storage_engine.locate(replacement.get_id().unwrap()).unwrap(), None);
+}
+
+#[test]
+fn test_delete_by_id() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("pk_delete.db"), 10);
+
+    let doc = user("Alice", 10);
+    let id = doc.get_id().unwrap().clone();
+    storage_engine.insert_document(&doc).unwrap();
+
+    assert!(storage_engine.delete_by_id(&id).unwrap());
+    assert!(!storage_engine.delete_by_id(&id).unwrap());
+    assert_eq!(storage_engine.get_by_id(&id).unwrap(), None);
+    assert!(storage_engine.update_by_id(&id, &user("Bob", 1)).is_err());
+}
+
+#[test]
+fn test_index_persists_across_reopen() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("pk_reopen.db");
+
+    let mut ids = Vec::new();
+    {
+        let mut storage_engine = create_engine(&db_path, 4);
+        for i in 0..500 {
+            let doc = user(&format!("user{}", i), 50);
+            ids.push(doc.get_id().unwrap().clone());
+            storage_engine.insert_document(&doc).unwrap();
+        }
+        storage_engine.flush().unwrap();
+    }
+
+    let storage_engine = StorageEngine::new(&db_path, 4).unwrap();
+    for (i, id) in ids.iter().enumerate() {
+        let doc = storage_engine.get_by_id(id).unwrap().unwrap();
+        assert_eq!(doc.get("name"), Some(&Value::String(format!("user{}", i))));
+    }
+}
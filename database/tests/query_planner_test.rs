@@ -0,0 +1,140 @@
+mod common;
+
+use common::create_engine;
+use database::{
+    Document, Value,
+    index::secondary::IndexDefinition,
+    query::{filter::Filter, planner::Plan, sort::Sort},
+    storage::{database::Database, storage_engine::StorageEngine},
+};
+use std::path::Path;
+use tempfile::tempdir;
+
+fn order(n: i32) -> Document {
+    let mut doc = Document::new();
+    doc.set("n", Value::I32(n));
+    doc.set(
+        "status",
+        Value::String(["open", "done"][n as usize % 2].to_string()),
+    );
+    doc.set("customer", Value::I32(n % 50));
+    // Four documents to a page
+    doc.set("notes", Value::String("n".repeat(2000)));
+    doc
+}
+
+fn database(path: &Path) -> Database {
+    let database = Database::from_engine(create_engine(path, 64));
+    database.create_collection("orders").unwrap();
+    for n in 0..200 {
+        database.insert_document("orders", &order(n)).unwrap();
+    }
+    database
+        .create_index("orders", IndexDefinition::new("status"))
+        .unwrap();
+    database
+        .create_index("orders", IndexDefinition::new("customer"))
+        .unwrap();
+    database
+}
+
+fn index_scan(index: &str) -> Plan {
+    Plan::IndexScan {
+        index: index.to_string(),
+        reverse: false,
+    }
+}
+
+#[test]
+fn test_explain_reports_the_chosen_and_rejected_plans() {
+    let temp_dir = tempdir().unwrap();
+    let database = database(&temp_dir.path().join("explain.db"));
+
+    let filter = r#"{"customer": 7, "status": "done"}"#;
+    let explain = database
+        .explain("orders", Filter::parse(filter).unwrap())
+        .unwrap();
+    assert_eq!(explain.plan.plan, index_scan("customer_1"));
+    assert!(!explain.cached);
+    let rejected: Vec<Plan> = explain
+        .rejected
+        .iter()
+        .map(|estimate| estimate.plan.clone())
+        .collect();
+    assert_eq!(rejected.len(), 2);
+    assert!(rejected.contains(&Plan::CollectionScan));
+    assert!(rejected.contains(&index_scan("status_1")));
+    assert!(
+        explain
+            .rejected
+            .iter()
+            .all(|estimate| estimate.cost >= explain.plan.cost)
+    );
+
+    // Four keys lead to four documents on four pages, all of them done
+    assert_eq!(explain.keys_examined, 4);
+    assert_eq!(explain.documents_examined, 4);
+    assert_eq!(explain.pages_examined, 4);
+    assert_eq!(explain.documents_returned, 4);
+
+    // The same shape with other values reuses the plan
+    let explain = database
+        .explain(
+            "orders",
+            Filter::parse(r#"{"customer": 8, "status": "open"}"#).unwrap(),
+        )
+        .unwrap();
+    assert!(explain.cached);
+    assert_eq!(explain.plan.plan, index_scan("customer_1"));
+    assert_eq!(explain.documents_returned, 4);
+
+    assert!(
+        database
+            .explain("missing", Filter::parse(filter).unwrap())
+            .is_err()
+    );
+}
+
+#[test]
+fn test_unselective_filters_scan_the_collection() {
+    let temp_dir = tempdir().unwrap();
+    let database = database(&temp_dir.path().join("scan.db"));
+
+    let explain = database
+        .explain("orders", Filter::parse(r#"{"status": "open"}"#).unwrap())
+        .unwrap();
+    assert_eq!(explain.plan.plan, Plan::CollectionScan);
+    assert_eq!(explain.plan.plan.to_string(), "COLLSCAN");
+    assert_eq!(explain.keys_examined, 0);
+    assert_eq!(explain.documents_examined, 200);
+    assert_eq!(explain.documents_returned, 100);
+    assert!(explain.pages_examined >= 50);
+
+    // Dropping an index drops it from the candidates and the cached plans
+    assert!(database.drop_index("orders", "status_1").unwrap());
+    let explain = database
+        .explain("orders", Filter::parse(r#"{"status": "open"}"#).unwrap())
+        .unwrap();
+    assert!(!explain.cached);
+    assert!(explain.rejected.is_empty());
+}
+
+#[test]
+fn test_sorts_are_served_by_the_planned_index() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("sorted.db");
+    drop(database(&path));
+
+    let mut storage_engine = StorageEngine::new(&path, 64).unwrap();
+    let orders = storage_engine.collection("orders").unwrap();
+    let cursor = orders
+        .find(Filter::parse(r#"{"customer": {"$gte": 10, "$lt": 12}}"#).unwrap())
+        .sort(Sort::new().descending("customer"));
+    assert!(cursor.is_presorted());
+    let customers: Vec<Value> = cursor
+        .map(|item| item.unwrap().1.get("customer").cloned().unwrap())
+        .collect();
+    assert_eq!(customers.len(), 8);
+    assert!(customers[..4].iter().all(|c| *c == Value::I32(11)));
+    assert!(customers[4..].iter().all(|c| *c == Value::I32(10)));
+}
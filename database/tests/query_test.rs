@@ -0,0 +1,193 @@
+mod common;
+
+use common::create_engine;
+use database::{
+    Document, Value,
+    query::{filter::Filter, projection::Projection, sort::Sort},
+    storage::{
+        database::Database,
+        storage_engine::DocumentId,
+    },
+};
+use tempfile::tempdir;
+
+fn person(name: &str, age: i32, tags: &[&str]) -> Document {
+    let mut doc = Document::new();
+    doc.set("name", Value::String(name.to_string()));
+    doc.set("age", Value::I32(age));
+    doc.set(
+        "tags",
+        Value::Array(
+            tags.iter()
+                .map(|tag| Value::String(tag.to_string()))
+                .collect(),
+        ),
+    );
+    doc
+}
+
+fn names(documents: impl Iterator<Item = anyhow::Result<(DocumentId, Document)>>) -> Vec<String> {
+    let mut names: Vec<String> = documents
+        .map(|item| match item.unwrap().1.get("name") {
+            Some(Value::String(name)) => name.clone(),
+            other => panic!("unexpected name {:?}", other),
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_find_filters_default_collection() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("find.db"), 10);
+    for (name, age, tags) in [
+        ("ada", 36, &["a", "x"][..]),
+        ("bob", 17, &["b"][..]),
+        ("cy", 52, &["c", "b"][..]),
+        ("di", 21, &[][..]),
+    ] {
+        storage_engine
+            .insert_document(&person(name, age, tags))
+            .unwrap();
+    }
+
+    let filter = Filter::parse(r#"{"age": {"$gte": 21}, "tags": {"$in": ["a", "b"]}}"#).unwrap();
+    assert_eq!(names(storage_engine.find(filter)), vec!["ada", "cy"]);
+
+    let filter =
+        Filter::parse(r#"{"$or": [{"age": {"$lt": 18}}, {"tags": {"$size": 0}}]}"#).unwrap();
+    assert_eq!(names(storage_engine.find(filter)), vec!["bob", "di"]);
+
+    assert_eq!(storage_engine.find(Filter::all()).count(), 4);
+}
+
+#[test]
+fn test_find_skips_deleted_and_sees_updates() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("find_updates.db"), 10);
+    let ada = storage_engine
+        .insert_document(&person("ada", 36, &[]))
+        .unwrap();
+    let bob = storage_engine
+        .insert_document(&person("bob", 40, &[]))
+        .unwrap();
+
+    storage_engine.delete_document(&bob).unwrap();
+    storage_engine
+        .update_document(&ada, &person("ada", 12, &[]))
+        .unwrap();
+
+    let filter = Filter::parse(r#"{"age": {"$gt": 30}}"#).unwrap();
+    assert_eq!(storage_engine.find(filter).count(), 0);
+    let filter = Filter::parse(r#"{"age": 12}"#).unwrap();
+    assert_eq!(names(storage_engine.find(filter)), vec!["ada"]);
+}
+
+#[test]
+fn test_find_in_collection() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("find_collection.db");
+    let database = Database::from_engine(create_engine(&db_path, 10));
+    database.create_collection("people").unwrap();
+    database
+        .insert_document("people", &person("ada", 36, &[]))
+        .unwrap();
+    database
+        .insert_document("people", &person("bob", 17, &[]))
+        .unwrap();
+    database
+        .insert_document("default", &person("eve", 50, &[]))
+        .unwrap();
+
+    let filter = Filter::parse(r#"{"age": {"$gte": 18}}"#).unwrap();
+    let found = database.find("people", filter.clone()).unwrap();
+    assert_eq!(names(found.into_iter().map(Ok)), vec!["ada"]);
+
+    assert!(database.find("missing", filter).is_err());
+}
+
+fn numbered(n: i32) -> Document {
+    let mut doc = person(&format!("p{:03}", n), n % 7, &[]);
+    doc.set("n", Value::I32(n));
+    doc.set("bio", Value::String("b".repeat(200)));
+    doc
+}
+
+#[test]
+fn test_sort_skip_limit() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("find_sorted.db"), 10);
+    for n in 0..40 {
+        storage_engine.insert_document(&numbered(n)).unwrap();
+    }
+
+    let page: Vec<i32> = storage_engine
+        .find(Filter::parse(r#"{"n": {"$gte": 10}}"#).unwrap())
+        .sort(Sort::new().ascending("age").descending("n"))
+        .skip(3)
+        .limit(4)
+        .map(|item| item.unwrap().1.get("n").unwrap().as_i32().unwrap())
+        .collect();
+    // Ages 0 come from n = 35, 28, 21, 14; ages 1 from n = 36, 29, 22, 15
+    assert_eq!(page, vec![14, 36, 29, 22]);
+
+    // Without a sort, skip and limit page through scan order
+    let streamed = storage_engine.find(Filter::all()).skip(38).limit(5).count();
+    assert_eq!(streamed, 2);
+}
+
+#[test]
+fn test_sort_spills_past_memory_budget() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("find_spill.db"), 10);
+    for n in (0..120).rev() {
+        storage_engine.insert_document(&numbered(n)).unwrap();
+    }
+
+    let sorted: Vec<i32> = storage_engine
+        .find(Filter::all())
+        .sort(Sort::parse(r#"{"n": 1}"#).unwrap())
+        .memory_budget(2048)
+        .map(|item| item.unwrap().1.get("n").unwrap().as_i32().unwrap())
+        .collect();
+    assert_eq!(sorted, (0..120).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_projection() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("find_projected.db"), 10);
+    let mut ids = Vec::new();
+    for n in 0..5 {
+        let doc = numbered(n);
+        ids.push(doc.get_id().unwrap().clone());
+        storage_engine.insert_document(&doc).unwrap();
+    }
+
+    // The filter and sort read fields the projection drops
+    let projected: Vec<Document> = storage_engine
+        .find(Filter::parse(r#"{"age": {"$gt": 1}}"#).unwrap())
+        .sort(Sort::new().descending("n"))
+        .project(Projection::parse(r#"{"name": 1}"#).unwrap())
+        .map(|item| item.unwrap().1)
+        .collect();
+    assert_eq!(projected.len(), 3);
+    assert_eq!(
+        projected[0].get("name"),
+        Some(&Value::String("p004".to_string()))
+    );
+    assert_eq!(projected[0].get_id(), Some(&ids[4]));
+    assert!(projected.iter().all(|doc| doc.len() == 1));
+
+    let excluded = storage_engine
+        .find(Filter::all())
+        .project(Projection::parse(r#"{"bio": 0, "tags": 0}"#).unwrap())
+        .next()
+        .unwrap()
+        .unwrap()
+        .1;
+    let mut fields: Vec<_> = excluded.keys().cloned().collect();
+    fields.sort();
+    assert_eq!(fields, vec!["age", "n", "name"]);
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use common::create_engine;
+use database::{
+    storage::storage_engine::StorageEngine,
+    Document, Value,
+};
+use std::collections::HashMap;
+use tempfile::tempdir;
+
+#[test]
+fn test_scan_empty_database() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("scan_empty.db");
+    let storage_engine = create_engine(&db_path, 10);
+
+    assert_eq!(storage_engine.scan().count(), 0);
+}
+
+#[test]
+fn test_scan_skips_deleted_documents() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("scan_deleted.db");
+    let mut storage_engine = create_engine(&db_path, 10);
+
+    let mut ids = Vec::new();
+    for i in 0..5 {
+        let mut doc = Document::new();
+        doc.set("n", Value::I32(i));
+        ids.push(storage_engine.insert_document(&doc).unwrap());
+    }
+
+    storage_engine.delete_document(&ids[1]).unwrap();
+    storage_engine.delete_document(&ids[3]).unwrap();
+
+    let scanned: Vec<_> = storage_engine
+        .scan()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let values: Vec<_> = scanned.iter().map(|(_, doc)| doc.get("n").cloned()).collect();
+
+    assert_eq!(
+        values,
+        vec![Some(Value::I32(0)), Some(Value::I32(2)), Some(Value::I32(4))]
+    );
+    assert_eq!(scanned[0].0, ids[0]);
+    assert_eq!(scanned[1].0, ids[2]);
+    assert_eq!(scanned[2].0, ids[4]);
+}
+
+#[test]
+fn test_scan_after_reopen_with_small_buffer_pool() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("scan_reopen.db");
+
+    // Write enough documents to span several pages
+    let mut expected = HashMap::new();
+    {
+        let mut storage_engine = create_engine(&db_path, 4);
+        for i in 0..200 {
+            let mut doc = Document::new();
+            doc.set("n", Value::I32(i));
+            doc.set("payload", Value::String("x".repeat(200)));
+            let id = storage_engine.insert_document(&doc).unwrap();
+            expected.insert(id, doc);
+        }
+        storage_engine.flush().unwrap();
+        assert!(storage_engine.database_file.page_count() > 4);
+    }
+
+    // A fresh engine that never saw the inserts can enumerate them all
+    let storage_engine = StorageEngine::new(&db_path, 2).unwrap();
+    let mut seen = 0;
+    for entry in storage_engine.scan() {
+        let (id, doc) = entry.unwrap();
+        assert_eq!(expected.get(&id), Some(&doc));
+        seen += 1;
+    }
+    assert_eq!(seen, expected.len());
+}
@@ -0,0 +1,363 @@
+mod common;
+
+use common::create_engine;
+use database::{
+    Document, Value,
+    error::DatabaseError,
+    index::secondary::IndexDefinition,
+    query::filter::Filter,
+    storage::{database::Database, storage_engine::StorageEngine},
+};
+use tempfile::tempdir;
+
+fn person(name: &str, age: i32, city: &str) -> Document {
+    let mut doc = Document::new();
+    doc.set("name", Value::String(name.to_string()));
+    doc.set("age", Value::I32(age));
+    doc.set_path("address.city", Value::String(city.to_string()));
+    doc
+}
+
+fn names(engine: &StorageEngine, filter: &str) -> Vec<String> {
+    let mut names: Vec<String> = engine
+        .find(Filter::parse(filter).unwrap())
+        .map(|item| item.unwrap().1.get("name").unwrap().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_index_serves_equality_and_range_queries() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("index_find.db"), 10);
+    for n in 0..300 {
+        let city = ["Oslo", "Lima", "Pune"][n % 3];
+        storage_engine
+            .insert_document(&person(&format!("p{:03}", n), n as i32, city))
+            .unwrap();
+    }
+    storage_engine
+        .create_index(IndexDefinition::new("age"))
+        .unwrap();
+    storage_engine
+        .create_index(IndexDefinition::new("address.city").named("city"))
+        .unwrap();
+
+    let filter = Filter::parse(r#"{"age": {"$gte": 10, "$lt": 13}}"#).unwrap();
+    let cursor = storage_engine.find(filter);
+    assert_eq!(cursor.index(), Some("age_1"));
+    let ages: Vec<Value> = cursor
+        .map(|item| item.unwrap().1.get("age").cloned().unwrap())
+        .collect();
+    // Index order, not page order
+    assert_eq!(ages, vec![Value::I32(10), Value::I32(11), Value::I32(12)]);
+
+    // The index with the fewest entries to read wins, equality or not
+    let filter = Filter::parse(r#"{"age": {"$gt": 290}, "address.city": "Lima"}"#).unwrap();
+    assert_eq!(storage_engine.find(filter).index(), Some("age_1"));
+    assert_eq!(
+        names(
+            &storage_engine,
+            r#"{"age": {"$gt": 290}, "address.city": "Lima"}"#
+        ),
+        vec!["p292", "p295", "p298"]
+    );
+    assert_eq!(
+        names(&storage_engine, r#"{"age": {"$in": [5, 7.0]}}"#),
+        vec!["p005", "p007"]
+    );
+
+    // Range conditions only match numbers, as in a scan
+    assert!(names(&storage_engine, r#"{"age": {"$gt": "a"}}"#).is_empty());
+    assert_eq!(
+        storage_engine
+            .find(Filter::parse(r#"{"name": "p001"}"#).unwrap())
+            .index(),
+        None
+    );
+}
+
+#[test]
+fn test_index_is_maintained_by_writes() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("index_writes.db"), 10);
+    storage_engine
+        .create_index(IndexDefinition::new("tags"))
+        .unwrap();
+
+    let mut doc = person("ann", 30, "Oslo");
+    doc.set(
+        "tags",
+        Value::Array(vec![
+            Value::String("a".to_string()),
+            Value::String("b".to_string()),
+        ]),
+    );
+    let ann = storage_engine.insert_document(&doc).unwrap();
+    let ben = storage_engine
+        .insert_document(&person("ben", 40, "Lima"))
+        .unwrap();
+
+    // Array elements are indexed one by one, and missing fields as null
+    assert_eq!(names(&storage_engine, r#"{"tags": "b"}"#), vec!["ann"]);
+    assert_eq!(
+        names(&storage_engine, r#"{"tags": ["a", "b"]}"#),
+        vec!["ann"]
+    );
+    assert_eq!(names(&storage_engine, r#"{"tags": null}"#), vec!["ben"]);
+
+    doc.set("tags", Value::Array(vec![Value::String("c".to_string())]));
+    storage_engine.update_document(&ann, &doc).unwrap();
+    assert!(names(&storage_engine, r#"{"tags": "b"}"#).is_empty());
+    assert_eq!(names(&storage_engine, r#"{"tags": "c"}"#), vec!["ann"]);
+
+    storage_engine.delete_document(&ben).unwrap();
+    assert!(names(&storage_engine, r#"{"tags": null}"#).is_empty());
+
+    // A failed transaction leaves no entries behind
+    let mut transaction = storage_engine.begin_transaction().unwrap();
+    let mut extra = person("cat", 50, "Pune");
+    extra.set("tags", Value::String("c".to_string()));
+    transaction.insert_document(&extra).unwrap();
+    transaction.rollback().unwrap();
+    assert_eq!(names(&storage_engine, r#"{"tags": "c"}"#), vec!["ann"]);
+}
+
+#[test]
+fn test_range_on_array_matches_elements_one_bound_each() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("index_multikey.db"), 10);
+    let numbers = |name: &str, values: &[i32]| {
+        let mut doc = Document::new();
+        doc.set("name", Value::String(name.to_string()));
+        doc.set(
+            "x",
+            Value::Array(values.iter().map(|value| Value::I32(*value)).collect()),
+        );
+        doc
+    };
+    storage_engine
+        .insert_document(&numbers("split", &[1, 20]))
+        .unwrap();
+    storage_engine
+        .insert_document(&numbers("above", &[20, 30]))
+        .unwrap();
+    for n in 0..2000 {
+        storage_engine
+            .insert_document(&numbers(&format!("pad{}", n), &[-n]))
+            .unwrap();
+    }
+    storage_engine
+        .create_index(IndexDefinition::new("x"))
+        .unwrap();
+
+    // 1 meets the upper bound and 20 the lower, as a scan would have it
+    let filter = r#"{"x": {"$gt": 5, "$lt": 10}}"#;
+    assert_eq!(
+        storage_engine.find(Filter::parse(filter).unwrap()).index(),
+        Some("x_1")
+    );
+    assert_eq!(names(&storage_engine, filter), vec!["split"]);
+}
+
+#[test]
+fn test_indexes_persist_and_drop() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("index_persist.db");
+    {
+        let database = Database::from_engine(create_engine(&path, 10));
+        database.create_collection("people").unwrap();
+        database
+            .create_index("people", IndexDefinition::new("age"))
+            .unwrap();
+        assert!(
+            database
+                .create_index("people", IndexDefinition::new("age"))
+                .is_err()
+        );
+        assert!(
+            database
+                .create_index("missing", IndexDefinition::new("age"))
+                .is_err()
+        );
+        for n in 0..50 {
+            database
+                .insert_document("people", &person(&format!("p{}", n), n % 5, "Oslo"))
+                .unwrap();
+        }
+    }
+
+    let mut storage_engine = StorageEngine::new(&path, 10).unwrap();
+    let mut people = storage_engine.collection("people").unwrap();
+    assert_eq!(
+        people.list_indexes().unwrap(),
+        vec![IndexDefinition::new("age")]
+    );
+    // One page is cheaper to scan, but the index is still weighed
+    let explain = people
+        .find(Filter::parse(r#"{"age": 3}"#).unwrap())
+        .explain()
+        .unwrap();
+    assert!(
+        explain
+            .rejected
+            .iter()
+            .any(|estimate| estimate.plan.to_string() == "IXSCAN age_1")
+    );
+    assert_eq!(explain.documents_returned, 10);
+
+    assert!(people.drop_index("age_1").unwrap());
+    assert!(!people.drop_index("age_1").unwrap());
+    let explain = people
+        .find(Filter::parse(r#"{"age": 3}"#).unwrap())
+        .explain()
+        .unwrap();
+    assert!(explain.rejected.is_empty());
+    assert_eq!(explain.documents_returned, 10);
+}
+
+fn user(email: &str) -> Document {
+    let mut doc = Document::new();
+    doc.set("email", Value::String(email.to_string()));
+    doc
+}
+
+fn duplicate_key(error: anyhow::Error) -> (String, Value) {
+    match error.downcast::<DatabaseError>() {
+        Ok(DatabaseError::DuplicateKey { index, key }) => (index, key),
+        other => panic!("Expected a duplicate key error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_unique_index_rejects_duplicates() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("unique.db"), 10);
+    storage_engine
+        .create_index(IndexDefinition::new("email").unique())
+        .unwrap();
+    storage_engine
+        .create_index(IndexDefinition::new("name"))
+        .unwrap();
+
+    let ann = storage_engine.insert_document(&user("ann@x.io")).unwrap();
+    let ben = storage_engine.insert_document(&user("ben@x.io")).unwrap();
+
+    let mut copy = user("ann@x.io");
+    copy.set("name", Value::String("copy".to_string()));
+    let (index, key) = duplicate_key(storage_engine.insert_document(&copy).unwrap_err());
+    assert_eq!(index, "email_1");
+    assert_eq!(key, Value::String("ann@x.io".to_string()));
+    // Nothing of the failed insert is left, not even in the other index
+    assert_eq!(storage_engine.scan().count(), 2);
+    assert!(names(&storage_engine, r#"{"name": "copy"}"#).is_empty());
+
+    // Updating into a taken key fails, keeping one's own key does not
+    let (_, key) = duplicate_key(
+        storage_engine
+            .update_document(&ben, &user("ann@x.io"))
+            .unwrap_err(),
+    );
+    assert_eq!(key, Value::String("ann@x.io".to_string()));
+    assert_eq!(
+        storage_engine.get_document(&ben).unwrap().get("email"),
+        Some(&Value::String("ben@x.io".to_string()))
+    );
+    let mut renamed = user("ann@x.io");
+    renamed.set("name", Value::String("ann".to_string()));
+    storage_engine.update_document(&ann, &renamed).unwrap();
+
+    // A freed key can be taken again, and only one document may lack the field
+    storage_engine.delete_document(&ann).unwrap();
+    storage_engine.insert_document(&user("ann@x.io")).unwrap();
+    storage_engine.insert_document(&Document::new()).unwrap();
+    let (_, key) = duplicate_key(
+        storage_engine
+            .insert_document(&Document::new())
+            .unwrap_err(),
+    );
+    assert_eq!(key, Value::Null);
+}
+
+#[test]
+fn test_unique_index_over_duplicates_is_not_created() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("unique_build.db"), 10);
+    storage_engine.insert_document(&user("ann@x.io")).unwrap();
+    storage_engine.insert_document(&user("ann@x.io")).unwrap();
+
+    let (index, _) = duplicate_key(
+        storage_engine
+            .create_index(IndexDefinition::new("email").unique())
+            .unwrap_err(),
+    );
+    assert_eq!(index, "email_1");
+    assert!(storage_engine.list_indexes().unwrap().is_empty());
+
+    // Once the duplicate is gone the index can be built
+    let (first, _) = storage_engine.scan().next().unwrap().unwrap();
+    storage_engine.delete_document(&first).unwrap();
+    storage_engine
+        .create_index(IndexDefinition::new("email").unique())
+        .unwrap();
+
+    // Within a transaction only the failed write is undone
+    let mut transaction = storage_engine.begin_transaction().unwrap();
+    transaction.insert_document(&user("cat@x.io")).unwrap();
+    assert!(transaction.insert_document(&user("cat@x.io")).is_err());
+    transaction.commit().unwrap();
+    assert_eq!(emails(&storage_engine), vec!["ann@x.io", "cat@x.io"]);
+}
+
+fn emails(engine: &StorageEngine) -> Vec<String> {
+    let mut emails: Vec<String> = engine
+        .scan()
+        .map(|item| item.unwrap().1.get("email").unwrap().to_string())
+        .collect();
+    emails.sort();
+    emails
+}
+
+#[test]
+fn test_unique_index_tells_apart_integers_beyond_f64() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("unique_wide.db"), 10);
+    storage_engine
+        .create_index(IndexDefinition::new("serial").unique())
+        .unwrap();
+    let serial = |n: i64| {
+        let mut doc = Document::new();
+        doc.set("serial", Value::I64(n));
+        doc
+    };
+
+    // 2^60 and 2^60 + 1 are the same f64, but not the same number
+    let big = 1i64 << 60;
+    let first = storage_engine.insert_document(&serial(big)).unwrap();
+    let second = storage_engine.insert_document(&serial(big + 1)).unwrap();
+    let (_, key) = duplicate_key(storage_engine.insert_document(&serial(big)).unwrap_err());
+    assert_eq!(key, Value::I64(big));
+
+    // Updates are checked against the other document's value as well
+    storage_engine
+        .update_document(&first, &serial(big + 2))
+        .unwrap();
+    duplicate_key(
+        storage_engine
+            .update_document(&first, &serial(big + 1))
+            .unwrap_err(),
+    );
+    let found: Vec<_> = storage_engine
+        .find(Filter::parse(&format!(r#"{{"serial": {}}}"#, big + 1)).unwrap())
+        .map(|item| item.unwrap().0)
+        .collect();
+    assert_eq!(found, vec![second]);
+
+    // Building the index over such values succeeds too
+    storage_engine.drop_index("serial_1").unwrap();
+    storage_engine.insert_document(&serial(big)).unwrap();
+    storage_engine
+        .create_index(IndexDefinition::new("serial").unique())
+        .unwrap();
+}
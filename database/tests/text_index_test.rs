@@ -0,0 +1,188 @@
+mod common;
+
+use common::create_engine;
+use database::{
+    Document, Value,
+    error::DatabaseError,
+    index::secondary::IndexDefinition,
+    query::{filter::Filter, sort::Sort},
+    storage::storage_engine::StorageEngine,
+};
+use tempfile::tempdir;
+
+fn product(name: &str, description: &str, price: i32) -> Document {
+    let mut doc = Document::new();
+    doc.set("name", Value::String(name.to_string()));
+    doc.set("description", Value::String(description.to_string()));
+    doc.set("price", Value::I32(price));
+    doc
+}
+
+fn catalog(engine: &mut StorageEngine) {
+    for (name, description, price) in [
+        ("Studio Headphones", "Wired headphones for the studio", 120),
+        ("Travel Buds", "Wireless earbuds with a charging case", 80),
+        (
+            "Noise Cancelling Headphones",
+            "Wireless headphones that cancel noise",
+            250,
+        ),
+        ("Desk Lamp", "A lamp with a wireless charging pad", 40),
+        ("Kettle", "Boils water quickly", 30),
+    ] {
+        engine
+            .insert_document(&product(name, description, price))
+            .unwrap();
+    }
+}
+
+fn search(engine: &StorageEngine, filter: &str) -> Vec<String> {
+    engine
+        .find(Filter::parse(filter).unwrap())
+        .map(|item| item.unwrap().1.get("name").unwrap().to_string())
+        .collect()
+}
+
+fn name(name: &str) -> String {
+    Value::String(name.to_string()).to_string()
+}
+
+#[test]
+fn test_text_search_ranks_matches() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("text.db"), 32);
+    catalog(&mut storage_engine);
+    storage_engine
+        .create_index(IndexDefinition::text(&["name", "description"]))
+        .unwrap();
+
+    // Both words, three times over, beat one word once
+    let found = search(
+        &storage_engine,
+        r#"{"$text": {"$search": "wireless headphone"}}"#,
+    );
+    assert_eq!(found[0], name("Noise Cancelling Headphones"));
+    assert_eq!(found.len(), 4);
+    assert!(!found.contains(&name("Kettle")));
+
+    // Other forms of a word match, other conditions still apply
+    assert_eq!(
+        search(
+            &storage_engine,
+            r#"{"$text": {"$search": "CHARGED"}, "price": {"$lt": 50}}"#
+        ),
+        vec![name("Desk Lamp")]
+    );
+    assert!(search(&storage_engine, r#"{"$text": {"$search": "the with"}}"#).is_empty());
+
+    // Scores can be kept and sorted on
+    let scored: Vec<(String, f64)> = storage_engine
+        .find(Filter::parse(r#"{"$text": {"$search": "wireless charging"}}"#).unwrap())
+        .text_score("score")
+        .sort(Sort::new().ascending("score"))
+        .map(|item| {
+            let document = item.unwrap().1;
+            match document.get("score") {
+                Some(Value::F64(score)) => (document.get("name").unwrap().to_string(), *score),
+                other => panic!("unexpected score {:?}", other),
+            }
+        })
+        .collect();
+    assert_eq!(scored.len(), 3);
+    assert!(scored.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    assert!(scored[0].1 > 0.0);
+    assert_eq!(scored[0].0, name("Noise Cancelling Headphones"));
+
+    let explain = storage_engine
+        .find(Filter::parse(r#"{"$text": {"$search": "lamp"}}"#).unwrap())
+        .explain()
+        .unwrap();
+    assert_eq!(
+        explain.plan.plan.to_string(),
+        "TEXT name_text_description_text"
+    );
+    assert_eq!(explain.documents_examined, 1);
+    assert_eq!(explain.documents_returned, 1);
+}
+
+#[test]
+fn test_text_index_is_maintained_and_persists() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("text_writes.db");
+    let mut storage_engine = create_engine(&path, 32);
+    storage_engine
+        .create_index(IndexDefinition::text(&["description"]))
+        .unwrap();
+    catalog(&mut storage_engine);
+
+    let (kettle, _) = storage_engine
+        .find(Filter::parse(r#"{"name": "Kettle"}"#).unwrap())
+        .next()
+        .unwrap()
+        .unwrap();
+    storage_engine
+        .update_document(&kettle, &product("Kettle", "Cordless kettle", 35))
+        .unwrap();
+    assert!(search(&storage_engine, r#"{"$text": {"$search": "water"}}"#).is_empty());
+    assert_eq!(
+        search(&storage_engine, r#"{"$text": {"$search": "cordless"}}"#),
+        vec![name("Kettle")]
+    );
+
+    let (lamp, _) = storage_engine
+        .find(Filter::parse(r#"{"$text": {"$search": "lamp"}}"#).unwrap())
+        .next()
+        .unwrap()
+        .unwrap();
+    storage_engine.delete_document(&lamp).unwrap();
+    assert_eq!(
+        search(&storage_engine, r#"{"$text": {"$search": "charging"}}"#),
+        vec![name("Travel Buds")]
+    );
+
+    drop(storage_engine);
+    let storage_engine = StorageEngine::new(&path, 32).unwrap();
+    assert_eq!(
+        search(&storage_engine, r#"{"$text": {"$search": "cordless"}}"#),
+        vec![name("Kettle")]
+    );
+}
+
+#[test]
+fn test_text_search_needs_one_text_index() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("text_errors.db"), 16);
+    catalog(&mut storage_engine);
+
+    let mut cursor =
+        storage_engine.find(Filter::parse(r#"{"$text": {"$search": "lamp"}}"#).unwrap());
+    let error = cursor.next().unwrap().unwrap_err();
+    assert!(matches!(
+        error.downcast::<DatabaseError>(),
+        Ok(DatabaseError::Query(_))
+    ));
+    assert!(cursor.next().is_none());
+
+    storage_engine
+        .create_index(IndexDefinition::text(&["name"]))
+        .unwrap();
+    assert!(
+        storage_engine
+            .create_index(IndexDefinition::text(&["description"]))
+            .is_err()
+    );
+    assert!(
+        storage_engine
+            .create_index(IndexDefinition::text(&["description"]).unique())
+            .is_err()
+    );
+
+    let nested = r#"{"$or": [{"$text": {"$search": "lamp"}}, {"price": 30}]}"#;
+    assert!(
+        storage_engine
+            .find(Filter::parse(nested).unwrap())
+            .next()
+            .unwrap()
+            .is_err()
+    );
+}
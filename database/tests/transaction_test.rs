@@ -0,0 +1,131 @@
+mod common;
+
+use common::{create_engine, numbered};
+use database::{
+    storage::{storage_engine::StorageEngine, wal::WriteAheadLog},
+    Value,
+};
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+
+fn crash_image(db_path: &Path, image_path: &Path) {
+    fs::copy(db_path, image_path).unwrap();
+    fs::copy(
+        WriteAheadLog::path_for(db_path),
+        WriteAheadLog::path_for(image_path),
+    )
+    .unwrap();
+}
+
+fn numbers(storage_engine: &mut StorageEngine) -> Vec<i32> {
+    let mut numbers: Vec<i32> = storage_engine
+        .scan()
+        .map(|entry| match entry.unwrap().1.get("n") {
+            Some(Value::I32(n)) => *n,
+            other => panic!("unexpected n: {:?}", other),
+        })
+        .collect();
+    numbers.sort_unstable();
+    numbers
+}
+
+#[test]
+fn test_commit_is_visible_and_durable() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("tx_commit.db");
+    let image_path = temp_dir.path().join("tx_commit_crashed.db");
+
+    let mut storage_engine = create_engine(&db_path, 8);
+    let mut transaction = storage_engine.begin_transaction().unwrap();
+    for i in 0..50 {
+        transaction.insert_document(&numbered(i, 500)).unwrap();
+    }
+    // Reads borrow the transaction shared, so a scan can look documents up as it goes
+    let mut scanned = 0;
+    for entry in transaction.scan() {
+        let (location, document) = entry.unwrap();
+        assert_eq!(transaction.get_document(&location).unwrap(), document);
+        assert_eq!(
+            transaction.get_by_id(document.get_id().unwrap()).unwrap(),
+            Some(document)
+        );
+        scanned += 1;
+    }
+    assert_eq!(scanned, 50);
+    transaction.commit().unwrap();
+
+    assert_eq!(numbers(&mut storage_engine), (0..50).collect::<Vec<_>>());
+
+    crash_image(&db_path, &image_path);
+    drop(storage_engine);
+    let mut recovered = StorageEngine::new(&image_path, 8).unwrap();
+    assert_eq!(numbers(&mut recovered), (0..50).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_rollback_undoes_every_write() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("tx_rollback.db"), 8);
+
+    let kept = numbered(1, 500);
+    let kept_location = storage_engine.insert_document(&kept).unwrap();
+    let deleted = numbered(2, 500);
+    storage_engine.insert_document(&deleted).unwrap();
+
+    let mut transaction = storage_engine.begin_transaction().unwrap();
+    let added = numbered(3, 500);
+    transaction.insert_document(&added).unwrap();
+    transaction.update_by_id(kept.get_id().unwrap(), &numbered(10, 500)).unwrap();
+    assert!(transaction.delete_by_id(deleted.get_id().unwrap()).unwrap());
+    assert_eq!(
+        transaction.get_by_id(kept.get_id().unwrap()).unwrap().unwrap().get("n"),
+        Some(&Value::I32(10))
+    );
+    transaction.rollback().unwrap();
+
+    assert_eq!(numbers(&mut storage_engine), vec![1, 2]);
+    assert_eq!(storage_engine.get_document(&kept_location).unwrap(), kept);
+    assert_eq!(storage_engine.get_by_id(deleted.get_id().unwrap()).unwrap(), Some(deleted));
+    assert_eq!(storage_engine.get_by_id(added.get_id().unwrap()).unwrap(), None);
+}
+
+#[test]
+fn test_dropped_transaction_rolls_back() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("tx_drop.db");
+
+    {
+        let mut storage_engine = create_engine(&db_path, 8);
+        storage_engine.insert_document(&numbered(1, 500)).unwrap();
+
+        let mut transaction = storage_engine.begin_transaction().unwrap();
+        for i in 100..120 {
+            transaction.insert_document(&numbered(i, 500)).unwrap();
+        }
+        drop(transaction);
+
+        assert_eq!(numbers(&mut storage_engine), vec![1]);
+    }
+
+    let mut reopened = StorageEngine::new(&db_path, 8).unwrap();
+    assert_eq!(numbers(&mut reopened), vec![1]);
+}
+
+#[test]
+fn test_failed_write_leaves_no_partial_state() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("tx_failed.db"), 8);
+
+    let first = numbered(1, 500);
+    storage_engine.insert_document(&first).unwrap();
+
+    let mut transaction = storage_engine.begin_transaction().unwrap();
+    transaction.insert_document(&numbered(2, 500)).unwrap();
+    // Duplicate _id: the insert fails but the transaction stays usable
+    assert!(transaction.insert_document(&first).is_err());
+    transaction.insert_document(&numbered(3, 500)).unwrap();
+    transaction.commit().unwrap();
+
+    assert_eq!(numbers(&mut storage_engine), vec![1, 2, 3]);
+}
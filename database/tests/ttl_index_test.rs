@@ -0,0 +1,203 @@
+mod common;
+
+use chrono::{Duration as Age, Utc};
+use common::{create_database, create_engine};
+use database::{
+    Document, Value,
+    index::secondary::IndexDefinition,
+    query::{filter::Filter, sort::Sort},
+    storage::{
+        database::Database,
+        storage_engine::DocumentId,
+        ttl::{TtlOptions, TtlReaper},
+    },
+};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::tempdir;
+
+const HOUR: Duration = Duration::from_secs(3600);
+
+fn session(user: &str, created: Value) -> Document {
+    let mut doc = Document::new();
+    doc.set("user", Value::String(user.to_string()));
+    doc.set("created", created);
+    doc
+}
+
+fn minutes_ago(minutes: i64) -> Value {
+    Value::DateTime(Utc::now() - Age::minutes(minutes))
+}
+
+fn users(documents: Vec<(DocumentId, Document)>) -> Vec<String> {
+    let mut users: Vec<String> = documents
+        .into_iter()
+        .map(|(_, document)| match document.get("user") {
+            Some(Value::String(user)) => user.clone(),
+            other => panic!("unexpected user {:?}", other),
+        })
+        .collect();
+    users.sort();
+    users
+}
+
+/// Wait for the reaper to finish `passes` passes.
+fn wait_for(reaper: &TtlReaper, passes: u64) {
+    let started = Instant::now();
+    while reaper.passes() < passes {
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "reaper stalled"
+        );
+        thread::sleep(Duration::from_millis(5));
+    }
+}
+
+#[test]
+fn test_expire_documents_deletes_old_dates_in_batches() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("ttl.db"), 16);
+    storage_engine
+        .create_index(IndexDefinition::new("created").expire_after(HOUR))
+        .unwrap();
+    for (user, created) in [
+        ("old", minutes_ago(120)),
+        ("fresh", minutes_ago(30)),
+        // The earliest date of an array counts
+        ("mixed", Value::Array(vec![minutes_ago(5), minutes_ago(90)])),
+        ("text", Value::String("2001-01-01".to_string())),
+        ("null", Value::Null),
+    ] {
+        storage_engine
+            .insert_document(&session(user, created))
+            .unwrap();
+    }
+    let mut undated = Document::new();
+    undated.set("user", Value::String("undated".to_string()));
+    storage_engine.insert_document(&undated).unwrap();
+
+    let report = storage_engine.expire_documents(Utc::now(), 1).unwrap();
+    assert_eq!(report.indexes_checked, 1);
+    assert_eq!(report.documents_removed, 1);
+    assert_eq!(report.removed_by_collection.get("default"), Some(&1));
+
+    let report = storage_engine.expire_documents(Utc::now(), 10).unwrap();
+    assert_eq!(report.documents_removed, 1);
+    assert_eq!(
+        users(storage_engine.scan().collect::<Result<_, _>>().unwrap()),
+        vec!["fresh", "null", "text", "undated"]
+    );
+    // An hour from now the fresh session is due as well
+    let later = Utc::now() + Age::hours(1);
+    assert_eq!(
+        storage_engine
+            .expire_documents(later, 10)
+            .unwrap()
+            .documents_removed,
+        1
+    );
+    assert!(
+        storage_engine
+            .find(Filter::parse(r#"{"user": "fresh"}"#).unwrap())
+            .next()
+            .is_none()
+    );
+}
+
+#[test]
+fn test_only_single_path_ordered_indexes_expire() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("ttl_invalid.db"), 16);
+    for definition in [
+        IndexDefinition::text(&["user"]).expire_after(HOUR),
+        IndexDefinition::geo("loc").expire_after(HOUR),
+        IndexDefinition::compound(Sort::new().ascending("user").ascending("created"))
+            .expire_after(HOUR),
+    ] {
+        assert!(storage_engine.create_index(definition).is_err());
+    }
+    assert!(storage_engine.list_indexes().unwrap().is_empty());
+}
+
+#[test]
+fn test_reaper_removes_expired_documents_in_the_background() {
+    let temp_dir = tempdir().unwrap();
+    let database = create_database(&temp_dir.path().join("reaper.db"), 32);
+    database.create_collection("sessions").unwrap();
+    database
+        .create_index(
+            "sessions",
+            IndexDefinition::new("created").expire_after(HOUR),
+        )
+        .unwrap();
+    for i in 0..25 {
+        database
+            .insert_document("sessions", &session(&format!("old{}", i), minutes_ago(61)))
+            .unwrap();
+    }
+    for i in 0..5 {
+        database
+            .insert_document("sessions", &session(&format!("new{}", i), minutes_ago(1)))
+            .unwrap();
+    }
+
+    let reaper = TtlReaper::start(
+        &database,
+        TtlOptions {
+            interval: Duration::from_millis(10),
+            batch_size: 10,
+        },
+    );
+    wait_for(&reaper, 1);
+    assert_eq!(reaper.removed(), 25);
+    assert_eq!(database.scan("sessions").unwrap().len(), 5);
+
+    // Later passes pick up documents as they expire
+    database
+        .insert_document("sessions", &session("late", minutes_ago(120)))
+        .unwrap();
+    let passes = reaper.passes();
+    wait_for(&reaper, passes + 2);
+    assert_eq!(reaper.stop(), 26);
+    assert_eq!(database.scan("sessions").unwrap().len(), 5);
+}
+
+#[test]
+fn test_expiry_survives_a_restart() {
+    let temp_dir = tempdir().unwrap();
+    let path = temp_dir.path().join("ttl_restart.db");
+    let database = create_database(&path, 16);
+    database
+        .create_index(
+            "default",
+            IndexDefinition::new("created").expire_after(HOUR),
+        )
+        .unwrap();
+    database
+        .insert_document("default", &session("fresh", minutes_ago(1)))
+        .unwrap();
+    database
+        .insert_document("default", &session("stale", minutes_ago(61)))
+        .unwrap();
+    drop(database);
+
+    let database = Arc::new(Database::open(&path, 16).unwrap());
+    assert_eq!(
+        database.list_indexes("default").unwrap()[0].expires_after(),
+        Some(HOUR)
+    );
+    let reaper = TtlReaper::start(&database, TtlOptions::default());
+    wait_for(&reaper, 1);
+    assert_eq!(reaper.removed(), 1);
+    assert_eq!(
+        users(database.scan("default").unwrap()),
+        vec!["fresh".to_string()]
+    );
+
+    // The reaper doesn't keep the database open
+    drop(database);
+    let database = Database::open(&path, 16).unwrap();
+    assert_eq!(database.scan("default").unwrap().len(), 1);
+    assert_eq!(reaper.stop(), 1);
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use common::create_engine;
+use database::{
+    Document, Value,
+    error::DatabaseError,
+    query::{filter::Filter, update::Update},
+    storage::database::Database,
+};
+use tempfile::tempdir;
+
+fn account(owner: &str, balance: i32) -> Document {
+    let mut doc = Document::new();
+    doc.set("owner", Value::String(owner.to_string()));
+    doc.set("balance", Value::I32(balance));
+    doc
+}
+
+#[test]
+fn test_update_one_by_id_and_filter() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("update_one.db"), 10);
+    let ann = account("ann", 10);
+    let ann_id = ann.get_id().unwrap().clone();
+    storage_engine.insert_document(&ann).unwrap();
+    storage_engine.insert_document(&account("ben", 20)).unwrap();
+
+    let update = Update::parse(r#"{"$inc": {"balance": 5}, "$push": {"log": "deposit"}}"#).unwrap();
+    assert!(
+        storage_engine
+            .update_one(&ann_id, &update)
+            .unwrap()
+            .is_some()
+    );
+    let stored = storage_engine.get_by_id(&ann_id).unwrap().unwrap();
+    assert_eq!(stored.get("balance"), Some(&Value::I32(15)));
+    assert_eq!(
+        stored.get("log"),
+        Some(&Value::Array(vec![Value::String("deposit".to_string())]))
+    );
+
+    let filter = Filter::parse(r#"{"owner": "ben"}"#).unwrap();
+    let update =
+        Update::parse(r#"{"$set": {"profile.tier": "gold"}, "$mul": {"balance": 1.5}}"#).unwrap();
+    let document_id = storage_engine.update_one(filter, &update).unwrap().unwrap();
+    let ben = storage_engine.get_document(&document_id).unwrap();
+    assert_eq!(
+        ben.get_path("profile.tier"),
+        Some(&Value::String("gold".to_string()))
+    );
+    assert_eq!(ben.get("balance"), Some(&Value::F64(30.0)));
+
+    let nobody = Filter::parse(r#"{"owner": "cy"}"#).unwrap();
+    assert_eq!(storage_engine.update_one(nobody, &update).unwrap(), None);
+}
+
+#[test]
+fn test_invalid_update_leaves_document_unchanged() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("update_invalid.db"), 10);
+    let ann = account("ann", 10);
+    let ann_id = ann.get_id().unwrap().clone();
+    storage_engine.insert_document(&ann).unwrap();
+
+    let update = Update::parse(r#"{"$inc": {"owner": 1}}"#).unwrap();
+    let error = storage_engine.update_one(&ann_id, &update).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<DatabaseError>(),
+        Some(DatabaseError::Validation(_))
+    ));
+    assert_eq!(storage_engine.get_by_id(&ann_id).unwrap().unwrap(), ann);
+}
+
+#[test]
+fn test_update_one_in_collection() {
+    let temp_dir = tempdir().unwrap();
+    let database = Database::from_engine(create_engine(&temp_dir.path().join("update_db.db"), 10));
+    database.create_collection("accounts").unwrap();
+    database
+        .insert_document("accounts", &account("ann", 10))
+        .unwrap();
+
+    let filter = Filter::parse(r#"{"balance": {"$lt": 100}}"#).unwrap();
+    let update = Update::parse(r#"{"$rename": {"owner": "holder"}}"#).unwrap();
+    assert!(
+        database
+            .update_one("accounts", filter.clone(), &update)
+            .unwrap()
+            .is_some()
+    );
+
+    let accounts = database.scan("accounts").unwrap();
+    assert_eq!(accounts[0].1.get("owner"), None);
+    assert_eq!(
+        accounts[0].1.get("holder"),
+        Some(&Value::String("ann".to_string()))
+    );
+
+    assert!(database.update_one("missing", filter, &update).is_err());
+}
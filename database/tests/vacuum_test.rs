@@ -0,0 +1,163 @@
+mod common;
+
+use common::{create_database, create_engine, numbered};
+use database::{
+    storage::{
+        storage_engine::StorageEngine,
+        vacuum::{VacuumOptions, VacuumReport, VacuumScheduler},
+    },
+    Document,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::tempdir;
+
+fn insert_numbered(storage_engine: &mut StorageEngine, count: i32) -> Vec<Document> {
+    (0..count)
+        .map(|i| {
+            let doc = numbered(i, 500);
+            storage_engine.insert_document(&doc).unwrap();
+            doc
+        })
+        .collect()
+}
+
+#[test]
+fn test_vacuum_frees_empty_pages() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("vacuum_free.db");
+    let mut storage_engine = create_engine(&db_path, 8);
+
+    let docs = insert_numbered(&mut storage_engine, 200);
+    let pages_before = storage_engine.collection_stats("default").unwrap().page_count;
+    // Empty the first half of the collection, page by page
+    for doc in &docs[..100] {
+        storage_engine.delete_by_id(doc.get_id().unwrap()).unwrap();
+    }
+
+    let report = storage_engine.vacuum().unwrap();
+    assert_eq!(report.pages_scanned, pages_before);
+    assert!(report.pages_freed > 0);
+    assert_eq!(storage_engine.last_vacuum_report(), Some(&report));
+
+    let stats = storage_engine.collection_stats("default").unwrap();
+    assert_eq!(stats.page_count, pages_before - report.pages_freed);
+    assert_eq!(stats.document_count, 100);
+    assert_eq!(
+        storage_engine.database_file.free_pages().unwrap().len(),
+        report.pages_freed
+    );
+
+    // The surviving documents are still found through the primary index
+    for doc in &docs[100..] {
+        assert_eq!(storage_engine.get_by_id(doc.get_id().unwrap()).unwrap().as_ref(), Some(doc));
+    }
+    drop(storage_engine);
+
+    let mut storage_engine = StorageEngine::new(&db_path, 8).unwrap();
+    assert_eq!(storage_engine.scan().count(), 100);
+    assert_eq!(storage_engine.vacuum().unwrap().pages_freed, 0);
+}
+
+#[test]
+fn test_vacuum_compacts_fragmented_pages() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("vacuum_compact.db"), 8);
+
+    let docs = insert_numbered(&mut storage_engine, 100);
+    for doc in docs.iter().step_by(2) {
+        storage_engine.delete_by_id(doc.get_id().unwrap()).unwrap();
+    }
+
+    let report = storage_engine.vacuum().unwrap();
+    assert!(report.pages_compacted > 0);
+    assert_eq!(report.pages_freed, 0);
+    assert!(report.bytes_reclaimed >= report.pages_compacted * 500);
+
+    for doc in docs.iter().skip(1).step_by(2) {
+        assert_eq!(storage_engine.get_by_id(doc.get_id().unwrap()).unwrap().as_ref(), Some(doc));
+    }
+
+    // Nothing is left to do on a second pass
+    let report = storage_engine.vacuum().unwrap();
+    assert_eq!(report.pages_compacted, 0);
+    assert_eq!(report.bytes_reclaimed, 0);
+}
+
+#[test]
+fn test_vacuum_threshold_skips_dense_pages() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("vacuum_threshold.db"), 8);
+
+    let docs = insert_numbered(&mut storage_engine, 100);
+    // One hole per page leaves every page mostly full
+    for doc in docs.iter().step_by(15) {
+        storage_engine.delete_by_id(doc.get_id().unwrap()).unwrap();
+    }
+
+    storage_engine.set_vacuum_options(VacuumOptions {
+        max_utilization: 10.0,
+        ..VacuumOptions::default()
+    });
+    assert_eq!(storage_engine.vacuum().unwrap().pages_compacted, 0);
+
+    storage_engine.set_vacuum_options(VacuumOptions {
+        max_utilization: 100.0,
+        ..VacuumOptions::default()
+    });
+    assert!(storage_engine.vacuum().unwrap().pages_compacted > 0);
+}
+
+#[test]
+fn test_scheduled_vacuum_runs_after_writes() {
+    let temp_dir = tempdir().unwrap();
+    let mut storage_engine = create_engine(&temp_dir.path().join("vacuum_schedule.db"), 8);
+
+    let docs = insert_numbered(&mut storage_engine, 50);
+    assert_eq!(storage_engine.last_vacuum_report(), None);
+
+    storage_engine.set_vacuum_options(VacuumOptions {
+        interval: Some(Duration::ZERO),
+        ..VacuumOptions::default()
+    });
+    for doc in &docs {
+        storage_engine.delete_by_id(doc.get_id().unwrap()).unwrap();
+    }
+
+    // The last delete emptied the collection and the vacuum that followed it freed the pages
+    let report = storage_engine.last_vacuum_report().cloned().unwrap();
+    assert!(report.pages_freed > 0);
+    assert_eq!(storage_engine.collection_stats("default").unwrap().page_count, 0);
+
+    storage_engine.set_vacuum_options(VacuumOptions {
+        interval: Some(Duration::from_secs(3600)),
+        ..VacuumOptions::default()
+    });
+    storage_engine.insert_document(&numbered(0, 500)).unwrap();
+    assert_eq!(storage_engine.last_vacuum_report(), Some(&report));
+    assert_ne!(report, VacuumReport::default());
+}
+
+#[test]
+fn test_scheduler_vacuums_an_idle_database() {
+    let temp_dir = tempdir().unwrap();
+    let database = create_database(&temp_dir.path().join("vacuum_scheduler.db"), 8);
+    let docs: Vec<Document> = (0..50).map(|i| numbered(i, 500)).collect();
+    for doc in &docs {
+        database.insert_document("default", doc).unwrap();
+    }
+    for doc in &docs {
+        database.delete_by_id("default", doc.get_id().unwrap()).unwrap();
+    }
+    assert!(database.collection_stats("default").unwrap().page_count > 0);
+
+    // No write follows the deletes; the scheduler's own pass frees the pages
+    let scheduler = VacuumScheduler::start(&database, Duration::from_millis(10));
+    let started = Instant::now();
+    while scheduler.passes() == 0 {
+        assert!(started.elapsed() < Duration::from_secs(10), "scheduler stalled");
+        thread::sleep(Duration::from_millis(5));
+    }
+    assert_eq!(database.collection_stats("default").unwrap().page_count, 0);
+    assert!(scheduler.stop() >= 1);
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::{create_engine, numbered};
+use database::{
+    storage::{storage_engine::StorageEngine, wal::WriteAheadLog},
+    Value,
+};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use tempfile::tempdir;
+
+/// Copy the on-disk state of a live database, as a crash at this instant would leave it.
+fn crash_image(db_path: &Path, image_path: &Path) {
+    fs::copy(db_path, image_path).unwrap();
+    fs::copy(
+        WriteAheadLog::path_for(db_path),
+        WriteAheadLog::path_for(image_path),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_acknowledged_inserts_survive_crash() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("live.db");
+    let image_path = temp_dir.path().join("crashed.db");
+
+    let mut storage_engine = create_engine(&db_path, 64);
+    let mut inserted = Vec::new();
+    for i in 0..100 {
+        let doc = numbered(i, 300);
+        let location = storage_engine.insert_document(&doc).unwrap();
+        inserted.push((location, doc));
+    }
+
+    // Nothing has been checkpointed, so the data file alone is missing the inserts
+    crash_image(&db_path, &image_path);
+    drop(storage_engine);
+
+    let recovered = StorageEngine::new(&image_path, 64).unwrap();
+    for (location, doc) in &inserted {
+        assert_eq!(&recovered.get_document(location).unwrap(), doc);
+        assert_eq!(
+            recovered.get_by_id(doc.get_id().unwrap()).unwrap().as_ref(),
+            Some(doc)
+        );
+    }
+    assert_eq!(recovered.scan().count(), inserted.len());
+}
+
+#[test]
+fn test_updates_and_deletes_survive_crash() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("live.db");
+    let image_path = temp_dir.path().join("crashed.db");
+
+    let mut storage_engine = create_engine(&db_path, 64);
+    let kept = storage_engine.insert_document(&numbered(1, 300)).unwrap();
+    let deleted = storage_engine.insert_document(&numbered(2, 300)).unwrap();
+    storage_engine.flush().unwrap();
+
+    let kept = storage_engine.update_document(&kept, &numbered(10, 300)).unwrap();
+    storage_engine.delete_document(&deleted).unwrap();
+
+    crash_image(&db_path, &image_path);
+    drop(storage_engine);
+
+    let recovered = StorageEngine::new(&image_path, 64).unwrap();
+    let documents: Vec<_> = recovered.scan().collect::<Result<_, _>>().unwrap();
+    assert_eq!(documents.len(), 1);
+    assert_eq!(documents[0].0, kept);
+    assert_eq!(documents[0].1.get("n"), Some(&Value::I32(10)));
+}
+
+#[test]
+fn test_torn_log_tail_is_discarded() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("live.db");
+    let image_path = temp_dir.path().join("crashed.db");
+
+    let mut storage_engine = create_engine(&db_path, 64);
+    let location = storage_engine.insert_document(&numbered(7, 300)).unwrap();
+    crash_image(&db_path, &image_path);
+    drop(storage_engine);
+
+    // A half-written record at the end of the log, as left by a crash mid-append
+    let mut wal = OpenOptions::new()
+        .append(true)
+        .open(WriteAheadLog::path_for(&image_path))
+        .unwrap();
+    wal.write_all(&[1, 0x0C, 0x20, 0, 0, 42, 42]).unwrap();
+    drop(wal);
+
+    let recovered = StorageEngine::new(&image_path, 64).unwrap();
+    assert_eq!(
+        recovered.get_document(&location).unwrap().get("n"),
+        Some(&Value::I32(7))
+    );
+}
+
+#[test]
+fn test_small_pool_with_uncheckpointed_writes() {
+    let temp_dir = tempdir().unwrap();
+    let db_path = temp_dir.path().join("small_pool.db");
+
+    // A pool smaller than the set of pages one insert can touch still works
+    let mut storage_engine = create_engine(&db_path, 2);
+    let mut locations = Vec::new();
+    for i in 0..300 {
+        locations.push(storage_engine.insert_document(&numbered(i, 300)).unwrap());
+    }
+    for (i, location) in locations.iter().enumerate() {
+        assert_eq!(
+            storage_engine.get_document(location).unwrap().get("n"),
+            Some(&Value::I32(i as i32))
+        );
+    }
+    drop(storage_engine);
+
+    // A clean shutdown checkpoints and leaves an empty log behind
+    assert_eq!(fs::metadata(WriteAheadLog::path_for(&db_path)).unwrap().len(), 0);
+}